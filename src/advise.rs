@@ -0,0 +1,120 @@
+//! Rough guidance on which tone frequencies people hear, for `nodoze advise`.
+//!
+//! The thresholds are deliberately simple. Hearing varies from person to
+//! person and with age, and whether a tone is noticed also depends on the
+//! speakers and the volume. They're meant to steer a newcomer away from an
+//! obviously audible frequency, not to promise silence.
+
+use crate::audio;
+use crate::config::Config;
+
+/// Below this a tone is infrasound. The threshold of hearing climbs steeply
+/// under 20 Hz (to around 80 dB SPL at 20 Hz), far above the quiet tones
+/// nodoze plays, and most speakers barely reproduce it anyway.
+const INFRASOUND_BELOW: f64 = 20.0;
+
+/// Up to here the threshold of hearing is still tens of dB SPL, so a quiet
+/// tone usually goes unnoticed, though some people hear it on a subwoofer
+/// or at a higher volume
+const LOW_BASS_BELOW: f64 = 40.0;
+
+/// Most adults don't hear above this. The upper limit is about 20 kHz for
+/// children and falls with age, to 15-17 kHz by middle age.
+const ULTRASONIC_ABOVE: f64 = 18_000.0;
+
+/// Fraction of the sample rate a tone can reach before the device's
+/// anti-aliasing filter starts cutting it (just under the Nyquist frequency
+/// of half the sample rate)
+const USABLE_BANDWIDTH: f64 = 0.45;
+
+/// Where a frequency falls relative to human hearing
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Band {
+    /// Below `INFRASOUND_BELOW`: inaudible
+    Infrasound,
+    /// Up to `LOW_BASS_BELOW`: quiet bass few people notice
+    LowBass,
+    /// The audible midrange, which a keep-alive tone should avoid
+    Audible,
+    /// Above `ULTRASONIC_ABOVE` but within what the device can play
+    Ultrasonic,
+    /// Too close to (or past) half the sample rate to play cleanly
+    BeyondDevice,
+}
+
+impl Band {
+    /// Classify `frequency` for a device running at `sample_rate`
+    fn of(frequency: f64, sample_rate: f64) -> Self {
+        if frequency >= sample_rate * USABLE_BANDWIDTH {
+            Self::BeyondDevice
+        } else if frequency < INFRASOUND_BELOW {
+            Self::Infrasound
+        } else if frequency < LOW_BASS_BELOW {
+            Self::LowBass
+        } else if frequency > ULTRASONIC_ABOVE {
+            Self::Ultrasonic
+        } else {
+            Self::Audible
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Self::Infrasound => "infrasound, inaudible",
+            Self::LowBass => "low bass, usually unnoticed at low volume",
+            Self::Audible => "audible midrange",
+            Self::Ultrasonic => "above most adults' hearing",
+            Self::BeyondDevice => "too close to half the sample rate for this device",
+        }
+    }
+}
+
+/// Print the inaudible frequency ranges for the configured device at the
+/// sample rate it would play at, and where the configured frequencies fall.
+/// Returns false if the device couldn't be opened.
+pub fn run(config: &Config) -> bool {
+    let (device, sample_rate) = match audio::output_rate(config) {
+        Ok(output) => output,
+        Err(e) => {
+            log::error!("{}", e);
+            return false;
+        }
+    };
+    let top = sample_rate * USABLE_BANDWIDTH;
+    println!("Device:        '{}' at {} Hz", device, sample_rate);
+    println!("Inaudible:     below {} Hz (infrasound)", INFRASOUND_BELOW);
+    if top > ULTRASONIC_ABOVE {
+        println!("               {}-{:.0} Hz (above most adults' hearing)", ULTRASONIC_ABOVE, top);
+    } else {
+        println!("               no ultrasonic band: this rate only reaches {:.0} Hz", top);
+    }
+    println!(
+        "Borderline:    {}-{} Hz (low bass, usually unnoticed at low volume)",
+        INFRASOUND_BELOW, LOW_BASS_BELOW
+    );
+
+    if !config.wav_path.is_empty() {
+        println!("Configured:    '{}', a clip whose frequencies aren't checked", config.wav_path);
+        return true;
+    }
+    let frequencies = if config.frequency_rotation.is_empty() {
+        vec![config.frequency]
+    } else {
+        config.frequency_rotation.clone()
+    };
+    let mut audible = Vec::new();
+    for frequency in frequencies {
+        let band = Band::of(frequency, sample_rate);
+        println!("Configured:    {} Hz, {}", frequency, band.describe());
+        if band == Band::Audible {
+            audible.push(frequency);
+        }
+    }
+    if !audible.is_empty() {
+        log::warn!(
+            "{} Hz is in the audible midrange; pick a frequency from the ranges above",
+            audible.iter().map(|f| f.to_string()).collect::<Vec<_>>().join(", ")
+        );
+    }
+    true
+}
@@ -0,0 +1,171 @@
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+use crate::audio;
+use crate::config::Config;
+
+/// Shortest gap between two "failing" alerts, so a long outage (or a device
+/// that keeps dropping out) doesn't flood the webhook
+const ALERT_REPEAT: Duration = Duration::from_secs(3600);
+
+/// How long to wait for the webhook to answer
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Consecutive failures before a desktop notification, so one transient
+/// glitch doesn't pop anything up
+const NOTIFY_AFTER: u32 = 2;
+
+/// Shortest gap between two failure notifications on the desktop
+const NOTIFY_REPEAT: Duration = Duration::from_secs(15 * 60);
+
+/// Shortest gap between two failure chirps, so an outage doesn't beep
+/// every interval
+const CHIRP_REPEAT: Duration = Duration::from_secs(30 * 60);
+
+/// Body POSTed to `alert_webhook`
+#[derive(Serialize)]
+struct Payload {
+    /// "failing" or "recovered"
+    event: &'static str,
+    consecutive_failures: u32,
+    device: String,
+    error: Option<String>,
+    timestamp: String,
+}
+
+/// Tracks consecutive tone failures and notifies the configured webhook
+/// once they reach `failure_threshold`, and the desktop (if enabled) from
+/// the second failure. Both are told again on recovery. With
+/// `failure_chirp`, an audible chirp also plays from `failure_threshold`.
+pub struct Alerter {
+    url: String,
+    threshold: u32,
+    device: String,
+    failures: u32,
+    last_alert: Option<Instant>,
+    /// A "failing" alert went out and no "recovered" one has followed
+    alerted: bool,
+    desktop: bool,
+    last_notification: Option<Instant>,
+    /// Same as `alerted`, for desktop notifications
+    notified: bool,
+    /// Audible tone for `failure_chirp`, if enabled
+    chirp: Option<Config>,
+    last_chirp: Option<Instant>,
+}
+
+impl Alerter {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            url: config.alert_webhook.clone(),
+            threshold: config.failure_threshold.max(1),
+            device: config.device_label(),
+            failures: 0,
+            last_alert: None,
+            alerted: false,
+            desktop: config.desktop_notifications,
+            last_notification: None,
+            notified: false,
+            chirp: config.failure_chirp.then(|| audio::failure_chirp_config(config)),
+            last_chirp: None,
+        }
+    }
+
+    pub fn failure(&mut self, error: &impl ToString) {
+        self.failures += 1;
+        if self.desktop
+            && self.failures >= NOTIFY_AFTER
+            && self.last_notification.is_none_or(|at| at.elapsed() >= NOTIFY_REPEAT)
+        {
+            self.last_notification = Some(Instant::now());
+            self.notified = true;
+            notify_desktop("nodoze: audio device unavailable", error.to_string());
+        }
+
+        if let Some(chirp) = &self.chirp {
+            if self.failures >= self.threshold
+                && self.last_chirp.is_none_or(|at| at.elapsed() >= CHIRP_REPEAT)
+            {
+                self.last_chirp = Some(Instant::now());
+                play_chirp(chirp.clone());
+            }
+        }
+
+        if self.url.is_empty() || self.failures < self.threshold {
+            return;
+        }
+        if self.last_alert.is_some_and(|at| at.elapsed() < ALERT_REPEAT) {
+            return;
+        }
+        self.last_alert = Some(Instant::now());
+        self.alerted = true;
+        self.send("failing", Some(error.to_string()));
+    }
+
+    /// Carry on from `failures` in a row left by the last run, as if they
+    /// had happened in this one. A "failing" alert is assumed to have gone
+    /// out already if there were enough, so recovery is still announced.
+    pub fn resume(&mut self, failures: u32) {
+        self.failures = failures;
+        self.alerted = !self.url.is_empty() && failures >= self.threshold;
+    }
+
+    pub fn success(&mut self) {
+        if self.alerted {
+            self.alerted = false;
+            self.send("recovered", None);
+        }
+        if self.notified {
+            self.notified = false;
+            notify_desktop("nodoze: tones playing again", self.device.clone());
+        }
+        self.failures = 0;
+    }
+
+    /// POST on a background thread so a slow webhook never delays a tone
+    fn send(&self, event: &'static str, error: Option<String>) {
+        let payload = Payload {
+            event,
+            consecutive_failures: self.failures,
+            device: self.device.clone(),
+            error,
+            timestamp: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+        };
+        let url = self.url.clone();
+        log::info!("Sending '{}' alert to {}", event, url);
+        std::thread::spawn(move || {
+            let result = ureq::post(&url)
+                .timeout(WEBHOOK_TIMEOUT)
+                .send_json(&payload);
+            if let Err(e) = result {
+                log::warn!("Alert webhook failed: {}", e);
+            }
+        });
+    }
+}
+
+/// Play the failure chirp on a background thread. If even that fails
+/// there's nothing more to try, so it's only logged.
+fn play_chirp(chirp: Config) {
+    log::info!("Tones keep failing, playing the failure chirp on the default device");
+    std::thread::spawn(move || {
+        if let Err(e) = audio::play_tone(&chirp).and_then(audio::TonePlayback::wait) {
+            log::warn!("Failure chirp failed: {}", e);
+        }
+    });
+}
+
+/// Show a desktop notification. Runs on a background thread since the
+/// notification service (D-Bus on Linux) can be slow to answer.
+fn notify_desktop(summary: &'static str, body: String) {
+    std::thread::spawn(move || {
+        let result = notify_rust::Notification::new()
+            .appname("nodoze")
+            .summary(summary)
+            .body(&body)
+            .show();
+        if let Err(e) = result {
+            log::warn!("Desktop notification failed: {}", e);
+        }
+    });
+}
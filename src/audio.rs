@@ -1,57 +1,508 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{BufferSize, Device, SampleFormat, StreamConfig};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use cpal::{
+    BufferSize, Device, DeviceId, FromSample, Host, SampleFormat, SizedSample, StreamConfig,
+    StreamError,
+};
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::config::Config;
+use crate::config::{Config, FadeCurve, Limiter, OutputFormat, Waveform};
+use crate::rng::Rng;
+use crate::state;
+
+#[cfg(feature = "mock-audio")]
+pub mod mock;
+
+/// Errors from resolving a device or playing a tone
+#[derive(Debug)]
+pub enum AudioError {
+    /// No output device matches any of the configured names
+    DeviceNotFound(Vec<String>),
+    /// The system has no audio output hardware at all
+    NoDevice,
+    /// The backend invalidated the stream on this device, e.g. because
+    /// another app changed its sample rate, so it has to be rebuilt
+    StreamInvalidated(String),
+    /// Any other audio backend failure
+    Other(String),
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AudioError::DeviceNotFound(names) if names.len() == 1 => {
+                write!(f, "No output device matching '{}' found", names[0])
+            }
+            AudioError::DeviceNotFound(names) => {
+                write!(f, "No output device matching any of '{}' found", names.join("', '"))
+            }
+            AudioError::NoDevice => {
+                f.write_str("No audio output devices found (headless system or container?)")
+            }
+            AudioError::StreamInvalidated(device) => write!(
+                f,
+                "The output stream on '{}' was invalidated (its sample rate or format changed)",
+                device
+            ),
+            AudioError::Other(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl From<String> for AudioError {
+    fn from(msg: String) -> Self {
+        AudioError::Other(msg)
+    }
+}
+
+/// Extra time a tone may take beyond its length before the audio callback
+/// is considered stalled
+const WATCHDOG_MARGIN: Duration = Duration::from_secs(5);
+
+/// How often a waiting tone checks whether its stream was invalidated
+const INVALIDATION_POLL: Duration = Duration::from_millis(100);
+
+/// Tones with an RMS level below this (in dBFS) are unlikely to register
+/// as activity on most hardware
+const QUIET_RMS_DBFS: f64 = -60.0;
+
+/// Fixed, clearly audible parameters for `test-device`
+const TEST_TONE_FREQUENCY: f64 = 440.0;
+const TEST_TONE_VOLUME: f64 = 0.2;
+const TEST_TONE_DURATION: u64 = 1;
+const TEST_TONE_FADE: f64 = 0.05;
+
+/// Length of a priming tone (`nodoze prime`), in seconds: enough to wake
+/// an amp without holding up the media about to start
+const PRIME_DURATION: u64 = 1;
+/// Fade in and out of a priming tone, in seconds
+const PRIME_FADE: f64 = 0.05;
+
+/// Pitch of the failure chirp, well above the test tone so it isn't
+/// mistaken for one
+const FAILURE_CHIRP_FREQUENCY: f64 = 1760.0;
+
+/// Shortest fade applied to a tone that has one, in seconds. Keeps the
+/// start and end of the signal continuous even when a fade is tiny. A fade
+/// of exactly 0 turns it off instead.
+const MIN_FADE: f64 = 0.005;
+
+/// Length of the crossfade joining the end of a looped WAV clip to its
+/// start, in seconds
+const LOOP_CROSSFADE: f64 = 0.02;
+
+/// Fraction of each cycle the positive part of the pulse waveform takes
+const PULSE_WIDTH: f64 = 0.1;
+
+/// Frames per audio callback buffer. Also the length of the single burst
+/// played when `duration` is 0.
+const BUFFER_FRAMES: u32 = 4096;
+
+/// Length of a continuous tone. Far longer than any uptime, yet small
+/// enough that sample arithmetic near it can't overflow.
+const ENDLESS_SAMPLES: u64 = u64::MAX / 4;
+
+/// What a newly opened stream plays first
+#[derive(Clone, Copy, PartialEq)]
+enum Start {
+    /// The configured tone, once
+    Tone,
+    /// The configured tone, until stopped
+    Endless,
+    /// Silence, until a tone is armed
+    Silence,
+}
 
 /// Get the human-readable name of a device
 fn device_name(device: &Device) -> Option<String> {
     device.description().ok().map(|d| d.name().to_string())
 }
 
+/// Device name for messages
+fn describe(device: &Device) -> String {
+    device_name(device).unwrap_or_else(|| "unknown".into())
+}
+
+/// Identifier that survives renames and re-enumeration, where the backend
+/// provides one (e.g. the ALSA PCM name, CoreAudio UID or WASAPI endpoint ID)
+fn stable_id(device: &Device) -> Option<DeviceId> {
+    device.id().ok()
+}
+
 /// Returns true for null/dummy audio devices that produce no output
 fn is_null_device(name: &str) -> bool {
     let lower = name.to_lowercase();
     lower.contains("discard") || lower.contains("null")
 }
 
-/// Find an output device by name, or return the default
-pub fn get_device(name: &str) -> Result<Device, String> {
-    let host = cpal::default_host();
+/// Returns true if a device name matches a configured name (case-insensitive substring)
+fn matches_name(dev_name: &str, name: &str) -> bool {
+    dev_name.to_lowercase().contains(&name.to_lowercase())
+}
+
+/// With `mock-audio` every name resolves to the in-memory mock host
+#[cfg(feature = "mock-audio")]
+fn select_host(_name: &str) -> Result<Host, String> {
+    Ok(mock::host())
+}
 
+/// Look up an audio host by name (e.g. "alsa", "jack"), or the platform
+/// default when the name is empty
+#[cfg(not(feature = "mock-audio"))]
+fn select_host(name: &str) -> Result<Host, String> {
     if name.is_empty() {
-        return host
-            .default_output_device()
-            .ok_or_else(|| "No default output device found".to_string());
+        return Ok(cpal::default_host());
     }
 
-    let devices = host
-        .output_devices()
-        .map_err(|e| format!("Failed to enumerate devices: {}", e))?;
+    let compiled = || {
+        cpal::ALL_HOSTS
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let id: cpal::HostId = name.parse().map_err(|_| {
+        format!(
+            "Audio host '{}' is not supported by this build (built with: {}){}",
+            name,
+            compiled(),
+            host_hint(name)
+        )
+    })?;
+    cpal::host_from_id(id)
+        .map_err(|_| format!("Audio host '{}' is not available on this system", id))
+}
 
-    for device in devices {
-        if let Some(dev_name) = device_name(&device) {
-            if dev_name.to_lowercase().contains(&name.to_lowercase()) {
-                return Ok(device);
+/// How to get a host that's behind a cargo feature, for the error when a
+/// build doesn't have it
+#[cfg(not(feature = "mock-audio"))]
+fn host_hint(name: &str) -> String {
+    match name.to_lowercase().as_str() {
+        "asio" if !cfg!(windows) => "; ASIO is only available on Windows".to_string(),
+        feature @ ("asio" | "jack") => format!("; rebuild with `--features {}`", feature),
+        _ => String::new(),
+    }
+}
+
+/// Devices resolved recently, so the daemon doesn't enumerate them before
+/// every tone. Off (a TTL of zero) until `set_device_cache_ttl` is called.
+static DEVICE_CACHE: Mutex<DeviceCache> = Mutex::new(DeviceCache {
+    ttl: Duration::ZERO,
+    entry: None,
+});
+
+/// Keep resolved devices for `ttl` (`device_cache_ttl`); zero turns the
+/// cache off
+pub fn set_device_cache_ttl(ttl: Duration) {
+    let mut cache = DEVICE_CACHE.lock().unwrap();
+    cache.ttl = ttl;
+    cache.entry = None;
+}
+
+/// Drop the cached device, e.g. after a stream on it failed, so the next
+/// lookup enumerates afresh and picks up hotplugged devices
+fn forget_device() {
+    DEVICE_CACHE.lock().unwrap().entry = None;
+}
+
+/// The last device `find_device` resolved, and for which host and names.
+/// Takes the current time from the caller so expiry doesn't depend on the
+/// real clock.
+struct DeviceCache {
+    ttl: Duration,
+    entry: Option<CachedDevice>,
+}
+
+struct CachedDevice {
+    host: String,
+    names: Vec<String>,
+    device: Device,
+    resolved: Instant,
+}
+
+impl DeviceCache {
+    fn get(&mut self, host: &str, names: &[String], now: Instant) -> Option<Device> {
+        let entry = self.entry.as_ref()?;
+        if now.saturating_duration_since(entry.resolved) >= self.ttl {
+            self.entry = None;
+            return None;
+        }
+        (entry.host == host && entry.names == names).then(|| entry.device.clone())
+    }
+
+    fn put(&mut self, host: &str, names: &[String], device: &Device, now: Instant) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        self.entry = Some(CachedDevice {
+            host: host.to_string(),
+            names: names.to_vec(),
+            device: device.clone(),
+            resolved: now,
+        });
+    }
+}
+
+/// Find the first output device on the host matching one of the names, in
+/// order, or return the default when no names are given. Served from the
+/// device cache while it's fresh.
+pub fn find_device(host: &str, names: &[String]) -> Result<Device, AudioError> {
+    if let Some(device) = DEVICE_CACHE.lock().unwrap().get(host, names, Instant::now()) {
+        return Ok(device);
+    }
+    match lookup_device(host, names) {
+        Ok(device) => {
+            DEVICE_CACHE.lock().unwrap().put(host, names, &device, Instant::now());
+            Ok(device)
+        }
+        Err(e) => {
+            forget_device();
+            Err(e)
+        }
+    }
+}
+
+fn lookup_device(host: &str, names: &[String]) -> Result<Device, AudioError> {
+    let host = select_host(host)?;
+
+    if names.is_empty() {
+        return host.default_output_device().ok_or_else(|| {
+            if has_output_devices(&host) {
+                AudioError::Other("No default output device found".to_string())
+            } else {
+                AudioError::NoDevice
+            }
+        });
+    }
+
+    for name in names {
+        // Names can change across reboots and hotplugs; the ID this name
+        // resolved to last time is more reliable when the backend has one
+        let remembered = state::device_id(name).and_then(|id| id.parse::<DeviceId>().ok());
+        if let Some(device) = remembered.as_ref().and_then(|id| host.device_by_id(id)) {
+            log::debug!("Using device '{}' (remembered for '{}')", describe(&device), name);
+            return Ok(device);
+        }
+
+        let devices = host
+            .output_devices()
+            .map_err(|e| format!("Failed to enumerate devices: {}", e))?;
+
+        if let Some(device) = first_matching(devices, name) {
+            if let Some(id) = stable_id(&device) {
+                if remembered.as_ref() != Some(&id) {
+                    if let Err(e) = state::record_device_id(name, &id.to_string()) {
+                        log::debug!("{}", e);
+                    }
+                }
+            }
+            return Ok(device);
+        }
+    }
+
+    if !has_output_devices(&host) {
+        return Err(AudioError::NoDevice);
+    }
+    Err(AudioError::DeviceNotFound(names.to_vec()))
+}
+
+/// Find the first input device on the host matching one of the names, in
+/// order, or return the default input when no names are given
+pub fn find_input_device(host: &str, names: &[String]) -> Result<Device, AudioError> {
+    let host = select_host(host)?;
+    if names.is_empty() {
+        return host
+            .default_input_device()
+            .ok_or_else(|| AudioError::Other("No default input device found".to_string()));
+    }
+    for name in names {
+        let devices = host
+            .input_devices()
+            .map_err(|e| format!("Failed to enumerate input devices: {}", e))?;
+        if let Some(device) = first_matching(devices, name) {
+            return Ok(device);
+        }
+    }
+    Err(AudioError::Other(format!(
+        "No input device matching '{}' found",
+        names.join("', '")
+    )))
+}
+
+/// First of the devices whose name matches `name`
+fn first_matching(devices: impl Iterator<Item = Device>, name: &str) -> Option<Device> {
+    devices.into_iter().find(|device| {
+        device_name(device).is_some_and(|dev_name| {
+            let matched = matches_name(&dev_name, name);
+            if matched {
+                log::debug!("Using device '{}' (matched '{}')", dev_name, name);
             }
+            matched
+        })
+    })
+}
+
+fn has_output_devices(host: &Host) -> bool {
+    host.output_devices()
+        .is_ok_and(|mut devices| devices.next().is_some())
+}
+
+/// Name of the device `find_device` resolves to
+pub fn resolve_device_name(host: &str, names: &[String]) -> Result<String, AudioError> {
+    let device = find_device(host, names)?;
+    Ok(describe(&device))
+}
+
+/// Copy of the config for a priming tone: short and at
+/// `wake_boost_volume`, on a stream of its own
+pub fn prime_config(config: &Config) -> Config {
+    Config {
+        duration: PRIME_DURATION,
+        volume: config.wake_boost_volume,
+        attack_duration: Some(PRIME_FADE),
+        release_duration: Some(PRIME_FADE),
+        persistent_stream: false,
+        ..config.clone()
+    }
+}
+
+/// Copy of the config that plays an audible test tone on the same device,
+/// ignoring the configured frequency, volume and shaping
+pub fn test_tone_config(config: &Config) -> Config {
+    Config {
+        frequency: TEST_TONE_FREQUENCY,
+        volume: TEST_TONE_VOLUME,
+        duration: TEST_TONE_DURATION,
+        fade_duration: TEST_TONE_FADE,
+        attack_duration: None,
+        release_duration: None,
+        fade_curve: FadeCurve::default(),
+        waveform: Waveform::Sine,
+        comb_count: 0,
+        highpass_hz: 0.0,
+        lowpass_hz: 0.0,
+        stereo_phase: 0.0,
+        pan: 0.0,
+        antiphase: false,
+        modulation: Default::default(),
+        wav_path: String::new(),
+        ..config.clone()
+    }
+}
+
+/// Copy of the config that plays the audible `failure_chirp` on the
+/// default device, which may still work when the configured one doesn't
+pub fn failure_chirp_config(config: &Config) -> Config {
+    Config {
+        frequency: FAILURE_CHIRP_FREQUENCY,
+        device: Vec::new(),
+        ..test_tone_config(config)
+    }
+}
+
+/// An audio host compiled into this build, as shown by `list-hosts`
+pub struct HostInfo {
+    /// Name as written in the `host` setting, e.g. "alsa"
+    pub name: String,
+    /// Usable on this system (e.g. the JACK server is running)
+    pub available: bool,
+    /// Used when `host` is empty
+    pub default: bool,
+}
+
+/// Every audio host compiled into this build
+pub fn list_hosts() -> Vec<HostInfo> {
+    let available = cpal::available_hosts();
+    let default = cpal::default_host().id();
+    cpal::ALL_HOSTS
+        .iter()
+        .map(|&id| HostInfo {
+            name: id.to_string(),
+            available: available.contains(&id),
+            default: id == default,
+        })
+        .collect()
+}
+
+/// An output device as shown by `list-devices`
+#[derive(Serialize)]
+pub struct DeviceInfo {
+    /// Position in the listing, from 0
+    pub index: usize,
+    /// Name, as matched by the `device` setting
+    pub name: String,
+    /// Whether it's the host's default device
+    pub is_default: bool,
+    /// Audio host the device belongs to, when listing every host
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Supported stream configurations, e.g. "F32, 2 ch, 44100-192000 Hz".
+    /// Only filled in for verbose listings.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub configs: Vec<String>,
+}
+
+impl DeviceInfo {
+    /// The name as `list-devices` prints it, prefixed with the host when
+    /// there is one and marked when it's the default
+    pub fn label(&self) -> String {
+        let mut label = match &self.host {
+            Some(host) => format!("{}: {}", host, self.name),
+            None => self.name.clone(),
+        };
+        if self.is_default {
+            label.push_str(" (default)");
         }
+        label
     }
+}
+
+/// List the output devices of a host (empty = platform default)
+pub fn list_devices(host: &str, verbose: bool, input: bool) -> Result<Vec<DeviceInfo>, String> {
+    host_devices(&select_host(host)?, verbose, input)
+}
 
-    Err(format!("No output device matching '{}' found", name))
+/// List the output (or input) devices of every available host, prefixed
+/// with the host
+pub fn list_all_devices(verbose: bool, input: bool) -> Result<Vec<DeviceInfo>, String> {
+    let mut all = Vec::new();
+    for id in cpal::available_hosts() {
+        let host = match cpal::host_from_id(id) {
+            Ok(host) => host,
+            Err(e) => {
+                log::debug!("Skipping audio host {}: {}", id, e);
+                continue;
+            }
+        };
+        for mut info in host_devices(&host, verbose, input)? {
+            info.index = all.len();
+            info.host = Some(id.to_string());
+            all.push(info);
+        }
+    }
+    Ok(all)
 }
 
-/// List all available output devices
-pub fn list_devices() -> Result<Vec<String>, String> {
-    let host = cpal::default_host();
-    let devices = host
-        .output_devices()
-        .map_err(|e| format!("Failed to enumerate devices: {}", e))?;
+fn host_devices(host: &Host, verbose: bool, input: bool) -> Result<Vec<DeviceInfo>, String> {
+    let devices: Vec<Device> = if input {
+        host.input_devices().map(|d| d.collect())
+    } else {
+        host.output_devices().map(|d| d.collect())
+    }
+    .map_err(|e| format!("Failed to enumerate devices: {}", e))?;
 
-    let mut names = Vec::new();
-    let default_name = host
-        .default_output_device()
+    let mut infos = Vec::new();
+    let default_device = if input {
+        host.default_input_device()
+    } else {
+        host.default_output_device()
+    };
+    let default_name = default_device
         .and_then(|d| device_name(&d))
         .unwrap_or_default();
 
@@ -60,177 +511,2667 @@ pub fn list_devices() -> Result<Vec<String>, String> {
             if is_null_device(&name) {
                 continue;
             }
-            let is_default = name == default_name;
-            names.push(if is_default {
-                format!("{} (default)", name)
+            let configs = if verbose {
+                supported_configs(&device, input)
             } else {
-                name
+                Vec::new()
+            };
+            infos.push(DeviceInfo {
+                index: infos.len(),
+                is_default: name == default_name,
+                name,
+                host: None,
+                configs,
             });
         }
     }
 
-    Ok(names)
+    Ok(infos)
+}
+
+/// Describe each output (or input) stream configuration a device supports
+fn supported_configs(device: &Device, input: bool) -> Vec<String> {
+    let configs = if input {
+        device.supported_input_configs().map(|c| c.collect::<Vec<_>>())
+    } else {
+        device.supported_output_configs().map(|c| c.collect::<Vec<_>>())
+    };
+    match configs {
+        Ok(configs) => configs
+            .into_iter()
+            .map(|c| {
+                let rates = if c.min_sample_rate() == c.max_sample_rate() {
+                    format!("{} Hz", c.min_sample_rate())
+                } else {
+                    format!("{}-{} Hz", c.min_sample_rate(), c.max_sample_rate())
+                };
+                format!("{}, {} ch, {}", c.sample_format(), c.channels(), rates)
+            })
+            .collect(),
+        Err(e) => vec![format!("(could not query formats: {})", e)],
+    }
+}
+
+/// An open output stream fed by the tone generator
+struct Output {
+    /// Dropping the stream closes the device
+    stream: cpal::Stream,
+    done: Receiver<()>,
+    stopper: ToneStopper,
+    /// Tone length to arm on the next callback; zero when nothing is pending
+    pending: Arc<AtomicU64>,
+    /// Frequency of the armed tone, as f64 bits
+    pending_frequency: Arc<AtomicU64>,
+    /// Set when the backend reports the stream can't continue
+    broken: Arc<AtomicBool>,
+    /// Set when the backend reports the stream's config is no longer
+    /// valid, e.g. after the device's sample rate changed
+    invalidated: Arc<AtomicBool>,
+    levels: Arc<Mutex<LevelMeter>>,
+    tone_samples: u64,
+    sample_rate: f64,
+    /// When the current tone started, for measuring how long it really took
+    started: Instant,
+    device: String,
+    config: Config,
+}
+
+impl Output {
+    /// Block until the current tone, `samples` long, has finished playing,
+    /// and return how long it took. Fails if the audio callback stalls and
+    /// the tone doesn't finish in time.
+    fn wait_tone(&self, samples: u64) -> Result<Duration, AudioError> {
+        let expected = Duration::from_secs_f64(samples as f64 / self.sample_rate);
+        let timeout = expected + WATCHDOG_MARGIN;
+
+        // Signalled by the audio callback once the last sample is written.
+        // A disconnect means the stream was torn down, which also ends
+        // playback. An invalidated stream stops calling back, so watch for
+        // that rather than sit out the whole timeout.
+        while let Err(RecvTimeoutError::Timeout) = self.done.recv_timeout(INVALIDATION_POLL) {
+            if self.invalidated.load(Ordering::Relaxed) {
+                return Err(AudioError::StreamInvalidated(self.device.clone()));
+            }
+            if self.started.elapsed() > timeout {
+                log::warn!(
+                    "Audio callback stalled, abandoning tone after {}s",
+                    timeout.as_secs()
+                );
+                return Err(AudioError::Other(
+                    "Tone did not finish playing (audio callback stalled)".to_string(),
+                ));
+            }
+        }
+
+        // Buffering means the tone can't end exactly on time
+        let elapsed = self.started.elapsed();
+        log::debug!(
+            "Tone took {:.3}s for {:.3}s requested ({:+.1} ms)",
+            elapsed.as_secs_f64(),
+            expected.as_secs_f64(),
+            (elapsed.as_secs_f64() - expected.as_secs_f64()) * 1000.0
+        );
+        Ok(elapsed)
+    }
+
+    /// Rebuild the stream after the backend invalidated it and play the
+    /// tone again from the start, at whatever rate the device now runs.
+    /// Keeps the old tone's clock, so stoppers handed out for it still
+    /// fade the new one.
+    fn restart(self) -> Result<Output, AudioError> {
+        let Output {
+            stream,
+            stopper,
+            sample_rate,
+            device,
+            config,
+            ..
+        } = self;
+        // Release the device before opening it again
+        drop(stream);
+        let mut output = build_output_on(
+            find_device(&config.host, &config.device)?,
+            &config,
+            Start::Tone,
+            Some(&stopper),
+        )?;
+        if output.sample_rate == sample_rate {
+            log::warn!("'{}' invalidated the output stream, restarting the tone", device);
+        } else {
+            log::warn!(
+                "'{}' changed sample rate from {} Hz to {} Hz, restarting the tone",
+                device,
+                sample_rate,
+                output.sample_rate
+            );
+        }
+        output
+            .stream
+            .play()
+            .map_err(|e| format!("Failed to play stream: {}", e))?;
+        output.started = Instant::now();
+        Ok(output)
+    }
+
+    fn log_played(&self) {
+        let levels = self.levels.lock().unwrap();
+        let config = &self.config;
+        if self.tone_samples == ENDLESS_SAMPLES {
+            log::info!("Continuous tone stopped ({})", levels.summary());
+            levels.warn_if_quiet();
+            return;
+        }
+        log::info!(
+            event = "tone_played",
+            device = self.device.as_str(),
+            frequency = config.frequency,
+            duration = config.duration,
+            volume = config.volume;
+            "Tone played: device='{}' sound={} duration={}s volume={:.0}% ({})",
+            self.device,
+            sound_label(config),
+            config.duration,
+            config.volume * 100.0,
+            levels.summary()
+        );
+        levels.warn_if_quiet();
+    }
+}
+
+/// A tone playing on an output device. Dropping it cuts playback off
+/// immediately; use `wait` to let it finish.
+pub struct TonePlayback {
+    output: Output,
+}
+
+impl TonePlayback {
+    /// Block until the tone has finished playing. Fails if the audio
+    /// callback stalls and the tone doesn't finish in time.
+    pub fn wait(self) -> Result<(), AudioError> {
+        self.wait_measured().map(|_| ())
+    }
+
+    /// Like `wait`, but returns how long the tone took from the stream
+    /// starting to the last sample being written
+    pub fn wait_measured(mut self) -> Result<Duration, AudioError> {
+        // Stopping early shortens the tone, so go by its current length
+        let samples = self.output.stopper.total_samples.load(Ordering::Relaxed);
+        let elapsed = match self.output.wait_tone(samples) {
+            // Rebuilt once; a stream invalidated again is left to the
+            // caller to retry. Not for a tone that was being stopped.
+            Err(AudioError::StreamInvalidated(_)) if samples == self.output.tone_samples => {
+                self.output = self.output.restart()?;
+                let samples = self.output.stopper.total_samples.load(Ordering::Relaxed);
+                self.output.wait_tone(samples)?
+            }
+            result => result?,
+        };
+
+        // Let the stream drain (`drain_ms`); dropping self closes it
+        std::thread::sleep(Duration::from_millis(self.output.config.drain_ms));
+        self.output.log_played();
+        Ok(elapsed)
+    }
+
+    /// Handle that can fade this tone out early, usable from other threads
+    pub fn stopper(&self) -> ToneStopper {
+        self.output.stopper.clone()
+    }
+
+    /// True once the device has gone away and playback can't continue
+    pub fn is_broken(&self) -> bool {
+        self.output.broken.load(Ordering::Relaxed)
+    }
+}
+
+/// Output stream kept open between tones (`persistent_stream`). The callback
+/// writes silence until `start` arms the next tone, so the device is only
+/// acquired once.
+pub struct PersistentStream {
+    output: Output,
+}
+
+impl PersistentStream {
+    /// Open the configured device and start streaming silence
+    pub fn open(config: &Config) -> Result<Self, AudioError> {
+        let output = open_output(config, Start::Silence)?;
+        log::info!("Keeping output stream open on '{}'", output.device);
+        Ok(Self { output })
+    }
+
+    /// Start the next tone at `frequency`, which may differ from tone to
+    /// tone. Returns a handle that can fade it out early.
+    pub fn start(&mut self, frequency: f64) -> Result<ToneStopper, AudioError> {
+        let output = &mut self.output;
+        let frequency = output.config.clamped_frequency(frequency);
+        if output.config.wav_path.is_empty() {
+            check_nyquist(frequency, output.sample_rate, &output.device)?;
+        }
+        output.config.frequency = frequency;
+        log_playing(&output.config, &output.device);
+        *output.levels.lock().unwrap() = LevelMeter::default();
+        while output.done.try_recv().is_ok() {}
+        output
+            .pending_frequency
+            .store(frequency.to_bits(), Ordering::Relaxed);
+        // Release so the callback sees the frequency along with the tone
+        output.pending.store(output.tone_samples, Ordering::Release);
+        output.started = Instant::now();
+        Ok(output.stopper.clone())
+    }
+
+    /// Block until the tone started by `start` has finished
+    pub fn wait(&self) -> Result<(), AudioError> {
+        self.output.wait_tone(self.output.tone_samples)?;
+        self.output.log_played();
+        Ok(())
+    }
+
+    /// True once the device has gone away and the stream must be reopened
+    pub fn is_broken(&self) -> bool {
+        self.output.broken.load(Ordering::Relaxed)
+    }
+}
+
+/// Ends a playing tone early with a normal fade-out
+#[derive(Clone)]
+pub struct ToneStopper {
+    sample_clock: Arc<AtomicU64>,
+    total_samples: Arc<AtomicU64>,
+    release_samples: u64,
+    sample_rate: f64,
+}
+
+impl ToneStopper {
+    pub fn stop(&self) {
+        let now = self.sample_clock.load(Ordering::Relaxed);
+        self.total_samples
+            .fetch_min(now + self.release_samples, Ordering::Relaxed);
+    }
+
+    /// How long the fade-out after `stop` takes
+    pub fn fade_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.release_samples as f64 / self.sample_rate)
+    }
+
+    /// Whether both stop the same tone
+    pub fn is(&self, other: &ToneStopper) -> bool {
+        Arc::ptr_eq(&self.sample_clock, &other.sample_clock)
+    }
+}
+
+/// What is being played, e.g. "20Hz tone", "0.5Hz pulse" or "'keepalive.wav'"
+fn sound_label(config: &Config) -> String {
+    if !config.wav_path.is_empty() {
+        format!("'{}'", config.wav_path)
+    } else if config.waveform == Waveform::Pulse {
+        format!("{}Hz pulse", config.frequency)
+    } else if config.waveform == Waveform::Noise {
+        "white noise".to_string()
+    } else if config.comb_count > 1 {
+        format!("{}Hz comb of {} harmonics", config.frequency, config.comb_count)
+    } else {
+        format!("{}Hz tone", config.frequency)
+    }
 }
 
-/// Play a sine wave tone with fade in/out
-pub fn play_tone(config: &Config) -> Result<(), String> {
-    let device = get_device(&config.device)?;
-    let dev_name = device_name(&device).unwrap_or_else(|| "unknown".into());
+fn log_playing(config: &Config, device: &str) {
     log::info!(
-        "Playing {}Hz tone for {}s at {:.0}% volume on '{}'",
-        config.frequency,
+        "Playing {} for {}s at {:.0}% volume on '{}'",
+        sound_label(config),
         config.duration,
         config.volume * 100.0,
-        dev_name
+        device
     );
+}
 
-    let supported_config = device
-        .default_output_config()
-        .map_err(|e| format!("Failed to get default output config: {}", e))?;
+/// Start playing a sine wave tone (or the configured WAV clip) with fade in/out
+pub fn play_tone(config: &Config) -> Result<TonePlayback, AudioError> {
+    Ok(TonePlayback {
+        output: open_output(config, Start::Tone)?,
+    })
+}
 
-    let sample_rate = supported_config.sample_rate() as f64;
-    let channels = supported_config.channels() as usize;
+/// Start playing the tone with no end, for continuous mode. It only fades
+/// out once stopped.
+pub fn play_endless(config: &Config) -> Result<TonePlayback, AudioError> {
+    Ok(TonePlayback {
+        output: open_output(config, Start::Endless)?,
+    })
+}
 
-    let frequency = config.frequency;
-    let volume = config.volume.clamp(0.0, 1.0) as f32;
-    let total_samples = (config.duration as f64 * sample_rate) as u64;
-    let fade_samples = (config.fade_duration * sample_rate) as u64;
+/// Audio captured from an input device while a tone played, mixed down to mono
+pub struct Recording {
+    pub samples: Vec<f32>,
+    pub sample_rate: f64,
+    /// Input device recorded from
+    pub device: String,
+}
 
-    let sample_clock = Arc::new(std::sync::atomic::AtomicU64::new(0));
-    let finished = Arc::new(AtomicBool::new(false));
-    let finished_clone = finished.clone();
-    let sample_clock_clone = sample_clock.clone();
+/// Play the tone while recording from `input_device` (or the default input).
+/// A loopback or monitor device hears exactly what the output is sent.
+pub fn record_tone(config: &Config) -> Result<Recording, AudioError> {
+    let device = find_input_device(&config.host, &config.input_device).map_err(|e| {
+        format!(
+            "{}. Set `input_device` or pass --input with a loopback or monitor device \
+             (e.g. \"Monitor of\" on PulseAudio, BlackHole on macOS, Stereo Mix on Windows)",
+            e
+        )
+    })?;
 
-    let mut stream_config: StreamConfig = supported_config.clone().into();
-    stream_config.buffer_size = BufferSize::Fixed(4096);
-
-    let err_fn = |err| log::warn!("Audio stream: {}", err);
-
-    let stream = match supported_config.sample_format() {
-        SampleFormat::F32 => device.build_output_stream(
-            &stream_config,
-            move |data: &mut [f32], _| {
-                write_samples(
-                    data,
-                    channels,
-                    &sample_clock_clone,
-                    sample_rate,
-                    frequency,
-                    volume,
-                    total_samples,
-                    fade_samples,
-                    &finished_clone,
-                );
-            },
-            err_fn,
-            None,
-        ),
-        SampleFormat::I16 => device.build_output_stream(
-            &stream_config,
-            move |data: &mut [i16], _| {
-                let mut float_buf = vec![0.0f32; data.len()];
-                write_samples(
-                    &mut float_buf,
-                    channels,
-                    &sample_clock_clone,
-                    sample_rate,
-                    frequency,
-                    volume,
-                    total_samples,
-                    fade_samples,
-                    &finished_clone,
-                );
-                for (out, &sample) in data.iter_mut().zip(float_buf.iter()) {
-                    *out = (sample * i16::MAX as f32) as i16;
-                }
-            },
-            err_fn,
-            None,
-        ),
-        SampleFormat::U16 => device.build_output_stream(
-            &stream_config,
-            move |data: &mut [u16], _| {
-                let mut float_buf = vec![0.0f32; data.len()];
-                write_samples(
-                    &mut float_buf,
-                    channels,
-                    &sample_clock_clone,
-                    sample_rate,
-                    frequency,
-                    volume,
-                    total_samples,
-                    fade_samples,
-                    &finished_clone,
-                );
-                for (out, &sample) in data.iter_mut().zip(float_buf.iter()) {
-                    *out = ((sample * 0.5 + 0.5) * u16::MAX as f32) as u16;
-                }
-            },
-            err_fn,
-            None,
-        ),
-        _ => return Err("Unsupported sample format".to_string()),
-    }
-    .map_err(|e| format!("Failed to build output stream: {}", e))?;
+    let supported = device
+        .default_input_config()
+        .map_err(|e| format!("Failed to get default input config: {}", e))?;
+    let channels = supported.channels() as usize;
+    let stream_config: StreamConfig = supported.config();
+    let samples = Arc::new(Mutex::new(Vec::new()));
 
+    let stream = match supported.sample_format() {
+        SampleFormat::F32 => build_input_stream::<f32>(&device, &stream_config, &samples),
+        SampleFormat::I16 => build_input_stream::<i16>(&device, &stream_config, &samples),
+        SampleFormat::I32 => build_input_stream::<i32>(&device, &stream_config, &samples),
+        SampleFormat::U16 => build_input_stream::<u16>(&device, &stream_config, &samples),
+        SampleFormat::U8 => build_input_stream::<u8>(&device, &stream_config, &samples),
+        other => return Err(format!("Unsupported input sample format: {}", other).into()),
+    }
+    .map_err(|e| format!("Failed to build input stream: {}", e))?;
     stream
+        .play()
+        .map_err(|e| format!("Failed to start recording: {}", e))?;
+
+    play_tone(config)?.wait()?;
+    // Let the end of the tone make it through the input's buffering
+    std::thread::sleep(Duration::from_millis(200));
+    drop(stream);
+
+    let samples = std::mem::take(&mut *samples.lock().unwrap());
+    let mono = samples
+        .chunks(channels.max(1))
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect();
+    Ok(Recording {
+        samples: mono,
+        sample_rate: stream_config.sample_rate as f64,
+        device: describe(&device),
+    })
+}
+
+/// Build an input stream that appends everything it hears as floats
+fn build_input_stream<T>(
+    device: &Device,
+    config: &StreamConfig,
+    samples: &Arc<Mutex<Vec<f32>>>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let samples = samples.clone();
+    device.build_input_stream(
+        config,
+        move |data: &[T], _| {
+            samples
+                .lock()
+                .unwrap()
+                .extend(data.iter().map(|&s| f32::from_sample_(s)));
+        },
+        |err| log::warn!("Input stream: {}", err),
+        None,
+    )
+}
+
+/// Go through everything `play_tone` does short of starting the stream:
+/// resolve the device, load the tone and build the output stream. Describes
+/// the device and rate the tone would play at.
+pub fn dry_run(config: &Config) -> Result<String, AudioError> {
+    let output = build_output(config, Start::Tone)?;
+    Ok(format!("'{}' at {} Hz", output.device, output.sample_rate))
+}
+
+/// Name of the configured device and the sample rate a tone would play at
+/// on it, without opening a stream
+pub fn output_rate(config: &Config) -> Result<(String, f64), AudioError> {
+    let device = find_device(&config.host, &config.device)?;
+    let supported = output_config(&device, config.sample_format, config.channels_count)?;
+    Ok((describe(&device), supported.sample_rate() as f64))
+}
+
+/// How long each phase of opening and closing the output device took
+pub struct OpenTimes {
+    /// Resolving the configured device
+    pub find: Duration,
+    /// Querying its format and building the stream
+    pub build: Duration,
+    /// Starting the stream
+    pub start: Duration,
+    /// Closing it again
+    pub close: Duration,
+}
+
+/// Open the configured device, start a silent stream and close it straight
+/// away, timing each phase
+pub fn time_open(config: &Config) -> Result<OpenTimes, AudioError> {
+    let phase = Instant::now();
+    let device = find_device(&config.host, &config.device)?;
+    let find = phase.elapsed();
+
+    let phase = Instant::now();
+    let output = build_output_on(device, config, Start::Silence, None)?;
+    let build = phase.elapsed();
+
+    let phase = Instant::now();
+    output
+        .stream
         .play()
         .map_err(|e| format!("Failed to play stream: {}", e))?;
+    let start = phase.elapsed();
+
+    let phase = Instant::now();
+    drop(output);
+    Ok(OpenTimes {
+        find,
+        build,
+        start,
+        close: phase.elapsed(),
+    })
+}
 
-    // Wait for playback to complete
-    while !finished.load(Ordering::Relaxed) {
-        std::thread::sleep(Duration::from_millis(100));
+/// Open the configured device and start its stream, either playing the tone
+/// straight away or writing silence until a tone is armed
+fn open_output(config: &Config, start: Start) -> Result<Output, AudioError> {
+    let config = &Config {
+        frequency: config.clamped_frequency(config.frequency),
+        ..config.clone()
+    };
+    let mut output = build_output(config, start)?;
+    match start {
+        Start::Tone => log_playing(config, &output.device),
+        Start::Endless => log::info!(
+            "Playing {} continuously at {:.0}% volume on '{}'",
+            sound_label(config),
+            config.volume * 100.0,
+            output.device
+        ),
+        Start::Silence => {}
     }
 
-    // Small delay to let the stream drain
-    std::thread::sleep(Duration::from_millis(50));
-    drop(stream);
+    output.stream.play().map_err(|e| {
+        forget_device();
+        format!("Failed to play stream: {}", e)
+    })?;
+    output.started = Instant::now();
+    Ok(output)
+}
 
-    log::info!("Tone playback complete");
-    Ok(())
+/// Build the output stream for the configured device without starting it
+fn build_output(config: &Config, start: Start) -> Result<Output, AudioError> {
+    let result = build_output_on(find_device(&config.host, &config.device)?, config, start, None);
+    if result.is_err() {
+        forget_device();
+    }
+    result
 }
 
-fn write_samples(
-    data: &mut [f32],
-    channels: usize,
-    sample_clock: &std::sync::atomic::AtomicU64,
-    sample_rate: f64,
-    frequency: f64,
-    volume: f32,
-    total_samples: u64,
-    fade_samples: u64,
-    finished: &AtomicBool,
-) {
-    for frame in data.chunks_mut(channels) {
-        let n = sample_clock.fetch_add(1, Ordering::Relaxed);
+/// Build the output stream for a device without starting it. A tone
+/// restarted after its stream was invalidated passes its `stopper`, whose
+/// clock the new stream takes over.
+fn build_output_on(
+    device: Device,
+    config: &Config,
+    start: Start,
+    stopper: Option<&ToneStopper>,
+) -> Result<Output, AudioError> {
+    let dev_name = describe(&device);
 
-        if n >= total_samples {
-            finished.store(true, Ordering::Relaxed);
-            for sample in frame.iter_mut() {
-                *sample = 0.0;
-            }
-            continue;
+    let supported_config = output_config(&device, config.sample_format, config.channels_count)?;
+    log::debug!(
+        "Output format on '{}': {}, {} Hz, {} channels",
+        dev_name,
+        supported_config.sample_format(),
+        supported_config.sample_rate(),
+        supported_config.channels()
+    );
+
+    let sample_rate = supported_config.sample_rate() as f64;
+    if config.wav_path.is_empty() {
+        check_nyquist(highest_frequency(config), sample_rate, &dev_name)?;
+    }
+    let channels = supported_config.channels() as usize;
+    if config.channel_target.is_none()
+        && config.channel_mode.max_channels().is_some_and(|n| n >= channels)
+    {
+        log::debug!(
+            "channel_mode {} has no effect on this {}-channel device",
+            config.channel_mode.name(),
+            channels
+        );
+    }
+
+    let mut params = ToneParams::new(config, sample_rate, channels)?;
+    if start == Start::Endless {
+        params.total_samples = ENDLESS_SAMPLES;
+    }
+    let release_samples = params.release_samples;
+    let tone_samples = params.total_samples;
+    let play = start != Start::Silence;
+
+    let (sample_clock, total) = match stopper {
+        Some(stopper) => (stopper.sample_clock.clone(), stopper.total_samples.clone()),
+        None => (Arc::default(), Arc::default()),
+    };
+    sample_clock.store(0, Ordering::Relaxed);
+    // Shared so the tone can be shortened while it plays. Starts at zero
+    // (silence) when the stream waits for a tone to be armed.
+    total.store(if play { tone_samples } else { 0 }, Ordering::Relaxed);
+    let pending = Arc::new(AtomicU64::new(0));
+    let pending_frequency = Arc::new(AtomicU64::new(config.frequency.to_bits()));
+    let broken = Arc::new(AtomicBool::new(false));
+    let invalidated = Arc::new(AtomicBool::new(false));
+    let (done_tx, done) = mpsc::channel();
+    let levels = Arc::new(Mutex::new(LevelMeter::default()));
+
+    let format = supported_config.sample_format();
+    let dither_step = if config.dither { quantization_step(format) } else { None };
+    if let Some(step) = dither_step {
+        log::debug!("Dithering {} output by ±{} (1 LSB)", format, step);
+    }
+
+    let filter = Filter::new(config, sample_rate, channels)?;
+    if config.wav_path.is_empty() && filter.as_ref().is_some_and(|f| !f.passes(config.frequency)) {
+        log::warn!(
+            "{} Hz is outside the filter's passband ({}); the tone will be attenuated",
+            config.frequency,
+            filter_label(config)
+        );
+    }
+
+    // A stream build consumes its generator, so a retry needs a fresh one
+    let generator = || Generator {
+        channels,
+        sample_clock: sample_clock.clone(),
+        total_samples: total.clone(),
+        pending: pending.clone(),
+        pending_frequency: pending_frequency.clone(),
+        params: params.clone(),
+        finished: AtomicBool::new(!play),
+        done: done_tx.clone(),
+        levels: levels.clone(),
+        broken: broken.clone(),
+        invalidated: invalidated.clone(),
+        realtime_priority: config.realtime_priority,
+        dither: dither_step.map(|step| (step, Rng::from_entropy())),
+        filter: filter.clone(),
+    };
+
+    let unsupported = || AudioError::Other(format!("Unsupported sample format {}", format));
+    let mut stream_config: StreamConfig = supported_config.clone().into();
+    stream_config.buffer_size = BufferSize::Fixed(BUFFER_FRAMES);
+
+    let mut result = build_native_stream(&device, &stream_config, format, generator())
+        .ok_or_else(unsupported)?;
+    if let Err(e) = &result {
+        if fixed_buffer_rejected(e) {
+            log::warn!(
+                "'{}' rejected a {}-frame buffer ({}), retrying with its default buffer size",
+                dev_name,
+                BUFFER_FRAMES,
+                e
+            );
+            stream_config.buffer_size = BufferSize::Default;
+            result = build_native_stream(&device, &stream_config, format, generator())
+                .ok_or_else(unsupported)?;
         }
+    }
+    let stream = result.map_err(|e| format!("Failed to build output stream: {}", e))?;
 
-        // Generate sine wave
-        let t = n as f64 / sample_rate;
-        let value = (2.0 * std::f64::consts::PI * frequency * t).sin();
+    Ok(Output {
+        stream,
+        done,
+        stopper: ToneStopper {
+            sample_clock,
+            total_samples: total,
+            release_samples,
+            sample_rate,
+        },
+        pending,
+        pending_frequency,
+        broken,
+        invalidated,
+        levels,
+        tone_samples,
+        sample_rate,
+        started: Instant::now(),
+        device: dev_name,
+        config: config.clone(),
+    })
+}
 
-        // Apply fade envelope
-        let envelope = if n < fade_samples {
-            // Fade in
-            n as f64 / fade_samples as f64
-        } else if n > total_samples - fade_samples {
-            // Fade out
-            (total_samples - n) as f64 / fade_samples as f64
-        } else {
-            1.0
-        };
+/// Highest frequency any tone from this config can have, counting
+/// `frequency_rotation`, `frequency_jitter`, `max_frequency` and the top
+/// harmonic of a comb
+fn highest_frequency(config: &Config) -> f64 {
+    let mut fundamental = config
+        .frequency_rotation
+        .iter()
+        .copied()
+        .fold(config.frequency, f64::max)
+        + config.frequency_jitter.max(0.0);
+    if config.max_frequency > 0.0 {
+        fundamental = fundamental.min(config.max_frequency);
+    }
+    fundamental * config.comb_count.max(1) as f64
+}
 
-        let sample = (value * envelope) as f32 * volume;
+/// Fail if `frequency` is at or above half the sample rate (the Nyquist
+/// frequency). The device can't reproduce it, and the samples alias to
+/// some lower frequency that may well be audible.
+fn check_nyquist(frequency: f64, sample_rate: f64, device: &str) -> Result<(), AudioError> {
+    if frequency < sample_rate / 2.0 {
+        return Ok(());
+    }
+    Err(AudioError::Other(format!(
+        "{} Hz is too high for '{}' at {} Hz; tones must be below {} Hz (half the sample rate)",
+        frequency,
+        device,
+        sample_rate,
+        sample_rate / 2.0
+    )))
+}
+
+/// The device's default output config, or with `sample_format` or
+/// `channels_count` set, the closest config the device supports with them
+fn output_config(
+    device: &Device,
+    format: OutputFormat,
+    channels: Option<u16>,
+) -> Result<cpal::SupportedStreamConfig, AudioError> {
+    let default = device
+        .default_output_config()
+        .map_err(|e| format!("Failed to get default output config: {}", e))?;
+    let wanted = match format {
+        OutputFormat::Auto => None,
+        OutputFormat::F32 => Some(SampleFormat::F32),
+        OutputFormat::I16 => Some(SampleFormat::I16),
+        OutputFormat::I32 => Some(SampleFormat::I32),
+    };
+    if wanted.is_none() && channels.is_none_or(|n| n == default.channels()) {
+        return Ok(default);
+    }
+    let ranges: Vec<_> = device
+        .supported_output_configs()
+        .map_err(|e| format!("Failed to get supported output configs: {}", e))?
+        .collect();
+    let mut chosen = default;
+    if let Some(wanted) = wanted {
+        chosen = pick_format(&chosen, &ranges, wanted).ok_or_else(|| {
+            AudioError::Other(format!(
+                "'{}' doesn't support sample format {} (see `nodoze list-devices --verbose`)",
+                describe(device),
+                format.name()
+            ))
+        })?;
+        log::info!("Using sample format {} on '{}'", format.name(), describe(device));
+    }
+    if let Some(channels) = channels {
+        chosen = pick_channels(&chosen, &ranges, channels).ok_or_else(|| {
+            AudioError::Other(format!(
+                "'{}' doesn't support {} channels in sample format {} \
+                 (see `nodoze list-devices --verbose`)",
+                describe(device),
+                channels,
+                chosen.sample_format()
+            ))
+        })?;
+        log::info!("Using {} channels on '{}'", channels, describe(device));
+    }
+    Ok(chosen)
+}
+
+/// Pick a config in the `wanted` sample format, keeping as much of the
+/// default config as possible: its own format if it matches, else the same
+/// channel count and sample rate, else the same rate, else the highest rate
+/// on offer. None if no range has that format.
+fn pick_format(
+    default: &cpal::SupportedStreamConfig,
+    ranges: &[cpal::SupportedStreamConfigRange],
+    wanted: SampleFormat,
+) -> Option<cpal::SupportedStreamConfig> {
+    if default.sample_format() == wanted {
+        return Some(default.clone());
+    }
+    let rate = default.sample_rate();
+    let candidates = || ranges.iter().filter(|r| r.sample_format() == wanted);
+    let has_rate = |r: &cpal::SupportedStreamConfigRange| {
+        r.min_sample_rate() <= rate && rate <= r.max_sample_rate()
+    };
+    candidates()
+        .find(|r| has_rate(r) && r.channels() == default.channels())
+        .or_else(|| candidates().find(|r| has_rate(r)))
+        .map(|r| r.with_sample_rate(rate))
+        .or_else(|| {
+            candidates()
+                .max_by_key(|r| r.max_sample_rate())
+                .map(|r| r.with_max_sample_rate())
+        })
+}
+
+/// Pick a config with `channels` channels in the same sample format as
+/// `chosen`, at its sample rate if offered, else the highest rate on offer.
+/// None if no range has that many channels in that format.
+fn pick_channels(
+    chosen: &cpal::SupportedStreamConfig,
+    ranges: &[cpal::SupportedStreamConfigRange],
+    channels: u16,
+) -> Option<cpal::SupportedStreamConfig> {
+    if chosen.channels() == channels {
+        return Some(chosen.clone());
+    }
+    let rate = chosen.sample_rate();
+    let candidates = || {
+        ranges
+            .iter()
+            .filter(|r| r.sample_format() == chosen.sample_format() && r.channels() == channels)
+    };
+    candidates()
+        .find(|r| r.min_sample_rate() <= rate && rate <= r.max_sample_rate())
+        .map(|r| r.with_sample_rate(rate))
+        .or_else(|| {
+            candidates()
+                .max_by_key(|r| r.max_sample_rate())
+                .map(|r| r.with_max_sample_rate())
+        })
+}
+
+/// Whether a failed stream build may be down to the fixed buffer size, so
+/// it's worth retrying with the device's default. Anything that says the
+/// device is gone won't be fixed by a different buffer.
+fn fixed_buffer_rejected(e: &cpal::BuildStreamError) -> bool {
+    matches!(
+        e,
+        cpal::BuildStreamError::StreamConfigNotSupported
+            | cpal::BuildStreamError::InvalidArgument
+            | cpal::BuildStreamError::BackendSpecific { .. }
+    )
+}
+
+/// Build a stream writing the device's native sample format, or None if
+/// that format isn't one we can write
+fn build_native_stream(
+    device: &Device,
+    config: &StreamConfig,
+    format: SampleFormat,
+    generator: Generator,
+) -> Option<Result<cpal::Stream, cpal::BuildStreamError>> {
+    Some(match format {
+        SampleFormat::F32 => build_stream::<f32>(device, config, generator),
+        SampleFormat::F64 => build_stream::<f64>(device, config, generator),
+        SampleFormat::I8 => build_stream::<i8>(device, config, generator),
+        SampleFormat::I16 => build_stream::<i16>(device, config, generator),
+        SampleFormat::I32 => build_stream::<i32>(device, config, generator),
+        SampleFormat::I64 => build_stream::<i64>(device, config, generator),
+        SampleFormat::U8 => build_stream::<u8>(device, config, generator),
+        SampleFormat::U16 => build_stream::<u16>(device, config, generator),
+        SampleFormat::U32 => build_stream::<u32>(device, config, generator),
+        SampleFormat::U64 => build_stream::<u64>(device, config, generator),
+        _ => return None,
+    })
+}
+
+/// Sample generator state owned by the audio callback
+struct Generator {
+    channels: usize,
+    sample_clock: Arc<AtomicU64>,
+    total_samples: Arc<AtomicU64>,
+    /// Tone armed from another thread, picked up at the start of a callback
+    pending: Arc<AtomicU64>,
+    pending_frequency: Arc<AtomicU64>,
+    params: ToneParams,
+    /// Only touched by the audio callback; completion is reported over `done`
+    finished: AtomicBool,
+    done: Sender<()>,
+    levels: Arc<Mutex<LevelMeter>>,
+    broken: Arc<AtomicBool>,
+    invalidated: Arc<AtomicBool>,
+    /// Raise the callback thread's scheduling priority on the first callback
+    realtime_priority: bool,
+    /// Quantization step of an integer output format, and the noise source
+    /// for dithering it, when `dither` is set
+    dither: Option<(f32, Rng)>,
+    /// `highpass_hz`/`lowpass_hz` filter, if either is set
+    filter: Option<Filter>,
+}
+
+/// Build an output stream for any sample type. Samples are generated as
+/// floats and converted to the device's format.
+fn build_stream<T>(
+    device: &Device,
+    config: &StreamConfig,
+    mut generator: Generator,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let mut float_buf = Vec::new();
+    let broken = generator.broken.clone();
+    let invalidated = generator.invalidated.clone();
+    let mut raise_priority = generator.realtime_priority;
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            // The backend owns the audio thread, so this is the first point
+            // where we run on it
+            if raise_priority {
+                raise_priority = false;
+                match raise_thread_priority() {
+                    Ok(()) => log::debug!("Audio thread running at realtime priority"),
+                    Err(e) => log::warn!(
+                        "Could not raise audio thread priority, continuing at normal priority: {}",
+                        e
+                    ),
+                }
+            }
+
+            // Restart the clock here rather than from the arming thread, so
+            // the callback stays its only writer
+            let pending = generator.pending.swap(0, Ordering::Acquire);
+            if pending > 0 {
+                let clock = generator.sample_clock.load(Ordering::Relaxed);
+                generator.params.continue_phase(clock);
+                generator.params.frequency =
+                    f64::from_bits(generator.pending_frequency.load(Ordering::Relaxed));
+                generator.sample_clock.store(0, Ordering::Relaxed);
+                generator.total_samples.store(pending, Ordering::Relaxed);
+                generator.finished.store(false, Ordering::Relaxed);
+            }
+
+            float_buf.resize(data.len(), 0.0f32);
+            let frames = write_samples(
+                &mut float_buf,
+                generator.channels,
+                &generator.sample_clock,
+                &generator.total_samples,
+                &generator.params,
+                &generator.finished,
+                &generator.done,
+            );
+            let tone = &mut float_buf[..frames * generator.channels];
+            if let Some(filter) = &mut generator.filter {
+                filter.process(tone);
+            }
+            generator.levels.lock().unwrap().add(tone);
+            // Only the tone, so the silence around it stays digital silence
+            if let Some((step, rng)) = &mut generator.dither {
+                dither(tone, *step, rng);
+            }
+            for (out, &sample) in data.iter_mut().zip(float_buf.iter()) {
+                *out = T::from_sample(sample);
+            }
+        },
+        move |err| {
+            match err {
+                StreamError::DeviceNotAvailable => broken.store(true, Ordering::Relaxed),
+                // Typically the device's sample rate changed under us, so
+                // the tone's precomputed rate no longer holds
+                StreamError::StreamInvalidated => {
+                    invalidated.store(true, Ordering::Relaxed);
+                    broken.store(true, Ordering::Relaxed);
+                }
+                _ => {}
+            }
+            log::warn!("Audio stream: {}", err)
+        },
+        None,
+    )
+}
+
+/// Butterworth (Q = 1/√2) high-pass and/or low-pass biquads over
+/// interleaved samples, one filter state per channel carried from one
+/// buffer to the next so the filter runs continuously through a tone
+#[derive(Clone)]
+struct Filter {
+    channels: usize,
+    stages: Vec<Biquad>,
+    /// Previous two inputs and outputs for each stage and channel
+    state: Vec<[f64; 4]>,
+    highpass: f64,
+    lowpass: f64,
+}
+
+/// Normalized biquad coefficients (a0 = 1)
+#[derive(Clone, Copy)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Biquad {
+    /// Second-order Butterworth section from the Audio EQ Cookbook
+    fn new(cutoff: f64, sample_rate: f64, highpass: bool) -> Self {
+        let w0 = 2.0 * std::f64::consts::PI * cutoff / sample_rate;
+        let cos = w0.cos();
+        let alpha = w0.sin() / std::f64::consts::SQRT_2;
+        let a0 = 1.0 + alpha;
+        let (b0, b1) = if highpass {
+            ((1.0 + cos) / 2.0, -(1.0 + cos))
+        } else {
+            ((1.0 - cos) / 2.0, 1.0 - cos)
+        };
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b0 / a0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+        }
+    }
+}
+
+impl Filter {
+    /// The filter `highpass_hz` and `lowpass_hz` describe, or None if
+    /// neither is set. Cutoffs must be below half the sample rate.
+    fn new(config: &Config, sample_rate: f64, channels: usize) -> Result<Option<Self>, String> {
+        let mut stages = Vec::new();
+        for (cutoff, highpass, name) in [
+            (config.highpass_hz, true, "highpass_hz"),
+            (config.lowpass_hz, false, "lowpass_hz"),
+        ] {
+            if cutoff <= 0.0 {
+                continue;
+            }
+            if cutoff >= sample_rate / 2.0 {
+                return Err(format!(
+                    "{} ({} Hz) must be below half the {} Hz sample rate",
+                    name, cutoff, sample_rate
+                ));
+            }
+            stages.push(Biquad::new(cutoff, sample_rate, highpass));
+        }
+        if stages.is_empty() {
+            return Ok(None);
+        }
+        let channels = channels.max(1);
+        Ok(Some(Self {
+            channels,
+            state: vec![[0.0; 4]; stages.len() * channels],
+            stages,
+            highpass: config.highpass_hz,
+            lowpass: config.lowpass_hz,
+        }))
+    }
+
+    /// Whether `frequency` lies between the cutoffs
+    fn passes(&self, frequency: f64) -> bool {
+        frequency >= self.highpass && (self.lowpass <= 0.0 || frequency <= self.lowpass)
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_mut(self.channels) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let mut x = *sample as f64;
+                for (stage, q) in self.stages.iter().enumerate() {
+                    let [x1, x2, y1, y2] = &mut self.state[stage * self.channels + channel];
+                    let y = q.b0 * x + q.b1 * *x1 + q.b2 * *x2 - q.a1 * *y1 - q.a2 * *y2;
+                    (*x2, *x1, *y2, *y1) = (*x1, x, *y1, y);
+                    x = y;
+                }
+                *sample = x as f32;
+            }
+        }
+    }
+}
+
+/// The configured filter for display, e.g. "high-pass 10 Hz, low-pass 40 Hz"
+pub fn filter_label(config: &Config) -> String {
+    let mut parts = Vec::new();
+    if config.highpass_hz > 0.0 {
+        parts.push(format!("high-pass {} Hz", config.highpass_hz));
+    }
+    if config.lowpass_hz > 0.0 {
+        parts.push(format!("low-pass {} Hz", config.lowpass_hz));
+    }
+    parts.join(", ")
+}
+
+/// Size of one quantization step (1 LSB) of an integer sample format, on
+/// the ±1.0 float scale. None for float formats, which need no dither.
+fn quantization_step(format: SampleFormat) -> Option<f32> {
+    let bits = match format {
+        SampleFormat::I8 | SampleFormat::U8 => 8,
+        SampleFormat::I16 | SampleFormat::U16 => 16,
+        SampleFormat::I32 | SampleFormat::U32 => 32,
+        SampleFormat::I64 | SampleFormat::U64 => 64,
+        _ => return None,
+    };
+    Some(2.0f32.powi(1 - bits))
+}
+
+/// Add triangular (TPDF) dither of at most ±1 `step` to each sample, so a
+/// very quiet tone turns into a little noise on integer output rather than
+/// a few coarse quantization steps that can buzz
+fn dither(samples: &mut [f32], step: f32, rng: &mut Rng) {
+    for sample in samples {
+        let noise = rng.next_f64() - rng.next_f64();
+        *sample += noise as f32 * step;
+    }
+}
+
+/// Priority requested for the audio thread under SCHED_FIFO. Modest, so it
+/// preempts ordinary work without competing with the system's own audio
+/// server threads.
+#[cfg(unix)]
+const AUDIO_THREAD_PRIORITY: libc::c_int = 10;
+
+/// Ask the OS to schedule the calling thread ahead of normal work. Usually
+/// needs privileges (CAP_SYS_NICE, an rtprio limit or rtkit on Linux).
+#[cfg(unix)]
+fn raise_thread_priority() -> Result<(), String> {
+    // SAFETY: only changes the scheduling policy of the calling thread
+    let rc = unsafe {
+        let policy = libc::SCHED_FIFO;
+        let priority = AUDIO_THREAD_PRIORITY.clamp(
+            libc::sched_get_priority_min(policy),
+            libc::sched_get_priority_max(policy),
+        );
+        let mut param: libc::sched_param = std::mem::zeroed();
+        param.sched_priority = priority;
+        libc::pthread_setschedparam(libc::pthread_self(), policy, &param)
+    };
+    if rc != 0 {
+        return Err(std::io::Error::from_raw_os_error(rc).to_string());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn raise_thread_priority() -> Result<(), String> {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_TIME_CRITICAL,
+    };
+
+    // SAFETY: GetCurrentThread returns a pseudo-handle that needs no cleanup
+    let ok = unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL) };
+    if ok == 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn raise_thread_priority() -> Result<(), String> {
+    Err("not supported on this platform".to_string())
+}
+
+/// Render the tone to a 32-bit float WAV file instead of playing it.
+/// Uses the same generator as `play_tone`, so the output is exactly what a
+/// device would receive at the given rate and channel count.
+pub fn export_wav(
+    config: &Config,
+    path: &Path,
+    sample_rate: u32,
+    channels: u16,
+) -> Result<(), String> {
+    let channels = channels.max(1);
+    let params = ToneParams::new(config, sample_rate as f64, channels as usize)?;
+    let mut filter = Filter::new(config, sample_rate as f64, channels as usize)?;
+
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)
+        .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+
+    let sample_clock = AtomicU64::new(0);
+    let total = AtomicU64::new(params.total_samples);
+    let finished = AtomicBool::new(false);
+    let (done_tx, _done) = mpsc::channel();
+    let mut levels = LevelMeter::default();
+
+    // Same buffer size the device streams use
+    let mut buf = vec![0.0f32; BUFFER_FRAMES as usize * channels as usize];
+    let mut remaining = params.total_samples * channels as u64;
+    while remaining > 0 {
+        write_samples(
+            &mut buf,
+            channels as usize,
+            &sample_clock,
+            &total,
+            &params,
+            &finished,
+            &done_tx,
+        );
+        let take = remaining.min(buf.len() as u64) as usize;
+        if let Some(filter) = &mut filter {
+            filter.process(&mut buf[..take]);
+        }
+        levels.add(&buf[..take]);
+        for &sample in &buf[..take] {
+            writer
+                .write_sample(sample)
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        }
+        remaining -= take as u64;
+    }
+
+    writer
+        .finalize()
+        .map_err(|e| format!("Failed to finalize {}: {}", path.display(), e))?;
+
+    log::info!(
+        "Exported {} ({}s, {} Hz, {} ch) to {} ({})",
+        sound_label(config),
+        config.duration,
+        sample_rate,
+        channels,
+        path.display(),
+        levels.summary()
+    );
+    levels.warn_if_quiet();
+    Ok(())
+}
+
+/// Frames printed either side of each envelope boundary by `dump_samples`
+const DUMP_CONTEXT: u64 = 8;
+
+/// Print the first `count` frames of the tone, then the frames around where
+/// the fade-in ends, the fade-out starts and the tone ends, one frame per
+/// line as its index and a sample per channel. Straight from
+/// `generate_frame`, so before any `filter` and without opening a device.
+pub fn dump_samples(
+    config: &Config,
+    sample_rate: u32,
+    channels: u16,
+    count: u64,
+) -> Result<(), String> {
+    let channels = channels.max(1);
+    let params = ToneParams::new(config, sample_rate as f64, channels as usize)?;
+    let total = params.total_samples;
+    let mut frame = vec![0.0f32; channels as usize];
+    let mut print = |label: String, frames: std::ops::Range<u64>| {
+        println!("# {}", label);
+        for n in frames.start..frames.end.min(total) {
+            generate_frame(&mut frame, n, total, &params);
+            let samples: Vec<String> = frame.iter().map(|s| s.to_string()).collect();
+            println!("{}\t{}", n, samples.join("\t"));
+        }
+    };
+
+    println!(
+        "# {}, {} Hz, {} ch, {} frames",
+        sound_label(config),
+        sample_rate,
+        channels,
+        total
+    );
+    print(format!("first {} frames", count.min(total)), 0..count);
+    let around = |at: u64| at.saturating_sub(DUMP_CONTEXT)..at + DUMP_CONTEXT;
+    if params.attack_samples > 0 {
+        let at = params.attack_samples;
+        print(format!("fade-in ends at frame {}", at), around(at));
+    }
+    if params.release_samples > 0 {
+        let at = total - params.release_samples;
+        print(format!("fade-out starts at frame {}", at), around(at));
+    }
+    print(format!("tone ends at frame {}", total), around(total));
+    Ok(())
+}
+
+/// Everything the audio callback needs to synthesize the tone
+#[derive(Clone)]
+struct ToneParams {
+    sample_rate: f64,
+    total_samples: u64,
+    frequency: f64,
+    waveform: Waveform,
+    /// Harmonics of `frequency` summed into the tone (1 = just the fundamental)
+    harmonics: u32,
+    volume: f32,
+    attack_samples: u64,
+    release_samples: u64,
+    /// Fade gain for each sample of the fade-in, from silent (index 0) to
+    /// full level (index `attack_samples`). Computed once so the callback
+    /// only indexes into it.
+    attack_ramp: Vec<f64>,
+    /// The same for the fade-out, indexed by samples left in the tone
+    release_ramp: Vec<f64>,
+    /// Phase of the sine at sample 0, in radians. Nonzero when a persistent
+    /// stream starts a tone where the previous one's oscillator left off.
+    start_phase: f64,
+    /// Phase offset of the right channel, in radians
+    right_phase: f64,
+    /// Balance gains for the left and right channels
+    left_gain: f32,
+    right_gain: f32,
+    /// Negate the samples of odd-numbered channels
+    antiphase: bool,
+    /// Seed of each channel's noise (`waveform = "noise"`), the same for
+    /// every channel unless `decorrelate` is set
+    noise_seeds: Vec<u64>,
+    /// Whether each channel carries the tone; the rest are silent
+    tone_channels: Vec<bool>,
+    /// Gain for each channel from `channel_gains`, after `volume`
+    channel_gains: Vec<f32>,
+    limiter: Limiter,
+    /// Amplitude modulation rate in Hz and depth (0 = off)
+    mod_rate: f64,
+    mod_depth: f64,
+    /// Audio clip played instead of the sine wave, when `wav_path` is set
+    clip: Option<Clip>,
+}
+
+impl ToneParams {
+    fn new(config: &Config, sample_rate: f64, channels: usize) -> Result<Self, String> {
+        let total_samples = if config.duration == 0 {
+            BUFFER_FRAMES as u64
+        } else {
+            (config.duration as f64 * sample_rate) as u64
+        };
+        let clip = if config.wav_path.is_empty() {
+            None
+        } else {
+            let mut clip = Clip::load(Path::new(&config.wav_path), sample_rate)?;
+            if (clip.frames as u64) < total_samples {
+                clip.crossfade_loop((LOOP_CROSSFADE * sample_rate) as usize);
+            }
+            Some(clip)
+        };
+        let fade_samples = |secs: f64| {
+            if secs == 0.0 {
+                0
+            } else {
+                (secs.max(MIN_FADE) * sample_rate) as u64
+            }
+        };
+        let mut attack_samples = fade_samples(config.attack());
+        let mut release_samples = fade_samples(config.release());
+        // Fade in and out must fit inside the tone, or the envelope would
+        // jump. Validation keeps a set attack and release within the
+        // duration, so this mostly shortens a long `fade_duration`.
+        if attack_samples + release_samples > total_samples {
+            attack_samples = attack_samples.min(total_samples / 2);
+            release_samples = release_samples.min(total_samples / 2);
+        }
+        let ramp = |samples: u64| -> Vec<f64> {
+            (0..=samples)
+                .map(|i| fade_gain(config.fade_curve, i as f64 / samples.max(1) as f64))
+                .collect()
+        };
+        let pan = config.pan.clamp(-1.0, 1.0) as f32;
+        Ok(Self {
+            sample_rate,
+            total_samples,
+            frequency: config.frequency,
+            waveform: config.waveform,
+            harmonics: config.comb_count.max(1),
+            volume: config.volume.clamp(0.0, 1.0) as f32,
+            attack_samples,
+            release_samples,
+            attack_ramp: ramp(attack_samples),
+            release_ramp: ramp(release_samples),
+            start_phase: 0.0,
+            right_phase: config.stereo_phase.to_radians(),
+            left_gain: (1.0 - pan).min(1.0),
+            right_gain: (1.0 + pan).min(1.0),
+            antiphase: config.antiphase,
+            noise_seeds: (0..channels as u64)
+                .map(|ch| if config.decorrelate { ch + 1 } else { 1 })
+                .collect(),
+            tone_channels: {
+                let mut tone_channels = vec![false; channels];
+                for ch in config.tone_channels(channels) {
+                    tone_channels[ch] = true;
+                }
+                tone_channels
+            },
+            channel_gains: channel_gains(config, channels),
+            limiter: config.limiter,
+            mod_rate: config.modulation.mod_rate.max(0.0),
+            mod_depth: config.modulation.mod_depth.clamp(0.0, 1.0),
+            clip,
+        })
+    }
+
+    /// Start the next tone where a free-running oscillator would be
+    /// `clock` samples into this one. The clock keeps running through the
+    /// silence between tones, so this keeps the sine continuous from tone
+    /// to tone.
+    fn continue_phase(&mut self, clock: u64) {
+        self.start_phase = (self.start_phase
+            + 2.0 * std::f64::consts::PI * self.frequency * clock as f64 / self.sample_rate)
+            % (2.0 * std::f64::consts::PI);
+    }
+}
+
+/// `channel_gains` for a device with `channels` channels, 1.0 where unset.
+/// Warns about any gains for channels the device doesn't have.
+fn channel_gains(config: &Config, channels: usize) -> Vec<f32> {
+    if config.channel_gains.len() > channels {
+        log::warn!(
+            "channel_gains has {} entries but the device has {} channels, ignoring the rest",
+            config.channel_gains.len(),
+            channels
+        );
+    }
+    (0..channels)
+        .map(|ch| config.channel_gains.get(ch).map_or(1.0, |&gain| gain as f32))
+        .collect()
+}
+
+/// A WAV clip decoded to floats and resampled to the output rate. Looped
+/// or cut short to fill the configured duration.
+#[derive(Clone)]
+struct Clip {
+    /// Interleaved samples
+    samples: Vec<f32>,
+    channels: usize,
+    frames: usize,
+}
+
+impl Clip {
+    /// Decode a WAV or FLAC file and resample it to the stream's rate
+    fn load(path: &Path, sample_rate: f64) -> Result<Self, String> {
+        let (samples, channels, source_rate) = if is_flac(path) {
+            decode_flac(path)?
+        } else {
+            decode_wav(path)?
+        };
+
+        let channels = channels.max(1);
+        let source_frames = samples.len() / channels;
+        if source_frames == 0 {
+            return Err(format!("{} contains no audio", path.display()));
+        }
+
+        // Linear interpolation is plenty for a keep-alive clip
+        let ratio = source_rate as f64 / sample_rate;
+        let frames = ((source_frames as f64 / ratio) as usize).max(1);
+        let mut resampled = Vec::with_capacity(frames * channels);
+        for i in 0..frames {
+            let pos = i as f64 * ratio;
+            let i0 = (pos as usize).min(source_frames - 1);
+            let i1 = (i0 + 1).min(source_frames - 1);
+            let frac = (pos - i0 as f64) as f32;
+            for ch in 0..channels {
+                let a = samples[i0 * channels + ch];
+                let b = samples[i1 * channels + ch];
+                resampled.push(a + (b - a) * frac);
+            }
+        }
+
+        Ok(Self {
+            samples: resampled,
+            channels,
+            frames,
+        })
+    }
+
+    /// Blend the last `len` frames into the first ones and drop them, so
+    /// playing the clip on repeat has no jump at the seam: the frame after
+    /// the (new) last one is the blend's start, which is mostly the original
+    /// tail's next frame.
+    fn crossfade_loop(&mut self, len: usize) {
+        let len = len.min(self.frames / 2);
+        if len == 0 {
+            return;
+        }
+        let tail_start = self.frames - len;
+        for i in 0..len {
+            let gain = i as f32 / len as f32;
+            for ch in 0..self.channels {
+                let head = self.samples[i * self.channels + ch];
+                let tail = self.samples[(tail_start + i) * self.channels + ch];
+                self.samples[i * self.channels + ch] = head * gain + tail * (1.0 - gain);
+            }
+        }
+        self.frames = tail_start;
+        self.samples.truncate(tail_start * self.channels);
+    }
+
+    /// Sample for output frame `n`. Channels beyond the clip's repeat its last one.
+    fn sample(&self, n: u64, ch: usize) -> f64 {
+        let frame = (n % self.frames as u64) as usize;
+        self.samples[frame * self.channels + ch.min(self.channels - 1)] as f64
+    }
+}
+
+/// True if the file starts with the FLAC stream marker
+pub fn is_flac(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    std::fs::File::open(path)
+        .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut magic))
+        .is_ok_and(|_| &magic == b"fLaC")
+}
+
+/// Interleaved samples in ±1.0, channel count and sample rate of a WAV file
+fn decode_wav(path: &Path) -> Result<(Vec<f32>, usize, u32), String> {
+    let mut reader = hound::WavReader::open(path).map_err(|e| {
+        format!("Failed to open {} (expected WAV or FLAC): {}", path.display(), e)
+    })?;
+    let spec = reader.spec();
+    let read_err = |e: hound::Error| format!("Failed to read {}: {}", path.display(), e);
+
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .collect::<Result<_, _>>()
+            .map_err(read_err)?,
+        hound::SampleFormat::Int => {
+            let scale = (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale))
+                .collect::<Result<_, _>>()
+                .map_err(read_err)?
+        }
+    };
+    Ok((samples, spec.channels as usize, spec.sample_rate))
+}
+
+/// Interleaved samples in ±1.0, channel count and sample rate of a FLAC file
+fn decode_flac(path: &Path) -> Result<(Vec<f32>, usize, u32), String> {
+    let mut reader = claxon::FlacReader::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let info = reader.streaminfo();
+    let scale = (1u64 << (info.bits_per_sample - 1)) as f32;
+    let samples = reader
+        .samples()
+        .map(|s| s.map(|s| s as f32 / scale))
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok((samples, info.channels as usize, info.sample_rate))
+}
+
+fn write_samples(
+    data: &mut [f32],
+    channels: usize,
+    sample_clock: &AtomicU64,
+    total_samples: &AtomicU64,
+    params: &ToneParams,
+    finished: &AtomicBool,
+    done: &Sender<()>,
+) -> usize {
+    let total_samples = total_samples.load(Ordering::Relaxed);
+    // The callback is the clock's only writer, so read it once and store it
+    // back once per buffer instead of an atomic add for every frame
+    let start = sample_clock.load(Ordering::Relaxed);
+    let mut frames = 0;
+
+    for (i, frame) in data.chunks_mut(channels).enumerate() {
+        let n = start + i as u64;
+
+        if n >= total_samples {
+            // Signal completion exactly once
+            if !finished.swap(true, Ordering::Relaxed) {
+                let _ = done.send(());
+            }
+            for sample in frame.iter_mut() {
+                *sample = 0.0;
+            }
+            continue;
+        }
+        frames += 1;
+        generate_frame(frame, n, total_samples, params);
+    }
+    sample_clock.store(start + data.len().div_ceil(channels) as u64, Ordering::Relaxed);
+    frames
+}
+
+/// Fill one frame (a sample per channel) of the tone at sample index `n`,
+/// which must be below `total_samples`. Pure, so the envelope and channel
+/// handling don't depend on a live stream.
+fn generate_frame(frame: &mut [f32], n: u64, total_samples: u64, params: &ToneParams) {
+    // Sine wave phase at this frame
+    let t = n as f64 / params.sample_rate;
+    let phase = 2.0 * std::f64::consts::PI * params.frequency * t + params.start_phase;
+
+    // Apply fade envelope. Taking the lower of the two ramps keeps it
+    // continuous when a tone is stopped early during its fade-in. With no
+    // fade (0 samples) a ramp doesn't apply, since `n` is always below
+    // `total_samples`.
+    let fade_in = if n < params.attack_samples {
+        params.attack_ramp[n as usize]
+    } else {
+        1.0
+    };
+    let fade_out = if n + params.release_samples > total_samples {
+        params.release_ramp[(total_samples - n) as usize]
+    } else {
+        1.0
+    };
+    let mut envelope = fade_in.min(fade_out);
+
+    // Amplitude modulation starts at full level and dips to 1 - depth
+    if params.mod_depth > 0.0 && params.mod_rate > 0.0 {
+        let swing = 0.5 - 0.5 * (2.0 * std::f64::consts::PI * params.mod_rate * t).cos();
+        envelope *= 1.0 - params.mod_depth * swing;
+    }
+
+    // Raw waveform for a channel, before the envelope and gains
+    let single = |phase: f64| match params.waveform {
+        Waveform::Sine => phase.sin(),
+        Waveform::Pulse => pulse(phase),
+        Waveform::Noise => unreachable!("noise has no phase"),
+    };
+    // A comb sums the harmonics at equal weight, scaled so the total stays
+    // within full scale however their peaks line up
+    let shape = |phase: f64| {
+        if params.harmonics == 1 {
+            return single(phase);
+        }
+        (1..=params.harmonics).map(|k| single(k as f64 * phase)).sum::<f64>()
+            / params.harmonics as f64
+    };
+    let wave = |ch: usize| match &params.clip {
+        Some(clip) => clip.sample(n, ch),
+        None if params.waveform == Waveform::Noise => noise(params.noise_seeds[ch], n),
+        None if ch == 1 => shape(phase + params.right_phase),
+        None => shape(phase),
+    };
+    let level = |ch: usize| limit(params.limiter, wave(ch) * envelope) as f32 * params.volume;
+
+    let gain = |ch: usize| params.channel_gains.get(ch).copied().unwrap_or(1.0);
+
+    if frame.len() < 2 {
+        frame[0] = level(0) * gain(0);
+        return;
+    }
+
+    // Stereo phase and pan apply to the front left/right pair only
+    for (ch, s) in frame.iter_mut().enumerate() {
+        if !params.tone_channels.get(ch).copied().unwrap_or(false) {
+            *s = 0.0;
+            continue;
+        }
+        *s = match ch {
+            0 => level(0) * params.left_gain,
+            1 => level(1) * params.right_gain,
+            _ => level(ch),
+        } * gain(ch);
+        if params.antiphase && ch % 2 == 1 {
+            *s = -*s;
+        }
+    }
+}
+
+/// One cycle of the pulse waveform at `phase` radians: a half-sine pulse
+/// at full level over the first `PULSE_WIDTH` of the cycle, then a shallow
+/// negative half-sine over the rest with the same area, so each cycle
+/// averages to zero. Both halves start and end at zero, so there are no
+/// steps for the speaker to click on.
+fn pulse(phase: f64) -> f64 {
+    let pos = (phase / std::f64::consts::TAU).rem_euclid(1.0);
+    if pos < PULSE_WIDTH {
+        (std::f64::consts::PI * pos / PULSE_WIDTH).sin()
+    } else {
+        let depth = PULSE_WIDTH / (1.0 - PULSE_WIDTH);
+        -depth * (std::f64::consts::PI * (pos - PULSE_WIDTH) / (1.0 - PULSE_WIDTH)).sin()
+    }
+}
+
+/// Sample `n` of the white noise seeded by `seed`, uniform in [-1, 1).
+/// Worked out from `n` rather than drawn in turn, so like the tone any
+/// frame can be generated on its own.
+fn noise(seed: u64, n: u64) -> f64 {
+    // SplitMix64's mixing function, over the seed's own stretch of indices
+    let mut x = n.wrapping_add(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+    (x >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+}
+
+/// Running peak and RMS level of the generated samples
+#[derive(Default)]
+struct LevelMeter {
+    peak: f32,
+    sum_squares: f64,
+    count: u64,
+}
+
+impl LevelMeter {
+    fn add(&mut self, samples: &[f32]) {
+        for &sample in samples {
+            self.peak = self.peak.max(sample.abs());
+            self.sum_squares += (sample as f64).powi(2);
+        }
+        self.count += samples.len() as u64;
+    }
+
+    fn rms(&self) -> f64 {
+        if self.count > 0 {
+            (self.sum_squares / self.count as f64).sqrt()
+        } else {
+            0.0
+        }
+    }
+
+    /// Levels in dBFS, e.g. "peak -26.0 dBFS, RMS -29.0 dBFS"
+    fn summary(&self) -> String {
+        format!(
+            "peak {} dBFS, RMS {} dBFS",
+            dbfs(self.peak as f64),
+            dbfs(self.rms())
+        )
+    }
+
+    fn warn_if_quiet(&self) {
+        if self.rms() < 10f64.powf(QUIET_RMS_DBFS / 20.0) {
+            log::warn!(
+                "Tone RMS is {} dBFS, which may be too quiet to keep speakers awake",
+                dbfs(self.rms())
+            );
+        }
+    }
+}
+
+/// Format a linear level in dBFS, e.g. "-26.0"
+pub fn dbfs(level: f64) -> String {
+    if level > 0.0 {
+        format!("{:.1}", 20.0 * level.log10())
+    } else {
+        "-inf".to_string()
+    }
+}
+
+/// Bring a sample back within full scale (±1.0) as the limiter mode says
+fn limit(limiter: Limiter, sample: f64) -> f64 {
+    match limiter {
+        Limiter::Off => sample,
+        Limiter::Clamp => sample.clamp(-1.0, 1.0),
+        // Unchanged up to the knee, then tanh squeezes the rest of the range
+        // into the headroom above it, meeting the straight part at slope 1
+        Limiter::Soft => {
+            const KNEE: f64 = 0.9;
+            let magnitude = sample.abs();
+            if magnitude <= KNEE {
+                sample
+            } else {
+                let headroom = 1.0 - KNEE;
+                sample.signum() * (KNEE + headroom * ((magnitude - KNEE) / headroom).tanh())
+            }
+        }
+    }
+}
+
+/// Map fade progress (0.0 = silent end, 1.0 = full level) onto the fade curve.
+/// Every curve hits exactly 0 and 1 at the boundaries.
+fn fade_gain(curve: FadeCurve, progress: f64) -> f64 {
+    match curve {
+        FadeCurve::Linear => progress,
+        // Raised cosine: smooth start and end, no slope discontinuity
+        FadeCurve::Cosine => 0.5 - 0.5 * (std::f64::consts::PI * progress).cos(),
+        // Exponential over a 60 dB range, normalized to reach 0 at the start
+        FadeCurve::Exponential => {
+            let k = 1000f64.ln();
+            ((k * progress).exp() - 1.0) / (k.exp() - 1.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f64 = 48_000.0;
+
+    fn params(config: &Config, channels: usize) -> ToneParams {
+        ToneParams::new(config, RATE, channels).unwrap()
+    }
+
+    /// Every frame of the tone, interleaved
+    fn render(params: &ToneParams, channels: usize) -> Vec<f32> {
+        let mut data = vec![0.0; params.total_samples as usize * channels];
+        for (n, frame) in data.chunks_mut(channels).enumerate() {
+            generate_frame(frame, n as u64, params.total_samples, params);
+        }
+        data
+    }
+
+    /// Largest jump between consecutive samples of any channel
+    fn max_step(data: &[f32], channels: usize) -> f32 {
+        (0..channels)
+            .map(|ch| {
+                let samples: Vec<f32> = data.iter().skip(ch).step_by(channels).copied().collect();
+                samples.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max)
+            })
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn names_match_case_insensitive_substrings() {
+        assert!(matches_name("Scarlett 2i2 USB", "scarlett"));
+        assert!(matches_name("Scarlett 2i2 USB", "2I2 usb"));
+        assert!(matches_name("Scarlett 2i2 USB", ""));
+        assert!(!matches_name("Scarlett 2i2 USB", "MacBook Pro Speakers"));
+        assert!(!matches_name("USB", "Scarlett 2i2 USB"));
+    }
+
+    #[test]
+    fn null_devices_are_recognized() {
+        assert!(is_null_device("Discard all samples (playback) or generate zero samples"));
+        assert!(is_null_device("Null Output"));
+        assert!(!is_null_device("HDA Intel PCH, ALC892 Analog"));
+    }
+
+    #[cfg(feature = "mock-audio")]
+    #[test]
+    fn first_matching_finds_a_reappeared_device() {
+        let host = mock::host();
+        let devices = || host.output_devices().unwrap();
+        let found = first_matching(devices(), "mock").expect("mock device matches");
+        assert_eq!(device_name(&found).as_deref(), Some(mock::DEVICE_NAME));
+        assert!(first_matching(devices(), "Scarlett").is_none());
+    }
+
+    #[cfg(feature = "mock-audio")]
+    #[test]
+    fn cached_device_expires_after_its_ttl() {
+        let device = mock::host().default_output_device().unwrap();
+        let names = vec!["mock".to_string()];
+        let start = Instant::now();
+        let mut cache = DeviceCache {
+            ttl: Duration::from_secs(5),
+            entry: None,
+        };
+        cache.put("", &names, &device, start);
+
+        assert!(cache.get("", &names, start + Duration::from_secs(4)).is_some());
+        // A different host or names isn't a hit, but doesn't evict either
+        assert!(cache.get("jack", &names, start).is_none());
+        assert!(cache.get("", &[], start).is_none());
+        assert!(cache.get("", &names, start + Duration::from_millis(4_999)).is_some());
+        assert!(cache.get("", &names, start + Duration::from_secs(5)).is_none());
+        assert!(cache.entry.is_none());
+    }
+
+    #[cfg(feature = "mock-audio")]
+    #[test]
+    fn zero_ttl_caches_nothing() {
+        let device = mock::host().default_output_device().unwrap();
+        let mut cache = DeviceCache {
+            ttl: Duration::ZERO,
+            entry: None,
+        };
+        let now = Instant::now();
+        cache.put("", &[], &device, now);
+        assert!(cache.get("", &[], now).is_none());
+    }
+
+    #[test]
+    fn device_list_json_has_index_name_and_default() {
+        let devices = [
+            DeviceInfo {
+                index: 0,
+                name: "Speakers".to_string(),
+                is_default: true,
+                host: None,
+                configs: Vec::new(),
+            },
+            DeviceInfo {
+                index: 1,
+                name: "Scarlett 2i2 USB".to_string(),
+                is_default: false,
+                host: Some("ALSA".to_string()),
+                configs: vec!["F32, 2 ch, 44100-192000 Hz".to_string()],
+            },
+        ];
+        let json: serde_json::Value = serde_json::to_value(devices).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"index": 0, "name": "Speakers", "is_default": true},
+                {
+                    "index": 1,
+                    "name": "Scarlett 2i2 USB",
+                    "is_default": false,
+                    "host": "ALSA",
+                    "configs": ["F32, 2 ch, 44100-192000 Hz"],
+                },
+            ])
+        );
+    }
+
+    #[cfg(feature = "mock-audio")]
+    #[test]
+    fn mock_host_lists_its_one_device() {
+        let devices = list_devices("", false, false).unwrap();
+        let json = serde_json::to_string(&devices).unwrap();
+        assert_eq!(json, r#"[{"index":0,"name":"Mock Output","is_default":true}]"#);
+    }
+
+    #[test]
+    fn every_fade_curve_runs_from_silent_to_full() {
+        for curve in [FadeCurve::Linear, FadeCurve::Cosine, FadeCurve::Exponential] {
+            assert!(fade_gain(curve, 0.0).abs() < 1e-12, "{:?} starts silent", curve);
+            assert!((fade_gain(curve, 1.0) - 1.0).abs() < 1e-12, "{:?} ends at full", curve);
+            let mut last = 0.0;
+            for i in 1..=100 {
+                let gain = fade_gain(curve, i as f64 / 100.0);
+                assert!(gain >= last, "{:?} rises monotonically", curve);
+                last = gain;
+            }
+        }
+    }
+
+    #[test]
+    fn cached_fade_ramps_match_the_curves() {
+        for curve in [FadeCurve::Linear, FadeCurve::Cosine, FadeCurve::Exponential] {
+            let config = Config {
+                frequency: 440.0,
+                duration: 2,
+                attack_duration: Some(0.5),
+                release_duration: Some(0.25),
+                fade_curve: curve,
+                volume: 1.0,
+                ..Config::default()
+            };
+            let params = params(&config, 1);
+            for (ramp, samples) in [
+                (&params.attack_ramp, params.attack_samples),
+                (&params.release_ramp, params.release_samples),
+            ] {
+                assert_eq!(ramp.len() as u64, samples + 1);
+                for (i, &gain) in ramp.iter().enumerate() {
+                    let expected = fade_gain(curve, i as f64 / samples as f64);
+                    assert!((gain - expected).abs() < 1e-12, "{:?} at {}", curve, i);
+                }
+            }
+
+            // The callback scales the raw tone by the cached ramps
+            let total = params.total_samples;
+            let raw = |n: u64| (std::f64::consts::TAU * config.frequency * n as f64 / RATE).sin();
+            let mut frame = [0.0];
+            for n in (0..params.attack_samples).step_by(97) {
+                generate_frame(&mut frame, n, total, &params);
+                let expected = (raw(n) * params.attack_ramp[n as usize]) as f32;
+                assert!((frame[0] - expected).abs() < 1e-6, "{:?} fade-in at {}", curve, n);
+            }
+            for n in (total - params.release_samples + 1..total).step_by(97) {
+                generate_frame(&mut frame, n, total, &params);
+                let expected = (raw(n) * params.release_ramp[(total - n) as usize]) as f32;
+                assert!((frame[0] - expected).abs() < 1e-6, "{:?} fade-out at {}", curve, n);
+            }
+        }
+    }
+
+    #[test]
+    fn tones_start_and_end_without_a_click() {
+        // Steepest slope of each waveform per radian of phase
+        for (waveform, slope) in [(Waveform::Sine, 1.0), (Waveform::Pulse, 0.5 / PULSE_WIDTH)] {
+            let config = Config {
+                frequency: 440.0,
+                waveform,
+                volume: 1.0,
+                ..Config::default()
+            };
+            let data = render(&params(&config, 2), 2);
+            let per_sample = slope * std::f64::consts::TAU * config.frequency / RATE;
+            let threshold = (per_sample * 1.05) as f32;
+            assert!(
+                max_step(&data, 2) <= threshold,
+                "{:?} jumps by {} (limit {})",
+                waveform,
+                max_step(&data, 2),
+                threshold
+            );
+            assert_eq!(data[0], 0.0);
+            assert!(data[data.len() - 2..].iter().all(|s| s.abs() < threshold));
+        }
+    }
+
+    #[test]
+    fn envelope_fades_in_sustains_and_fades_out() {
+        let config = Config {
+            duration: 2,
+            fade_duration: 0.5,
+            volume: 0.5,
+            limiter: Limiter::Off,
+            ..Config::default()
+        };
+        let params = params(&config, 1);
+        let total = params.total_samples;
+        let sample = |n: u64| {
+            let mut frame = [0.0];
+            generate_frame(&mut frame, n, total, &params);
+            frame[0]
+        };
+
+        assert!(sample(0).abs() < 1e-6, "fade-in starts at {}", sample(0));
+        assert!(sample(total - 1).abs() < 1e-3, "fade-out ends at {}", sample(total - 1));
+
+        // One full cycle of the 20 Hz tone in the middle, past both fades
+        let cycle = (RATE / config.frequency) as u64;
+        let sustain: Vec<f32> = (total / 2..total / 2 + cycle).map(sample).collect();
+        let peak = sustain.iter().fold(0.0, |peak: f32, s| peak.max(s.abs()));
+        assert!(peak <= 0.5 + 1e-6, "sustain peaks at {}", peak);
+        assert!((peak - 0.5).abs() < 1e-3, "sustain only reaches {}", peak);
+    }
+
+    #[test]
+    fn zero_fade_plays_the_bare_tone_throughout() {
+        let config = Config {
+            duration: 1,
+            fade_duration: 0.0,
+            ..Config::default()
+        };
+        let params = params(&config, 1);
+        assert_eq!((params.attack_samples, params.release_samples), (0, 0));
+        let data = render(&params, 1);
+        for (n, &s) in data.iter().enumerate() {
+            let phase = std::f64::consts::TAU * config.frequency * n as f64 / RATE;
+            let expected = (phase.sin() * config.volume) as f32;
+            assert!((s - expected).abs() < 1e-6, "sample {} is {}, not {}", n, s, expected);
+        }
+    }
+
+    /// The tone's envelope: its level at each frame with a constant signal
+    /// in place of the sine
+    fn envelope(config: &Config) -> Vec<f32> {
+        let mut params = params(config, 1);
+        params.clip = Some(Clip {
+            samples: vec![1.0],
+            channels: 1,
+            frames: 1,
+        });
+        render(&params, 1)
+    }
+
+    #[test]
+    fn attack_and_release_have_their_own_lengths() {
+        let config = Config {
+            duration: 2,
+            attack_duration: Some(0.1),
+            release_duration: Some(1.0),
+            fade_curve: FadeCurve::Linear,
+            volume: 1.0,
+            ..Config::default()
+        };
+        let level = envelope(&config);
+        let total = level.len();
+        assert_eq!(total, 2 * RATE as usize);
+
+        // Halfway through each fade, then where each has just finished
+        assert!((level[2_400] - 0.5).abs() < 1e-3, "{}", level[2_400]);
+        assert!((level[total - 24_000] - 0.5).abs() < 1e-3, "{}", level[total - 24_000]);
+        assert_eq!(level[4_800], 1.0);
+        assert_eq!(level[total - 48_000], 1.0);
+        assert!(level[4_800..total - 48_000].iter().all(|&l| l == 1.0));
+    }
+
+    #[test]
+    fn attack_and_release_must_fit_the_tone() {
+        let config = Config {
+            duration: 2,
+            attack_duration: Some(0.5),
+            release_duration: Some(1.6),
+            ..Config::default()
+        };
+        let errors = config.validate().unwrap_err();
+        assert!(errors.iter().any(|e| e.contains("must fit within duration")), "{:?}", errors);
+    }
+
+    #[test]
+    fn past_the_end_is_silent_and_signals_done_once() {
+        let params = params(&Config::default(), 2);
+        let clock = AtomicU64::new(params.total_samples - 10);
+        let total = AtomicU64::new(params.total_samples);
+        let finished = AtomicBool::new(false);
+        let (done, signal) = mpsc::channel();
+
+        let mut data = vec![1.0; 64 * 2];
+        let frames = write_samples(&mut data, 2, &clock, &total, &params, &finished, &done);
+        assert_eq!(frames, 10);
+        assert!(data[20..].iter().all(|&s| s == 0.0));
+        assert!(finished.load(Ordering::Relaxed));
+
+        let mut data = vec![1.0; 64 * 2];
+        assert_eq!(write_samples(&mut data, 2, &clock, &total, &params, &finished, &done), 0);
+        assert!(data.iter().all(|&s| s == 0.0));
+        assert_eq!(signal.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn next_tone_continues_the_oscillator_phase() {
+        let config = Config {
+            duration: 1,
+            fade_duration: 0.0,
+            limiter: Limiter::Off,
+            ..Config::default()
+        };
+        let mut params = params(&config, 1);
+        let sample = |params: &ToneParams, n: u64| {
+            let mut frame = [0.0];
+            generate_frame(&mut frame, n, params.total_samples, params);
+            frame[0]
+        };
+        // Stop part way through a cycle, where the sine is far from zero
+        let stop = params.total_samples / 3 + 7;
+        let last = sample(&params, stop - 1);
+        let free_running = sample(&params, stop);
+
+        params.continue_phase(stop);
+        let first = sample(&params, 0);
+        assert!(last.abs() > 0.01, "stopped at {}", last);
+        assert!((first - free_running).abs() < 1e-6, "{} after {}", first, last);
+        for burst in 1..4 {
+            let before = sample(&params, stop - 1);
+            params.continue_phase(stop);
+            let after = sample(&params, 0);
+            let step = std::f64::consts::TAU * config.frequency / RATE * config.volume;
+            assert!(((after - before).abs() as f64) < step * 1.01, "jump at burst {}", burst);
+        }
+    }
+
+    #[test]
+    fn completion_is_signaled_after_exactly_the_tone() {
+        let config = Config {
+            duration: 2,
+            ..Config::default()
+        };
+        let params = params(&config, 2);
+        let clock = AtomicU64::new(0);
+        let total = AtomicU64::new(params.total_samples);
+        let finished = AtomicBool::new(false);
+        let (done, signal) = mpsc::channel();
+
+        let mut data = vec![0.0; BUFFER_FRAMES as usize * 2];
+        let mut frames = 0;
+        while signal.try_recv().is_err() {
+            frames += write_samples(&mut data, 2, &clock, &total, &params, &finished, &done);
+            assert!(frames <= params.total_samples as usize, "ran past the tone");
+        }
+        let played = frames as f64 / RATE;
+        assert!((played - config.duration as f64).abs() < 0.005, "played {}s", played);
+    }
+
+    #[test]
+    fn zero_duration_plays_one_short_burst() {
+        let config = Config {
+            duration: 0,
+            ..Config::default()
+        };
+        let params = params(&config, 2);
+        assert_eq!(params.total_samples, BUFFER_FRAMES as u64);
+        let clock = AtomicU64::new(0);
+        let total = AtomicU64::new(params.total_samples);
+        let finished = AtomicBool::new(false);
+        let (done, signal) = mpsc::channel();
+
+        let mut data = vec![0.0; 1024 * 2];
+        let mut buffers = Vec::new();
+        while signal.try_recv().is_err() {
+            assert!(buffers.len() < 10, "burst never finished");
+            write_samples(&mut data, 2, &clock, &total, &params, &finished, &done);
+            buffers.push(data.clone());
+        }
+        assert!(buffers[0].iter().any(|s| s.abs() > 0.0));
+        assert!(buffers.last().unwrap().iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn sample_indices_run_on_across_callbacks() {
+        let config = Config {
+            duration: 1,
+            ..Config::default()
+        };
+        let params = params(&config, 2);
+        let clock = AtomicU64::new(0);
+        let total = AtomicU64::new(params.total_samples);
+        let finished = AtomicBool::new(false);
+        let (done, _signal) = mpsc::channel();
+
+        // Uneven buffer sizes, as backends hand out
+        let mut streamed = Vec::new();
+        for frames in [100, 37, 512, 1, 4096].iter().cycle() {
+            if clock.load(Ordering::Relaxed) >= params.total_samples {
+                break;
+            }
+            let mut data = vec![0.0; frames * 2];
+            write_samples(&mut data, 2, &clock, &total, &params, &finished, &done);
+            streamed.extend_from_slice(&data);
+            assert_eq!(clock.load(Ordering::Relaxed) as usize, streamed.len() / 2);
+        }
+        let expected = render(&params, 2);
+        assert_eq!(streamed[..expected.len()], expected[..]);
+    }
+
+    #[test]
+    fn opposite_stereo_phase_inverts_the_right_channel() {
+        let config = Config {
+            stereo_phase: 180.0,
+            ..Config::default()
+        };
+        let data = render(&params(&config, 2), 2);
+        assert!(data.iter().any(|s| s.abs() > 0.01));
+        for frame in data.chunks(2) {
+            assert!((frame[0] + frame[1]).abs() < 1e-5, "{:?}", frame);
+        }
+    }
+
+    #[test]
+    fn antiphase_negates_odd_channels() {
+        let config = Config {
+            antiphase: true,
+            ..Config::default()
+        };
+        let data = render(&params(&config, 4), 4);
+        assert!(data.iter().any(|s| s.abs() > 0.01));
+        for frame in data.chunks(4) {
+            assert_eq!(frame[1], -frame[0]);
+            assert_eq!(frame[3], -frame[2]);
+        }
+    }
+
+    #[test]
+    fn modulation_swings_between_one_minus_depth_and_full() {
+        let config = Config {
+            frequency: 1000.0,
+            duration: 2,
+            fade_duration: 0.0,
+            volume: 1.0,
+            limiter: Limiter::Off,
+            modulation: crate::config::Modulation {
+                mod_rate: 1.0,
+                mod_depth: 0.8,
+            },
+            ..Config::default()
+        };
+        let data = render(&params(&config, 1), 1);
+        // Peak of each carrier cycle traces the envelope
+        let cycle = (RATE / config.frequency) as usize;
+        let peaks: Vec<f32> = data
+            .chunks(cycle)
+            .map(|c| c.iter().fold(0.0, |peak: f32, s| peak.max(s.abs())))
+            .collect();
+        let lowest = peaks.iter().copied().fold(f32::MAX, f32::min);
+        let highest = peaks.iter().copied().fold(0.0, f32::max);
+        assert!((lowest - 0.2).abs() < 0.01, "dips to {}", lowest);
+        assert!((highest - 1.0).abs() < 0.01, "peaks at {}", highest);
+    }
+
+    #[test]
+    fn looped_clip_has_no_jump_at_the_seam() {
+        // 100 Hz for just over two cycles, so a plain loop would jump
+        let natural = std::f64::consts::TAU * 100.0 / RATE;
+        let mut clip = Clip {
+            samples: (0..1000).map(|i| (natural * i as f64).sin() as f32).collect(),
+            channels: 1,
+            frames: 1000,
+        };
+        let seam = (clip.sample(999, 0) - clip.sample(1000, 0)).abs();
+        assert!(seam > 10.0 * natural, "test clip should jump at the seam ({})", seam);
+
+        clip.crossfade_loop((LOOP_CROSSFADE * RATE) as usize);
+        let looped: Vec<f64> = (0..3 * clip.frames as u64).map(|n| clip.sample(n, 0)).collect();
+        for (n, pair) in looped.windows(2).enumerate() {
+            let step = (pair[1] - pair[0]).abs();
+            assert!(step <= 2.0 * natural, "jump of {} at frame {}", step, n);
+        }
+    }
+
+    #[test]
+    fn only_config_errors_retry_with_the_default_buffer() {
+        use cpal::BuildStreamError;
+
+        let backend = BuildStreamError::BackendSpecific {
+            err: cpal::BackendSpecificError {
+                description: "snd_pcm_hw_params failed".to_string(),
+            },
+        };
+        assert!(fixed_buffer_rejected(&BuildStreamError::StreamConfigNotSupported));
+        assert!(fixed_buffer_rejected(&BuildStreamError::InvalidArgument));
+        assert!(fixed_buffer_rejected(&backend));
+        assert!(!fixed_buffer_rejected(&BuildStreamError::DeviceNotAvailable));
+        assert!(!fixed_buffer_rejected(&BuildStreamError::StreamIdOverflow));
+    }
+
+    #[test]
+    fn limiter_keeps_an_over_unity_signal_in_range() {
+        // Three harmonics summed at full level reach well past full scale
+        let summed = |i: usize| {
+            let phase = i as f64 * 0.01;
+            3.0 * (phase.sin() + (2.0 * phase).sin() + (3.0 * phase).sin()) / 2.0
+        };
+        assert!((0..1000).any(|i| summed(i).abs() > 2.0));
+        for limiter in [Limiter::Clamp, Limiter::Soft] {
+            for i in 0..1000 {
+                let out = limit(limiter, summed(i));
+                assert!(out.abs() <= 1.0, "{:?} gave {}", limiter, out);
+                assert_eq!(out.signum(), summed(i).signum());
+            }
+        }
+        assert_eq!(limit(Limiter::Off, 1.5), 1.5);
+    }
+
+    #[test]
+    fn soft_limiter_leaves_signals_below_the_knee_alone() {
+        for sample in [-0.9, -0.5, 0.0, 0.3, 0.9] {
+            assert_eq!(limit(Limiter::Soft, sample), sample);
+        }
+    }
+
+    /// Which of a 6-channel device's channels carry any signal
+    fn active_channels(config: &Config) -> Vec<bool> {
+        let data = render(&params(config, 6), 6);
+        (0..6).map(|ch| data.iter().skip(ch).step_by(6).any(|s| s.abs() > 0.0)).collect()
+    }
+
+    #[test]
+    fn channel_mode_picks_the_channels_that_play() {
+        use crate::config::ChannelMode;
+
+        for (mode, active) in [
+            (ChannelMode::Duplicate, [true; 6]),
+            (ChannelMode::FrontOnly, [true, true, false, false, false, false]),
+            (ChannelMode::Single, [true, false, false, false, false, false]),
+        ] {
+            let config = Config {
+                channel_mode: mode,
+                duration: 1,
+                ..Config::default()
+            };
+            assert_eq!(active_channels(&config), active, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn duplicated_channels_carry_the_same_tone() {
+        let data = render(&params(&Config::default(), 6), 6);
+        for frame in data.chunks(6) {
+            assert!(frame.iter().all(|&s| s == frame[0]), "{:?}", frame);
+        }
+    }
+
+    fn range(
+        channels: u16,
+        min: u32,
+        max: u32,
+        format: SampleFormat,
+    ) -> cpal::SupportedStreamConfigRange {
+        cpal::SupportedStreamConfigRange::new(
+            channels,
+            min,
+            max,
+            cpal::SupportedBufferSize::Unknown,
+            format,
+        )
+    }
+
+    fn stereo_48k(format: SampleFormat) -> cpal::SupportedStreamConfig {
+        cpal::SupportedStreamConfig::new(2, 48_000, cpal::SupportedBufferSize::Unknown, format)
+    }
+
+    #[test]
+    fn wanted_format_keeps_the_default_rate_and_channels() {
+        let ranges = [
+            range(8, 44_100, 192_000, SampleFormat::F32),
+            range(2, 44_100, 192_000, SampleFormat::F32),
+            range(2, 44_100, 192_000, SampleFormat::I16),
+        ];
+        let default = stereo_48k(SampleFormat::I16);
+        let picked = pick_format(&default, &ranges, SampleFormat::F32).unwrap();
+        assert_eq!(picked.sample_format(), SampleFormat::F32);
+        assert_eq!((picked.channels(), picked.sample_rate()), (2, 48_000));
+
+        assert_eq!(pick_format(&default, &ranges, SampleFormat::I16), Some(default));
+    }
+
+    #[test]
+    fn wanted_format_falls_back_to_the_highest_rate() {
+        let ranges = [
+            range(2, 8_000, 22_050, SampleFormat::I32),
+            range(2, 8_000, 32_000, SampleFormat::I32),
+        ];
+        let default = stereo_48k(SampleFormat::F32);
+        let picked = pick_format(&default, &ranges, SampleFormat::I32).unwrap();
+        assert_eq!(picked.sample_rate(), 32_000);
+        assert_eq!(pick_format(&default, &ranges, SampleFormat::I16), None);
+    }
+
+    #[test]
+    fn wanted_channels_keep_the_format_and_rate() {
+        let ranges = [
+            range(6, 44_100, 96_000, SampleFormat::I16),
+            range(6, 44_100, 96_000, SampleFormat::F32),
+            range(2, 44_100, 96_000, SampleFormat::F32),
+        ];
+        let default = stereo_48k(SampleFormat::F32);
+        let picked = pick_channels(&default, &ranges, 6).unwrap();
+        assert_eq!(picked.sample_format(), SampleFormat::F32);
+        assert_eq!((picked.channels(), picked.sample_rate()), (6, 48_000));
+
+        assert_eq!(pick_channels(&default, &ranges, 2), Some(default));
+    }
+
+    #[test]
+    fn wanted_channels_fall_back_to_the_highest_rate() {
+        let ranges = [
+            range(1, 8_000, 22_050, SampleFormat::F32),
+            range(1, 8_000, 32_000, SampleFormat::F32),
+            range(1, 8_000, 44_100, SampleFormat::I16),
+        ];
+        let picked = pick_channels(&stereo_48k(SampleFormat::F32), &ranges, 1).unwrap();
+        assert_eq!(picked.sample_format(), SampleFormat::F32);
+        assert_eq!((picked.channels(), picked.sample_rate()), (1, 32_000));
+    }
+
+    #[test]
+    fn wanted_channels_not_on_offer_are_none() {
+        let ranges = [
+            range(2, 44_100, 96_000, SampleFormat::F32),
+            range(8, 44_100, 96_000, SampleFormat::I16),
+        ];
+        assert_eq!(pick_channels(&stereo_48k(SampleFormat::F32), &ranges, 8), None);
+        assert_eq!(pick_channels(&stereo_48k(SampleFormat::F32), &ranges, 4), None);
+    }
+
+    #[cfg(feature = "mock-audio")]
+    #[test]
+    fn unsupported_channel_count_is_an_error() {
+        let device = mock::host().default_output_device().unwrap();
+        let e = output_config(&device, OutputFormat::Auto, Some(6)).unwrap_err().to_string();
+        assert!(e.contains("doesn't support 6 channels"), "{}", e);
+
+        let config = output_config(&device, OutputFormat::Auto, Some(mock::CHANNELS)).unwrap();
+        assert_eq!(config.channels(), mock::CHANNELS);
+    }
+
+    #[test]
+    fn frequencies_must_be_below_nyquist() {
+        assert!(check_nyquist(23_999.9, 48_000.0, "Speakers").is_ok());
+        let e = check_nyquist(24_000.0, 48_000.0, "Speakers").unwrap_err().to_string();
+        assert!(e.contains("'Speakers' at 48000 Hz"), "{}", e);
+        assert!(e.contains("below 24000 Hz"), "{}", e);
+        assert!(check_nyquist(30_000.0, 44_100.0, "Speakers").is_err());
+    }
+
+    #[test]
+    fn highest_frequency_counts_rotation_jitter_and_harmonics() {
+        let config = Config {
+            frequency: 20.0,
+            frequency_rotation: vec![18.0, 25.0],
+            frequency_jitter: 2.0,
+            comb_count: 3,
+            ..Config::default()
+        };
+        assert_eq!(highest_frequency(&config), 81.0);
+        let capped = Config {
+            max_frequency: 22.0,
+            ..config
+        };
+        assert_eq!(highest_frequency(&capped), 66.0);
+    }
+
+    #[test]
+    fn sub_hertz_tones_complete_their_cycles() {
+        let config = Config {
+            frequency: 0.5,
+            duration: 4,
+            fade_duration: 0.0,
+            volume: 1.0,
+            ..Config::default()
+        };
+        let data = render(&params(&config, 1), 1);
+        let crossings = data.windows(2).filter(|w| (w[0] < 0.0) != (w[1] < 0.0)).count();
+        assert!((3..=4).contains(&crossings), "{} zero crossings", crossings);
+        let peak = data.iter().fold(0.0, |peak: f32, s| peak.max(s.abs()));
+        assert!((peak - 1.0).abs() < 1e-4, "peaks at {}", peak);
+    }
+
+    #[test]
+    fn pulse_is_a_short_peak_then_a_shallow_balancing_dip() {
+        let at = |pos: f64| pulse(pos * std::f64::consts::TAU);
+        assert!(at(0.0).abs() < 1e-12);
+        assert!((at(PULSE_WIDTH / 2.0) - 1.0).abs() < 1e-12);
+        assert!(at(PULSE_WIDTH).abs() < 1e-12);
+        let depth = PULSE_WIDTH / (1.0 - PULSE_WIDTH);
+        assert!((at((1.0 + PULSE_WIDTH) / 2.0) + depth).abs() < 1e-12);
+
+        // No DC: each cycle averages to zero
+        let steps = 100_000;
+        let mean = (0..steps).map(|i| at(i as f64 / steps as f64)).sum::<f64>() / steps as f64;
+        assert!(mean.abs() < 1e-6, "averages {}", mean);
+    }
+
+    /// Amplitude of the `frequency` component of a mono signal
+    fn amplitude_at(data: &[f32], frequency: f64) -> f64 {
+        let step = std::f64::consts::TAU * frequency / RATE;
+        let (re, im) = data.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, &s)| {
+            let phase = step * n as f64;
+            (re + s as f64 * phase.cos(), im - s as f64 * phase.sin())
+        });
+        2.0 * (re * re + im * im).sqrt() / data.len() as f64
+    }
+
+    #[test]
+    fn comb_sums_equal_harmonics_within_full_scale() {
+        let config = Config {
+            frequency: 20.0,
+            comb_count: 4,
+            duration: 1,
+            fade_duration: 0.0,
+            volume: 1.0,
+            limiter: Limiter::Off,
+            ..Config::default()
+        };
+        let data = render(&params(&config, 1), 1);
+        assert!(data.iter().all(|s| s.abs() <= 1.0));
+        for partial in [20.0, 40.0, 60.0, 80.0] {
+            let amplitude = amplitude_at(&data, partial);
+            assert!((amplitude - 0.25).abs() < 0.01, "{} Hz at {}", partial, amplitude);
+        }
+        for between in [30.0, 100.0] {
+            assert!(amplitude_at(&data, between) < 0.01, "{} Hz present", between);
+        }
+    }
+
+    #[test]
+    fn only_integer_formats_are_dithered() {
+        for format in [SampleFormat::I16, SampleFormat::U16, SampleFormat::I32, SampleFormat::U8] {
+            assert!(quantization_step(format).is_some(), "{:?}", format);
+        }
+        for format in [SampleFormat::F32, SampleFormat::F64] {
+            assert_eq!(quantization_step(format), None, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn dither_stays_within_one_lsb() {
+        let step = quantization_step(SampleFormat::I16).unwrap();
+        let tone: Vec<f32> = (0..10_000).map(|n| (n as f32 * 0.01).sin() * 0.001).collect();
+        let mut dithered = tone.clone();
+        dither(&mut dithered, step, &mut Rng::new(7));
+        let mut moved = 0;
+        for (before, after) in tone.iter().zip(&dithered) {
+            assert!((after - before).abs() <= step, "{} became {}", before, after);
+            moved += (after != before) as usize;
+        }
+        assert!(moved > 9_000, "only {} samples dithered", moved);
+    }
+
+    fn white_noise(frames: usize) -> Vec<f32> {
+        let mut rng = Rng::new(7);
+        (0..frames).map(|_| rng.symmetric(1.0) as f32).collect()
+    }
+
+    fn power(samples: &[f32]) -> f64 {
+        samples.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / samples.len() as f64
+    }
+
+    #[test]
+    fn lowpass_removes_the_high_frequencies_of_white_noise() {
+        let config = Config {
+            lowpass_hz: 40.0,
+            ..Config::default()
+        };
+        let noise = white_noise(RATE as usize);
+        let mut filtered = noise.clone();
+        Filter::new(&config, RATE, 1).unwrap().unwrap().process(&mut filtered);
+
+        // A 40 Hz low-pass keeps well under 1% of noise spread up to 24 kHz
+        assert!(power(&filtered) < power(&noise) / 100.0);
+        assert!(amplitude_at(&filtered, 2000.0) < amplitude_at(&noise, 2000.0) / 100.0);
+        let kept = amplitude_at(&filtered, 10.0) / amplitude_at(&noise, 10.0);
+        assert!((0.8..1.2).contains(&kept), "{} of 10 Hz kept", kept);
+    }
+
+    #[test]
+    fn highpass_removes_the_lowest_frequencies() {
+        let config = Config {
+            highpass_hz: 100.0,
+            ..Config::default()
+        };
+        let mut filter = Filter::new(&config, RATE, 1).unwrap().unwrap();
+        let tone = |frequency: f64| -> Vec<f32> {
+            let step = std::f64::consts::TAU * frequency / RATE;
+            (0..RATE as usize).map(|n| (step * n as f64).sin() as f32).collect()
+        };
+        let mut low = tone(5.0);
+        filter.process(&mut low);
+        assert!(power(&low[4800..]) < power(&tone(5.0)) / 100.0);
+        let mut high = tone(2000.0);
+        filter.process(&mut high);
+        assert!(power(&high[4800..]) > power(&tone(2000.0)) * 0.9);
+    }
+
+    #[test]
+    fn filter_runs_on_across_buffers() {
+        let config = Config {
+            highpass_hz: 10.0,
+            lowpass_hz: 40.0,
+            ..Config::default()
+        };
+        let filter = Filter::new(&config, RATE, 2).unwrap().unwrap();
+        let noise = white_noise(2 * 4096);
+        let mut whole = noise.clone();
+        filter.clone().process(&mut whole);
+        let mut split = noise;
+        let mut streaming = filter;
+        for buffer in split.chunks_mut(2 * 300) {
+            streaming.process(buffer);
+        }
+        assert_eq!(whole, split);
+    }
+
+    /// Output sample for full scale down, silence, half scale and full
+    /// scale up, as `build_stream` converts them
+    fn scaled<T: SizedSample + FromSample<f32>>() -> [T; 4] {
+        [-1.0, 0.0, 0.5, 1.0].map(T::from_sample)
+    }
+
+    #[test]
+    fn float_samples_scale_to_every_integer_width() {
+        assert_eq!(scaled::<i8>(), [i8::MIN, 0, 64, i8::MAX]);
+        assert_eq!(scaled::<i16>(), [i16::MIN, 0, 16_384, i16::MAX]);
+        assert_eq!(scaled::<i32>(), [i32::MIN, 0, 1 << 30, i32::MAX]);
+        assert_eq!(scaled::<u8>(), [0, 128, 192, u8::MAX]);
+        assert_eq!(scaled::<u16>(), [0, 32_768, 49_152, u16::MAX]);
+        assert_eq!(scaled::<u32>(), [0, 1 << 31, 3 << 30, u32::MAX]);
+        assert_eq!(scaled::<f64>(), [-1.0, 0.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn quantization_step_is_one_lsb_of_each_width() {
+        assert_eq!(quantization_step(SampleFormat::I8), Some(1.0 / 128.0));
+        assert_eq!(quantization_step(SampleFormat::U16), Some(1.0 / 32_768.0));
+        assert_eq!(quantization_step(SampleFormat::I32), Some(2.0f32.powi(-31)));
+        assert_eq!(quantization_step(SampleFormat::F32), None);
+    }
+
+    fn with_gains(gains: &[f64]) -> Config {
+        Config {
+            duration: 1,
+            channel_gains: gains.to_vec(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn channel_gains_are_padded_with_unity() {
+        assert_eq!(channel_gains(&with_gains(&[0.5, 2.0]), 4), [0.5, 2.0, 1.0, 1.0]);
+        assert_eq!(channel_gains(&with_gains(&[]), 2), [1.0, 1.0]);
+    }
+
+    #[test]
+    fn channel_gains_beyond_the_device_are_dropped() {
+        assert_eq!(channel_gains(&with_gains(&[0.5, 2.0, 0.25, 3.0]), 2), [0.5, 2.0]);
+    }
+
+    #[test]
+    fn channel_gains_scale_each_channel() {
+        let peak = |data: &[f32], ch: usize| {
+            data.iter().skip(ch).step_by(3).fold(0.0, |peak: f32, s| peak.max(s.abs()))
+        };
+        let plain = render(&params(&with_gains(&[]), 3), 3);
+        let scaled = render(&params(&with_gains(&[0.5, 0.0]), 3), 3);
+        let unity = peak(&plain, 0);
+        assert!(unity > 0.0);
+        assert!((peak(&scaled, 0) - unity * 0.5).abs() < 1e-4);
+        assert_eq!(peak(&scaled, 1), 0.0);
+        // Past the end of the list, the channel plays as usual
+        assert!((peak(&scaled, 2) - unity).abs() < 1e-6);
+    }
+
+    fn noise_config(decorrelate: bool) -> Config {
+        Config {
+            waveform: Waveform::Noise,
+            duration: 1,
+            volume: 1.0,
+            decorrelate,
+            ..Config::default()
+        }
+    }
+
+    /// Correlation of channels `a` and `b`, from -1 to 1
+    fn correlation(data: &[f32], channels: usize, a: usize, b: usize) -> f64 {
+        let frames = data.chunks(channels);
+        let (mut ab, mut aa, mut bb) = (0.0, 0.0, 0.0);
+        for frame in frames {
+            let (x, y) = (frame[a] as f64, frame[b] as f64);
+            ab += x * y;
+            aa += x * x;
+            bb += y * y;
+        }
+        ab / (aa * bb).sqrt()
+    }
+
+    #[test]
+    fn noise_is_white_and_within_full_scale() {
+        let samples: Vec<f64> = (0..100_000).map(|n| noise(1, n)).collect();
+        assert!(samples.iter().all(|s| (-1.0..1.0).contains(s)));
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        assert!(mean.abs() < 0.01, "{}", mean);
+        // Uniform over ±1, so a mean square of 1/3
+        let power = samples.iter().map(|s| s * s).sum::<f64>() / samples.len() as f64;
+        assert!((power - 1.0 / 3.0).abs() < 0.01, "{}", power);
+        // Each sample says nothing about the next
+        let lag = samples.windows(2).map(|w| w[0] * w[1]).sum::<f64>() / samples.len() as f64;
+        assert!((lag / power).abs() < 0.01, "{}", lag / power);
+    }
+
+    #[test]
+    fn decorrelated_noise_differs_between_channels() {
+        let data = render(&params(&noise_config(true), 2), 2);
+        let differing = data.chunks(2).filter(|frame| frame[0] != frame[1]).count();
+        assert!(differing > data.len() / 2 - 10, "{} of {}", differing, data.len() / 2);
+        assert!(correlation(&data, 2, 0, 1).abs() < 0.05);
+    }
 
-        for s in frame.iter_mut() {
-            *s = sample;
+    #[test]
+    fn noise_is_the_same_on_every_channel_unless_decorrelated() {
+        let data = render(&params(&noise_config(false), 4), 4);
+        for frame in data.chunks(4) {
+            assert!(frame.iter().all(|&s| s == frame[0]), "{:?}", frame);
         }
+        // In mono, decorrelation changes nothing
+        let mono = render(&params(&noise_config(true), 1), 1);
+        assert_eq!(mono, render(&params(&noise_config(false), 1), 1));
+        assert!(mono.iter().any(|&s| s != 0.0));
     }
 }
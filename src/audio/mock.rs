@@ -0,0 +1,257 @@
+//! In-memory audio backend (`mock-audio` feature). Every host resolves to
+//! one fake output device whose streams run the tone generator faster than
+//! real time and keep what it wrote, so the daemon and tone generation can
+//! be exercised where there's no audio hardware, e.g. in CI.
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{
+    BuildStreamError, Data, DefaultStreamConfigError, DeviceDescription, DeviceDescriptionBuilder,
+    DeviceId, DeviceIdError, DeviceNameError, DevicesError, InputCallbackInfo, OutputCallbackInfo,
+    OutputStreamTimestamp, PauseStreamError, PlayStreamError, SampleFormat, StreamConfig,
+    StreamError, StreamInstant, SupportedBufferSize, SupportedStreamConfig,
+    SupportedStreamConfigRange, SupportedStreamConfigsError,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Name of the one mock output device
+pub const DEVICE_NAME: &str = "Mock Output";
+
+/// The mock device's only stream format: stereo f32 at 48 kHz
+pub const SAMPLE_RATE: u32 = 48_000;
+pub const CHANNELS: u16 = 2;
+
+/// Frames per callback when the stream doesn't ask for a buffer size
+const DEFAULT_BUFFER_FRAMES: usize = 1024;
+
+/// Wait between callbacks. Far shorter than the audio in each buffer, so
+/// a 15 s tone finishes in a fraction of a second.
+const CALLBACK_SPACING: Duration = Duration::from_millis(1);
+
+/// What one stream on the mock device wrote
+#[derive(Debug, Clone, Default)]
+pub struct Take {
+    pub sample_rate: f64,
+    pub channels: usize,
+    /// Interleaved samples of every buffer with any sound in it
+    pub samples: Vec<f32>,
+    /// Frames of buffers that were pure silence, which aren't kept, e.g.
+    /// a persistent stream waiting for its next tone
+    pub silent_frames: u64,
+}
+
+/// Every stream opened since the last `takes`, in order
+static TAKES: Mutex<Vec<Arc<Mutex<Take>>>> = Mutex::new(Vec::new());
+
+/// What each stream opened since the last call wrote so far, oldest first,
+/// forgetting them. A stream still playing keeps writing to its own take,
+/// which is no longer returned.
+pub fn takes() -> Vec<Take> {
+    std::mem::take(&mut *TAKES.lock().unwrap())
+        .iter()
+        .map(|take| take.lock().unwrap().clone())
+        .collect()
+}
+
+/// The mock host, standing in for whichever one is configured
+pub(super) fn host() -> cpal::Host {
+    cpal::Host::from(cpal::platform::CustomHost::from_host(MockHost))
+}
+
+#[derive(Clone)]
+struct MockHost;
+
+#[derive(Clone)]
+struct MockDevice;
+
+impl HostTrait for MockHost {
+    type Device = MockDevice;
+    type Devices = std::iter::Once<MockDevice>;
+
+    fn is_available() -> bool {
+        true
+    }
+
+    fn devices(&self) -> Result<Self::Devices, DevicesError> {
+        Ok(std::iter::once(MockDevice))
+    }
+
+    fn default_input_device(&self) -> Option<Self::Device> {
+        None
+    }
+
+    fn default_output_device(&self) -> Option<Self::Device> {
+        Some(MockDevice)
+    }
+}
+
+impl DeviceTrait for MockDevice {
+    type SupportedInputConfigs = std::iter::Empty<SupportedStreamConfigRange>;
+    type SupportedOutputConfigs = std::iter::Once<SupportedStreamConfigRange>;
+    type Stream = MockStream;
+
+    fn name(&self) -> Result<String, DeviceNameError> {
+        Ok(DEVICE_NAME.to_string())
+    }
+
+    fn description(&self) -> Result<DeviceDescription, DeviceNameError> {
+        Ok(DeviceDescriptionBuilder::new(DEVICE_NAME.to_string()).build())
+    }
+
+    fn id(&self) -> Result<DeviceId, DeviceIdError> {
+        Err(DeviceIdError::UnsupportedPlatform)
+    }
+
+    fn supports_input(&self) -> bool {
+        false
+    }
+
+    fn supported_input_configs(
+        &self,
+    ) -> Result<Self::SupportedInputConfigs, SupportedStreamConfigsError> {
+        Ok(std::iter::empty())
+    }
+
+    fn supported_output_configs(
+        &self,
+    ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError> {
+        Ok(std::iter::once(SupportedStreamConfigRange::new(
+            CHANNELS,
+            SAMPLE_RATE,
+            SAMPLE_RATE,
+            SupportedBufferSize::Unknown,
+            SampleFormat::F32,
+        )))
+    }
+
+    fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        Err(DefaultStreamConfigError::StreamTypeNotSupported)
+    }
+
+    fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        Ok(SupportedStreamConfig::new(
+            CHANNELS,
+            SAMPLE_RATE,
+            SupportedBufferSize::Unknown,
+            SampleFormat::F32,
+        ))
+    }
+
+    fn build_input_stream_raw<D, E>(
+        &self,
+        _config: &StreamConfig,
+        _format: SampleFormat,
+        _data_callback: D,
+        _error_callback: E,
+        _timeout: Option<Duration>,
+    ) -> Result<Self::Stream, BuildStreamError>
+    where
+        D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        Err(BuildStreamError::StreamConfigNotSupported)
+    }
+
+    fn build_output_stream_raw<D, E>(
+        &self,
+        config: &StreamConfig,
+        format: SampleFormat,
+        mut data_callback: D,
+        _error_callback: E,
+        _timeout: Option<Duration>,
+    ) -> Result<Self::Stream, BuildStreamError>
+    where
+        D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        if format != SampleFormat::F32
+            || config.channels != CHANNELS
+            || config.sample_rate != SAMPLE_RATE
+        {
+            return Err(BuildStreamError::StreamConfigNotSupported);
+        }
+        let frames = match config.buffer_size {
+            cpal::BufferSize::Fixed(frames) => frames as usize,
+            cpal::BufferSize::Default => DEFAULT_BUFFER_FRAMES,
+        };
+        let channels = CHANNELS as usize;
+        let take = Arc::new(Mutex::new(Take {
+            sample_rate: SAMPLE_RATE as f64,
+            channels,
+            ..Take::default()
+        }));
+        TAKES.lock().unwrap().push(take.clone());
+
+        let playing = Arc::new(AtomicBool::new(false));
+        let closed = Arc::new(AtomicBool::new(false));
+        let (thread_playing, thread_closed) = (playing.clone(), closed.clone());
+        let thread = std::thread::spawn(move || {
+            let mut buffer = vec![0.0_f32; frames * channels];
+            let mut written = 0u64;
+            while !thread_closed.load(Ordering::Relaxed) {
+                std::thread::sleep(CALLBACK_SPACING);
+                if !thread_playing.load(Ordering::Relaxed) {
+                    continue;
+                }
+                buffer.fill(0.0);
+                // SAFETY: the pointer and length describe `buffer`, which
+                // outlives `data`
+                let mut data = unsafe {
+                    Data::from_parts(buffer.as_mut_ptr().cast(), buffer.len(), SampleFormat::F32)
+                };
+                // Stream time as if played in real time
+                let at = Duration::from_secs_f64(written as f64 / SAMPLE_RATE as f64);
+                let instant = StreamInstant::new(at.as_secs() as i64, at.subsec_nanos());
+                let timestamp = OutputStreamTimestamp {
+                    callback: instant,
+                    playback: instant,
+                };
+                data_callback(&mut data, &OutputCallbackInfo::new(timestamp));
+                written += frames as u64;
+
+                let mut take = take.lock().unwrap();
+                if buffer.iter().all(|&sample| sample == 0.0) {
+                    take.silent_frames += frames as u64;
+                } else {
+                    take.samples.extend_from_slice(&buffer);
+                }
+            }
+        });
+        Ok(MockStream {
+            playing,
+            closed,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// A stream on the mock device, calling back from its own thread while
+/// playing
+struct MockStream {
+    playing: Arc<AtomicBool>,
+    closed: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StreamTrait for MockStream {
+    fn play(&self) -> Result<(), PlayStreamError> {
+        self.playing.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn pause(&self) -> Result<(), PauseStreamError> {
+        self.playing.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for MockStream {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::io::Write;
+
+use crate::audio;
+use crate::config::Config;
+use crate::verify;
+use crate::wizard;
+
+/// Quietest volume tried (-60 dBFS)
+const START_VOLUME: f64 = 0.001;
+
+/// How much louder each step is than the last, in dB
+const STEP_DB: f64 = 3.0;
+
+/// Headroom added to the quietest volume that worked, so a slightly less
+/// sensitive moment (or a noisier room) still wakes the amp
+const MARGIN_DB: f64 = 3.0;
+
+/// Play the tone at rising volumes, from -60 dBFS up to `max_volume`, until
+/// it is detected, and print the `volume` to use. Detection records
+/// `input_device` like `verify` does; with `interactive`, or if recording
+/// isn't possible, it asks instead. Returns false if no volume worked.
+pub fn run(config: &Config, threshold: f64, max_volume: f64, mut interactive: bool) -> bool {
+    if !interactive && !config.wav_path.is_empty() {
+        log::error!(
+            "calibrate listens for the sine tone's frequency; use --interactive with `wav_path`"
+        );
+        return false;
+    }
+
+    let volumes = steps(max_volume);
+    println!(
+        "Playing {} tones from {} to {} dBFS, stopping at the first that is {}",
+        volumes.len(),
+        audio::dbfs(volumes[0]),
+        audio::dbfs(max_volume),
+        if interactive { "noticed" } else { "detected" }
+    );
+
+    let mut i = 0;
+    while i < volumes.len() {
+        let volume = volumes[i];
+        let tone = Config {
+            volume,
+            ..config.clone()
+        };
+        print!("{:.2}% ({} dBFS): ", volume * 100.0, audio::dbfs(volume));
+        let _ = std::io::stdout().flush();
+
+        let found = if interactive {
+            println!();
+            if let Err(e) = audio::play_tone(&tone).and_then(|playback| playback.wait()) {
+                log::error!("{}", e);
+                return false;
+            }
+            match wizard::prompt_raw("Did the amp wake up, or could you hear it? [y/N/q]") {
+                Ok(answer) if answer.eq_ignore_ascii_case("q") => return false,
+                Ok(answer) => answer.eq_ignore_ascii_case("y"),
+                Err(e) => {
+                    log::error!("{}", e);
+                    return false;
+                }
+            }
+        } else {
+            match audio::record_tone(&tone) {
+                Ok(recording) => {
+                    let detection = verify::measure(&recording, tone.frequency);
+                    println!(
+                        "{:.1} dBFS recorded ({:.0} dB above nearby frequencies)",
+                        detection.level_db, detection.snr_db
+                    );
+                    detection.detected(threshold)
+                }
+                Err(e) if i == 0 => {
+                    println!();
+                    log::warn!("Can't record ({}), asking instead", e);
+                    interactive = true;
+                    continue;
+                }
+                Err(e) => {
+                    println!();
+                    log::error!("{}", e);
+                    return false;
+                }
+            }
+        };
+
+        if found {
+            let recommended = (volume * 10f64.powf(MARGIN_DB / 20.0)).min(1.0);
+            println!();
+            println!(
+                "Quietest volume that worked: {:.4} ({} dBFS)",
+                volume,
+                audio::dbfs(volume)
+            );
+            println!(
+                "Recommended, with {} dB to spare: volume = {:.4}  # {} dBFS",
+                MARGIN_DB,
+                recommended,
+                audio::dbfs(recommended)
+            );
+            return true;
+        }
+        i += 1;
+    }
+
+    println!();
+    println!(
+        "Not {} even at {} dBFS; check the device and input, or raise --max-volume",
+        if interactive { "noticed" } else { "detected" },
+        audio::dbfs(max_volume)
+    );
+    false
+}
+
+/// Volumes to try, `STEP_DB` apart, ending exactly at `max_volume`
+fn steps(max_volume: f64) -> Vec<f64> {
+    let factor = 10f64.powf(STEP_DB / 20.0);
+    let mut volumes = Vec::new();
+    let mut volume = START_VOLUME.min(max_volume);
+    while volume < max_volume {
+        volumes.push(volume);
+        volume *= factor;
+    }
+    volumes.push(max_volume);
+    volumes
+}
@@ -1,108 +1,2682 @@
-use serde::Deserialize;
-use std::path::PathBuf;
+use chrono::{NaiveTime, Weekday};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
-#[derive(Debug, Deserialize)]
+use crate::paths;
+
+/// Longest accepted `drain_ms`. Buffers on even high-latency backends play
+/// out well within this.
+const MAX_DRAIN_MS: u64 = 5000;
+
+/// Fields that have been renamed, as (old name, new name). Old names are
+/// still read, with a warning, and rewritten by `nodoze migrate`.
+const RENAMED_FIELDS: &[(&str, &str)] = &[
+    // The harmonic comb's first names, before it had a prefix of its own
+    ("fundamental", "comb_fundamental"),
+    ("harmonics", "comb_count"),
+];
+
+/// Most harmonics `comb_count` may sum. Past this each partial is too quiet
+/// to matter and the top ones climb into the audible range.
+const MAX_COMB_COUNT: u32 = 16;
+
+/// Name the project (and its config directory) had before nodoze
+const LEGACY_NAME: &str = "wake-speaker";
+
+/// Project-local config file, looked for in the current directory
+const LOCAL_CONFIG: &str = "nodoze.toml";
+
+/// System-wide config file, for installs shared by every user
+#[cfg(unix)]
+const SYSTEM_CONFIG: &str = "/etc/nodoze/config.toml";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
-    /// Tone frequency in Hz
+    /// Exit with an error when the file can't be parsed or has invalid
+    /// settings, rather than warning and running with the defaults
+    #[serde(default)]
+    pub strict: bool,
+
+    /// Starting point for frequency, volume and duration. Fields set
+    /// explicitly in the file take precedence.
+    #[serde(default)]
+    pub preset: Option<Preset>,
+
+    /// Tone frequency in Hz. The file may give a note name such as "A0"
+    /// instead, which is resolved to Hz on load.
     #[serde(default = "default_frequency")]
     pub frequency: f64,
 
-    /// Duration of each tone in seconds
-    #[serde(default = "default_duration")]
+    /// Pitch of A4 in Hz, which note names in `frequency` are tuned to
+    #[serde(default = "default_a4_frequency")]
+    pub a4_frequency: f64,
+
+    /// Shape of the tone; `frequency` sets the pulse rate for pulses
+    #[serde(default)]
+    pub waveform: Waveform,
+
+    /// Fundamental of the harmonic comb, replacing `frequency` when set
+    /// (0 = `frequency`)
+    #[serde(default)]
+    pub comb_fundamental: f64,
+
+    /// Harmonics summed into each tone: the fundamental and its multiples,
+    /// at equal weight (0 or 1 = a single frequency)
+    #[serde(default)]
+    pub comb_count: u32,
+
+    /// Cutoff of a high-pass filter on the generated signal, in Hz
+    /// (0 = off)
+    #[serde(default)]
+    pub highpass_hz: f64,
+
+    /// Cutoff of a low-pass filter on the generated signal, in Hz, to keep
+    /// anything above it out of hearing range (0 = off)
+    #[serde(default)]
+    pub lowpass_hz: f64,
+
+    /// Random offset of up to ± this many Hz applied to each tone's frequency
+    #[serde(default)]
+    pub frequency_jitter: f64,
+
+    /// Frequencies used in turn for successive tones, wrapping around
+    /// (empty = always `frequency`)
+    #[serde(default)]
+    pub frequency_rotation: Vec<f64>,
+
+    /// Lowest frequency any tone may play at, in Hz; lower ones are raised
+    /// to it (0 = no limit)
+    #[serde(default)]
+    pub min_frequency: f64,
+
+    /// Highest frequency any tone may play at, in Hz; higher ones are
+    /// lowered to it (0 = no limit)
+    #[serde(default)]
+    pub max_frequency: f64,
+
+    /// Duration of each tone in seconds (0 = one short burst)
+    #[serde(default = "default_duration", deserialize_with = "whole_seconds")]
     pub duration: u64,
 
-    /// Interval between tones in seconds
-    #[serde(default = "default_interval")]
+    /// Split each tone into this many shorter bursts (1 = one tone)
+    #[serde(default = "default_burst_count")]
+    pub burst_count: u32,
+
+    /// Silence in seconds between the bursts of a tone
+    #[serde(default = "default_burst_gap", deserialize_with = "seconds")]
+    pub burst_gap: f64,
+
+    /// Interval between tones in seconds (0 = continuous)
+    #[serde(default = "default_interval", deserialize_with = "whole_seconds")]
     pub interval: u64,
 
+    /// Play the tone without gaps instead of periodic bursts
+    #[serde(default)]
+    pub continuous: bool,
+
+    /// Random offset of up to ± this many seconds applied to each interval
+    #[serde(default)]
+    pub interval_jitter: u64,
+
+    /// Lengthen the interval towards `max_interval` while tones keep
+    /// playing on time, and shorten it towards `min_interval` after one
+    /// plays late
+    #[serde(default)]
+    pub adaptive_interval: bool,
+
+    /// Tones on time in a row before an adaptive interval lengthens
+    #[serde(default = "default_adaptive_after")]
+    pub adaptive_after: u32,
+
+    /// Shortest adaptive interval in seconds (0 = `interval`)
+    #[serde(default, deserialize_with = "whole_seconds")]
+    pub min_interval: u64,
+
+    /// Longest adaptive interval in seconds
+    #[serde(default, deserialize_with = "whole_seconds")]
+    pub max_interval: u64,
+
+    /// Seed for the jitter's random numbers, so the same seed gives the
+    /// same sequence of intervals and frequencies (unset = random)
+    #[serde(default)]
+    pub random_seed: Option<u64>,
+
+    /// WAV or FLAC file played instead of the synthesized tone (empty = tone).
+    /// Looped or cut short to `duration`.
+    #[serde(default)]
+    pub wav_path: String,
+
     /// Fade in/out duration in seconds
-    #[serde(default = "default_fade_duration")]
+    #[serde(default = "default_fade_duration", deserialize_with = "seconds")]
     pub fade_duration: f64,
 
-    /// Volume (0.0 to 1.0, where 0.05 = 5%)
-    #[serde(default = "default_volume")]
+    /// Fade-in duration in seconds (unset = `fade_duration`)
+    #[serde(default, deserialize_with = "optional_seconds")]
+    pub attack_duration: Option<f64>,
+
+    /// Fade-out duration in seconds (unset = `fade_duration`)
+    #[serde(default, deserialize_with = "optional_seconds")]
+    pub release_duration: Option<f64>,
+
+    /// Shape of the fade in/out ramp
+    #[serde(default)]
+    pub fade_curve: FadeCurve,
+
+    /// How the signal is kept within full scale before the volume is applied
+    #[serde(default)]
+    pub limiter: Limiter,
+
+    /// Volume (0.0 to 1.0, where 0.05 = 5%). Also accepts percentages like
+    /// "5%" and dBFS strings like "-26dB".
+    #[serde(default = "default_volume", deserialize_with = "volume")]
     pub volume: f64,
 
-    /// Audio output device name (empty = default)
+    /// Volumes used in turn for successive tones, wrapping around
+    /// (empty = always `volume`)
+    #[serde(default, deserialize_with = "volumes")]
+    pub volume_schedule: Vec<f64>,
+
+    /// Phase offset of the right channel relative to the left, in degrees
+    #[serde(default)]
+    pub stereo_phase: f64,
+
+    /// Left/right balance (-1.0 = left only, 0.0 = centered, 1.0 = right only)
+    #[serde(default)]
+    pub pan: f64,
+
+    /// Invert the tone on odd-numbered channels so what leaks audibly from
+    /// the speakers partly cancels in the room
+    #[serde(default)]
+    pub antiphase: bool,
+
+    /// With `waveform = "noise"`, play different noise on each channel.
+    /// The same noise on a pair of subwoofers can cancel acoustically at
+    /// low frequencies.
+    #[serde(default)]
+    pub decorrelate: bool,
+
+    /// Which of the device's channels carry the tone; the rest are silent
+    #[serde(default)]
+    pub channel_mode: ChannelMode,
+
+    /// Speaker layout the device's channels are in, for `channel_target`
+    #[serde(default)]
+    pub channel_layout: ChannelLayout,
+
+    /// Speakers that carry the tone, by name rather than channel number.
+    /// Replaces `channel_mode` when set.
+    #[serde(default)]
+    pub channel_target: Option<ChannelTarget>,
+
+    /// Gain for each channel in turn, applied on top of `volume`, e.g. to
+    /// give the LFE more level than the mains. Missing entries are 1.0.
+    #[serde(default)]
+    pub channel_gains: Vec<f64>,
+
+    /// Sample format to open the output stream with, instead of the
+    /// device's default
+    #[serde(default)]
+    pub sample_format: OutputFormat,
+
+    /// Number of channels to open the output stream with, for devices
+    /// whose default has more than they really have (unset = default)
+    #[serde(default)]
+    pub channels_count: Option<u16>,
+
+    /// Add ±1 LSB triangular dither before converting to an integer sample
+    /// format (no effect on float output)
+    #[serde(default)]
+    pub dither: bool,
+
+    /// Slow amplitude modulation applied on top of the fade envelope
+    #[serde(default)]
+    pub modulation: Modulation,
+
+    /// Audio host to use, e.g. "alsa", "jack" or "asio" (empty = platform default)
+    #[serde(default)]
+    pub host: String,
+
+    /// Audio output device names, tried in order (empty = default).
+    /// A single string in the file is a one-element list.
+    #[serde(default, deserialize_with = "name_list")]
+    pub device: Vec<String>,
+
+    /// Input devices `nodoze verify` records from, tried in order (empty =
+    /// default input). Ideally a loopback or monitor of the output device.
+    #[serde(default, deserialize_with = "name_list")]
+    pub input_device: Vec<String>,
+
+    /// Play on every device in `device` at once instead of only the first
+    /// one found
+    #[serde(default)]
+    pub all_devices: bool,
+
+    /// Which device in `device` each tone plays on
+    #[serde(default)]
+    pub device_selection: DeviceSelection,
+
+    /// Seconds to wait after startup before the first tone
+    #[serde(default)]
+    pub initial_delay: u64,
+
+    /// Volume for a single wake tone requested with `play-now --boost` or
+    /// `once --boost`, louder than `volume` to rouse a speaker that has
+    /// already gone to sleep
+    #[serde(default = "default_wake_boost_volume", deserialize_with = "volume")]
+    pub wake_boost_volume: f64,
+
+    /// Shortest time in seconds between two tones from any source (the
+    /// schedule, `play-now`, `nodoze once`); a tone due sooner is skipped
+    /// (0 = no minimum)
+    #[serde(default)]
+    pub min_gap: u64,
+
+    /// Play a tone as soon as the daemon starts. When false the first tone
+    /// waits a full interval.
+    #[serde(default = "default_play_on_start")]
+    pub play_on_start: bool,
+
+    /// Interval between tones while warming up after startup
+    #[serde(default, deserialize_with = "whole_seconds")]
+    pub warmup_interval: u64,
+
+    /// Seconds after startup during which `warmup_interval` is used instead
+    /// of `interval` (0 = no warmup)
+    #[serde(default, deserialize_with = "whole_seconds")]
+    pub warmup_duration: u64,
+
+    /// Interval between tones for a while after one that played late,
+    /// e.g. after the system woke from sleep
+    #[serde(default, deserialize_with = "whole_seconds")]
+    pub post_wake_interval: u64,
+
+    /// Seconds after a late tone during which `post_wake_interval` is used
+    /// instead of `interval` (0 = off)
+    #[serde(default, deserialize_with = "whole_seconds")]
+    pub post_wake_duration: u64,
+
+    /// Exit after this many successful tones (0 = run indefinitely)
+    #[serde(default)]
+    pub repeat_count: u64,
+
+    /// Seconds the daemon reuses a resolved output device before looking it
+    /// up again (0 = look it up for every tone)
+    #[serde(default = "default_device_cache_ttl")]
+    pub device_cache_ttl: u64,
+
+    /// Play on the system default device while the configured one is missing
+    #[serde(default)]
+    pub fallback_to_default: bool,
+
+    /// Exit the daemon instead of retrying when the system has no audio
+    /// output hardware at all
+    #[serde(default)]
+    pub exit_if_no_device: bool,
+
+    /// Stop the daemon with an error once this many tones in a row have
+    /// failed, leaving restarts to the service manager (0 = never)
+    #[serde(default)]
+    pub max_consecutive_failures: u32,
+
+    /// Keep the daemon's output stream open between tones, writing silence
+    /// in the gaps, instead of opening the device for every tone
+    #[serde(default)]
+    pub persistent_stream: bool,
+
+    /// Play tones on worker threads so the daemon keeps polling while they
+    /// play, letting tones on different devices overlap
+    #[serde(default)]
+    pub concurrent_plays: bool,
+
+    /// Milliseconds to keep the stream open after the last sample, so the
+    /// backend can play out its buffer before the stream is closed
+    #[serde(default = "default_drain_ms")]
+    pub drain_ms: u64,
+
+    /// Request realtime scheduling for the audio thread
+    #[serde(default)]
+    pub realtime_priority: bool,
+
+    /// Upper bound in seconds for the retry backoff after failed tones
+    #[serde(default = "default_max_retry_delay")]
+    pub max_retry_delay: u64,
+
+    /// Seconds before the first retry after a failed tone; doubles with
+    /// each further failure
+    #[serde(default = "default_retry_delay", deserialize_with = "whole_seconds")]
+    pub retry_delay: u64,
+
+    /// Seconds between the daemon's checks of the clock, control requests
+    /// and quiet hours
+    #[serde(default = "default_poll_interval", deserialize_with = "whole_seconds")]
+    pub poll_interval: u64,
+
+    /// Play a tone as soon as the OS reports the system resumed from
+    /// sleep, rather than at the first poll that notices the time passed
+    #[serde(default)]
+    pub wake_on_resume: bool,
+
+    /// Shorten the interval while the user is idle (display likely off)
+    /// and lengthen it while they're active
+    #[serde(default)]
+    pub idle_aware: bool,
+
+    /// Only play scheduled tones while a process with one of these names
+    /// is running (empty = always)
+    #[serde(default, deserialize_with = "name_list")]
+    pub require_process: Vec<String>,
+
+    /// Start of the daily quiet window, local time "HH:MM"
+    #[serde(default)]
+    pub quiet_hours_start: Option<String>,
+
+    /// End of the daily quiet window, local time "HH:MM"
+    #[serde(default)]
+    pub quiet_hours_end: Option<String>,
+
+    /// Interval and quiet hours for particular days of the week, from
+    /// `[schedule.<day>]` tables. Days left out use the settings above.
+    #[serde(default)]
+    pub schedule: WeekSchedule,
+
+    /// Directory for nodoze's state files, control socket and (relative)
+    /// log file, so they are all in one place (empty = the defaults)
+    #[serde(default)]
+    pub data_dir: String,
+
+    /// Log file path, relative to `data_dir` if that is set
+    /// (empty = log to stderr)
+    #[serde(default)]
+    pub log_file: String,
+
+    /// Log level used when RUST_LOG and -v/-q are absent (empty = info)
     #[serde(default)]
-    pub device: String,
+    pub log_level: String,
+
+    /// Log line format
+    #[serde(default)]
+    pub log_format: LogFormat,
+
+    /// Where log output goes
+    #[serde(default)]
+    pub log_target: LogTarget,
+
+    /// Rotate the log file once it grows past this many bytes
+    #[serde(default = "default_log_max_bytes")]
+    pub log_max_bytes: u64,
+
+    /// Number of rotated log files to keep
+    #[serde(default = "default_log_max_files")]
+    pub log_max_files: u32,
+
+    /// Directory for the macOS LaunchAgent's stdout/stderr files
+    /// (empty = `data_dir`, else ~/Library/Logs/nodoze)
+    #[serde(default)]
+    pub launchd_log_dir: String,
+
+    /// When the installed service restarts nodoze after it exits
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+
+    /// Seconds the service waits before restarting nodoze
+    #[serde(default = "default_restart_delay")]
+    pub restart_delay: u64,
+
+    /// Port for the Prometheus metrics endpoint (unset = disabled)
+    #[serde(default)]
+    pub metrics_port: Option<u16>,
+
+    /// Address the metrics endpoint binds to
+    #[serde(default = "default_metrics_address")]
+    pub metrics_address: String,
+
+    /// URL POSTed to when tones keep failing and again on recovery
+    /// (empty = no alerts)
+    #[serde(default)]
+    pub alert_webhook: String,
+
+    /// Consecutive failed tones before the webhook is alerted and the
+    /// failure chirp plays
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+
+    /// Show a desktop notification when tones keep failing
+    #[serde(default)]
+    pub desktop_notifications: bool,
+
+    /// Play a short audible chirp on the default device when tones keep
+    /// failing
+    #[serde(default)]
+    pub failure_chirp: bool,
+
+    /// TOML file re-read before each scheduled tone and merged over this
+    /// config for that tone, for tuning without a restart (empty = none)
+    #[serde(default)]
+    pub runtime_overrides: String,
+
+    /// Reload this file when it changes while the daemon runs
+    #[serde(default)]
+    pub watch_config: bool,
+
+    /// Name of the `[[profile]]` table merged over the top-level settings.
+    /// Not dumped, since the profile is already applied.
+    #[serde(default, skip_serializing)]
+    pub active_profile: Option<String>,
+
+    /// File this config was loaded from, if any
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+
+    /// Keys that aren't settings, e.g. removed fields or typos. Warned
+    /// about on load and dropped by `nodoze migrate`.
+    #[serde(flatten, skip_serializing)]
+    pub extra: HashMap<String, toml::Value>,
 }
 
-fn default_frequency() -> f64 {
-    20.0
+/// Amplitude modulation for amplifiers that only stay awake on a changing
+/// signal level. The level swings between `1 - mod_depth` and full volume.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Modulation {
+    /// Modulation frequency in Hz
+    #[serde(default)]
+    pub mod_rate: f64,
+
+    /// Modulation depth (0.0 = off, 1.0 = down to silence)
+    #[serde(default)]
+    pub mod_depth: f64,
 }
-fn default_duration() -> u64 {
-    15
+
+impl Modulation {
+    pub fn is_active(&self) -> bool {
+        self.mod_rate > 0.0 && self.mod_depth > 0.0
+    }
 }
-fn default_interval() -> u64 {
-    540
+
+/// Per-weekday overrides, one optional `[schedule.<day>]` table per day
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct WeekSchedule {
+    #[serde(default)]
+    pub monday: Option<DaySchedule>,
+    #[serde(default)]
+    pub tuesday: Option<DaySchedule>,
+    #[serde(default)]
+    pub wednesday: Option<DaySchedule>,
+    #[serde(default)]
+    pub thursday: Option<DaySchedule>,
+    #[serde(default)]
+    pub friday: Option<DaySchedule>,
+    #[serde(default)]
+    pub saturday: Option<DaySchedule>,
+    #[serde(default)]
+    pub sunday: Option<DaySchedule>,
 }
-fn default_fade_duration() -> f64 {
-    1.0
+
+/// Settings that differ on one day of the week. Unset fields keep the
+/// top-level value.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct DaySchedule {
+    /// Replaces `interval` on this day (ignored in continuous mode)
+    #[serde(default, deserialize_with = "optional_whole_seconds")]
+    pub interval: Option<u64>,
+
+    /// Replace the quiet hours window on this day; set both or neither
+    #[serde(default)]
+    pub quiet_hours_start: Option<String>,
+    #[serde(default)]
+    pub quiet_hours_end: Option<String>,
 }
-fn default_volume() -> f64 {
-    0.05
+
+impl WeekSchedule {
+    pub const DAYS: [Weekday; 7] = [
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+        Weekday::Sat,
+        Weekday::Sun,
+    ];
+
+    /// The overrides for `day`, if it has a table
+    pub fn day(&self, day: Weekday) -> Option<&DaySchedule> {
+        match day {
+            Weekday::Mon => self.monday.as_ref(),
+            Weekday::Tue => self.tuesday.as_ref(),
+            Weekday::Wed => self.wednesday.as_ref(),
+            Weekday::Thu => self.thursday.as_ref(),
+            Weekday::Fri => self.friday.as_ref(),
+            Weekday::Sat => self.saturday.as_ref(),
+            Weekday::Sun => self.sunday.as_ref(),
+        }
+    }
+
+    /// True if no day has a table
+    pub fn is_empty(&self) -> bool {
+        Self::DAYS.iter().all(|&day| self.day(day).is_none())
+    }
+
+    /// Day name as written in the table header, e.g. "monday"
+    pub fn name(day: Weekday) -> &'static str {
+        match day {
+            Weekday::Mon => "monday",
+            Weekday::Tue => "tuesday",
+            Weekday::Wed => "wednesday",
+            Weekday::Thu => "thursday",
+            Weekday::Fri => "friday",
+            Weekday::Sat => "saturday",
+            Weekday::Sun => "sunday",
+        }
+    }
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            frequency: default_frequency(),
-            duration: default_duration(),
-            interval: default_interval(),
-            fade_duration: default_fade_duration(),
-            volume: default_volume(),
-            device: String::new(),
+/// How log lines are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, for log shippers
+    Json,
+}
+
+/// Where log output goes (`log_target`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTarget {
+    /// stderr, or `log_file` if one is set
+    #[default]
+    Stderr,
+    /// The system logger, via its local socket (Unix only)
+    Syslog,
+}
+
+/// Why a config file couldn't be loaded
+enum LoadError {
+    /// Unreadable or malformed. `load` falls back to the defaults.
+    Invalid(String),
+    /// The selected profile is missing or malformed, which is always fatal
+    Profile(String),
+}
+
+/// Tuned frequency/volume/duration combinations for common hardware
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    /// Subwoofers and full-range speakers (the built-in defaults)
+    Sub,
+    /// Laptop speakers, which can't reproduce very low frequencies
+    Laptop,
+    /// Soundbars with aggressive auto-standby
+    Soundbar,
+    /// Dedicated subwoofers fed from a surround receiver's LFE output
+    Lfe,
+}
+
+impl Preset {
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Sub => "sub",
+            Preset::Laptop => "laptop",
+            Preset::Soundbar => "soundbar",
+            Preset::Lfe => "lfe",
+        }
+    }
+
+    /// Frequency in Hz, volume and duration in seconds
+    fn values(self) -> (f64, f64, u64) {
+        match self {
+            Preset::Sub => (default_frequency(), default_volume(), default_duration()),
+            Preset::Laptop => (60.0, 0.01, 10),
+            Preset::Soundbar => (30.0, 0.08, 20),
+            Preset::Lfe => (25.0, 0.05, 20),
         }
     }
 }
 
-impl Config {
-    pub fn load(path: Option<&str>) -> Self {
-        if let Some(p) = path {
-            return Self::load_from_path(&PathBuf::from(p));
+/// Shape of the fade in/out envelope
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FadeCurve {
+    /// Straight ramp (the original behavior)
+    Linear,
+    /// Raised cosine, gentlest on low tones
+    #[default]
+    Cosine,
+    /// Exponential ramp over a 60 dB range
+    Exponential,
+}
+
+impl FadeCurve {
+    pub fn name(self) -> &'static str {
+        match self {
+            FadeCurve::Linear => "linear",
+            FadeCurve::Cosine => "cosine",
+            FadeCurve::Exponential => "exponential",
         }
+    }
+}
 
-        if let Some(config_path) = Self::config_path() {
-            if config_path.exists() {
-                return Self::load_from_path(&config_path);
-            }
+/// Shape of the synthesized tone
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Waveform {
+    /// Steady sine wave
+    #[default]
+    Sine,
+    /// A short positive pulse once per cycle, balanced by a long shallow
+    /// negative swing so the signal carries no DC
+    Pulse,
+    /// White noise; `frequency` is ignored. `lowpass_hz` keeps it to the
+    /// low end.
+    Noise,
+}
+
+impl Waveform {
+    pub fn name(self) -> &'static str {
+        match self {
+            Waveform::Sine => "sine",
+            Waveform::Pulse => "pulse",
+            Waveform::Noise => "noise",
         }
+    }
+}
 
-        log::info!("No config file found, using defaults");
-        Self::default()
+/// When the installed service restarts nodoze, translated into each
+/// platform's service definition by `nodoze install`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Whenever it exits, even cleanly
+    Always,
+    /// Only after a crash or an error exit
+    #[default]
+    OnFailure,
+    /// Leave it stopped
+    Never,
+}
+
+impl RestartPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            RestartPolicy::Always => "always",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::Never => "never",
+        }
     }
+}
 
-    fn load_from_path(path: &PathBuf) -> Self {
-        match std::fs::read_to_string(path) {
-            Ok(contents) => match toml::from_str(&contents) {
-                Ok(config) => {
-                    log::info!("Loaded config from {}", path.display());
-                    config
-                }
-                Err(e) => {
-                    log::warn!("Failed to parse config {}: {}", path.display(), e);
-                    Self::default()
-                }
-            },
-            Err(e) => {
-                log::warn!("Failed to read config {}: {}", path.display(), e);
-                Self::default()
-            }
+/// Stage that keeps the signal within ±1.0 (full scale) before the volume
+/// is applied, for sources that can exceed it such as float WAV clips
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Limiter {
+    /// Leave the signal alone
+    Off,
+    /// Cut anything beyond full scale. Leaves in-range signals untouched.
+    #[default]
+    Clamp,
+    /// Round off peaks above 90% of full scale with a tanh curve, which
+    /// distorts less audibly than clamping
+    Soft,
+}
+
+impl Limiter {
+    pub fn name(self) -> &'static str {
+        match self {
+            Limiter::Off => "off",
+            Limiter::Clamp => "clamp",
+            Limiter::Soft => "soft",
         }
     }
+}
 
-    /// Returns the config file path.
-    /// On macOS/Linux: ~/.config/nodoze/config.toml
-    /// On Windows: %APPDATA%/nodoze/config.toml
-    pub fn config_path() -> Option<PathBuf> {
-        #[cfg(any(target_os = "macos", target_os = "linux"))]
-        {
-            dirs::home_dir().map(|h| h.join(".config").join("nodoze").join("config.toml"))
+/// Channels the tone is written to, for multichannel devices where a low
+/// tone on the surround channels is unwanted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelMode {
+    /// Every channel
+    #[default]
+    Duplicate,
+    /// Front left and right (channels 0 and 1)
+    FrontOnly,
+    /// The first channel only
+    Single,
+}
+
+impl ChannelMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChannelMode::Duplicate => "duplicate",
+            ChannelMode::FrontOnly => "front_only",
+            ChannelMode::Single => "single",
         }
-        #[cfg(not(any(target_os = "macos", target_os = "linux")))]
-        {
-            dirs::config_dir().map(|d| d.join("nodoze").join("config.toml"))
+    }
+
+    /// Number of leading channels that carry the tone, or None for all
+    pub fn max_channels(self) -> Option<usize> {
+        match self {
+            ChannelMode::Duplicate => None,
+            ChannelMode::FrontOnly => Some(2),
+            ChannelMode::Single => Some(1),
+        }
+    }
+}
+
+/// Speaker layout of a multichannel device, in the usual WAVE/SMPTE channel
+/// order, so `channel_target` can find e.g. the LFE channel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub enum ChannelLayout {
+    /// Guessed from the channel count: 2 is 2.0, 6 is 5.1 and 8 is 7.1
+    #[default]
+    #[serde(rename = "auto")]
+    Auto,
+    /// Front left, front right
+    #[serde(rename = "2.0")]
+    Stereo,
+    /// Front left, front right, center, LFE, surround left, surround right
+    #[serde(rename = "5.1")]
+    Surround51,
+    /// 5.1 followed by side left and side right
+    #[serde(rename = "7.1")]
+    Surround71,
+}
+
+impl ChannelLayout {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChannelLayout::Auto => "auto",
+            ChannelLayout::Stereo => "2.0",
+            ChannelLayout::Surround51 => "5.1",
+            ChannelLayout::Surround71 => "7.1",
+        }
+    }
+
+    /// The layout of a device with `channels` channels, or None if `Auto`
+    /// can't tell from that count
+    pub fn resolve(self, channels: usize) -> Option<Self> {
+        match (self, channels) {
+            (ChannelLayout::Auto, 2) => Some(ChannelLayout::Stereo),
+            (ChannelLayout::Auto, 6) => Some(ChannelLayout::Surround51),
+            (ChannelLayout::Auto, 8) => Some(ChannelLayout::Surround71),
+            (ChannelLayout::Auto, _) => None,
+            (layout, _) => Some(layout),
         }
     }
 }
+
+/// Speakers the tone is played on, found in the `channel_layout`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelTarget {
+    All,
+    /// Front left and right
+    Fronts,
+    Center,
+    Lfe,
+    /// The surround (and on 7.1, side) pairs
+    Surrounds,
+    AllExceptLfe,
+    AllExceptCenterLfe,
+}
+
+impl ChannelTarget {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChannelTarget::All => "all",
+            ChannelTarget::Fronts => "fronts",
+            ChannelTarget::Center => "center",
+            ChannelTarget::Lfe => "lfe",
+            ChannelTarget::Surrounds => "surrounds",
+            ChannelTarget::AllExceptLfe => "all_except_lfe",
+            ChannelTarget::AllExceptCenterLfe => "all_except_center_lfe",
+        }
+    }
+
+    /// Channel numbers of these speakers in a resolved `layout`. Empty if
+    /// the layout has none of them, e.g. the LFE in 2.0.
+    pub fn channels(self, layout: ChannelLayout) -> &'static [usize] {
+        use ChannelLayout::*;
+        use ChannelTarget::*;
+        match (self, layout) {
+            (All | AllExceptLfe | AllExceptCenterLfe | Fronts, Stereo | Auto) => &[0, 1],
+            (Center | Lfe | Surrounds, Stereo | Auto) => &[],
+            (Fronts, _) => &[0, 1],
+            (Center, _) => &[2],
+            (Lfe, _) => &[3],
+            (All, Surround51) => &[0, 1, 2, 3, 4, 5],
+            (All, Surround71) => &[0, 1, 2, 3, 4, 5, 6, 7],
+            (Surrounds, Surround51) => &[4, 5],
+            (Surrounds, Surround71) => &[4, 5, 6, 7],
+            (AllExceptLfe, Surround51) => &[0, 1, 2, 4, 5],
+            (AllExceptLfe, Surround71) => &[0, 1, 2, 4, 5, 6, 7],
+            (AllExceptCenterLfe, Surround51) => &[0, 1, 4, 5],
+            (AllExceptCenterLfe, Surround71) => &[0, 1, 4, 5, 6, 7],
+        }
+    }
+}
+
+/// How each tone picks a device from the `device` list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceSelection {
+    /// The first one present, every time
+    #[default]
+    First,
+    /// The next one in the list each tone, going round
+    RoundRobin,
+    /// One at random each tone
+    Random,
+}
+
+impl DeviceSelection {
+    pub fn name(self) -> &'static str {
+        match self {
+            DeviceSelection::First => "first",
+            DeviceSelection::RoundRobin => "round_robin",
+            DeviceSelection::Random => "random",
+        }
+    }
+}
+
+/// Sample format of the output stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Whatever the device reports as its default
+    #[default]
+    Auto,
+    F32,
+    I16,
+    I32,
+}
+
+impl OutputFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Auto => "auto",
+            OutputFormat::F32 => "f32",
+            OutputFormat::I16 => "i16",
+            OutputFormat::I32 => "i32",
+        }
+    }
+}
+
+fn default_frequency() -> f64 {
+    20.0
+}
+fn default_a4_frequency() -> f64 {
+    440.0
+}
+fn default_duration() -> u64 {
+    15
+}
+fn default_burst_count() -> u32 {
+    1
+}
+fn default_burst_gap() -> f64 {
+    1.0
+}
+fn default_interval() -> u64 {
+    540
+}
+fn default_adaptive_after() -> u32 {
+    5
+}
+fn default_fade_duration() -> f64 {
+    1.0
+}
+fn default_volume() -> f64 {
+    0.05
+}
+fn default_max_retry_delay() -> u64 {
+    300
+}
+fn default_retry_delay() -> u64 {
+    5
+}
+fn default_drain_ms() -> u64 {
+    50
+}
+fn default_play_on_start() -> bool {
+    true
+}
+fn default_restart_delay() -> u64 {
+    10
+}
+fn default_wake_boost_volume() -> f64 {
+    0.2
+}
+fn default_device_cache_ttl() -> u64 {
+    5
+}
+fn default_poll_interval() -> u64 {
+    1
+}
+fn default_log_max_bytes() -> u64 {
+    1024 * 1024
+}
+fn default_log_max_files() -> u32 {
+    3
+}
+fn default_metrics_address() -> String {
+    "127.0.0.1".to_string()
+}
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            strict: false,
+            preset: None,
+            frequency: default_frequency(),
+            a4_frequency: default_a4_frequency(),
+            waveform: Waveform::default(),
+            comb_fundamental: 0.0,
+            comb_count: 0,
+            highpass_hz: 0.0,
+            lowpass_hz: 0.0,
+            frequency_jitter: 0.0,
+            frequency_rotation: Vec::new(),
+            min_frequency: 0.0,
+            max_frequency: 0.0,
+            duration: default_duration(),
+            burst_count: default_burst_count(),
+            burst_gap: default_burst_gap(),
+            interval: default_interval(),
+            continuous: false,
+            interval_jitter: 0,
+            adaptive_interval: false,
+            adaptive_after: default_adaptive_after(),
+            min_interval: 0,
+            max_interval: 0,
+            random_seed: None,
+            wav_path: String::new(),
+            fade_duration: default_fade_duration(),
+            attack_duration: None,
+            release_duration: None,
+            fade_curve: FadeCurve::default(),
+            limiter: Limiter::default(),
+            volume: default_volume(),
+            volume_schedule: Vec::new(),
+            stereo_phase: 0.0,
+            pan: 0.0,
+            antiphase: false,
+            decorrelate: false,
+            channel_mode: ChannelMode::default(),
+            channel_layout: ChannelLayout::default(),
+            channel_target: None,
+            channel_gains: Vec::new(),
+            sample_format: OutputFormat::default(),
+            channels_count: None,
+            dither: false,
+            modulation: Modulation::default(),
+            host: String::new(),
+            device: Vec::new(),
+            input_device: Vec::new(),
+            all_devices: false,
+            device_selection: DeviceSelection::default(),
+            initial_delay: 0,
+            wake_boost_volume: default_wake_boost_volume(),
+            min_gap: 0,
+            play_on_start: default_play_on_start(),
+            warmup_interval: 0,
+            warmup_duration: 0,
+            post_wake_interval: 0,
+            post_wake_duration: 0,
+            repeat_count: 0,
+            device_cache_ttl: default_device_cache_ttl(),
+            fallback_to_default: false,
+            exit_if_no_device: false,
+            max_consecutive_failures: 0,
+            persistent_stream: false,
+            concurrent_plays: false,
+            drain_ms: default_drain_ms(),
+            realtime_priority: false,
+            max_retry_delay: default_max_retry_delay(),
+            retry_delay: default_retry_delay(),
+            poll_interval: default_poll_interval(),
+            wake_on_resume: false,
+            idle_aware: false,
+            require_process: Vec::new(),
+            quiet_hours_start: None,
+            quiet_hours_end: None,
+            schedule: WeekSchedule::default(),
+            data_dir: String::new(),
+            log_file: String::new(),
+            log_level: String::new(),
+            log_format: LogFormat::default(),
+            log_target: LogTarget::default(),
+            log_max_bytes: default_log_max_bytes(),
+            log_max_files: default_log_max_files(),
+            launchd_log_dir: String::new(),
+            restart_policy: RestartPolicy::default(),
+            restart_delay: default_restart_delay(),
+            metrics_port: None,
+            metrics_address: default_metrics_address(),
+            alert_webhook: String::new(),
+            failure_threshold: default_failure_threshold(),
+            desktop_notifications: false,
+            failure_chirp: false,
+            runtime_overrides: String::new(),
+            watch_config: false,
+            active_profile: None,
+            source: None,
+            extra: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the config, optionally selecting a named profile.
+    /// A profile given here takes precedence over `active_profile` in the file.
+    /// A file that can't be parsed falls back to the defaults with a warning,
+    /// unless `strict` (or `strict = true` in the file), which also rejects
+    /// invalid settings.
+    pub fn load(path: Option<&str>, profile: Option<&str>, strict: bool) -> Result<Self, String> {
+        if let Some(p) = path {
+            return Self::load_from_path(&PathBuf::from(p), profile, strict);
+        }
+
+        match Self::default_path() {
+            Ok(config_path) if config_path.exists() => {
+                if config_file(LEGACY_NAME).is_ok_and(|p| p == config_path) {
+                    log::warn!(
+                        "Reading config from the old {} directory; run `nodoze migrate` \
+                         and move the result to {}",
+                        LEGACY_NAME,
+                        Self::config_path().unwrap_or_default().display()
+                    );
+                }
+                return Self::load_from_path(&config_path, profile, strict);
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("{}", e),
+        }
+
+        if let Some(name) = profile {
+            return Err(format!("Profile '{}' requested but no config file found", name));
+        }
+
+        log::info!("No config file found, using defaults");
+        Ok(Self::default())
+    }
+
+    fn load_from_path(path: &Path, profile: Option<&str>, strict: bool) -> Result<Self, String> {
+        match Self::from_file(path, profile) {
+            Ok(config) => {
+                if strict || config.strict {
+                    config.validate().map_err(|errors| {
+                        format!("Invalid config {}: {}", path.display(), errors.join("; "))
+                    })?;
+                }
+                log::info!("Loaded config from {}", path.display());
+                if let Some(name) = &config.active_profile {
+                    log::info!("Using profile '{}'", name);
+                }
+                Ok(config)
+            }
+            Err(LoadError::Invalid(e)) if strict || requests_strict(path) => {
+                Err(format!("{} (strict mode, not falling back to the defaults)", e))
+            }
+            Err(LoadError::Invalid(e)) => {
+                log::warn!("{}", e);
+                Ok(Self::default())
+            }
+            Err(LoadError::Profile(e)) => Err(e),
+        }
+    }
+
+    /// Load a config file without falling back to the defaults on errors.
+    /// Used to check a file before relying on it.
+    pub fn load_strict(
+        path: Option<&str>,
+        profile: Option<&str>,
+    ) -> Result<(Self, PathBuf), String> {
+        let path = match path {
+            Some(p) => PathBuf::from(p),
+            None => Self::default_path()?,
+        };
+        match Self::from_file(&path, profile) {
+            Ok(config) => Ok((config, path)),
+            Err(LoadError::Invalid(e) | LoadError::Profile(e)) => Err(e),
+        }
+    }
+
+    /// Read `source` again with the same profile, for `watch_config`.
+    /// Never falls back to the defaults, and rejects invalid settings.
+    pub fn reload(&self) -> Result<Self, String> {
+        let path = self.source.as_deref().ok_or("not loaded from a file")?;
+        let config = match Self::from_file(path, self.active_profile.as_deref()) {
+            Ok(config) => config,
+            Err(LoadError::Invalid(e) | LoadError::Profile(e)) => return Err(e),
+        };
+        config.validate().map_err(|errors| {
+            format!("Invalid config {}: {}", path.display(), errors.join("; "))
+        })?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path, profile: Option<&str>) -> Result<Self, LoadError> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            LoadError::Invalid(format!("Failed to parse config {}: {}", path.display(), e))
+        };
+
+        let table = read_table(path, &mut Vec::new()).map_err(LoadError::Invalid)?;
+        let table = apply_preset(apply_profile(table, profile).map_err(LoadError::Profile)?);
+        let table = apply_comb(apply_notes(table).map_err(|e| invalid(&e))?);
+        let mut config = toml::Value::Table(table)
+            .try_into::<Self>()
+            .map_err(|e| invalid(&e))?;
+        config.source = Some(path.to_path_buf());
+        let mut unknown: Vec<&String> = config.extra.keys().collect();
+        unknown.sort();
+        for key in unknown {
+            log::warn!("Ignoring unknown config key `{}` in {}", key, path.display());
+        }
+        Ok(config)
+    }
+
+    /// Check for settings that parse but can't work. Returns every problem
+    /// found rather than stopping at the first.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        if self.volume <= 0.0 {
+            errors.push("volume must be above 0; a silent tone keeps nothing awake".to_string());
+        }
+        if self.volume_schedule.iter().any(|&v| v <= 0.0) {
+            errors.push("volume_schedule entries must be above 0".to_string());
+        }
+        if self.channel_gains.iter().any(|&g| g < 0.0 || !g.is_finite()) {
+            errors.push("channel_gains entries must be 0 or more".to_string());
+        }
+        if self.wake_boost_volume <= 0.0 || self.wake_boost_volume > 1.0 {
+            errors.push("wake_boost_volume must be above 0 and at most 1.0".to_string());
+        }
+        if self.retry_delay == 0 {
+            errors.push("retry_delay must be at least 1 second".to_string());
+        }
+        if self.drain_ms > MAX_DRAIN_MS {
+            errors.push(format!(
+                "drain_ms must be at most {} (a few seconds covers any backend)",
+                MAX_DRAIN_MS
+            ));
+        }
+        if self.poll_interval == 0 {
+            errors.push("poll_interval must be at least 1 second".to_string());
+        }
+        if self.adaptive_interval && !self.is_continuous() {
+            if self.max_interval <= self.interval {
+                errors.push(format!(
+                    "max_interval ({}s) must be above interval ({}s) for adaptive_interval to \
+                     lengthen it",
+                    self.max_interval, self.interval
+                ));
+            }
+            if self.min_interval > self.interval {
+                errors.push(format!(
+                    "min_interval ({}s) must be at most interval ({}s)",
+                    self.min_interval, self.interval
+                ));
+            }
+            if self.adaptive_after == 0 {
+                errors.push("adaptive_after must be at least 1 tone".to_string());
+            }
+        }
+        if self.warmup_duration > 0 && self.warmup_interval == 0 {
+            errors.push(
+                "warmup_interval must be at least 1 second when warmup_duration is set".to_string(),
+            );
+        }
+        if self.post_wake_duration > 0 && self.post_wake_interval == 0 {
+            errors.push(
+                "post_wake_interval must be at least 1 second when post_wake_duration is set"
+                    .to_string(),
+            );
+        }
+        if self.all_devices && self.device_selection != DeviceSelection::First {
+            errors.push(format!(
+                "device_selection = \"{}\" picks one device per tone, but all_devices plays on \
+                 every one; set only one of them",
+                self.device_selection.name()
+            ));
+        }
+        if self.burst_count == 0 {
+            errors.push("burst_count must be at least 1".to_string());
+        }
+        if self.burst_gap < 0.0 {
+            errors.push("burst_gap must not be negative".to_string());
+        } else if self.is_burst() && self.burst_train_length() >= self.interval as f64 {
+            errors.push(format!(
+                "{} bursts with {}s gaps take {}s, which must be below interval ({}s)",
+                self.burst_count,
+                self.burst_gap,
+                self.burst_train_length(),
+                self.interval
+            ));
+        }
+        if self.min_gap > 0 && !self.is_continuous() && self.min_gap >= self.interval {
+            errors.push(format!(
+                "min_gap ({}s) must be below interval ({}s) or every scheduled tone is skipped",
+                self.min_gap, self.interval
+            ));
+        }
+        for day in WeekSchedule::DAYS {
+            let Some(table) = self.schedule.day(day) else {
+                continue;
+            };
+            let name = WeekSchedule::name(day);
+            if table.interval == Some(0) && !self.is_continuous() {
+                errors.push(format!(
+                    "schedule.{}: interval must be at least 1 second (continuous mode can't be \
+                     set per day)",
+                    name
+                ));
+                continue;
+            }
+            let on_day = self.on_day(day);
+            if self.min_gap > 0 && !self.is_continuous() && self.min_gap >= on_day.interval {
+                errors.push(format!(
+                    "schedule.{}: min_gap ({}s) must be below interval ({}s)",
+                    name, self.min_gap, on_day.interval
+                ));
+            }
+            if table.quiet_hours_start.is_some() || table.quiet_hours_end.is_some() {
+                if let Err(e) = on_day.quiet_hours() {
+                    errors.push(format!("schedule.{}: {}", name, e));
+                }
+            }
+        }
+        if self.fade_duration < 0.0 || self.attack() < 0.0 || self.release() < 0.0 {
+            errors.push(
+                "fade_duration, attack_duration and release_duration must not be negative"
+                    .to_string(),
+            );
+        } else if (self.attack_duration.is_some() || self.release_duration.is_some())
+            && !self.is_continuous()
+            && self.attack() + self.release() > self.duration as f64
+        {
+            // A plain fade_duration is still shortened to fit, as it always was
+            errors.push(format!(
+                "attack ({}s) plus release ({}s) must fit within duration ({}s)",
+                self.attack(),
+                self.release(),
+                self.duration
+            ));
+        }
+        if self.frequency <= 0.0 {
+            errors.push(
+                "frequency must be above 0 Hz (fractions such as 0.5 are fine)".to_string(),
+            );
+        }
+        if self.a4_frequency <= 0.0 {
+            errors.push("a4_frequency must be above 0 Hz".to_string());
+        }
+        if self.comb_fundamental < 0.0 {
+            errors.push("comb_fundamental must not be negative".to_string());
+        }
+        if self.highpass_hz < 0.0 || self.lowpass_hz < 0.0 {
+            errors.push("highpass_hz and lowpass_hz must not be negative".to_string());
+        } else if self.highpass_hz > 0.0
+            && self.lowpass_hz > 0.0
+            && self.highpass_hz >= self.lowpass_hz
+        {
+            errors.push(format!(
+                "highpass_hz ({} Hz) must be below lowpass_hz ({} Hz), or nothing gets through",
+                self.highpass_hz, self.lowpass_hz
+            ));
+        }
+        if self.channels_count == Some(0) {
+            errors.push(
+                "channels_count must be at least 1 (leave it out for the device's default)"
+                    .to_string(),
+            );
+        }
+        if self.comb_count > MAX_COMB_COUNT {
+            errors.push(format!("comb_count must be at most {}", MAX_COMB_COUNT));
+        }
+        if self.frequency_rotation.iter().any(|&f| f <= 0.0) {
+            errors.push("frequency_rotation entries must be above 0 Hz".to_string());
+        }
+        if self.min_frequency < 0.0 || self.max_frequency < 0.0 {
+            errors.push("min_frequency and max_frequency must not be negative".to_string());
+        } else if self.max_frequency > 0.0 && self.min_frequency > self.max_frequency {
+            errors.push(format!(
+                "min_frequency ({} Hz) must not be above max_frequency ({} Hz)",
+                self.min_frequency, self.max_frequency
+            ));
+        }
+        let lowest = self.frequency_rotation.iter().copied().fold(self.frequency, f64::min);
+        if self.frequency_jitter < 0.0 {
+            errors.push("frequency_jitter must not be negative".to_string());
+        } else if self.frequency_jitter > 0.0 && lowest - self.frequency_jitter <= 0.0 {
+            errors.push(format!(
+                "frequency_jitter ({} Hz) must be below frequency ({} Hz) to keep tones above 0 Hz",
+                self.frequency_jitter, lowest
+            ));
+        }
+        if !self.wav_path.is_empty() {
+            if let Err(e) = check_clip(&self.wav_path) {
+                errors.push(e);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// How long ago a tone played at `last_play`, if that is within
+    /// `min_gap` and another tone must not start yet. A tone that seems to
+    /// have played in the future, because the clock has since gone back,
+    /// doesn't hold the next one up.
+    pub fn within_min_gap(&self, last_play: SystemTime) -> Option<Duration> {
+        let ago = last_play.elapsed().ok()?;
+        (self.min_gap > 0 && ago < Duration::from_secs(self.min_gap)).then_some(ago)
+    }
+
+    /// Whether a tone is so slow it's essentially DC: less than one full
+    /// cycle fits in it, so the speaker cone is pushed one way and held
+    /// rather than moved back and forth. A `duration = 0` burst is a single
+    /// buffer, whose length depends on the device, so it isn't judged.
+    /// Nor is noise, which has no frequency.
+    pub fn is_near_dc(&self) -> bool {
+        if (self.duration == 0 && !self.is_continuous()) || self.waveform == Waveform::Noise {
+            return false;
+        }
+        let lowest = self.frequency_rotation.iter().copied().fold(self.frequency, f64::min);
+        let seconds = if self.is_continuous() { f64::INFINITY } else { self.duration as f64 };
+        self.wav_path.is_empty() && lowest - self.frequency_jitter.max(0.0) < 1.0 / seconds
+    }
+
+    /// `frequency` kept within `min_frequency` and `max_frequency`, with a
+    /// warning if it had to move
+    pub fn clamped_frequency(&self, frequency: f64) -> f64 {
+        let mut clamped = frequency.max(self.min_frequency);
+        if self.max_frequency > 0.0 {
+            clamped = clamped.min(self.max_frequency);
+        }
+        if clamped != frequency {
+            log::warn!(
+                "{} Hz is outside min_frequency/max_frequency, playing {} Hz instead",
+                frequency,
+                clamped
+            );
+        }
+        clamped
+    }
+
+    /// Numbers of the channels that carry the tone on a device with
+    /// `channels` channels, from `channel_target` if set or else
+    /// `channel_mode`. Warns and uses every channel if the target can't be
+    /// found on the device.
+    pub fn tone_channels(&self, channels: usize) -> Vec<usize> {
+        let Some(target) = self.channel_target else {
+            let active = self.channel_mode.max_channels().unwrap_or(channels);
+            return (0..active.min(channels)).collect();
+        };
+        let Some(layout) = self.channel_layout.resolve(channels) else {
+            log::warn!(
+                "Can't tell the speaker layout of a {}-channel device, playing on every channel \
+                 (set channel_layout)",
+                channels
+            );
+            return (0..channels).collect();
+        };
+        let found: Vec<usize> =
+            target.channels(layout).iter().copied().filter(|&ch| ch < channels).collect();
+        if found.is_empty() {
+            log::warn!(
+                "channel_target {} isn't in a {} layout with {} channels, playing on every channel",
+                target.name(),
+                layout.name(),
+                channels
+            );
+            return (0..channels).collect();
+        }
+        found
+    }
+
+    /// Whether each tone is played as a train of bursts (`burst_count`)
+    pub fn is_burst(&self) -> bool {
+        self.burst_count > 1 && !self.is_continuous()
+    }
+
+    /// Length in seconds of each burst: `duration` split evenly between
+    /// them, so 0 (one short burst) once there are more bursts than seconds
+    pub fn burst_duration(&self) -> u64 {
+        self.duration / u64::from(self.burst_count.max(1))
+    }
+
+    /// Seconds from the start of the first burst to the end of the last
+    pub fn burst_train_length(&self) -> f64 {
+        let count = self.burst_count.max(1);
+        (self.burst_duration() * u64::from(count)) as f64
+            + self.burst_gap.max(0.0) * f64::from(count - 1)
+    }
+
+    /// Whether each tone picks its own device from `device`
+    /// (`device_selection`)
+    pub fn rotates_devices(&self) -> bool {
+        self.device_selection != DeviceSelection::First && self.device.len() > 1
+    }
+
+    /// Configured devices for display, e.g. "USB DAC, HDMI"
+    pub fn device_label(&self) -> String {
+        if self.device.is_empty() {
+            "(system default)".to_string()
+        } else {
+            self.device.join(", ")
+        }
+    }
+
+    /// True if the daemon should play one unbroken tone rather than
+    /// bursts, set either explicitly or with an interval of 0
+    pub fn is_continuous(&self) -> bool {
+        self.continuous || self.interval == 0
+    }
+
+    /// Fade-in duration in seconds: `attack_duration`, or `fade_duration`
+    /// when that isn't set
+    pub fn attack(&self) -> f64 {
+        self.attack_duration.unwrap_or(self.fade_duration)
+    }
+
+    /// Fade-out duration in seconds: `release_duration`, or
+    /// `fade_duration` when that isn't set
+    pub fn release(&self) -> f64 {
+        self.release_duration.unwrap_or(self.fade_duration)
+    }
+
+    /// Bounds of an adaptive interval in seconds, shortest first
+    pub fn adaptive_bounds(&self) -> (u64, u64) {
+        let min = if self.min_interval == 0 { self.interval } else { self.min_interval };
+        (min, self.max_interval.max(min))
+    }
+
+    /// Jitter actually applied: capped at half the interval so the
+    /// randomized interval always stays positive
+    pub fn max_jitter(&self) -> u64 {
+        self.interval_jitter.min(self.interval / 2)
+    }
+
+    /// Parse the quiet hours window, if configured.
+    /// The window may cross midnight (e.g. 22:00 to 07:00).
+    pub fn quiet_hours(&self) -> Result<Option<(NaiveTime, NaiveTime)>, String> {
+        match (&self.quiet_hours_start, &self.quiet_hours_end) {
+            (None, None) => Ok(None),
+            (Some(start), Some(end)) => Ok(Some((parse_hhmm(start)?, parse_hhmm(end)?))),
+            _ => Err("quiet_hours_start and quiet_hours_end must both be set".to_string()),
+        }
+    }
+
+    /// This config with the `[schedule.<day>]` overrides for `day` applied.
+    /// In continuous mode a day's interval is ignored, so it can't turn
+    /// continuous mode off.
+    pub fn on_day(&self, day: Weekday) -> Self {
+        let mut config = self.clone();
+        let Some(table) = self.schedule.day(day) else {
+            return config;
+        };
+        if let Some(interval) = table.interval.filter(|_| !self.is_continuous()) {
+            config.interval = interval;
+        }
+        if table.quiet_hours_start.is_some() || table.quiet_hours_end.is_some() {
+            config.quiet_hours_start = table.quiet_hours_start.clone();
+            config.quiet_hours_end = table.quiet_hours_end.clone();
+        }
+        config
+    }
+
+    /// This config with the settings from the `runtime_overrides` file
+    /// merged over it. The file is read on every call; if it is missing or
+    /// invalid the config is returned unchanged.
+    pub fn with_runtime_overrides(&self) -> Self {
+        if self.runtime_overrides.is_empty() {
+            return self.clone();
+        }
+        let path = Path::new(&self.runtime_overrides);
+        let merged = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| contents.parse::<toml::Table>().map_err(|e| e.to_string()))
+            .and_then(|overrides| {
+                let toml::Value::Table(base) =
+                    toml::Value::try_from(self).map_err(|e| e.to_string())?
+                else {
+                    return Err("config is not a table".to_string());
+                };
+                let table = apply_notes(merge_tables(base, overrides))?;
+                toml::Value::Table(apply_comb(table))
+                    .try_into::<Self>()
+                    .map_err(|e| e.to_string())
+            })
+            .and_then(|merged| match merged.validate() {
+                Ok(()) => Ok(merged),
+                Err(errors) => Err(errors.join("; ")),
+            });
+        match merged {
+            Ok(merged) => merged,
+            Err(e) => {
+                log::debug!("Not applying runtime overrides from {}: {}", path.display(), e);
+                self.clone()
+            }
+        }
+    }
+
+    /// Where to write the log file, or None to log to stderr. A relative
+    /// `log_file` is taken to be inside `data_dir`, if set.
+    pub fn log_path(&self) -> Option<PathBuf> {
+        if self.log_file.is_empty() {
+            return None;
+        }
+        if self.data_dir.is_empty() {
+            return Some(PathBuf::from(&self.log_file));
+        }
+        Some(Path::new(&self.data_dir).join(&self.log_file))
+    }
+
+    /// Returns the config file path.
+    /// On macOS/Linux: ~/.config/nodoze/config.toml
+    /// On Windows: %APPDATA%/nodoze/config.toml
+    /// A named instance's is `config-NAME.toml` in the same directory.
+    pub fn config_path() -> Result<PathBuf, String> {
+        paths::config_dir().map(|d| d.join("nodoze").join(instance_config()))
+    }
+
+    /// Where a config file is looked for when none is given, highest
+    /// precedence first: `nodoze.toml` in the current directory, the user's
+    /// `config_path()`, the old wake-speaker one, then the system-wide
+    /// `/etc/nodoze/config.toml` (Unix). A named instance only looks for its
+    /// own `config-NAME.toml`, the user's then the system-wide one.
+    pub fn search_paths() -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        if paths::instance().is_some() {
+            paths.extend(Self::config_path().ok());
+            #[cfg(unix)]
+            paths.extend(Path::new(SYSTEM_CONFIG).parent().map(|d| d.join(instance_config())));
+            return paths;
+        }
+        if let Ok(dir) = std::env::current_dir() {
+            paths.push(dir.join(LOCAL_CONFIG));
+        }
+        paths.extend(Self::config_path().ok());
+        paths.extend(config_file(LEGACY_NAME).ok());
+        #[cfg(unix)]
+        paths.push(PathBuf::from(SYSTEM_CONFIG));
+        paths
+    }
+
+    /// The config file read when none is given: the first of
+    /// `search_paths()` that exists, else `config_path()`
+    pub fn default_path() -> Result<PathBuf, String> {
+        match Self::search_paths().into_iter().find(|p| p.exists()) {
+            Some(path) => Ok(path),
+            None => Self::config_path(),
+        }
+    }
+}
+
+/// Name of the config file in the config directory: `config.toml`, or
+/// `config-NAME.toml` for a named instance
+fn instance_config() -> String {
+    format!("{}.toml", paths::instance_name("config"))
+}
+
+fn config_file(app: &str) -> Result<PathBuf, String> {
+    paths::config_dir().map(|d| d.join(app).join("config.toml"))
+}
+
+/// Rewrite a config file's old settings into a copy next to it, named
+/// `<name>.migrated.toml`: renamed fields get their new names, unknown keys
+/// are dropped, and so is `device = ""` (the old way of selecting the
+/// default device). Returns the copy's path and a line per change, or no
+/// path if there was nothing to change. Comments are not carried over.
+pub fn migrate(path: &Path) -> Result<(Option<PathBuf>, Vec<String>), String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+    let mut table = contents
+        .parse::<toml::Table>()
+        .map_err(|e| format!("Failed to parse config {}: {}", path.display(), e))?;
+
+    let mut changes = migrate_table(&mut table, "")?;
+    if let Some(toml::Value::Array(profiles)) = table.get_mut("profile") {
+        for profile in profiles {
+            let toml::Value::Table(profile) = profile else {
+                continue;
+            };
+            let name = profile.get("name").and_then(|v| v.as_str()).unwrap_or("?");
+            let label = format!("profile '{}': ", name);
+            changes.extend(migrate_table(profile, &label)?);
+        }
+    }
+    if changes.is_empty() {
+        return Ok((None, changes));
+    }
+
+    let migrated = toml::to_string(&table)
+        .map_err(|e| format!("Failed to write migrated config: {}", e))?;
+    let out = path.with_extension("migrated.toml");
+    std::fs::write(&out, migrated)
+        .map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+    Ok((Some(out), changes))
+}
+
+/// Migrate the settings in one table (the top level or a `[[profile]]`),
+/// prefixing each change with `label`
+fn migrate_table(table: &mut toml::Table, label: &str) -> Result<Vec<String>, String> {
+    let mut changes: Vec<String> = rename_fields(table)
+        .into_iter()
+        .map(|change| format!("{}{}", label, change))
+        .collect();
+
+    if table.get("device").and_then(|v| v.as_str()) == Some("") {
+        table.remove("device");
+        changes.push(format!(
+            "{}removed `device = \"\"` (the default device is used without it)",
+            label
+        ));
+    }
+
+    // Keys that belong to the file rather than the settings
+    let mut settings = table.clone();
+    for key in ["include", "profile", "name"] {
+        settings.remove(key);
+    }
+    let parsed = toml::Value::Table(apply_notes(settings).map_err(|e| format!("{}{}", label, e))?)
+        .try_into::<Config>()
+        .map_err(|e| format!("{}{}", label, e))?;
+    let mut unknown: Vec<String> = parsed.extra.into_keys().collect();
+    unknown.sort();
+    for key in unknown {
+        table.remove(&key);
+        changes.push(format!("{}removed unknown key `{}`", label, key));
+    }
+    Ok(changes)
+}
+
+/// Move renamed fields to their new names. If both are set, the new one
+/// wins. Returns a description of each change.
+fn rename_fields(table: &mut toml::Table) -> Vec<String> {
+    let mut changes = Vec::new();
+    for &(old, new) in RENAMED_FIELDS {
+        let Some(value) = table.remove(old) else {
+            continue;
+        };
+        if table.contains_key(new) {
+            changes.push(format!("removed `{}`, already set as `{}`", old, new));
+        } else {
+            table.insert(new.to_string(), value);
+            changes.push(format!("renamed `{}` to `{}`", old, new));
+        }
+    }
+    changes
+}
+
+/// `device` or `require_process` as either a single name or a list of
+/// names. Empty names (the old way of selecting the default device) are
+/// dropped.
+fn name_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NameValue {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let names = match NameValue::deserialize(deserializer)? {
+        NameValue::One(name) => vec![name],
+        NameValue::Many(names) => names,
+    };
+    Ok(names.into_iter().filter(|n| !n.is_empty()).collect())
+}
+
+/// Make sure `wav_path` is a FLAC file, or a PCM or float WAV file, that
+/// we can decode
+fn check_clip(path: &str) -> Result<(), String> {
+    if crate::audio::is_flac(Path::new(path)) {
+        return claxon::FlacReader::open(path)
+            .map(|_| ())
+            .map_err(|e| format!("wav_path {} can't be used: {}", path, e));
+    }
+    let reader = hound::WavReader::open(path)
+        .map_err(|e| format!("wav_path {} can't be used (expected WAV or FLAC): {}", path, e))?;
+    let spec = reader.spec();
+    match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Int, 8..=32) | (hound::SampleFormat::Float, 32) => Ok(()),
+        (format, bits) => Err(format!(
+            "wav_path {} uses an unsupported format ({}-bit {:?})",
+            path, bits, format
+        )),
+    }
+}
+
+/// Volume as a linear gain, a percentage like "5%" or a dBFS string like
+/// "-26dB"
+fn volume<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum VolumeValue {
+        Linear(f64),
+        Text(String),
+    }
+
+    match VolumeValue::deserialize(deserializer)? {
+        VolumeValue::Linear(v) => Ok(v),
+        VolumeValue::Text(s) if s.trim_end().ends_with('%') => {
+            parse_percent(&s).map_err(serde::de::Error::custom)
+        }
+        VolumeValue::Text(s) => parse_dbfs(&s).map_err(serde::de::Error::custom),
+    }
+}
+
+/// List of volumes, each as accepted by `volume`
+fn volumes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f64>, D::Error> {
+    #[derive(Deserialize)]
+    struct Volume(#[serde(deserialize_with = "volume")] f64);
+
+    Ok(Vec::<Volume>::deserialize(deserializer)?
+        .into_iter()
+        .map(|v| v.0)
+        .collect())
+}
+
+/// Parse "5%" (or "5 %") into a linear gain
+fn parse_percent(s: &str) -> Result<f64, String> {
+    let number = s.trim().trim_end_matches('%').trim();
+    let percent: f64 = number.parse().map_err(|_| invalid_volume(s))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(format!("Volume '{}' must be from 0% to 100%", s));
+    }
+    Ok(percent / 100.0)
+}
+
+/// Parse "-26dB" (or "-26 dBFS") into a linear gain
+fn parse_dbfs(s: &str) -> Result<f64, String> {
+    let invalid = || invalid_volume(s);
+
+    let lower = s.trim().to_ascii_lowercase();
+    let number = lower
+        .strip_suffix("dbfs")
+        .or_else(|| lower.strip_suffix("db"))
+        .ok_or_else(invalid)?;
+    let db: f64 = number.trim().parse().map_err(|_| invalid())?;
+    if db > 0.0 {
+        return Err(format!("Volume '{}' is above 0 dBFS and would clip", s));
+    }
+    Ok(10f64.powf(db / 20.0))
+}
+
+fn invalid_volume(s: &str) -> String {
+    format!(
+        "Invalid volume '{}', expected 0.0 to 1.0 or a string like \"5%\" or \"-26dB\"",
+        s
+    )
+}
+
+/// A duration in the config: plain seconds or a string like "9m" or "1h30m"
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DurationValue {
+    Integer(u64),
+    Float(f64),
+    Text(String),
+}
+
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    match DurationValue::deserialize(deserializer)? {
+        DurationValue::Integer(n) => Ok(n as f64),
+        DurationValue::Float(n) => Ok(n),
+        DurationValue::Text(s) => parse_duration(&s).map_err(serde::de::Error::custom),
+    }
+}
+
+fn optional_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    seconds(deserializer).map(Some)
+}
+
+fn whole_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let secs = seconds(deserializer)?;
+    if secs < 0.0 || secs.fract() != 0.0 {
+        return Err(serde::de::Error::custom(format!(
+            "expected a whole number of seconds, got {}",
+            secs
+        )));
+    }
+    Ok(secs as u64)
+}
+
+fn optional_whole_seconds<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    whole_seconds(deserializer).map(Some)
+}
+
+/// Parse "90", "90s", "9m", "1h30m", "1.5s" or "500ms" into seconds
+fn parse_duration(s: &str) -> Result<f64, String> {
+    let invalid = || {
+        format!(
+            "Invalid duration '{}', expected seconds or a string like \"90s\", \"9m\" or \"1h30m\"",
+            s
+        )
+    };
+
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(invalid());
+    }
+    if let Ok(secs) = s.parse::<f64>() {
+        return Ok(secs);
+    }
+
+    let mut total = 0.0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or_else(invalid)?;
+        let value: f64 = rest[..number_len].parse().map_err(|_| invalid())?;
+        rest = &rest[number_len..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_len] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return Err(invalid()),
+        };
+        rest = &rest[unit_len..];
+
+        total += value * scale;
+    }
+    Ok(total)
+}
+
+fn parse_hhmm(s: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(s, "%H:%M")
+        .map_err(|_| format!("Invalid time '{}', expected HH:MM", s))
+}
+
+/// Fill in frequency, volume and duration from the `preset`, if any, unless
+/// they are set explicitly. An invalid preset name is left for the final
+/// deserialization to report.
+fn apply_preset(mut table: toml::Table) -> toml::Table {
+    let Some(Ok(preset)) = table.get("preset").cloned().map(|v| v.try_into::<Preset>()) else {
+        return table;
+    };
+    let (frequency, volume, duration) = preset.values();
+    for (key, value) in [
+        ("frequency", toml::Value::Float(frequency)),
+        ("volume", toml::Value::Float(volume)),
+        ("duration", toml::Value::Integer(duration as i64)),
+    ] {
+        table.entry(key).or_insert(value);
+    }
+    table
+}
+
+/// A `frequency` given as a note name becomes its pitch in Hz, tuned to
+/// the table's `a4_frequency`
+fn apply_notes(mut table: toml::Table) -> Result<toml::Table, String> {
+    let Some(toml::Value::String(note)) = table.get("frequency") else {
+        return Ok(table);
+    };
+    let a4 = match table.get("a4_frequency") {
+        Some(toml::Value::Float(f)) => *f,
+        Some(toml::Value::Integer(i)) => *i as f64,
+        _ => default_a4_frequency(),
+    };
+    let frequency = parse_note(note, a4)?;
+    table.insert("frequency".to_string(), toml::Value::Float(frequency));
+    Ok(table)
+}
+
+/// Parse a note name such as "A0", "E1", "F#1" or "Bb0" into its frequency
+/// in equal temperament, with A4 at `a4` Hz, to the nearest 0.001 Hz.
+/// Octaves run from -1 to 9.
+fn parse_note(s: &str, a4: f64) -> Result<f64, String> {
+    let invalid = || {
+        format!(
+            "Invalid frequency '{}', expected Hz or a note name like \"A0\", \"E1\" or \"F#1\"",
+            s
+        )
+    };
+
+    let mut chars = s.trim().chars();
+    // Semitones above C in the same octave
+    let semitone = match chars.next().map(|c| c.to_ascii_uppercase()) {
+        Some('C') => 0,
+        Some('D') => 2,
+        Some('E') => 4,
+        Some('F') => 5,
+        Some('G') => 7,
+        Some('A') => 9,
+        Some('B') => 11,
+        _ => return Err(invalid()),
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = if let Some(octave) = rest.strip_prefix(['#', '♯']) {
+        (1, octave)
+    } else if let Some(octave) = rest.strip_prefix(['b', '♭']) {
+        (-1, octave)
+    } else {
+        (0, rest)
+    };
+    let octave: i32 = octave.parse().map_err(|_| invalid())?;
+    if !(-1..=9).contains(&octave) {
+        return Err(invalid());
+    }
+    // MIDI note number, where A4 is 69
+    let note = (octave + 1) * 12 + semitone + accidental;
+    let frequency = a4 * 2f64.powf((note - 69) as f64 / 12.0);
+    Ok((frequency * 1000.0).round() / 1000.0)
+}
+
+/// A `comb_fundamental` that is set becomes the tone's `frequency`, so
+/// jitter, rotation and the Nyquist check all work from it
+fn apply_comb(mut table: toml::Table) -> toml::Table {
+    let fundamental = match table.get("comb_fundamental") {
+        Some(toml::Value::Float(f)) => *f,
+        Some(toml::Value::Integer(i)) => *i as f64,
+        _ => return table,
+    };
+    if fundamental > 0.0 {
+        table.insert("frequency".to_string(), toml::Value::Float(fundamental));
+    }
+    table
+}
+
+/// Whether a file that failed to load sets `strict = true`. Looked for line
+/// by line, since the file may not be valid TOML.
+fn requests_strict(path: &Path) -> bool {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return false;
+    };
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").replace([' ', '\t'], ""))
+        .take_while(|line| !line.starts_with('['))
+        .any(|line| line == "strict=true")
+}
+
+/// Read a config file as a TOML table. If it has an `include` key, the named
+/// file (relative to this one) is read first and this file's settings are
+/// merged over it. `chain` holds the files currently being read, to catch
+/// include cycles.
+fn read_table(path: &Path, chain: &mut Vec<PathBuf>) -> Result<toml::Table, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+    let mut table = contents
+        .parse::<toml::Table>()
+        .map_err(|e| format!("Failed to parse config {}: {}", path.display(), e))?;
+
+    let mut renamed = rename_fields(&mut table);
+    if let Some(toml::Value::Array(profiles)) = table.get_mut("profile") {
+        for profile in profiles.iter_mut().filter_map(|p| p.as_table_mut()) {
+            renamed.extend(rename_fields(profile));
+        }
+    }
+    for change in renamed {
+        log::warn!("{}: {}; run `nodoze migrate` to update the file", path.display(), change);
+    }
+
+    let include = match table.remove("include") {
+        Some(toml::Value::String(include)) => include,
+        Some(_) => return Err(format!("`include` in {} must be a file path", path.display())),
+        None => return Ok(table),
+    };
+
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    chain.push(canonical);
+    let include_path = path.parent().unwrap_or(Path::new(".")).join(include);
+    let include_canonical = include_path
+        .canonicalize()
+        .unwrap_or_else(|_| include_path.clone());
+    if chain.contains(&include_canonical) {
+        let files: Vec<String> = chain
+            .iter()
+            .chain([&include_canonical])
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(format!("Config include cycle: {}", files.join(" -> ")));
+    }
+
+    let base = read_table(&include_path, chain)?;
+    chain.pop();
+    Ok(merge_tables(base, table))
+}
+
+/// Merge `overrides` into `base`. Nested tables are merged key by key; any
+/// other value (including arrays such as `[[profile]]`) replaces the base's.
+fn merge_tables(mut base: toml::Table, overrides: toml::Table) -> toml::Table {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(table)) => {
+                *base_table = merge_tables(std::mem::take(base_table), table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+    base
+}
+
+/// Merge the selected `[[profile]]` table over the top-level settings.
+/// Fields a profile leaves out inherit the top-level values.
+fn apply_profile(mut table: toml::Table, requested: Option<&str>) -> Result<toml::Table, String> {
+    let profiles = match table.remove("profile") {
+        Some(toml::Value::Array(profiles)) => profiles,
+        Some(_) => return Err("`profile` must be a list of [[profile]] tables".to_string()),
+        None => Vec::new(),
+    };
+
+    let name = match requested {
+        Some(name) => name.to_string(),
+        None => match table.get("active_profile").and_then(|v| v.as_str()) {
+            Some(name) => name.to_string(),
+            None => return Ok(table),
+        },
+    };
+
+    let mut available = Vec::new();
+    let mut selected = None;
+    for profile in profiles {
+        let toml::Value::Table(profile) = profile else {
+            return Err("`profile` must be a list of [[profile]] tables".to_string());
+        };
+        let profile_name = profile
+            .get("name")
+            .and_then(|v| v.as_str())
+            .ok_or("Every [[profile]] needs a `name`")?
+            .to_string();
+        if profile_name == name {
+            selected = Some(profile);
+        }
+        available.push(profile_name);
+    }
+
+    let profile = selected.ok_or_else(|| {
+        if available.is_empty() {
+            format!("Profile '{}' not found (no profiles defined)", name)
+        } else {
+            format!(
+                "Profile '{}' not found (available: {})",
+                name,
+                available.join(", ")
+            )
+        }
+    })?;
+
+    for (key, value) in profile {
+        if key != "name" {
+            table.insert(key, value);
+        }
+    }
+    table.insert("active_profile".to_string(), toml::Value::String(name));
+
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `contents` to a config file in a fresh temporary directory
+    fn write_config(contents: &str) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, contents).unwrap();
+        (dir, path)
+    }
+
+    fn load(path: &Path, profile: Option<&str>) -> Result<Config, String> {
+        Config::load(path.to_str(), profile, false)
+    }
+
+    const PROFILES: &str = r#"
+        frequency = 25.0
+        volume = 0.1
+        active_profile = "studio"
+
+        [[profile]]
+        name = "studio"
+        frequency = 30.0
+
+        [[profile]]
+        name = "laptop"
+        volume = 0.3
+    "#;
+
+    #[test]
+    fn profile_inherits_top_level_settings() {
+        let (_dir, path) = write_config(PROFILES);
+        let config = load(&path, Some("laptop")).unwrap();
+        assert_eq!(config.volume, 0.3);
+        assert_eq!(config.frequency, 25.0);
+        assert_eq!(config.active_profile.as_deref(), Some("laptop"));
+    }
+
+    #[test]
+    fn active_profile_is_used_unless_one_is_requested() {
+        let (_dir, path) = write_config(PROFILES);
+        let config = load(&path, None).unwrap();
+        assert_eq!(config.frequency, 30.0);
+        assert_eq!(config.volume, 0.1);
+    }
+
+    #[test]
+    fn unknown_profile_is_an_error_naming_the_others() {
+        let (_dir, path) = write_config(PROFILES);
+        let e = load(&path, Some("kitchen")).unwrap_err();
+        assert!(e.contains("Profile 'kitchen' not found"), "{}", e);
+        assert!(e.contains("studio, laptop"), "{}", e);
+    }
+
+    #[test]
+    fn profile_without_any_defined_is_an_error() {
+        let (_dir, path) = write_config("frequency = 25.0\n");
+        let e = load(&path, Some("studio")).unwrap_err();
+        assert!(e.contains("no profiles defined"), "{}", e);
+    }
+
+    #[test]
+    fn durations_parse_from_seconds_and_units() {
+        for (text, secs) in [
+            ("90", 90.0),
+            ("90s", 90.0),
+            ("9m", 540.0),
+            ("2h", 7200.0),
+            ("1h30m", 5400.0),
+            ("1.5s", 1.5),
+            ("500ms", 0.5),
+            (" 9m ", 540.0),
+        ] {
+            assert_eq!(parse_duration(text), Ok(secs), "{}", text);
+        }
+    }
+
+    #[test]
+    fn malformed_durations_are_rejected() {
+        for text in ["", "m", "9x", "9 m", "h9", "1..5s", "-9m"] {
+            let e = parse_duration(text).unwrap_err();
+            assert!(e.contains(&format!("Invalid duration '{}'", text)), "{}", e);
+        }
+    }
+
+    #[test]
+    fn config_durations_accept_unit_strings() {
+        let (_dir, path) = write_config("interval = \"9m\"\nfade_duration = \"500ms\"\n");
+        let config = load(&path, None).unwrap();
+        assert_eq!(config.interval, 540);
+        assert_eq!(config.fade_duration, 0.5);
+
+        let (_dir, path) = write_config("interval = \"1.5s\"\n");
+        let e = Config::load(path.to_str(), None, true).unwrap_err();
+        assert!(e.contains("whole number of seconds"), "{}", e);
+    }
+
+    #[test]
+    fn dbfs_volumes_convert_to_linear_gain() {
+        for (text, gain) in [("0dB", 1.0), ("-6dB", 0.501), ("-20 dBFS", 0.1), ("-26dB", 0.050)] {
+            let volume = parse_dbfs(text).unwrap();
+            assert!((volume - gain).abs() < 0.001, "{} gave {}", text, volume);
+        }
+    }
+
+    #[test]
+    fn positive_dbfs_volumes_are_rejected() {
+        let e = parse_dbfs("+3dB").unwrap_err();
+        assert!(e.contains("above 0 dBFS and would clip"), "{}", e);
+        assert!(parse_dbfs("-26").unwrap_err().contains("Invalid volume"));
+    }
+
+    #[test]
+    fn config_volume_accepts_dbfs() {
+        let (_dir, path) = write_config("volume = \"-20dB\"\n");
+        let config = load(&path, None).unwrap();
+        assert!((config.volume - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sub_hertz_frequencies_are_allowed_unless_near_dc() {
+        let config = Config {
+            frequency: 0.5,
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+        assert!(!config.is_near_dc());
+
+        let slow = Config {
+            frequency: 0.05,
+            ..config
+        };
+        assert!(slow.is_near_dc());
+
+        // A burst's length depends on the device, so it isn't judged
+        let burst = Config {
+            duration: 0,
+            ..slow
+        };
+        assert!(!burst.is_near_dc());
+    }
+
+    #[test]
+    fn config_is_found_in_precedence_order() {
+        let _env = paths::env_lock();
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path().canonicalize().unwrap();
+        let cwd = std::env::current_dir().unwrap();
+        let xdg = std::env::var_os("XDG_CONFIG_HOME");
+        std::fs::create_dir(root.join("project")).unwrap();
+        std::env::set_current_dir(root.join("project")).unwrap();
+        std::env::set_var("XDG_CONFIG_HOME", root.join("xdg"));
+
+        let local = root.join("project").join(LOCAL_CONFIG);
+        let user = root.join("xdg").join("nodoze").join("config.toml");
+        let legacy = root.join("xdg").join(LEGACY_NAME).join("config.toml");
+        let searched = Config::search_paths();
+        assert_eq!(searched[..3], [local.clone(), user.clone(), legacy.clone()]);
+        #[cfg(unix)]
+        assert_eq!(searched[3], Path::new(SYSTEM_CONFIG));
+
+        // Nothing there yet, so a new config goes in the user's directory
+        let found = |expected: &Path| Config::default_path().is_ok_and(|p| p == expected);
+        let nothing = found(&user);
+        for path in [&legacy, &user, &local] {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let local_first = found(&local);
+        std::fs::remove_file(&local).unwrap();
+        let user_next = found(&user);
+        std::fs::remove_file(&user).unwrap();
+        let legacy_last = found(&legacy);
+
+        std::env::set_current_dir(cwd).unwrap();
+        match xdg {
+            Some(xdg) => std::env::set_var("XDG_CONFIG_HOME", xdg),
+            None => std::env::remove_var("XDG_CONFIG_HOME"),
+        }
+        assert!(nothing && local_first && user_next && legacy_last);
+    }
+
+    #[test]
+    fn note_names_resolve_to_equal_temperament() {
+        for (note, frequency) in [
+            ("A4", 440.0),
+            ("A0", 27.5),
+            ("E1", 41.203),
+            ("F#1", 46.249),
+            ("Gb1", 46.249),
+            ("bb0", 29.135),
+            ("C-1", 8.176),
+            ("B♭0", 29.135),
+        ] {
+            assert_eq!(parse_note(note, 440.0), Ok(frequency), "{}", note);
+        }
+        assert_eq!(parse_note("A0", 432.0), Ok(27.0));
+    }
+
+    #[test]
+    fn malformed_note_names_are_rejected() {
+        for note in ["H2", "A", "A10", "C#", "A#b1", "20Hz", ""] {
+            let e = parse_note(note, 440.0).unwrap_err();
+            assert!(e.contains("expected Hz or a note name"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn config_frequency_accepts_a_note_name() {
+        let (_dir, path) = write_config("frequency = \"A0\"\na4_frequency = 432\n");
+        assert_eq!(load(&path, None).unwrap().frequency, 27.0);
+    }
+
+    #[test]
+    fn including_file_overrides_the_included_one() {
+        let (dir, path) = write_config(
+            "include = \"base.toml\"\nvolume = 0.2\n[modulation]\nmod_depth = 0.5\n",
+        );
+        std::fs::write(
+            dir.path().join("base.toml"),
+            "frequency = 30.0\nvolume = 0.1\n[modulation]\nmod_rate = 2.0\nmod_depth = 0.1\n",
+        )
+        .unwrap();
+        let config = load(&path, None).unwrap();
+        assert_eq!(config.frequency, 30.0);
+        assert_eq!(config.volume, 0.2);
+        assert_eq!(config.modulation.mod_rate, 2.0);
+        assert_eq!(config.modulation.mod_depth, 0.5);
+    }
+
+    #[test]
+    fn include_cycles_are_reported() {
+        let (dir, path) = write_config("include = \"a.toml\"\n");
+        std::fs::write(dir.path().join("a.toml"), "include = \"b.toml\"\n").unwrap();
+        std::fs::write(dir.path().join("b.toml"), "include = \"a.toml\"\n").unwrap();
+        let e = Config::load(path.to_str(), None, true).unwrap_err();
+        assert!(e.contains("Config include cycle"), "{}", e);
+        assert!(e.contains("a.toml -> "), "{}", e);
+    }
+
+    #[test]
+    fn percent_volumes_convert_to_linear_gain() {
+        for (text, gain) in [("5%", 0.05), ("100%", 1.0), ("0 %", 0.0), ("12.5%", 0.125)] {
+            let volume = parse_percent(text).unwrap();
+            assert!((volume - gain).abs() < 1e-9, "{} gave {}", text, volume);
+        }
+    }
+
+    #[test]
+    fn percent_volumes_above_100_are_rejected() {
+        let e = parse_percent("150%").unwrap_err();
+        assert!(e.contains("must be from 0% to 100%"), "{}", e);
+        assert!(parse_percent("-5%").is_err());
+        assert!(parse_percent("loud%").unwrap_err().contains("Invalid volume"));
+    }
+
+    #[test]
+    fn config_volume_accepts_percent() {
+        let (_dir, path) = write_config("volume = \"5%\"\n");
+        let config = load(&path, None).unwrap();
+        assert!((config.volume - 0.05).abs() < 1e-9);
+
+        let (_dir, path) = write_config("volume = \"150%\"\n");
+        let e = Config::load(path.to_str(), None, true).unwrap_err();
+        assert!(e.contains("must be from 0% to 100%"), "{}", e);
+    }
+
+    /// Warnings logged on this thread while `during` runs. Tests run on
+    /// threads of their own, so others' warnings don't show up.
+    fn warnings(during: impl FnOnce()) -> Vec<String> {
+        type Captured = std::sync::Mutex<Vec<(std::thread::ThreadId, String)>>;
+        struct Capture(Captured);
+        impl log::Log for Capture {
+            fn enabled(&self, metadata: &log::Metadata) -> bool {
+                metadata.level() <= log::Level::Warn
+            }
+            fn log(&self, record: &log::Record) {
+                if self.enabled(record.metadata()) {
+                    let id = std::thread::current().id();
+                    self.0.lock().unwrap().push((id, record.args().to_string()));
+                }
+            }
+            fn flush(&self) {}
+        }
+        static CAPTURE: Capture = Capture(std::sync::Mutex::new(Vec::new()));
+
+        if log::set_logger(&CAPTURE).is_ok() {
+            log::set_max_level(log::LevelFilter::Warn);
+        }
+        let id = std::thread::current().id();
+        CAPTURE.0.lock().unwrap().retain(|(thread, _)| *thread != id);
+        during();
+        let captured = CAPTURE.0.lock().unwrap();
+        captured.iter().filter(|(thread, _)| *thread == id).map(|(_, msg)| msg.clone()).collect()
+    }
+
+    fn bounded() -> Config {
+        Config {
+            min_frequency: 10.0,
+            max_frequency: 60.0,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn frequency_within_bounds_is_kept_without_a_warning() {
+        let config = bounded();
+        let logged = warnings(|| {
+            assert_eq!(config.clamped_frequency(10.0), 10.0);
+            assert_eq!(config.clamped_frequency(33.0), 33.0);
+            assert_eq!(config.clamped_frequency(60.0), 60.0);
+        });
+        assert!(logged.is_empty(), "{:?}", logged);
+    }
+
+    #[test]
+    fn frequency_outside_bounds_is_clamped_with_a_warning() {
+        let config = bounded();
+        let logged = warnings(|| assert_eq!(config.clamped_frequency(4.0), 10.0));
+        assert_eq!(
+            logged,
+            ["4 Hz is outside min_frequency/max_frequency, playing 10 Hz instead"]
+        );
+        let logged = warnings(|| assert_eq!(config.clamped_frequency(75.0), 60.0));
+        assert_eq!(
+            logged,
+            ["75 Hz is outside min_frequency/max_frequency, playing 60 Hz instead"]
+        );
+    }
+
+    #[test]
+    fn no_max_frequency_leaves_high_frequencies_alone() {
+        let config = Config {
+            min_frequency: 10.0,
+            ..Config::default()
+        };
+        assert_eq!(config.clamped_frequency(15_000.0), 15_000.0);
+    }
+
+    #[test]
+    fn rotated_frequencies_are_clamped_each_in_turn() {
+        let config = Config {
+            frequency_rotation: vec![5.0, 20.0, 90.0],
+            ..bounded()
+        };
+        let mut played = Vec::new();
+        let logged = warnings(|| {
+            for &frequency in &config.frequency_rotation {
+                played.push(config.clamped_frequency(frequency));
+            }
+        });
+        assert_eq!(played, [10.0, 20.0, 60.0]);
+        assert_eq!(logged.len(), 2, "{:?}", logged);
+    }
+
+    #[test]
+    fn jittered_frequencies_are_clamped_to_the_bounds() {
+        let config = Config {
+            frequency: 55.0,
+            frequency_jitter: 20.0,
+            ..bounded()
+        };
+        let mut rng = crate::rng::Rng::new(7);
+        let mut clamped = 0;
+        let logged = warnings(|| {
+            for _ in 0..1_000 {
+                let jittered = config.frequency + rng.symmetric(config.frequency_jitter);
+                let frequency = config.clamped_frequency(jittered);
+                assert!((10.0..=60.0).contains(&frequency), "{} Hz", frequency);
+                if frequency != jittered {
+                    clamped += 1;
+                }
+            }
+        });
+        assert!(clamped > 0);
+        assert_eq!(logged.len(), clamped);
+    }
+
+    fn targeted(layout: ChannelLayout, target: ChannelTarget) -> Config {
+        Config {
+            channel_layout: layout,
+            channel_target: Some(target),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn auto_layout_is_guessed_from_the_channel_count() {
+        assert_eq!(ChannelLayout::Auto.resolve(2), Some(ChannelLayout::Stereo));
+        assert_eq!(ChannelLayout::Auto.resolve(6), Some(ChannelLayout::Surround51));
+        assert_eq!(ChannelLayout::Auto.resolve(8), Some(ChannelLayout::Surround71));
+        assert_eq!(ChannelLayout::Auto.resolve(4), None);
+        assert_eq!(ChannelLayout::Surround51.resolve(8), Some(ChannelLayout::Surround51));
+    }
+
+    #[test]
+    fn channel_targets_map_to_layout_channels() {
+        use ChannelTarget::*;
+        let cases: [(ChannelTarget, usize, &[usize]); 16] = [
+            (All, 2, &[0, 1]),
+            (Fronts, 2, &[0, 1]),
+            (AllExceptLfe, 2, &[0, 1]),
+            (All, 6, &[0, 1, 2, 3, 4, 5]),
+            (Fronts, 6, &[0, 1]),
+            (Center, 6, &[2]),
+            (Lfe, 6, &[3]),
+            (Surrounds, 6, &[4, 5]),
+            (AllExceptLfe, 6, &[0, 1, 2, 4, 5]),
+            (AllExceptCenterLfe, 6, &[0, 1, 4, 5]),
+            (All, 8, &[0, 1, 2, 3, 4, 5, 6, 7]),
+            (Center, 8, &[2]),
+            (Lfe, 8, &[3]),
+            (Surrounds, 8, &[4, 5, 6, 7]),
+            (AllExceptLfe, 8, &[0, 1, 2, 4, 5, 6, 7]),
+            (AllExceptCenterLfe, 8, &[0, 1, 4, 5, 6, 7]),
+        ];
+        for (target, channels, expected) in cases {
+            let config = targeted(ChannelLayout::Auto, target);
+            let found = config.tone_channels(channels);
+            assert_eq!(found, expected, "{} on {} channels", target.name(), channels);
+        }
+    }
+
+    #[test]
+    fn channel_target_missing_from_the_device_plays_everywhere() {
+        // No LFE in 2.0
+        let config = targeted(ChannelLayout::Stereo, ChannelTarget::Lfe);
+        let logged = warnings(|| assert_eq!(config.tone_channels(2), [0, 1]));
+        assert_eq!(logged.len(), 1, "{:?}", logged);
+        assert!(logged[0].contains("channel_target lfe isn't in a 2.0 layout"));
+
+        // A 4-channel device has no layout to guess
+        let config = targeted(ChannelLayout::Auto, ChannelTarget::Center);
+        let logged = warnings(|| assert_eq!(config.tone_channels(4), [0, 1, 2, 3]));
+        assert!(logged[0].contains("set channel_layout"), "{:?}", logged);
+    }
+
+    #[test]
+    fn channel_target_is_cut_to_the_device_channels() {
+        // 7.1 named for a device that only opened with 6 channels
+        let config = targeted(ChannelLayout::Surround71, ChannelTarget::Surrounds);
+        assert_eq!(config.tone_channels(6), [4, 5]);
+    }
+
+    #[test]
+    fn channel_mode_applies_without_a_target() {
+        let config = Config {
+            channel_mode: ChannelMode::FrontOnly,
+            ..Config::default()
+        };
+        assert_eq!(config.tone_channels(6), [0, 1]);
+        assert_eq!(Config::default().tone_channels(6), [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn channel_layout_and_target_parse_from_config() {
+        let (_dir, path) =
+            write_config("channel_layout = \"7.1\"\nchannel_target = \"all_except_center_lfe\"\n");
+        let config = load(&path, None).unwrap();
+        assert_eq!(config.channel_layout, ChannelLayout::Surround71);
+        assert_eq!(config.channel_target, Some(ChannelTarget::AllExceptCenterLfe));
+        for (name, layout) in [
+            ("auto", ChannelLayout::Auto),
+            ("2.0", ChannelLayout::Stereo),
+            ("5.1", ChannelLayout::Surround51),
+        ] {
+            let (_dir, path) = write_config(&format!("channel_layout = \"{}\"\n", name));
+            assert_eq!(load(&path, None).unwrap().channel_layout, layout);
+            assert_eq!(layout.name(), name);
+        }
+    }
+
+    #[test]
+    fn old_field_names_still_load() {
+        let (_dir, path) = write_config("fundamental = 20.0\nharmonics = 4\n");
+        let config = Config::load(path.to_str(), None, true).unwrap();
+        assert_eq!(config.comb_fundamental, 20.0);
+        assert_eq!(config.comb_count, 4);
+    }
+
+    #[test]
+    fn migrate_renames_old_fields() {
+        let (_dir, path) = write_config("fundamental = 20.0\nharmonics = 4\ncomb_count = 3\n");
+        let (out, changes) = migrate(&path).unwrap();
+        assert_eq!(
+            changes,
+            [
+                "renamed `fundamental` to `comb_fundamental`",
+                "removed `harmonics`, already set as `comb_count`",
+            ]
+        );
+        let out = out.unwrap();
+        assert_eq!(out, path.with_file_name("config.migrated.toml"));
+        let table = std::fs::read_to_string(&out).unwrap().parse::<toml::Table>().unwrap();
+        assert_eq!(table.get("comb_fundamental"), Some(&toml::Value::Float(20.0)));
+        // The new name wins when both are set
+        assert_eq!(table.get("comb_count"), Some(&toml::Value::Integer(3)));
+        assert!(!table.contains_key("fundamental") && !table.contains_key("harmonics"));
+    }
+
+    #[test]
+    fn migrate_drops_unknown_keys_and_empty_device() {
+        let (_dir, path) = write_config(
+            "frequency = 25.0\ndevice = \"\"\nspeaker_name = \"den\"\n\n\
+             [[profile]]\nname = \"night\"\nharmonics = 2\nloudness = 3\n",
+        );
+        let (out, changes) = migrate(&path).unwrap();
+        assert_eq!(
+            changes,
+            [
+                "removed `device = \"\"` (the default device is used without it)",
+                "removed unknown key `speaker_name`",
+                "profile 'night': renamed `harmonics` to `comb_count`",
+                "profile 'night': removed unknown key `loudness`",
+            ]
+        );
+        let table = std::fs::read_to_string(out.unwrap()).unwrap().parse::<toml::Table>().unwrap();
+        assert_eq!(table.get("frequency"), Some(&toml::Value::Float(25.0)));
+        assert!(!table.contains_key("device") && !table.contains_key("speaker_name"));
+        let profile = table["profile"][0].as_table().unwrap();
+        assert_eq!(profile.get("comb_count"), Some(&toml::Value::Integer(2)));
+        assert!(!profile.contains_key("loudness"));
+
+        // The original file is left as it was
+        assert!(std::fs::read_to_string(&path).unwrap().contains("speaker_name"));
+    }
+
+    #[test]
+    fn migrate_leaves_a_current_config_alone() {
+        let (dir, path) = write_config("frequency = 25.0\ncomb_count = 2\n");
+        let (out, changes) = migrate(&path).unwrap();
+        assert_eq!(out, None);
+        assert!(changes.is_empty());
+        assert!(!dir.path().join("config.migrated.toml").exists());
+    }
+
+    #[test]
+    fn zero_channels_count_is_rejected() {
+        let config = Config {
+            channels_count: Some(0),
+            ..Config::default()
+        };
+        let errors = config.validate().unwrap_err();
+        let rejected = errors.iter().any(|e| e.contains("channels_count must be at least 1"));
+        assert!(rejected, "{:?}", errors);
+
+        let config = Config {
+            channels_count: Some(6),
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+}
@@ -0,0 +1,397 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::audio::ToneStopper;
+use crate::{paths, state};
+
+/// How long a `prime` request waits for the loop to play its tone
+const PRIME_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[cfg(unix)]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// State shared between the daemon loop and the control socket
+#[derive(Default)]
+pub struct Shared {
+    paused: AtomicBool,
+    play_now: AtomicBool,
+    /// The requested tone should play at `wake_boost_volume`
+    boost: AtomicBool,
+    last_play: Mutex<Option<SystemTime>>,
+    /// When the next scheduled tone is due; None while paused, in quiet
+    /// hours or playing continuously
+    next_play: Mutex<Option<SystemTime>>,
+    /// Tones that failed since the last one that played
+    failures: AtomicU32,
+    /// Tones currently playing (one per device), so pausing can fade them
+    /// out early
+    playing: Mutex<Vec<ToneStopper>>,
+    /// Set once the daemon loop has been asked to exit
+    stopped: Mutex<bool>,
+    stop_signal: Condvar,
+    /// The system resumed from sleep since the loop last checked
+    woke: AtomicBool,
+    /// Set by `wake` and `prime` to end the loop's current sleep early
+    interrupted: AtomicBool,
+    /// A priming tone was requested and the loop hasn't played it yet
+    prime: AtomicBool,
+    /// The loop's answer to the pending priming request
+    prime_reply: Mutex<Option<Result<String, String>>>,
+    primed: Condvar,
+}
+
+impl Shared {
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Returns true (once) if a tone was requested out-of-band
+    pub fn take_play_now(&self) -> bool {
+        self.play_now.swap(false, Ordering::Relaxed)
+    }
+
+    /// Returns true (once) if the requested tone should be boosted. Only
+    /// meaningful after `take_play_now` returned true.
+    pub fn take_boost(&self) -> bool {
+        self.boost.swap(false, Ordering::Relaxed)
+    }
+
+    /// Whether the system woke since the last call, clearing it
+    pub fn take_woke(&self) -> bool {
+        self.woke.swap(false, Ordering::Relaxed)
+    }
+
+    /// Returns true (once) if a priming tone was requested
+    pub fn take_prime(&self) -> bool {
+        self.prime.swap(false, Ordering::Relaxed)
+    }
+
+    /// Answer the pending priming request: what happened, or why no tone
+    /// played
+    pub fn primed(&self, reply: Result<String, String>) {
+        *self.prime_reply.lock().unwrap() = Some(reply);
+        self.primed.notify_all();
+    }
+
+    /// Play a short tone at `wake_boost_volume` now, ahead of media
+    /// starting, and wait for it to finish. Plays while paused and in quiet
+    /// hours, but not within `min_gap` of another tone, since the speakers
+    /// are awake then anyway.
+    pub fn prime(&self) -> Result<String, String> {
+        if !self.playing.lock().unwrap().is_empty() {
+            return Ok("a tone is already playing".to_string());
+        }
+        *self.prime_reply.lock().unwrap() = None;
+        self.prime.store(true, Ordering::Relaxed);
+        self.interrupt();
+        let reply = self.prime_reply.lock().unwrap();
+        let (mut reply, _) = self
+            .primed
+            .wait_timeout_while(reply, PRIME_TIMEOUT, |reply| reply.is_none())
+            .unwrap();
+        reply
+            .take()
+            .unwrap_or_else(|| Err("timed out waiting for the daemon to play the tone".to_string()))
+    }
+
+    pub fn set_playing(&self, stoppers: Vec<ToneStopper>) {
+        *self.playing.lock().unwrap() = stoppers;
+    }
+
+    /// Track tones as playing alongside any that already are, for tones
+    /// played at the same time by `concurrent_plays`
+    pub fn add_playing(&self, stoppers: &[ToneStopper]) {
+        self.playing.lock().unwrap().extend(stoppers.iter().cloned());
+    }
+
+    /// Stop tracking tones added with `add_playing`, once they've ended
+    pub fn remove_playing(&self, stoppers: &[ToneStopper]) {
+        self.playing
+            .lock()
+            .unwrap()
+            .retain(|playing| !stoppers.iter().any(|stopper| stopper.is(playing)));
+    }
+
+    /// Record a tone that played, which also ends any run of failures
+    pub fn set_last_play(&self, time: SystemTime) {
+        *self.last_play.lock().unwrap() = Some(time);
+        self.failures.store(0, Ordering::Relaxed);
+    }
+
+    pub fn tone_failed(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Start from failures carried over from the last run
+    pub fn set_failures(&self, failures: u32) {
+        self.failures.store(failures, Ordering::Relaxed);
+    }
+
+    pub fn set_next_play(&self, time: Option<SystemTime>) {
+        *self.next_play.lock().unwrap() = time;
+    }
+
+    /// Fade out the tones currently playing, if any, and wait for the fade
+    /// to finish
+    pub fn fade_out(&self) {
+        let fade = self
+            .playing
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|stopper| {
+                stopper.stop();
+                stopper.fade_duration()
+            })
+            .max();
+        if let Some(fade) = fade {
+            std::thread::sleep(fade + Duration::from_millis(50));
+        }
+    }
+
+    /// Ask the daemon loop to exit, fading out any tone first
+    pub fn stop(&self) {
+        *self.stopped.lock().unwrap() = true;
+        self.stop_signal.notify_all();
+        self.fade_out();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.stopped.lock().unwrap()
+    }
+
+    /// Sleep for `duration`, waking early if the daemon is asked to stop
+    /// or `wake` or `prime` cuts it short. Returns true if the daemon has been stopped.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let stopped = self.stopped.lock().unwrap();
+        let (stopped, _) = self
+            .stop_signal
+            .wait_timeout_while(stopped, duration, |stopped| {
+                !*stopped && !self.interrupted.swap(false, Ordering::Relaxed)
+            })
+            .unwrap();
+        *stopped
+    }
+
+    /// The system has resumed from sleep: cut the loop's current sleep
+    /// short so it plays a tone now rather than at the next poll. Unlike
+    /// `play-now`, the tone still waits out pauses and quiet hours.
+    pub fn wake(&self) {
+        self.woke.store(true, Ordering::Relaxed);
+        self.interrupt();
+    }
+
+    /// End the loop's current sleep early
+    fn interrupt(&self) {
+        // Under the lock, so the notification can't slip in between the
+        // sleeping thread checking the flag and starting to wait
+        let _stopped = self.stopped.lock().unwrap();
+        self.interrupted.store(true, Ordering::Relaxed);
+        self.stop_signal.notify_all();
+    }
+
+    /// Apply a single line command and return the reply line
+    fn handle(&self, command: &str) -> String {
+        match command {
+            "pause" => {
+                if !self.paused.swap(true, Ordering::Relaxed) {
+                    log::info!("Paused via control socket");
+                }
+                for stopper in self.playing.lock().unwrap().iter() {
+                    stopper.stop();
+                }
+                "ok: paused".to_string()
+            }
+            "resume" => {
+                if self.paused.swap(false, Ordering::Relaxed) {
+                    log::info!("Resumed via control socket");
+                }
+                "ok: running".to_string()
+            }
+            "play-now" => {
+                self.play_now.store(true, Ordering::Relaxed);
+                log::info!("Tone requested via control socket");
+                "ok: tone queued".to_string()
+            }
+            "play-now boost" => {
+                // Before play_now, so the loop never sees the request without it
+                self.boost.store(true, Ordering::Relaxed);
+                self.play_now.store(true, Ordering::Relaxed);
+                log::info!("Boosted wake tone requested via control socket");
+                "ok: boosted tone queued".to_string()
+            }
+            "prime" => {
+                log::info!("Priming tone requested via control socket");
+                match self.prime() {
+                    Ok(reply) => format!("ok: {}", reply),
+                    Err(e) => format!("error: {}", e),
+                }
+            }
+            "status" => self.status_line(),
+            "info" => serde_json::to_string(&self.info())
+                .unwrap_or_else(|e| format!("error: {}", e)),
+            "pid" => std::process::id().to_string(),
+            other => format!("error: unknown command '{}'", other),
+        }
+    }
+
+    fn status_line(&self) -> String {
+        let state = if self.is_paused() { "paused" } else { "running" };
+        let last = match *self.last_play.lock().unwrap() {
+            Some(t) => match t.elapsed() {
+                Ok(elapsed) => format!("last tone {}s ago", elapsed.as_secs()),
+                Err(_) => "last tone just now".to_string(),
+            },
+            None => "no tone played yet".to_string(),
+        };
+        format!("{}, {}", state, last)
+    }
+
+    fn info(&self) -> Info {
+        let unix = |time: Option<SystemTime>| {
+            time.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+        };
+        Info {
+            paused: self.is_paused(),
+            last_play_unix: unix(*self.last_play.lock().unwrap()),
+            next_play_unix: unix(*self.next_play.lock().unwrap()),
+            failures: self.failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Reply to the `info` command: the daemon's schedule as one JSON line
+#[derive(Serialize, Deserialize)]
+pub struct Info {
+    pub paused: bool,
+    pub last_play_unix: Option<u64>,
+    pub next_play_unix: Option<u64>,
+    /// Consecutive failed tones
+    pub failures: u32,
+}
+
+/// Returns the control socket path.
+/// Uses `data_dir` if set, else $XDG_RUNTIME_DIR where available, otherwise
+/// the per-user temp dir. A named instance has its own, `nodoze-NAME.sock`.
+pub fn socket_path() -> PathBuf {
+    let name = format!("{}.sock", paths::instance_name("nodoze"));
+    if let Some(dir) = state::data_dir() {
+        return dir.join(name);
+    }
+    dirs::runtime_dir().unwrap_or_else(std::env::temp_dir).join(name)
+}
+
+/// Listening control socket, removed from disk when dropped
+pub struct Server {
+    path: PathBuf,
+}
+
+impl Server {
+    /// Bind the control socket and service it on a background thread
+    #[cfg(unix)]
+    pub fn bind(shared: Arc<Shared>) -> Result<Self, String> {
+        let path = socket_path();
+
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(format!(
+                    "Another daemon is already listening on {}",
+                    path.display()
+                ));
+            }
+            // Left behind by a daemon that didn't shut down cleanly
+            std::fs::remove_file(&path)
+                .map_err(|e| format!("Failed to remove stale socket {}: {}", path.display(), e))?;
+        }
+
+        let listener = UnixListener::bind(&path)
+            .map_err(|e| format!("Failed to bind control socket {}: {}", path.display(), e))?;
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        // On its own thread, since `prime` waits for a tone
+                        let shared = shared.clone();
+                        std::thread::spawn(move || {
+                            if let Err(e) = serve_client(stream, &shared) {
+                                log::debug!("Control client error: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => log::warn!("Control socket accept failed: {}", e),
+                }
+            }
+        });
+
+        log::info!("Control socket listening on {}", path.display());
+        Ok(Self { path })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(_shared: Arc<Shared>) -> Result<Self, String> {
+        Err("Control socket not supported on this platform".to_string())
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+fn serve_client(stream: UnixStream, shared: &Shared) -> std::io::Result<()> {
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let reply = shared.handle(line.trim());
+    writeln!(&stream, "{}", reply)
+}
+
+/// Returns true if a daemon is listening on the control socket
+#[cfg(unix)]
+pub fn daemon_running() -> bool {
+    UnixStream::connect(socket_path()).is_ok()
+}
+
+#[cfg(not(unix))]
+pub fn daemon_running() -> bool {
+    false
+}
+
+/// Send a command to the running daemon and return its reply
+#[cfg(unix)]
+pub fn send(command: &str) -> Result<String, String> {
+    let path = socket_path();
+    let stream = UnixStream::connect(&path).map_err(|e| {
+        format!(
+            "Could not reach the daemon at {} (is `nodoze run` running?): {}",
+            path.display(),
+            e
+        )
+    })?;
+
+    writeln!(&stream, "{}", command).map_err(|e| format!("Failed to send command: {}", e))?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut reply)
+        .map_err(|e| format!("Failed to read reply: {}", e))?;
+
+    let reply = reply.trim();
+    match reply.strip_prefix("error: ") {
+        Some(err) => Err(err.to_string()),
+        None => Ok(reply.to_string()),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn send(_command: &str) -> Result<String, String> {
+    Err("Control socket not supported on this platform".to_string())
+}
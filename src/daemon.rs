@@ -1,12 +1,66 @@
+use chrono::Local;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
 use std::time::{Duration, SystemTime};
 
-use crate::audio;
-use crate::config::Config;
+use crate::alert::Alerter;
+use crate::audio::{self, AudioError};
+use crate::config::{Config, DeviceSelection, WeekSchedule};
+use crate::control;
+use crate::idle::IdleWatch;
+use crate::metrics::{self, Metrics};
+use crate::process::ProcessGate;
+use crate::reload::ConfigWatch;
+use crate::rng::Rng;
+use crate::schedule::{
+    clock_went_back, due_interval, next_interval, next_slot, Adaptive, Today,
+};
+use crate::state;
+use crate::wake;
 
-const POLL_INTERVAL: Duration = Duration::from_secs(1);
-const RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Typical speaker sleep timeout; jittered intervals shouldn't reach it
+const TYPICAL_SLEEP_TIMEOUT: u64 = 600;
+/// How often to look for a configured device that has disappeared
+const RECONNECT_PROBE: Duration = Duration::from_secs(5);
+/// How often a failure that keeps repeating is logged again
+const STILL_FAILING_EVERY: Duration = Duration::from_secs(600);
 
-/// Run the nodoze daemon loop.
+/// The daemon loop running on a background thread, for embedding nodoze in
+/// another program. Unlike `run`, it leaves SIGINT and SIGTERM to the host.
+pub struct Daemon {
+    shared: Arc<control::Shared>,
+    thread: JoinHandle<Result<(), String>>,
+}
+
+impl Daemon {
+    /// Start playing tones as `config` says
+    pub fn start(config: Config) -> Self {
+        let shared = Arc::new(control::Shared::default());
+        let loop_shared = shared.clone();
+        let thread = std::thread::spawn(move || serve(&config, loop_shared));
+        Self { shared, thread }
+    }
+
+    /// Play a short tone at `wake_boost_volume` straight away, e.g. just
+    /// before media starts so its first second isn't lost to a sleeping
+    /// amp. Blocks until the tone has played; see `control::Shared::prime`.
+    pub fn prime(&self) -> Result<String, String> {
+        self.shared.prime()
+    }
+
+    /// Fade out any tone and wait for the loop to exit. Err if the loop
+    /// had already given up (`max_consecutive_failures`).
+    pub fn stop(self) -> Result<(), String> {
+        self.shared.stop();
+        join(self.thread)
+    }
+}
+
+/// Run the nodoze daemon loop until the process is stopped by a signal (or
+/// `repeat_count` tones have played). Err once `max_consecutive_failures`
+/// tones have failed in a row, so the process can exit nonzero and leave
+/// restarting it to the service manager.
 ///
 /// Uses wall-clock time (SystemTime) to track intervals rather than
 /// monotonic sleep. This correctly handles system sleep/wake:
@@ -14,38 +68,333 @@ const RETRY_DELAY: Duration = Duration::from_secs(5);
 ///   not account for time spent in system sleep
 /// - Wall-clock time advances during sleep, so after waking we
 ///   immediately detect that the interval has elapsed and play a tone
-pub fn run(config: &Config) {
+pub fn run(config: &Config) -> Result<(), String> {
+    let (stop_sender, stop) = mpsc::channel();
+    #[cfg(unix)]
+    if let Err(e) = forward_signals(stop_sender.clone()) {
+        log::warn!("{}", e);
+    }
+    run_until(config, stop_sender, stop)
+}
+
+/// Run the daemon loop on its own thread until it returns by itself or a
+/// message arrives on `stop`, then stop it cleanly: any tone fades out and
+/// the control socket is removed. `stop_sender` is `stop`'s sending half,
+/// used to wake this thread when the loop returns.
+pub fn run_until(
+    config: &Config,
+    stop_sender: Sender<()>,
+    stop: Receiver<()>,
+) -> Result<(), String> {
+    let shared = Arc::new(control::Shared::default());
+    let loop_shared = shared.clone();
+    let config = config.clone();
+    let thread = std::thread::spawn(move || {
+        let result = serve(&config, loop_shared);
+        let _ = stop_sender.send(());
+        result
+    });
+    let _ = stop.recv();
+    shared.stop();
+    join(thread)
+}
+
+/// Wait for the daemon loop's thread and pass on how it ended
+fn join(thread: JoinHandle<Result<(), String>>) -> Result<(), String> {
+    thread
+        .join()
+        .unwrap_or_else(|_| Err("The daemon loop panicked".to_string()))
+}
+
+/// Ask `run_until` to stop on the first SIGINT or SIGTERM. A second one
+/// exits at once, in case shutting down hangs.
+#[cfg(unix)]
+fn forward_signals(stop: Sender<()>) -> Result<(), String> {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    let mut signals = Signals::new([SIGINT, SIGTERM])
+        .map_err(|e| format!("Failed to register signal handler: {}", e))?;
+    std::thread::spawn(move || {
+        let mut received = signals.forever();
+        if let Some(signal) = received.next() {
+            log::info!("Received signal {}, shutting down", signal);
+            let _ = stop.send(());
+        }
+        if let Some(signal) = received.next() {
+            log::warn!("Received signal {} again, exiting immediately", signal);
+            std::process::exit(1);
+        }
+    });
+    Ok(())
+}
+
+/// The daemon loop, returning once `shared` is stopped, or with an error once
+/// `max_consecutive_failures` tones have failed in a row
+fn serve(config: &Config, shared: Arc<control::Shared>) -> Result<(), String> {
+    if config.is_continuous() {
+        log::info!("Starting nodoze daemon: {}Hz tone, continuous", config.frequency);
+    } else {
+        log::info!(
+            "Starting nodoze daemon: {}Hz tone, {}s duration, every {}s",
+            config.frequency,
+            config.duration,
+            config.interval
+        );
+    }
     log::info!(
-        "Starting nodoze daemon: {}Hz tone, {}s duration, every {}s",
-        config.frequency,
-        config.duration,
-        config.interval
+        "Effective volume {:.0}% ({} dBFS)",
+        config.volume.clamp(0.0, 1.0) * 100.0,
+        audio::dbfs(config.volume.clamp(0.0, 1.0))
     );
 
-    let interval = Duration::from_secs(config.interval);
+    audio::set_device_cache_ttl(Duration::from_secs(config.device_cache_ttl));
 
-    // Play immediately on startup
-    let mut last_play = match audio::play_tone(config) {
-        Ok(()) => {
-            log::info!("Initial tone played successfully");
-            SystemTime::now()
+    if let Err(e) = state::record_daemon_version() {
+        log::debug!("{}", e);
+    }
+
+    if !config.data_dir.is_empty() {
+        match state::create_dir() {
+            Ok(dir) => log::info!("Data directory: {}", dir.display()),
+            Err(e) => log::warn!("{}", e),
         }
-        Err(e) => {
-            log::error!("Initial tone failed: {}", e);
-            // Set last_play far in the past so we retry quickly
-            SystemTime::UNIX_EPOCH
+    }
+
+    let mut rng = match config.random_seed {
+        Some(seed) => {
+            log::info!("Using random_seed {}", seed);
+            Rng::new(seed)
         }
+        None => Rng::from_entropy(),
     };
+    // Tones played so far, to step through `volume_schedule` and `frequency_rotation`
+    let mut played = 0;
+    let mut jitter = config.max_jitter();
+    if jitter > 0 && config.interval + jitter >= TYPICAL_SLEEP_TIMEOUT {
+        log::warn!(
+            "Interval with jitter can reach {}s, which may let speakers sleep (typical timeout {}s)",
+            config.interval + jitter,
+            TYPICAL_SLEEP_TIMEOUT
+        );
+    }
+    if config.is_near_dc() {
+        log::warn!(
+            "At {} Hz less than one cycle fits in a {}s tone, which is essentially DC and can \
+             hold speaker cones off centre; raise frequency or duration",
+            config.frequency,
+            config.duration
+        );
+    }
+    let mut today = Today::new(config, SystemTime::now());
+    log_today(config, &today);
+    let mut adaptive = Adaptive::new(config);
+    if adaptive.is_some() {
+        let (min, max) = config.adaptive_bounds();
+        log::info!(
+            "Adaptive interval between {}s and {}s, lengthening after {} tones on time",
+            min,
+            max,
+            config.adaptive_after
+        );
+    }
+    let base = next_interval(&today.config, &mut rng);
+    let mut interval = adaptive.as_mut().map_or(base, |adaptive| adaptive.apply(base));
+    let mut poll_interval = Duration::from_secs(config.poll_interval);
+    #[cfg(target_os = "linux")]
+    if let Some(usec) = std::env::var("WATCHDOG_USEC").ok().and_then(|v| v.parse().ok()) {
+        if Duration::from_micros(usec) <= poll_interval + Duration::from_secs(config.duration) {
+            log::warn!(
+                "systemd WatchdogSec ({}s) is shorter than poll_interval plus duration; \
+                 the service may be restarted while healthy",
+                usec / 1_000_000
+            );
+        }
+    }
+    heartbeat();
+    let mut first_retry_delay = Duration::from_secs(config.retry_delay);
+    let mut max_retry_delay = Duration::from_secs(config.max_retry_delay).max(first_retry_delay);
+    let mut retry_at: Option<SystemTime> = None;
 
-    loop {
-        std::thread::sleep(POLL_INTERVAL);
+    let mut device = DeviceWatch::new(config);
+    // Continuous mode has no interval to scale
+    let mut idle = (config.idle_aware && !config.is_continuous()).then(IdleWatch::new);
+    // `require_process`: scheduled tones wait for one of the programs
+    let mut gate = ProcessGate::new(config);
+    // Set while a due tone waits for `require_process`, quiet hours or a
+    // pause, which makes it late without the system having slept
+    let mut held_back = false;
+
+    // Tones left to play before exiting, when a repeat count is set
+    let mut remaining = (config.repeat_count > 0).then_some(config.repeat_count);
+
+    // Held for the lifetime of the loop so the socket is removed on exit
+    let _control = control::Server::bind(shared.clone())
+        .map_err(|e| log::warn!("Control socket unavailable: {}", e))
+        .ok();
+
+    if config.wake_on_resume {
+        match wake::watch(shared.clone()) {
+            Ok(source) => log::info!("Listening for wake events from {}", source),
+            Err(e) => log::warn!("{}; noticing wakes from elapsed time instead", e),
+        }
+    }
+
+    let metrics = Arc::new(Metrics::default());
+    if let Some(port) = config.metrics_port {
+        if let Err(e) = metrics::serve(&config.metrics_address, port, metrics.clone()) {
+            log::warn!("Metrics endpoint unavailable: {}", e);
+        }
+    }
+
+    let mut alerts = Alerter::new(config);
+    // Tones failed in a row, for `max_consecutive_failures`, counting any
+    // the last run ended with
+    let mut failures = resume_failures(&shared, &mut alerts);
+    // Repeats of the same failure are logged only now and then
+    let mut failure_log = FailureLog::default();
+    let mut retry_delay = backoff(first_retry_delay, max_retry_delay, failures);
+
+    // Give audio hardware time to settle when launched at login
+    if config.initial_delay > 0 {
+        log::info!("Waiting {}s before the first tone", config.initial_delay);
+        if shared.sleep(Duration::from_secs(config.initial_delay)) {
+            return Ok(());
+        }
+    }
+
+    // Shorter interval for a while after startup, until the speakers have
+    // certainly been woken
+    let mut warmup_until = (config.warmup_duration > 0 && !config.is_continuous()).then(|| {
+        log::info!(
+            "Warming up: a tone every {}s for the first {}s",
+            config.warmup_interval,
+            config.warmup_duration
+        );
+        SystemTime::now() + Duration::from_secs(config.warmup_duration)
+    });
+    let warmup_interval = Duration::from_secs(config.warmup_interval);
+    // Shorter interval for a while after a tone that played late, as the
+    // audio stack may still be settling after the system woke
+    let mut post_wake_interval = Duration::from_secs(config.post_wake_interval);
+    let mut post_wake_until: Option<SystemTime> = None;
+
+    let mut quiet = today.in_quiet_hours(SystemTime::now());
 
-        let elapsed = last_play.elapsed().unwrap_or(interval);
+    // `watch_config`: the file is checked every poll and applied in place
+    let mut watch = ConfigWatch::new(config);
+    let mut config = config.clone();
 
-        if elapsed >= interval {
-            match audio::play_tone(config) {
+    if config.is_continuous() {
+        return run_continuous(config, watch, &shared, &metrics, &mut alerts, today, failures);
+    }
+
+    // Play immediately on startup
+    let recent = state::last_play().filter(|&at| config.within_min_gap(at).is_some());
+    let mut last_play = if quiet {
+        log::info!("In quiet hours, skipping initial tone");
+        // Due straight away, so it plays as soon as quiet hours end
+        held_back = true;
+        SystemTime::UNIX_EPOCH
+    } else if !config.play_on_start {
+        // Counts as if a tone had just played, so the first one waits an interval
+        log::info!("play_on_start is off, skipping initial tone");
+        SystemTime::now()
+    } else if let Some(at) = recent {
+        log::info!("A tone played within min_gap, skipping initial tone");
+        shared.set_last_play(at);
+        at
+    } else if gate.as_mut().is_some_and(|gate| !gate.allows()) {
+        // Due straight away, so it plays as soon as the program starts
+        held_back = true;
+        SystemTime::UNIX_EPOCH
+    } else {
+        match device.play_scheduled(&tone_config(&config, &mut rng, played), &shared) {
+            Ok(()) => {
+                log::info!("Initial tone played successfully");
+                played += 1;
+                reset_failures(&mut failures);
+                failure_log.recovered();
+                metrics.tone_played();
+                alerts.success();
+                if repeats_done(&mut remaining) {
+                    log::info!("Played {} tone(s), exiting", config.repeat_count);
+                    return Ok(());
+                }
+                let now = SystemTime::now();
+                shared.set_last_play(now);
+                record_last_play(now);
+                now
+            }
+            Err(e) => {
+                log::error!("Initial tone failed: {}", e);
+                failure_log.report(&e);
+                metrics.tone_failed();
+                shared.tone_failed();
+                alerts.failure(&e);
+                if let AudioError::NoDevice = e {
+                    if config.exit_if_no_device {
+                        log::error!("Exiting because exit_if_no_device is set");
+                        return Ok(());
+                    }
+                    retry_delay = max_retry_delay;
+                    retry_at = Some(SystemTime::now() + retry_delay);
+                }
+                count_failure(&config, &mut failures, &e)?;
+                // Set last_play far in the past so we retry quickly
+                SystemTime::UNIX_EPOCH
+            }
+        }
+    };
+
+    // Start of the current interval. Steps on by whole intervals so tones
+    // keep a fixed cadence however long each one takes to play.
+    let mut slot = last_play;
+    if last_play != SystemTime::UNIX_EPOCH {
+        let windows = [(warmup_interval, warmup_until)];
+        let due = due_interval(interval, &windows, SystemTime::now());
+        let due = idle.as_mut().map_or(due, |idle| idle.scale(due));
+        log_next_tone(slot + due);
+    }
+
+    // Tones that have played but not been dealt with yet
+    let mut finished = Vec::new();
+    // `concurrent_plays`: tones playing on worker threads
+    let mut in_flight = InFlight::default();
+
+    loop {
+        // Skip the wait after a tone played on this thread, to deal with
+        // it straight away
+        if finished.is_empty() && shared.sleep(poll_interval) {
+            log::info!("Daemon stopped");
+            return Ok(());
+        }
+        heartbeat();
+
+        // Tones played since the last poll, here or on worker threads
+        for (dispatch, result) in in_flight.finished() {
+            if let Err(AudioError::DeviceNotFound(names)) = &result {
+                device.lost(names);
+            }
+            finished.push((dispatch, result));
+        }
+        for (dispatch, result) in std::mem::take(&mut finished) {
+            let Dispatch {
+                prime,
+                play_now,
+                woke,
+                late,
+                elapsed,
+                due,
+            } = dispatch;
+            match result {
                 Ok(()) => {
-                    if elapsed > interval + Duration::from_secs(10) {
+                    if prime {
+                        log::info!("Priming tone played on request");
+                        shared.primed(Ok("primed".to_string()));
+                    } else if play_now {
+                        log::info!("Tone played on request");
+                    } else if late {
                         log::info!(
                             "Tone played after wake ({}s since last play)",
                             elapsed.as_secs()
@@ -53,15 +402,1451 @@ pub fn run(config: &Config) {
                     } else {
                         log::debug!("Tone played successfully");
                     }
+                    played += 1;
+                    reset_failures(&mut failures);
+                    failure_log.recovered();
+                    metrics.tone_played();
+                    alerts.success();
+                    if repeats_done(&mut remaining) {
+                        log::info!("Played {} tone(s), exiting", config.repeat_count);
+                        return Ok(());
+                    }
                     last_play = SystemTime::now();
+                    // Tones played on request or at wake restart the
+                    // cadence from now
+                    slot = if play_now || woke {
+                        last_play
+                    } else {
+                        next_slot(slot, elapsed, due, last_play)
+                    };
+                    shared.set_last_play(last_play);
+                    record_last_play(last_play);
+                    // Tones played on request say nothing about the schedule
+                    let adapted = !play_now
+                        && adaptive.as_mut().is_some_and(|adaptive| adaptive.played(late));
+                    if jitter > 0 || adapted {
+                        let previous = interval;
+                        let base = next_interval(&today.config, &mut rng);
+                        interval = adaptive.as_mut().map_or(base, |adaptive| adaptive.apply(base));
+                        if adapted {
+                            log_adapted(&config, late, previous, interval);
+                        }
+                    }
+                    let now = SystemTime::now();
+                    if late && config.post_wake_duration > 0 {
+                        let settle = Duration::from_secs(config.post_wake_duration);
+                        post_wake_until = Some(now + settle);
+                        log::info!(
+                            "Playing every {}s for the next {}s while the audio stack settles",
+                            post_wake_interval.min(interval).as_secs(),
+                            config.post_wake_duration
+                        );
+                    }
+                    let windows = [
+                        (warmup_interval, warmup_until),
+                        (post_wake_interval, post_wake_until),
+                    ];
+                    let due = due_interval(interval, &windows, now);
+                    let next = slot + idle.as_mut().map_or(due, |idle| idle.scale(due));
+                    log_next_tone(next);
+                    retry_delay = first_retry_delay;
+                    retry_at = None;
                 }
                 Err(e) => {
-                    log::warn!("Failed to play tone (retrying in {}s): {}", RETRY_DELAY.as_secs(), e);
-                    // Sleep a short retry delay. On next poll, elapsed will still
-                    // be >= interval so we'll try again immediately.
-                    std::thread::sleep(RETRY_DELAY);
+                    if prime {
+                        shared.primed(Err(e.to_string()));
+                    }
+                    if let Some(adaptive) = &mut adaptive {
+                        adaptive.failed();
+                    }
+                    metrics.tone_failed();
+                    shared.tone_failed();
+                    alerts.failure(&e);
+                    if let AudioError::NoDevice = e {
+                        if config.exit_if_no_device {
+                            log::error!("{}, exiting because exit_if_no_device is set", e);
+                            return Ok(());
+                        }
+                        // Hardware won't appear within seconds, so don't
+                        // retry as if it were a transient glitch
+                        retry_delay = max_retry_delay;
+                    }
+                    count_failure(&config, &mut failures, &e)?;
+                    if failure_log.report(&e) {
+                        log::warn!(
+                            "Failed to play tone (retrying in {}s): {}",
+                            retry_delay.as_secs(),
+                            e
+                        );
+                    }
+                    // elapsed stays >= interval, so we try again once the
+                    // backoff expires. Each consecutive failure doubles it.
+                    retry_at = Some(SystemTime::now() + retry_delay);
+                    retry_delay = (retry_delay * 2).min(max_retry_delay);
+                }
+            }
+        }
+
+        if let Some(reloaded) = watch.as_mut().and_then(|watch| watch.changed(&config)) {
+            apply_reload(&mut config, reloaded, &mut watch, &mut today);
+            let base = next_interval(&today.config, &mut rng);
+            interval = adaptive.as_mut().map_or(base, |adaptive| adaptive.apply(base));
+            jitter = config.max_jitter();
+            poll_interval = Duration::from_secs(config.poll_interval);
+            post_wake_interval = Duration::from_secs(config.post_wake_interval);
+            first_retry_delay = Duration::from_secs(config.retry_delay);
+            max_retry_delay = Duration::from_secs(config.max_retry_delay).max(first_retry_delay);
+            retry_delay = retry_delay.clamp(first_retry_delay, max_retry_delay);
+            // Picks up changes to the device and how tones reach it
+            device = DeviceWatch::new(&config);
+        }
+
+        // The wall clock went back: restart the interval from now rather
+        // than wait for the clock to catch up, and don't take it for a wake
+        let now = SystemTime::now();
+        if let Some(back) = clock_went_back(slot.max(last_play), now) {
+            log::info!("Clock went back {}s, restarting the interval", back.as_secs());
+            slot = now;
+            last_play = last_play.min(now);
+            retry_at = retry_at.map(|at| at.min(now + retry_delay));
+        }
+
+        // A tone played by `nodoze once` counts towards the interval. One
+        // recorded before the clock went back looks to be in the future.
+        if let Some(external) = state::last_play() {
+            if external > last_play && external <= now {
+                log::info!("Tone played by another nodoze process, restarting the interval");
+                last_play = external;
+                slot = external;
+                shared.set_last_play(external);
+            }
+        }
+
+        // Requested via the control socket; played here so it never
+        // overlaps a scheduled tone on the same device
+        let prime = shared.take_prime();
+        let play_now = shared.take_play_now() || prime;
+        let boost = play_now && shared.take_boost();
+        // Reported by the OS as the system resumed (`wake_on_resume`)
+        let woke = shared.take_woke();
+
+        // `[schedule.<day>]` settings switch over at local midnight
+        if today.update(&config, SystemTime::now()) {
+            log_today(&config, &today);
+            let base = next_interval(&today.config, &mut rng);
+            interval = adaptive.as_mut().map_or(base, |adaptive| adaptive.apply(base));
+        }
+
+        let now_quiet = today.in_quiet_hours(SystemTime::now());
+        if now_quiet != quiet {
+            if now_quiet {
+                log::info!("Entering quiet hours, scheduled tones suppressed");
+            } else {
+                log::info!("Quiet hours over, resuming tones");
+            }
+            quiet = now_quiet;
+        }
+
+        // Paused via the control socket or in quiet hours: keep looping
+        // but skip scheduled tones
+        if (shared.is_paused() || quiet) && !play_now {
+            shared.set_next_play(None);
+            if slot.elapsed().unwrap_or_default() >= interval {
+                held_back = true;
+            }
+            continue;
+        }
+
+        if device.probe_reconnect(&config) {
+            // Don't wait out the backoff now the device is back
+            retry_delay = first_retry_delay;
+            retry_at = None;
+        }
+
+        if warmup_until.is_some_and(|until| SystemTime::now() >= until) {
+            log::info!("Warmup over, playing every {}s", interval.as_secs());
+            warmup_until = None;
+        }
+        if post_wake_until.is_some_and(|until| SystemTime::now() >= until) {
+            log::info!("Settled after wake, playing every {}s", interval.as_secs());
+            post_wake_until = None;
+        }
+        let windows = [
+            (warmup_interval, warmup_until),
+            (post_wake_interval, post_wake_until),
+        ];
+        let due = due_interval(interval, &windows, SystemTime::now());
+        // `idle_aware`: sooner while the user is away, later while they're here
+        let due = idle.as_mut().map_or(due, |idle| idle.scale(due));
+        shared.set_next_play(Some(retry_at.unwrap_or(slot + due)));
+
+        // Backing off after a failure
+        if let Some(at) = retry_at {
+            if !play_now && !woke && SystemTime::now() < at {
+                continue;
+            }
+        }
+
+        // Zero if the clock went back again since the check above
+        let elapsed = slot.elapsed().unwrap_or_default();
+
+        if play_now || woke || elapsed >= due {
+            // Checked only once a tone is due, so processes aren't listed
+            // every poll
+            if !play_now && gate.as_mut().is_some_and(|gate| !gate.allows()) {
+                held_back = true;
+                continue;
+            }
+            // Still playing the scheduled tone on a worker thread
+            if !play_now && in_flight.has_scheduled() {
+                continue;
+            }
+            if let Some(ago) = config.within_min_gap(last_play) {
+                if prime {
+                    shared.primed(Ok(format!(
+                        "skipped, a tone played {}s ago (min_gap {}s)",
+                        ago.as_secs(),
+                        config.min_gap
+                    )));
+                } else if play_now {
+                    log::info!(
+                        "Skipping requested tone: one played {}s ago (min_gap {}s)",
+                        ago.as_secs(),
+                        config.min_gap
+                    );
+                } else {
+                    log::debug!(
+                        "Tone due, but one played {}s ago; waiting out min_gap",
+                        ago.as_secs()
+                    );
                 }
+                continue;
+            }
+            let tone = tone_config(&config, &mut rng, played);
+            let late = played_late(woke, held_back, elapsed, due);
+            let dispatch = Dispatch {
+                prime,
+                play_now,
+                woke,
+                late,
+                elapsed,
+                due,
+            };
+            if config.concurrent_plays && !in_flight.can_start(&tone.device) {
+                if prime {
+                    shared.primed(Ok("skipped, a tone is already playing".to_string()));
+                } else if play_now {
+                    log::info!(
+                        "Skipping requested tone: one is still playing on {}",
+                        tone.device_label()
+                    );
+                }
+                continue;
+            }
+            held_back = false;
+            // Priming and boosted tones use the loop's own stream handling
+            if config.concurrent_plays && !prime && !boost {
+                in_flight.start(tone, dispatch, shared.clone());
+                continue;
             }
+            let result = if prime {
+                device.play_prime(&tone, &shared)
+            } else if boost {
+                device.play_boosted(&tone, &shared)
+            } else {
+                device.play_scheduled(&tone, &shared)
+            };
+            // Dealt with at the top of the loop, straight away
+            finished.push((dispatch, result));
+        }
+    }
+}
+
+/// Continuous mode: keep one unbroken tone playing instead of bursts,
+/// restarting it after failures and around pauses and quiet hours
+fn run_continuous(
+    mut config: Config,
+    mut watch: Option<ConfigWatch>,
+    shared: &control::Shared,
+    metrics: &Metrics,
+    alerts: &mut Alerter,
+    mut today: Today,
+    mut failures: u32,
+) -> Result<(), String> {
+    let first_retry_delay = Duration::from_secs(config.retry_delay);
+    let max_retry_delay = Duration::from_secs(config.max_retry_delay).max(first_retry_delay);
+    let mut retry_delay = backoff(first_retry_delay, max_retry_delay, failures);
+    let mut failure_log = FailureLog::default();
+    let mut default = DefaultDevice::default();
+
+    loop {
+        heartbeat();
+        if let Some(reloaded) = watch.as_mut().and_then(|watch| watch.changed(&config)) {
+            apply_reload(&mut config, reloaded, &mut watch, &mut today);
+        }
+        let poll_interval = Duration::from_secs(config.poll_interval);
+        let first_retry_delay = Duration::from_secs(config.retry_delay);
+        let max_retry_delay = Duration::from_secs(config.max_retry_delay).max(first_retry_delay);
+        // Nothing to queue while the tone never stops
+        shared.take_play_now();
+        shared.take_boost();
+        shared.take_woke();
+        if shared.take_prime() {
+            shared.primed(Ok("playing continuously".to_string()));
+        }
+        today.update(&config, SystemTime::now());
+        if shared.is_paused() || today.in_quiet_hours(SystemTime::now()) {
+            if shared.sleep(poll_interval) {
+                log::info!("Daemon stopped");
+                return Ok(());
+            }
+            continue;
+        }
+
+        let result =
+            play_continuously(&mut config, &mut watch, shared, &mut today, &mut default);
+        if shared.is_stopped() {
+            log::info!("Daemon stopped");
+            return Ok(());
+        }
+        match result {
+            Ok(()) => {
+                metrics.tone_played();
+                alerts.success();
+                retry_delay = first_retry_delay;
+                reset_failures(&mut failures);
+                failure_log.recovered();
+            }
+            Err(e) => {
+                metrics.tone_failed();
+                shared.tone_failed();
+                alerts.failure(&e);
+                if let AudioError::NoDevice = e {
+                    if config.exit_if_no_device {
+                        log::error!("{}, exiting because exit_if_no_device is set", e);
+                        return Ok(());
+                    }
+                    retry_delay = max_retry_delay;
+                }
+                count_failure(&config, &mut failures, &e)?;
+                if failure_log.report(&e) {
+                    log::warn!(
+                        "Continuous tone failed (retrying in {}s): {}",
+                        retry_delay.as_secs(),
+                        e
+                    );
+                }
+                if shared.sleep(retry_delay) {
+                    log::info!("Daemon stopped");
+                    return Ok(());
+                }
+                retry_delay = (retry_delay * 2).min(max_retry_delay);
+            }
+        }
+    }
+}
+
+/// Play the endless tone until the daemon is paused, quiet hours begin,
+/// the default device changes, the config file is reloaded or the device
+/// goes away. Pausing fades it out through the control socket; the others
+/// fade it out here.
+fn play_continuously(
+    config: &mut Config,
+    watch: &mut Option<ConfigWatch>,
+    shared: &control::Shared,
+    today: &mut Today,
+    default: &mut DefaultDevice,
+) -> Result<(), AudioError> {
+    let poll_interval = Duration::from_secs(config.poll_interval);
+    default.changed(config);
+    let playback = audio::play_endless(config)?;
+    let stopper = playback.stopper();
+    shared.set_playing(vec![stopper.clone()]);
+    shared.set_last_play(SystemTime::now());
+
+    while !shared.is_paused() {
+        heartbeat();
+        today.update(config, SystemTime::now());
+        if today.in_quiet_hours(SystemTime::now()) {
+            log::info!("Entering quiet hours, fading out the continuous tone");
+            stopper.stop();
+            break;
+        }
+        if default.changed(config) {
+            // Restarted on the new default by `run_continuous`
+            stopper.stop();
+            break;
+        }
+        if let Some(reloaded) = watch.as_mut().and_then(|watch| watch.changed(config)) {
+            log::info!("Restarting the continuous tone with the reloaded config");
+            apply_reload(config, reloaded, watch, today);
+            stopper.stop();
+            break;
+        }
+        if playback.is_broken() {
+            shared.set_playing(Vec::new());
+            return Err(AudioError::Other("Output stream lost".to_string()));
+        }
+        // `stop` fades the tone out itself
+        if shared.sleep(poll_interval) {
+            break;
+        }
+    }
+
+    let result = playback.wait();
+    shared.set_playing(Vec::new());
+    result
+}
+
+/// Switch over to a reloaded config (`watch_config`), starting the day's
+/// settings afresh
+fn apply_reload(
+    config: &mut Config,
+    reloaded: Config,
+    watch: &mut Option<ConfigWatch>,
+    today: &mut Today,
+) {
+    *config = reloaded;
+    audio::set_device_cache_ttl(Duration::from_secs(config.device_cache_ttl));
+    *today = Today::new(config, SystemTime::now());
+    log_today(config, today);
+    if !config.watch_config {
+        log::info!("watch_config is off, no longer watching the config file");
+        *watch = None;
+    }
+}
+
+/// Log a change to an adaptive interval (`adaptive_interval`)
+fn log_adapted(config: &Config, late: bool, previous: Duration, interval: Duration) {
+    if late {
+        log::info!(
+            "Tone played late, shortening the interval from {:.0}s to {:.0}s",
+            previous.as_secs_f64(),
+            interval.as_secs_f64()
+        );
+    } else {
+        log::info!(
+            "{} tones on time in a row, lengthening the interval from {:.0}s to {:.0}s",
+            config.adaptive_after,
+            previous.as_secs_f64(),
+            interval.as_secs_f64()
+        );
+    }
+}
+
+/// Log the settings today's `[schedule.<day>]` table gives, if it has one
+fn log_today(config: &Config, today: &Today) {
+    if config.schedule.day(today.day).is_none() {
+        return;
+    }
+    let quiet = match &today.quiet_hours {
+        Some((start, end)) => format!("quiet {} - {}", start.format("%H:%M"), end.format("%H:%M")),
+        None => "no quiet hours".to_string(),
+    };
+    if config.is_continuous() {
+        log::info!("Schedule for {}: {}", WeekSchedule::name(today.day), quiet);
+    } else {
+        log::info!(
+            "Schedule for {}: a tone every {}s, {}",
+            WeekSchedule::name(today.day),
+            today.config.interval,
+            quiet
+        );
+    }
+}
+
+/// Log when the next scheduled tone is due, in seconds and local time
+fn log_next_tone(at: SystemTime) {
+    let wait = at.duration_since(SystemTime::now()).unwrap_or_default();
+    log::info!(
+        "Next tone in {}s at {}",
+        wait.as_secs(),
+        chrono::DateTime::<Local>::from(at).format("%H:%M:%S")
+    );
+}
+
+/// Config for the next tone. With `frequency_jitter` set, the frequency is
+/// offset by a random amount within it (to 0.1 Hz), so smart amps don't
+/// learn to ignore an identical repeated tone. `step` counts the tones
+/// played so far, and picks this tone's entries from `volume_schedule` and
+/// `frequency_rotation`, and its device with `device_selection`. Settings
+/// from the `runtime_overrides` file are applied first.
+fn tone_config(config: &Config, rng: &mut Rng, step: usize) -> Config {
+    let mut tone = config.with_runtime_overrides();
+    if tone.rotates_devices() {
+        let count = tone.device.len();
+        let first = match tone.device_selection {
+            DeviceSelection::Random => (rng.next_u64() % count as u64) as usize,
+            _ => step % count,
+        };
+        // The others follow in list order, so a missing one falls through
+        // to the next as usual
+        tone.device.rotate_left(first);
+        log::info!(
+            "Picked '{}' for this tone (device_selection = {})",
+            tone.device[0],
+            tone.device_selection.name()
+        );
+    }
+    if !tone.volume_schedule.is_empty() {
+        tone.volume = tone.volume_schedule[step % tone.volume_schedule.len()];
+        log::debug!("Tone volume {:.0}% (volume_schedule)", tone.volume * 100.0);
+    }
+    if !tone.frequency_rotation.is_empty() {
+        tone.frequency = tone.frequency_rotation[step % tone.frequency_rotation.len()];
+        log::debug!("Tone frequency {} Hz (frequency_rotation)", tone.frequency);
+    }
+    if tone.frequency_jitter > 0.0 {
+        let base = tone.frequency;
+        let offset = rng.symmetric(tone.frequency_jitter);
+        tone.frequency = ((base + offset) * 10.0).round() / 10.0;
+        log::debug!(
+            "Tone frequency {} Hz ({} ± {} Hz)",
+            tone.frequency,
+            base,
+            tone.frequency_jitter
+        );
+    }
+    tone
+}
+
+/// Play a tone to completion. While it plays, pausing the daemon through the
+/// control socket fades it out early.
+fn play_to_end(config: &Config, shared: &control::Shared) -> Result<(), AudioError> {
+    let playback = audio::play_tone(config)?;
+    let stoppers = [playback.stopper()];
+    shared.add_playing(&stoppers);
+    let result = playback.wait();
+    shared.remove_playing(&stoppers);
+    result
+}
+
+/// Play a tone on every configured device at once (`all_devices`). A device
+/// that fails doesn't stop the others; the tone counts as played if any
+/// device played it.
+fn play_all(config: &Config, shared: &control::Shared) -> Result<(), AudioError> {
+    let mut playing = Vec::new();
+    let mut failures = Vec::new();
+    for name in &config.device {
+        let single = Config {
+            device: vec![name.clone()],
+            ..config.clone()
+        };
+        match audio::play_tone(&single) {
+            Ok(playback) => playing.push((name, playback)),
+            Err(e) => failures.push((name, e)),
+        }
+    }
+
+    let stoppers: Vec<_> = playing.iter().map(|(_, playback)| playback.stopper()).collect();
+    shared.add_playing(&stoppers);
+    let mut played = 0;
+    for (name, playback) in playing {
+        match playback.wait() {
+            Ok(()) => played += 1,
+            Err(e) => failures.push((name, e)),
+        }
+    }
+    shared.remove_playing(&stoppers);
+
+    for (name, e) in &failures {
+        log::warn!("Tone failed on '{}': {}", name, e);
+    }
+    if played > 0 {
+        if !failures.is_empty() {
+            log::info!("Tone played on {} of {} devices", played, config.device.len());
+        }
+        return Ok(());
+    }
+
+    // Only report the devices as missing if that's all that went wrong, so
+    // the reconnect and fallback handling applies
+    match failures
+        .into_iter()
+        .map(|(_, e)| e)
+        .find(|e| !matches!(e, AudioError::DeviceNotFound(_)))
+    {
+        Some(e) => Err(e),
+        None => Err(AudioError::DeviceNotFound(config.device.clone())),
+    }
+}
+
+/// Play a tone with `play`, as a train of `burst_count` shorter bursts if
+/// set. Each burst plays out before the gap starts, so they never overlap.
+/// Stopping or pausing the daemon ends the train early.
+fn play_bursts(
+    config: &Config,
+    shared: &control::Shared,
+    mut play: impl FnMut(&Config) -> Result<(), AudioError>,
+) -> Result<(), AudioError> {
+    if !config.is_burst() {
+        return play(config);
+    }
+    let burst = Config {
+        duration: config.burst_duration(),
+        ..config.clone()
+    };
+    let gap = Duration::from_secs_f64(config.burst_gap.max(0.0));
+    for n in 1..=config.burst_count {
+        log::debug!("Burst {} of {}", n, config.burst_count);
+        play(&burst)?;
+        if n < config.burst_count && (shared.sleep(gap) || shared.is_paused()) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Play a tone on a worker thread (`concurrent_plays`). The same as
+/// `DeviceWatch::play` but for the persistent stream, which only the loop
+/// thread holds.
+fn play_detached(config: &Config, shared: &control::Shared) -> Result<(), AudioError> {
+    let result = if config.all_devices && config.device.len() > 1 {
+        play_all(config, shared)
+    } else {
+        play_to_end(config, shared)
+    };
+    match result {
+        Err(AudioError::DeviceNotFound(_))
+            if config.fallback_to_default && !config.device.is_empty() =>
+        {
+            log::info!("Falling back to the default output device");
+            let fallback = Config {
+                device: Vec::new(),
+                ..config.clone()
+            };
+            play_to_end(&fallback, shared)
         }
+        result => result,
+    }
+}
+
+/// Most tones playing at once with `concurrent_plays`
+pub const MAX_CONCURRENT_PLAYS: usize = 4;
+
+/// Why and when a tone was played, for dealing with the result once it's
+/// done
+struct Dispatch {
+    prime: bool,
+    play_now: bool,
+    woke: bool,
+    /// Played well after it was due, so the system likely slept
+    late: bool,
+    /// Time into the interval when it started
+    elapsed: Duration,
+    due: Duration,
+}
+
+/// Tones playing on worker threads (`concurrent_plays`), so the loop keeps
+/// polling while they play. At most `MAX_CONCURRENT_PLAYS` at once, and
+/// never two on the same device.
+#[derive(Default)]
+struct InFlight {
+    plays: Vec<Running>,
+}
+
+struct Running {
+    /// Devices it plays on, as configured (empty = the default)
+    devices: Vec<String>,
+    dispatch: Dispatch,
+    thread: JoinHandle<Result<(), AudioError>>,
+}
+
+impl InFlight {
+    /// Whether a tone can start on `devices` now
+    fn can_start(&self, devices: &[String]) -> bool {
+        self.plays.len() < MAX_CONCURRENT_PLAYS
+            && !self.plays.iter().any(|play| same_device(&play.devices, devices))
+    }
+
+    /// Whether a scheduled tone (rather than one requested) is playing
+    fn has_scheduled(&self) -> bool {
+        self.plays.iter().any(|play| !play.dispatch.play_now)
+    }
+
+    fn start(&mut self, tone: Config, dispatch: Dispatch, shared: Arc<control::Shared>) {
+        let devices = tone.device.clone();
+        let thread = std::thread::spawn(move || {
+            play_bursts(&tone, &shared, |tone| play_detached(tone, &shared))
+        });
+        self.plays.push(Running {
+            devices,
+            dispatch,
+            thread,
+        });
+    }
+
+    /// Tones that have finished since the last call, with how they went
+    fn finished(&mut self) -> Vec<(Dispatch, Result<(), AudioError>)> {
+        let (done, running) = std::mem::take(&mut self.plays)
+            .into_iter()
+            .partition(|play: &Running| play.thread.is_finished());
+        self.plays = running;
+        done.into_iter()
+            .map(|play| {
+                let result = play.thread.join().unwrap_or_else(|_| {
+                    Err(AudioError::Other("Tone thread panicked".to_string()))
+                });
+                (play.dispatch, result)
+            })
+            .collect()
+    }
+}
+
+impl Drop for InFlight {
+    /// Let tones still playing (fading out, if the daemon was stopped)
+    /// finish before the loop returns
+    fn drop(&mut self) {
+        for play in self.plays.drain(..) {
+            let _ = play.thread.join();
+        }
+    }
+}
+
+/// Whether two tones' devices could be the same one: both the default, or
+/// a device name in common
+fn same_device(a: &[String], b: &[String]) -> bool {
+    (a.is_empty() && b.is_empty()) || a.iter().any(|name| b.contains(name))
+}
+
+/// Share the time of a tone with other nodoze processes
+fn record_last_play(time: SystemTime) {
+    if let Err(e) = state::record_last_play(time) {
+        log::debug!("{}", e);
+    }
+}
+
+/// Tell watchdogs the daemon loop is still turning: touch the liveness file
+/// and, under systemd with `WatchdogSec` set, ping the service manager
+fn heartbeat() {
+    if let Err(e) = state::touch_alive() {
+        log::debug!("{}", e);
+    }
+    #[cfg(target_os = "linux")]
+    if let Err(e) = notify_watchdog() {
+        log::debug!("Watchdog notification failed: {}", e);
+    }
+}
+
+/// Send `WATCHDOG=1` to systemd's notify socket (the sd_notify protocol).
+/// Does nothing unless systemd enabled the watchdog for this process.
+#[cfg(target_os = "linux")]
+fn notify_watchdog() -> std::io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    if std::env::var_os("WATCHDOG_USEC").is_none() {
+        return Ok(());
+    }
+    // Set when the watchdog is meant for another process of the service
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return Ok(());
+        }
+    }
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+
+    let bytes = socket.as_encoded_bytes();
+    let addr = match bytes.strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(&socket)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(b"WATCHDOG=1", &addr)?;
+    Ok(())
+}
+
+/// Whether a tone played well after it was due, so the system (and likely
+/// the speakers) slept in between. A tone `held_back` by `require_process`,
+/// quiet hours or a pause is late without that, so it doesn't count.
+fn played_late(woke: bool, held_back: bool, elapsed: Duration, due: Duration) -> bool {
+    woke || (!held_back && elapsed > due + Duration::from_secs(10))
+}
+
+/// Count a failed tone towards `max_consecutive_failures`. Err once that
+/// many have failed in a row, to stop the daemon. The count is kept in the
+/// state directory so a restart carries on from it.
+fn count_failure(config: &Config, failures: &mut u32, error: &AudioError) -> Result<(), String> {
+    *failures += 1;
+    if let Err(e) = state::record_failures(*failures, &error.to_string()) {
+        log::debug!("{}", e);
+    }
+    if config.max_consecutive_failures > 0 && *failures >= config.max_consecutive_failures {
+        return Err(format!(
+            "{} tones in a row failed, giving up (max_consecutive_failures)",
+            failures
+        ));
+    }
+    Ok(())
+}
+
+/// End a run of failed tones, after one played
+fn reset_failures(failures: &mut u32) {
+    if *failures > 0 {
+        if let Err(e) = state::clear_failures() {
+            log::debug!("{}", e);
+        }
+    }
+    *failures = 0;
+}
+
+/// Failed tones in a row the last run ended with, e.g. before a crash or a
+/// restart by `max_consecutive_failures`, so backoff, alerts and the limit
+/// carry on from there rather than starting over
+fn resume_failures(shared: &control::Shared, alerts: &mut Alerter) -> u32 {
+    let Some((failures, reason)) = state::failures() else {
+        return 0;
+    };
+    log::info!(
+        "Carrying on from {} failed tone(s) in a row before the restart (last: {})",
+        failures,
+        reason
+    );
+    shared.set_failures(failures);
+    alerts.resume(failures);
+    failures
+}
+
+/// Keeps a failure that repeats every retry, e.g. an unplugged device, from
+/// flooding the log. The first of a run of identical errors is logged in
+/// full, the rest only every `STILL_FAILING_EVERY` as a count, and the
+/// recovery once a tone plays.
+#[derive(Default)]
+struct FailureLog {
+    /// The error being repeated
+    last: Option<String>,
+    /// Times in a row it happened
+    count: u32,
+    /// When it was last logged
+    logged_at: Option<SystemTime>,
+    /// Failures of any kind since the last tone played
+    failures: u32,
+}
+
+impl FailureLog {
+    /// Note a failure. True if it should be logged in full, because it
+    /// differs from the last one; otherwise a summary is logged if it's
+    /// been long enough since the last.
+    fn report(&mut self, error: &AudioError) -> bool {
+        let now = SystemTime::now();
+        let error = error.to_string();
+        self.failures += 1;
+        if self.last.as_ref() != Some(&error) {
+            self.last = Some(error);
+            self.count = 1;
+            self.logged_at = Some(now);
+            return true;
+        }
+        self.count += 1;
+        let due = self.logged_at.is_none_or(|at| {
+            now.duration_since(at).map_or(true, |since| since >= STILL_FAILING_EVERY)
+        });
+        if due {
+            log::warn!("Still failing ({} times in a row): {}", self.count, error);
+            self.logged_at = Some(now);
+        }
+        false
+    }
+
+    /// A tone played. Logs the recovery if any failed before it.
+    fn recovered(&mut self) {
+        if self.failures > 0 {
+            log::info!("Playing tones again after {} failed", self.failures);
+        }
+        *self = Self::default();
+    }
+}
+
+/// Retry delay after `failures` in a row: `first`, doubled for each, up
+/// to `max`
+fn backoff(first: Duration, max: Duration, failures: u32) -> Duration {
+    1u32.checked_shl(failures)
+        .and_then(|factor| first.checked_mul(factor))
+        .map_or(max, |delay| delay.min(max))
+}
+
+/// Count down one successful tone. Returns true when the repeat count is used up.
+fn repeats_done(remaining: &mut Option<u64>) -> bool {
+    match remaining {
+        Some(n) => {
+            *n -= 1;
+            *n == 0
+        }
+        None => false,
+    }
+}
+
+/// Tracks whether the configured device has disappeared so the daemon can
+/// tell "device gone" apart from other failures and notice when it returns
+struct DeviceWatch {
+    missing: bool,
+    last_probe: SystemTime,
+    /// Play on the system default device while the configured one is
+    /// missing (`fallback_to_default`)
+    fallback: bool,
+    /// Stream held open between tones when `persistent_stream` is set
+    stream: Option<audio::PersistentStream>,
+    default: DefaultDevice,
+}
+
+impl DeviceWatch {
+    fn new(config: &Config) -> Self {
+        Self {
+            missing: false,
+            last_probe: SystemTime::UNIX_EPOCH,
+            fallback: config.fallback_to_default && !config.device.is_empty(),
+            stream: None,
+            default: DefaultDevice::default(),
+        }
+    }
+
+    /// Play a tone on the configured device, falling back to the default
+    /// device while it's missing if enabled
+    fn play(&mut self, config: &Config, shared: &control::Shared) -> Result<(), AudioError> {
+        if self.default.changed(config) {
+            // Held open on the old default; reopen on the new one
+            self.stream = None;
+        }
+        let result = if config.all_devices && config.device.len() > 1 {
+            play_all(config, shared)
+        } else if config.persistent_stream && !config.rotates_devices() {
+            // A stream stays on one device, so one picked per tone opens its own
+            self.play_persistent(config, shared)
+        } else {
+            play_to_end(config, shared)
+        };
+        match result {
+            Err(AudioError::DeviceNotFound(names)) => {
+                self.lost(&names);
+                if self.fallback {
+                    log::info!("Falling back to the default output device");
+                    let fallback = Config {
+                        device: Vec::new(),
+                        ..config.clone()
+                    };
+                    play_to_end(&fallback, shared)
+                } else {
+                    Err(AudioError::DeviceNotFound(names))
+                }
+            }
+            result => result,
+        }
+    }
+
+    /// Note that the configured device has gone, to wait for it to
+    /// reconnect
+    fn lost(&mut self, names: &[String]) {
+        if !self.missing {
+            log::warn!("Device '{}' not found, waiting for it to reconnect", names.join("', '"));
+            self.missing = true;
+            self.last_probe = SystemTime::now();
+        }
+    }
+
+    /// Play a scheduled tone, in bursts if `burst_count` is set
+    fn play_scheduled(
+        &mut self,
+        config: &Config,
+        shared: &control::Shared,
+    ) -> Result<(), AudioError> {
+        play_bursts(config, shared, |tone| self.play(tone, shared))
+    }
+
+    /// Play one tone at `wake_boost_volume`, for a speaker that has already
+    /// gone to sleep. A persistent stream plays at the normal volume, so
+    /// it's closed first and reopened for the next regular tone.
+    fn play_boosted(
+        &mut self,
+        config: &Config,
+        shared: &control::Shared,
+    ) -> Result<(), AudioError> {
+        self.stream = None;
+        log::info!(
+            "Playing a boosted wake tone at {:.0}% volume",
+            config.wake_boost_volume * 100.0
+        );
+        let boosted = Config {
+            volume: config.wake_boost_volume,
+            persistent_stream: false,
+            ..config.clone()
+        };
+        self.play(&boosted, shared)
+    }
+
+    /// Play a short priming tone at `wake_boost_volume`, ahead of media
+    /// starting. Like a boosted tone it gets a stream of its own.
+    fn play_prime(&mut self, config: &Config, shared: &control::Shared) -> Result<(), AudioError> {
+        self.stream = None;
+        self.play(&audio::prime_config(config), shared)
+    }
+
+    /// Play a tone on the stream kept open between tones, opening it first
+    /// if needed. The stream is dropped after a failure so the next attempt
+    /// acquires the device afresh.
+    fn play_persistent(
+        &mut self,
+        config: &Config,
+        shared: &control::Shared,
+    ) -> Result<(), AudioError> {
+        let mut stream = match self.stream.take() {
+            Some(stream) if !stream.is_broken() => stream,
+            Some(_) => {
+                log::info!("Output stream lost, reopening the device");
+                audio::PersistentStream::open(config)?
+            }
+            None => audio::PersistentStream::open(config)?,
+        };
+        let stopper = match stream.start(config.frequency) {
+            Ok(stopper) => stopper,
+            Err(e) => {
+                self.stream = Some(stream);
+                return Err(e);
+            }
+        };
+        shared.set_playing(vec![stopper]);
+        let result = stream.wait();
+        shared.set_playing(Vec::new());
+        if result.is_ok() {
+            self.stream = Some(stream);
+        }
+        result
+    }
+
+    /// Periodically re-enumerate while the device is missing.
+    /// Returns true once when it reappears.
+    fn probe_reconnect(&mut self, config: &Config) -> bool {
+        if !self.missing
+            || self.last_probe.elapsed().unwrap_or(RECONNECT_PROBE) < RECONNECT_PROBE
+        {
+            return false;
+        }
+        self.last_probe = SystemTime::now();
+
+        if audio::find_device(&config.host, &config.device).is_ok() {
+            log::info!("Device '{}' reconnected", config.device_label());
+            self.missing = false;
+            return true;
+        }
+        false
+    }
+}
+
+/// Follows the system default output device when no `device` is set, so
+/// tones go to whatever the user is listening on now rather than the
+/// device that was the default at startup
+#[derive(Default)]
+struct DefaultDevice {
+    name: Option<String>,
+}
+
+impl DefaultDevice {
+    /// Look up the current default device. True if it has changed since
+    /// the last look; the first look only records it.
+    fn changed(&mut self, config: &Config) -> bool {
+        if !config.device.is_empty() {
+            return false;
+        }
+        let Ok(name) = audio::resolve_device_name(&config.host, &[]) else {
+            return false;
+        };
+        match self.name.replace(name.clone()) {
+            Some(previous) if previous != name => {
+                log::info!("Default output device changed from '{}' to '{}'", previous, name);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jittered_frequency_stays_within_bounds() {
+        let config = Config {
+            frequency: 20.0,
+            frequency_jitter: 5.0,
+            ..Config::default()
+        };
+        let mut rng = Rng::new(7);
+        for step in 0..10_000 {
+            let frequency = tone_config(&config, &mut rng, step).frequency;
+            assert!((15.0..=25.0).contains(&frequency), "{} Hz", frequency);
+        }
+    }
+
+    #[test]
+    fn volume_schedule_advances_and_wraps() {
+        let config = Config {
+            volume_schedule: vec![0.05, 0.1, 0.2],
+            ..Config::default()
+        };
+        let mut rng = Rng::new(7);
+        let volumes: Vec<f64> =
+            (0..7).map(|step| tone_config(&config, &mut rng, step).volume).collect();
+        assert_eq!(volumes, [0.05, 0.1, 0.2, 0.05, 0.1, 0.2, 0.05]);
+    }
+
+    #[test]
+    fn frequency_rotation_advances_and_wraps() {
+        let config = Config {
+            frequency_rotation: vec![18.0, 22.0],
+            ..Config::default()
+        };
+        let mut rng = Rng::new(7);
+        let frequencies: Vec<f64> =
+            (0..5).map(|step| tone_config(&config, &mut rng, step).frequency).collect();
+        assert_eq!(frequencies, [18.0, 22.0, 18.0, 22.0, 18.0]);
+
+        let fixed = tone_config(&Config::default(), &mut rng, 3);
+        assert_eq!(fixed.frequency, Config::default().frequency);
+    }
+
+    /// The devices each of `steps` tones would try, in order
+    fn device_order(selection: DeviceSelection, steps: usize) -> Vec<Vec<String>> {
+        let config = Config {
+            device: vec!["A".to_string(), "B".to_string(), "C".to_string()],
+            device_selection: selection,
+            ..Config::default()
+        };
+        let mut rng = Rng::new(7);
+        (0..steps).map(|step| tone_config(&config, &mut rng, step).device).collect()
+    }
+
+    #[test]
+    fn first_selection_always_tries_the_list_in_order() {
+        for devices in device_order(DeviceSelection::First, 4) {
+            assert_eq!(devices, ["A", "B", "C"]);
+        }
+    }
+
+    #[test]
+    fn round_robin_selection_goes_round_the_list() {
+        let firsts: Vec<String> = device_order(DeviceSelection::RoundRobin, 5)
+            .into_iter()
+            .map(|devices| devices[0].clone())
+            .collect();
+        assert_eq!(firsts, ["A", "B", "C", "A", "B"]);
+        // The rest follow in list order, as fallbacks
+        assert_eq!(device_order(DeviceSelection::RoundRobin, 2)[1], ["B", "C", "A"]);
+    }
+
+    #[test]
+    fn random_selection_reaches_every_device() {
+        let orders = device_order(DeviceSelection::Random, 300);
+        for name in ["A", "B", "C"] {
+            let picked = orders.iter().filter(|devices| devices[0] == name).count();
+            assert!((50..=150).contains(&picked), "{} picked {} times", name, picked);
+        }
+        for devices in &orders {
+            let mut sorted = devices.clone();
+            sorted.sort();
+            assert_eq!(sorted, ["A", "B", "C"]);
+        }
+    }
+
+    #[test]
+    fn jitter_reaching_zero_hz_is_rejected() {
+        let config = Config {
+            frequency: 20.0,
+            frequency_jitter: 20.0,
+            ..Config::default()
+        };
+        let errors = config.validate().unwrap_err();
+        assert!(errors.iter().any(|e| e.contains("keep tones above 0 Hz")), "{:?}", errors);
+    }
+
+    /// The state directory is shared by the whole test process: point it at
+    /// a temporary one, and let one test at a time use it
+    fn state_dir() -> std::sync::MutexGuard<'static, ()> {
+        static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        static DIR: std::sync::OnceLock<tempfile::TempDir> = std::sync::OnceLock::new();
+
+        let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = DIR.get_or_init(|| tempfile::tempdir().unwrap());
+        state::set_data_dir(dir.path().to_str().unwrap());
+        state::clear_failures().unwrap();
+        guard
+    }
+
+    #[test]
+    fn consecutive_failures_stop_the_daemon_at_the_limit() {
+        let _guard = state_dir();
+        let config = Config {
+            max_consecutive_failures: 3,
+            ..Config::default()
+        };
+        let error = AudioError::NoDevice;
+        let mut failures = 0;
+        assert!(count_failure(&config, &mut failures, &error).is_ok());
+        assert!(count_failure(&config, &mut failures, &error).is_ok());
+        let err = count_failure(&config, &mut failures, &error).unwrap_err();
+        assert!(err.contains("3 tones in a row failed"), "{}", err);
+
+        // A tone playing in between starts the count over
+        failures = 0;
+        count_failure(&config, &mut failures, &error).unwrap();
+        count_failure(&config, &mut failures, &error).unwrap();
+        reset_failures(&mut failures);
+        assert!(count_failure(&config, &mut failures, &error).is_ok());
+    }
+
+    #[test]
+    fn no_failure_limit_keeps_the_daemon_going() {
+        let _guard = state_dir();
+        let config = Config {
+            max_consecutive_failures: 0,
+            ..Config::default()
+        };
+        let mut failures = 0;
+        for _ in 0..100 {
+            count_failure(&config, &mut failures, &AudioError::NoDevice).unwrap();
+        }
+        assert_eq!(failures, 100);
+    }
+
+    fn burst_train() -> Config {
+        Config {
+            duration: 3,
+            burst_count: 3,
+            burst_gap: 0.05,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn bursts_play_in_sequence_with_gaps_between() {
+        let shared = control::Shared::default();
+        let mut starts = Vec::new();
+        play_bursts(&burst_train(), &shared, |burst| {
+            assert_eq!(burst.duration, 1);
+            starts.push(std::time::Instant::now());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(starts.len(), 3);
+        for pair in starts.windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn single_tone_plays_once_at_full_duration() {
+        let shared = control::Shared::default();
+        let mut durations = Vec::new();
+        play_bursts(&Config::default(), &shared, |config| {
+            durations.push(config.duration);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(durations, [Config::default().duration]);
+    }
+
+    #[test]
+    fn failed_burst_ends_the_train() {
+        let shared = control::Shared::default();
+        let mut played = 0;
+        let result = play_bursts(&burst_train(), &shared, |_| {
+            played += 1;
+            if played == 2 {
+                return Err(AudioError::NoDevice);
+            }
+            Ok(())
+        });
+        assert!(matches!(result, Err(AudioError::NoDevice)));
+        assert_eq!(played, 2);
+    }
+
+    #[test]
+    fn stopping_ends_the_train_early() {
+        let shared = control::Shared::default();
+        let mut played = 0;
+        play_bursts(&burst_train(), &shared, |_| {
+            played += 1;
+            shared.stop();
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(played, 1);
+    }
+
+    /// Add a tone on `devices` that plays until `release` is dropped
+    fn hold(in_flight: &mut InFlight, devices: &[&str]) -> Sender<()> {
+        let (release, held) = mpsc::channel::<()>();
+        in_flight.plays.push(Running {
+            devices: devices.iter().map(|name| name.to_string()).collect(),
+            dispatch: Dispatch {
+                prime: false,
+                play_now: false,
+                woke: false,
+                late: false,
+                elapsed: Duration::ZERO,
+                due: Duration::from_secs(20),
+            },
+            thread: std::thread::spawn(move || {
+                let _ = held.recv();
+                Ok(())
+            }),
+        });
+        release
+    }
+
+    #[test]
+    fn concurrent_plays_are_bounded() {
+        let mut in_flight = InFlight::default();
+        let names = ["A", "B", "C", "D", "E"];
+        let mut releases = Vec::new();
+        for name in &names[..MAX_CONCURRENT_PLAYS] {
+            assert!(in_flight.can_start(&[name.to_string()]));
+            releases.push(hold(&mut in_flight, &[name]));
+        }
+        assert!(!in_flight.can_start(&["E".to_string()]));
+
+        // A slot frees once a tone finishes
+        drop(releases.remove(0));
+        while in_flight.finished().is_empty() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(in_flight.can_start(&["E".to_string()]));
+    }
+
+    #[test]
+    fn no_two_concurrent_plays_share_a_device() {
+        let mut in_flight = InFlight::default();
+        let _a = hold(&mut in_flight, &["A", "B"]);
+        let _default = hold(&mut in_flight, &[]);
+        assert!(!in_flight.can_start(&["B".to_string()]));
+        assert!(!in_flight.can_start(&[]));
+        assert!(in_flight.can_start(&["C".to_string()]));
+    }
+
+    #[test]
+    fn failure_count_survives_a_restart() {
+        let _guard = state_dir();
+        let config = Config {
+            max_consecutive_failures: 3,
+            ..Config::default()
+        };
+        let mut failures = 0;
+        count_failure(&config, &mut failures, &AudioError::NoDevice).unwrap();
+        count_failure(&config, &mut failures, &AudioError::NoDevice).unwrap();
+        assert_eq!(state::failures(), Some((2, AudioError::NoDevice.to_string())));
+
+        // The next run carries on from there, so one more failure is the limit
+        let shared = control::Shared::default();
+        let mut failures = resume_failures(&shared, &mut Alerter::new(&config));
+        assert_eq!(failures, 2);
+        assert!(count_failure(&config, &mut failures, &AudioError::NoDevice).is_err());
+    }
+
+    #[test]
+    fn failure_count_clears_once_a_tone_plays() {
+        let _guard = state_dir();
+        let config = Config::default();
+        let mut failures = 0;
+        count_failure(&config, &mut failures, &AudioError::NoDevice).unwrap();
+        reset_failures(&mut failures);
+        assert_eq!(failures, 0);
+        assert_eq!(state::failures(), None);
+
+        let shared = control::Shared::default();
+        assert_eq!(resume_failures(&shared, &mut Alerter::new(&config)), 0);
+    }
+
+    #[test]
+    fn repeated_failure_is_logged_in_full_once() {
+        let mut log = FailureLog::default();
+        assert!(log.report(&AudioError::NoDevice));
+        let logged_at = log.logged_at;
+        for _ in 0..5 {
+            assert!(!log.report(&AudioError::NoDevice));
+        }
+        assert_eq!(log.count, 6);
+        // Too soon for a summary
+        assert_eq!(log.logged_at, logged_at);
+
+        // A different error is logged in full and starts a new count
+        assert!(log.report(&AudioError::Other("Stream closed".to_string())));
+        assert_eq!(log.count, 1);
+        assert_eq!(log.failures, 7);
+    }
+
+    #[test]
+    fn repeated_failure_is_summarized_now_and_then() {
+        let mut log = FailureLog::default();
+        log.report(&AudioError::NoDevice);
+        let long_ago = SystemTime::now() - STILL_FAILING_EVERY - Duration::from_secs(1);
+        log.logged_at = Some(long_ago);
+        assert!(!log.report(&AudioError::NoDevice));
+        // The summary went out, so the next one is another while off
+        assert!(log.logged_at.unwrap() > long_ago + STILL_FAILING_EVERY);
+        assert_eq!(log.count, 2);
+    }
+
+    #[test]
+    fn recovery_starts_failure_logging_over() {
+        let mut log = FailureLog::default();
+        log.report(&AudioError::NoDevice);
+        log.report(&AudioError::NoDevice);
+        log.recovered();
+        assert_eq!(log.failures, 0);
+        assert_eq!(log.count, 0);
+        // The same error after a tone played is news again
+        assert!(log.report(&AudioError::NoDevice));
+    }
+
+    #[test]
+    fn tone_after_quiet_hours_is_not_late() {
+        let due = Duration::from_secs(540);
+        // Started in quiet hours, so the interval runs from the epoch
+        let elapsed = SystemTime::UNIX_EPOCH.elapsed().unwrap();
+        assert!(!played_late(false, true, elapsed, due));
+        // Nor is one held back for a few hours from a recent slot
+        assert!(!played_late(false, true, Duration::from_secs(6 * 3600), due));
+        assert!(!played_late(false, false, due + Duration::from_secs(10), due));
+    }
+
+    #[test]
+    fn tone_well_past_due_or_at_wake_is_late() {
+        let due = Duration::from_secs(540);
+        assert!(played_late(false, false, Duration::from_secs(3600), due));
+        assert!(played_late(true, false, Duration::ZERO, due));
+        // A wake reported by the OS counts even after a hold-up
+        assert!(played_late(true, true, Duration::ZERO, due));
+    }
+
+    #[test]
+    fn tone_resumed_after_quiet_hours_keeps_the_adaptive_interval() {
+        let config = Config {
+            interval: 300,
+            adaptive_interval: true,
+            adaptive_after: 2,
+            min_interval: 120,
+            max_interval: 540,
+            ..Config::default()
+        };
+        let mut adaptive = Adaptive::new(&config).unwrap();
+        let base = Duration::from_secs(300);
+        let mut interval = adaptive.apply(base);
+        // On time, a failure, on time twice (the interval grows)
+        for late in [Some(false), None, Some(false), Some(false)] {
+            match late {
+                Some(late) => adaptive.played(late),
+                None => {
+                    adaptive.failed();
+                    false
+                }
+            };
+            interval = adaptive.apply(base);
+        }
+        assert_eq!(interval, Duration::from_secs(330));
+
+        // Hours overdue, but held back by quiet hours rather than a sleep
+        let late = played_late(false, true, Duration::from_secs(6 * 3600), interval);
+        adaptive.played(late);
+        assert!(adaptive.apply(base) >= interval);
     }
 }
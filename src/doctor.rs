@@ -0,0 +1,248 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::audio::{self, AudioError};
+use crate::config::{Config, Preset};
+use crate::{service, state};
+
+/// Outcome of one check
+enum Status {
+    Pass,
+    /// Worth knowing, but nodoze can still work
+    Warn,
+    Fail,
+}
+
+/// Checklist printed by `nodoze doctor`
+#[derive(Default)]
+struct Report {
+    failed: bool,
+}
+
+impl Report {
+    fn check(&mut self, status: Status, label: &str, detail: &str, hint: Option<&str>) {
+        let mark = match status {
+            Status::Pass => "[ok]  ",
+            Status::Warn => "[warn]",
+            Status::Fail => {
+                self.failed = true;
+                "[FAIL]"
+            }
+        };
+        println!("{} {}: {}", mark, label, detail);
+        if let Some(hint) = hint {
+            println!("       hint: {}", hint);
+        }
+    }
+}
+
+/// Check the environment nodoze depends on and print a pass/fail checklist.
+/// `config` is the leniently loaded config; the file is re-read strictly to
+/// report any problems with it. Returns false if anything failed.
+pub fn run(config: &Config, path: Option<&str>, profile: Option<&str>) -> bool {
+    let mut report = Report::default();
+    println!("nodoze {} on {}", env!("CARGO_PKG_VERSION"), std::env::consts::OS);
+
+    check_config(&mut report, path, profile);
+
+    match state::dir() {
+        Ok(dir) => report.check(
+            Status::Pass,
+            "Data directory",
+            &format!(
+                "{}{}",
+                dir.display(),
+                if dir.exists() { "" } else { " (created when the daemon starts)" }
+            ),
+            None,
+        ),
+        Err(e) => report.check(
+            Status::Warn,
+            "Data directory",
+            &e,
+            Some("set `data_dir` to keep state and the control socket somewhere writable"),
+        ),
+    }
+
+    match audio::list_devices(&config.host, false, false) {
+        Ok(devices) if devices.is_empty() => report.check(
+            Status::Fail,
+            "Output devices",
+            "none found",
+            Some("check that the audio server is running and this user may use it"),
+        ),
+        Ok(devices) => report.check(
+            Status::Pass,
+            "Output devices",
+            &format!("{} found", devices.len()),
+            None,
+        ),
+        Err(e) => report.check(
+            Status::Fail,
+            "Output devices",
+            &e,
+            Some("leave `host` empty to use the platform default"),
+        ),
+    }
+
+    match audio::resolve_device_name(&config.host, &[]) {
+        Ok(name) => report.check(Status::Pass, "Default output", &name, None),
+        Err(e) => report.check(
+            Status::Warn,
+            "Default output",
+            &e.to_string(),
+            Some("set `device` to one of the names from `nodoze list-devices`"),
+        ),
+    }
+
+    if !config.device.is_empty() {
+        match audio::resolve_device_name(&config.host, &config.device) {
+            Ok(name) => report.check(Status::Pass, "Configured device", &name, None),
+            Err(AudioError::DeviceNotFound(_)) if config.fallback_to_default => report.check(
+                Status::Warn,
+                "Configured device",
+                &format!("'{}' not found", config.device_label()),
+                Some("tones will play on the default device until it is plugged in"),
+            ),
+            Err(e) => report.check(
+                Status::Fail,
+                "Configured device",
+                &e.to_string(),
+                Some("compare `device` against `nodoze list-devices`"),
+            ),
+        }
+    }
+
+    match audio::dry_run(config) {
+        Ok(target) => report.check(Status::Pass, "Tone setup", &target, None),
+        Err(e) => report.check(
+            Status::Fail,
+            "Tone setup",
+            &e.to_string(),
+            Some("`nodoze test-device` plays an audible tone with more logging (add -v)"),
+        ),
+    }
+
+    if config.preset == Some(Preset::Lfe) {
+        report.check(
+            Status::Warn,
+            "Preset",
+            "lfe plays on every channel, not only the LFE channel",
+            Some("the receiver's bass management routes it to the subwoofer"),
+        );
+    }
+
+    let status = service::status();
+    match &status {
+        Ok(service::Status {
+            location: Some(location),
+            program: Some(program),
+            program_matches: Some(false),
+            ..
+        }) => report.check(
+            Status::Warn,
+            "Service",
+            &format!("{} runs {}, not this binary", location, program),
+            Some("run `nodoze install` again to point the service at this binary"),
+        ),
+        Ok(service::Status {
+            location: Some(location),
+            ..
+        }) => report.check(Status::Pass, "Service", location, None),
+        Ok(_) => report.check(
+            Status::Warn,
+            "Service",
+            "not installed",
+            Some("run `nodoze install` to start nodoze at login"),
+        ),
+        Err(e) => report.check(Status::Warn, "Service", e, None),
+    }
+
+    if let Ok(service::Status {
+        daemon_version: Some(version),
+        version_matches: Some(false),
+        ..
+    }) = &status
+    {
+        report.check(
+            Status::Warn,
+            "Daemon version",
+            &format!("running {}, but this binary is {}", version, env!("CARGO_PKG_VERSION")),
+            Some("restart the service (or run `nodoze install` again) to run this version"),
+        );
+    }
+
+    if let Ok(service::Status {
+        running: true,
+        last_alive_unix: Some(at),
+        ..
+    }) = status
+    {
+        check_heartbeat(&mut report, config, at);
+    }
+
+    !report.failed
+}
+
+/// A running daemon touches its liveness file every `poll_interval`, plus
+/// however long a tone blocks the loop. Much older than that means it's hung.
+fn check_heartbeat(report: &mut Report, config: &Config, at: u64) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let age = now.saturating_sub(at);
+    if age > config.poll_interval * 2 + config.duration + 60 {
+        report.check(
+            Status::Warn,
+            "Daemon",
+            &format!("running, but last heartbeat was {}s ago", age),
+            Some("the daemon may be hung; restart the service"),
+        );
+    } else {
+        report.check(Status::Pass, "Daemon", &format!("heartbeat {}s ago", age), None);
+    }
+}
+
+fn check_config(report: &mut Report, path: Option<&str>, profile: Option<&str>) {
+    if path.is_none() {
+        let default_path = match Config::default_path() {
+            Ok(default_path) => default_path,
+            Err(e) => {
+                report.check(
+                    Status::Warn,
+                    "Config",
+                    &format!("{}, using defaults", e),
+                    Some("pass --config to read a config file from elsewhere"),
+                );
+                return;
+            }
+        };
+        if !default_path.exists() {
+            report.check(
+                Status::Pass,
+                "Config",
+                &format!("no file at {}, using defaults", default_path.display()),
+                None,
+            );
+            return;
+        }
+    }
+
+    match Config::load_strict(path, profile) {
+        Ok((checked, path)) => match checked.validate() {
+            Ok(()) => report.check(Status::Pass, "Config", &path.display().to_string(), None),
+            Err(errors) => report.check(
+                Status::Fail,
+                "Config",
+                &format!("{}: {}", path.display(), errors.join("; ")),
+                Some("`nodoze check` lists each problem"),
+            ),
+        },
+        Err(e) => report.check(
+            Status::Fail,
+            "Config",
+            &e,
+            Some("`nodoze config` shows the settings in effect"),
+        ),
+    }
+}
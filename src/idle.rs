@@ -0,0 +1,258 @@
+//! How long since the user last touched the keyboard or mouse
+//! (`idle_aware`). With the display likely off after a while idle, speakers
+//! that sleep with it get tones more often; while someone is at the machine
+//! they get fewer.
+
+use std::time::Duration;
+
+/// Idle this long and the display is probably off or about to be
+const IDLE_AFTER: Duration = Duration::from_secs(300);
+
+/// Interval multiplier while idle
+const IDLE_SCALE: f64 = 0.5;
+
+/// Interval multiplier while someone is using the machine
+const ACTIVE_SCALE: f64 = 1.5;
+
+/// Tracks whether the user is idle and scales the interval to match
+pub(crate) struct IdleWatch {
+    source: Option<platform::Source>,
+    /// Whether the user was idle at the last check, once known
+    idle: Option<bool>,
+}
+
+impl IdleWatch {
+    /// Open the platform's idle time source, logging when there is none
+    pub fn new() -> Self {
+        let source = platform::Source::open()
+            .map_err(|e| log::warn!("Idle time unavailable ({}); keeping the fixed interval", e))
+            .ok();
+        Self { source, idle: None }
+    }
+
+    /// `due` scaled for whether the user is idle right now: shorter while
+    /// idle, longer while active. Unchanged if idle time can't be read.
+    pub fn scale(&mut self, due: Duration) -> Duration {
+        let Some(idle_for) = self.source.as_mut().and_then(|source| source.idle_time()) else {
+            return due;
+        };
+        let idle = idle_for >= IDLE_AFTER;
+        let scaled = due.mul_f64(if idle { IDLE_SCALE } else { ACTIVE_SCALE });
+        if self.idle != Some(idle) {
+            if idle {
+                log::info!(
+                    "Idle for {}s, display likely off: playing every {}s",
+                    idle_for.as_secs(),
+                    scaled.as_secs()
+                );
+            } else {
+                log::info!("User active: playing every {}s", scaled.as_secs());
+            }
+            self.idle = Some(idle);
+        }
+        scaled
+    }
+}
+
+/// XScreenSaver extension, loaded at runtime so the binary still starts
+/// where X11 isn't installed
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::ffi::{c_char, c_int, c_ulong, c_void, CStr};
+    use std::time::Duration;
+
+    #[repr(C)]
+    struct XScreenSaverInfo {
+        window: c_ulong,
+        state: c_int,
+        kind: c_int,
+        til_or_since: c_ulong,
+        /// Milliseconds since the last input
+        idle: c_ulong,
+        event_mask: c_ulong,
+    }
+
+    type Display = c_void;
+
+    pub struct Source {
+        display: *mut Display,
+        root: c_ulong,
+        info: *mut XScreenSaverInfo,
+        query_info: unsafe extern "C" fn(*mut Display, c_ulong, *mut XScreenSaverInfo) -> c_int,
+        close_display: unsafe extern "C" fn(*mut Display) -> c_int,
+        free: unsafe extern "C" fn(*mut c_void) -> c_int,
+    }
+
+    /// Open `name` with dlopen, leaving it loaded for the life of the process
+    fn library(name: &CStr) -> Result<*mut c_void, String> {
+        // SAFETY: dlopen takes a NUL-terminated name and returns null on failure
+        let handle = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            return Err(format!("can't load {}", name.to_string_lossy()));
+        }
+        Ok(handle)
+    }
+
+    /// Look up `name` in `library` as a function pointer of type `T`
+    ///
+    /// SAFETY: `T` must be an `extern "C" fn` type matching the symbol
+    unsafe fn symbol<T: Copy>(library: *mut c_void, name: &CStr) -> Result<T, String> {
+        let address = unsafe { libc::dlsym(library, name.as_ptr()) };
+        if address.is_null() {
+            return Err(format!("missing symbol {}", name.to_string_lossy()));
+        }
+        Ok(unsafe { std::mem::transmute_copy(&address) })
+    }
+
+    impl Source {
+        pub fn open() -> Result<Self, String> {
+            let x11 = library(c"libX11.so.6")?;
+            let xss = library(c"libXss.so.1")?;
+            // SAFETY: the signatures match Xlib.h and scrnsaver.h
+            unsafe {
+                let open_display: unsafe extern "C" fn(*const c_char) -> *mut Display =
+                    symbol(x11, c"XOpenDisplay")?;
+                let default_root: unsafe extern "C" fn(*mut Display) -> c_ulong =
+                    symbol(x11, c"XDefaultRootWindow")?;
+                let query_extension: unsafe extern "C" fn(
+                    *mut Display,
+                    *mut c_int,
+                    *mut c_int,
+                ) -> c_int = symbol(xss, c"XScreenSaverQueryExtension")?;
+                let alloc_info: unsafe extern "C" fn() -> *mut XScreenSaverInfo =
+                    symbol(xss, c"XScreenSaverAllocInfo")?;
+                let query_info = symbol(xss, c"XScreenSaverQueryInfo")?;
+                let close_display: unsafe extern "C" fn(*mut Display) -> c_int =
+                    symbol(x11, c"XCloseDisplay")?;
+                let free = symbol(x11, c"XFree")?;
+
+                let display = open_display(std::ptr::null());
+                if display.is_null() {
+                    return Err("can't open the X display (is DISPLAY set?)".to_string());
+                }
+                let (mut event_base, mut error_base) = (0, 0);
+                if query_extension(display, &mut event_base, &mut error_base) == 0 {
+                    close_display(display);
+                    return Err("the X server has no MIT-SCREEN-SAVER extension".to_string());
+                }
+                Ok(Self {
+                    display,
+                    root: default_root(display),
+                    info: alloc_info(),
+                    query_info,
+                    close_display,
+                    free,
+                })
+            }
+        }
+
+        pub fn idle_time(&mut self) -> Option<Duration> {
+            if self.info.is_null() {
+                return None;
+            }
+            // SAFETY: display, root and info all came from Xlib in `open`
+            let ok = unsafe { (self.query_info)(self.display, self.root, self.info) };
+            if ok == 0 {
+                return None;
+            }
+            // c_ulong is only 32 bits on some targets
+            #[allow(clippy::unnecessary_cast)]
+            let idle = unsafe { (*self.info).idle } as u64;
+            Some(Duration::from_millis(idle))
+        }
+    }
+
+    impl Drop for Source {
+        fn drop(&mut self) {
+            // SAFETY: both were allocated by Xlib and aren't used after this
+            unsafe {
+                if !self.info.is_null() {
+                    (self.free)(self.info as *mut c_void);
+                }
+                (self.close_display)(self.display);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::time::Duration;
+
+    /// `kCGEventSourceStateCombinedSessionState`
+    const COMBINED_SESSION_STATE: i32 = 0;
+    /// `kCGAnyInputEventType`
+    const ANY_INPUT_EVENT: u32 = !0;
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGEventSourceSecondsSinceLastEventType(state: i32, event_type: u32) -> f64;
+    }
+
+    pub struct Source;
+
+    impl Source {
+        pub fn open() -> Result<Self, String> {
+            Ok(Self)
+        }
+
+        pub fn idle_time(&mut self) -> Option<Duration> {
+            // SAFETY: a pure query with no pointers involved
+            let secs = unsafe {
+                CGEventSourceSecondsSinceLastEventType(COMBINED_SESSION_STATE, ANY_INPUT_EVENT)
+            };
+            Duration::try_from_secs_f64(secs).ok()
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::time::Duration;
+
+    pub struct Source;
+
+    impl Source {
+        pub fn open() -> Result<Self, String> {
+            Ok(Self)
+        }
+
+        /// Only sees input in the daemon's own session, so a daemon running
+        /// as a service (session 0) never sees the user go active
+        pub fn idle_time(&mut self) -> Option<Duration> {
+            use windows_sys::Win32::System::SystemInformation::GetTickCount;
+            use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+                GetLastInputInfo, LASTINPUTINFO,
+            };
+
+            let mut info = LASTINPUTINFO {
+                cbSize: std::mem::size_of::<LASTINPUTINFO>() as u32,
+                dwTime: 0,
+            };
+            // SAFETY: info is a correctly sized LASTINPUTINFO
+            if unsafe { GetLastInputInfo(&mut info) } == 0 {
+                return None;
+            }
+            // Both are tick counts in milliseconds that wrap every 49.7 days
+            let now = unsafe { GetTickCount() };
+            Some(Duration::from_millis(now.wrapping_sub(info.dwTime) as u64))
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod platform {
+    use std::time::Duration;
+
+    pub struct Source;
+
+    impl Source {
+        pub fn open() -> Result<Self, String> {
+            Err("not supported on this platform".to_string())
+        }
+
+        pub fn idle_time(&mut self) -> Option<Duration> {
+            None
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Keep speakers awake by playing an inaudible tone periodically.
+//!
+//! The `nodoze` binary is a thin command line over this library, which can
+//! also be embedded in another program:
+//!
+//! ```no_run
+//! let config = nodoze::Config::load(None, None, false).unwrap();
+//!
+//! // One tone, blocking until it has played
+//! nodoze::play_tone(&config).unwrap().wait().unwrap();
+//!
+//! // Or the daemon loop on a background thread
+//! let daemon = nodoze::Daemon::start(config);
+//! // ...
+//! daemon.stop().unwrap();
+//! ```
+//!
+//! Nothing here installs a logger; messages go through the `log` crate to
+//! whichever logger the host program sets up.
+
+mod alert;
+pub mod audio;
+pub mod config;
+pub mod control;
+pub mod daemon;
+mod idle;
+mod metrics;
+pub mod paths;
+mod process;
+mod reload;
+mod rng;
+pub mod schedule;
+pub mod state;
+mod wake;
+
+pub use audio::{list_devices, play_tone, AudioError, TonePlayback};
+pub use config::Config;
+pub use daemon::Daemon;
@@ -0,0 +1,372 @@
+use log::kv::{self, Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::config::{Config, LogFormat, LogTarget};
+
+/// Logger installed at startup. Records are buffered until the config has
+/// been loaded (the log destination depends on it), then forwarded to the
+/// real backend.
+struct Logger {
+    backend: OnceLock<Box<dyn Log>>,
+    pending: Mutex<Vec<(Level, String, String)>>,
+}
+
+static LOGGER: Logger = Logger {
+    backend: OnceLock::new(),
+    pending: Mutex::new(Vec::new()),
+};
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.backend.get().is_none_or(|b| b.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        match self.backend.get() {
+            Some(backend) => backend.log(record),
+            None => self.pending.lock().unwrap().push((
+                record.level(),
+                record.target().to_string(),
+                record.args().to_string(),
+            )),
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(backend) = self.backend.get() {
+            backend.flush();
+        }
+    }
+}
+
+/// Install the buffering logger. Call before anything logs.
+pub fn install() {
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(LevelFilter::Trace);
+}
+
+/// Build the real log backend from the config and replay buffered records.
+/// `default_filter` (from the command line flags) applies unless RUST_LOG is
+/// set; without either, the config's `log_level` does.
+pub fn init(config: &Config, default_filter: Option<&str>) {
+    let mut level_error = None;
+    let default_filter = match default_filter {
+        Some(filter) => filter.to_string(),
+        None if config.log_level.is_empty() => "info".to_string(),
+        None => match config.log_level.parse::<LevelFilter>() {
+            Ok(level) => level.to_string().to_lowercase(),
+            Err(_) => {
+                level_error = Some(format!(
+                    "Unknown log_level '{}' (expected off, error, warn, info, debug or trace), \
+                     using info",
+                    config.log_level
+                ));
+                "info".to_string()
+            }
+        },
+    };
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_filter));
+    builder.format_timestamp_secs();
+    if config.log_format == LogFormat::Json {
+        builder.format(write_json);
+    }
+
+    let syslog = config.log_target == LogTarget::Syslog;
+    let mut file_error = None;
+    if let Some(path) = config.log_path().filter(|_| !syslog) {
+        match RotatingFile::open(path, config.log_max_bytes, config.log_max_files) {
+            Ok(file) => {
+                builder.target(env_logger::Target::Pipe(Box::new(file)));
+            }
+            Err(e) => file_error = Some(e),
+        }
+    }
+
+    let logger = builder.build();
+    log::set_max_level(logger.filter());
+    let (backend, syslog_error) = if syslog {
+        syslog_backend(logger, config.log_format == LogFormat::Json)
+    } else {
+        (Box::new(logger) as Box<dyn Log>, None)
+    };
+    let _ = LOGGER.backend.set(backend);
+
+    let pending = std::mem::take(&mut *LOGGER.pending.lock().unwrap());
+    for (level, target, message) in pending {
+        LOGGER.log(
+            &Record::builder()
+                .level(level)
+                .target(&target)
+                .args(format_args!("{}", message))
+                .build(),
+        );
+    }
+
+    if let Some(e) = level_error {
+        log::warn!("{}", e);
+    }
+    if let Some(e) = file_error {
+        log::warn!("{}, logging to stderr", e);
+    }
+    if let Some(e) = syslog_error {
+        log::warn!("{}, logging to stderr", e);
+    }
+}
+
+/// Backend for `log_target = "syslog"`, connected to the local syslog
+/// socket. If that fails, `filter` logs to stderr instead, along with why.
+#[cfg(unix)]
+fn syslog_backend(filter: env_logger::Logger, json: bool) -> (Box<dyn Log>, Option<String>) {
+    let formatter = syslog::Formatter3164 {
+        facility: syslog::Facility::LOG_DAEMON,
+        hostname: None,
+        process: "nodoze".to_string(),
+        pid: std::process::id(),
+    };
+    match syslog::unix(formatter) {
+        Ok(writer) => {
+            let syslog = Syslog {
+                filter,
+                json,
+                writer: Mutex::new(writer),
+            };
+            (Box::new(syslog), None)
+        }
+        // Only an error that isn't a missing socket has a cause
+        Err(e) => {
+            let reason = match std::error::Error::source(&e) {
+                Some(cause) => cause.to_string(),
+                None => "no syslog socket found".to_string(),
+            };
+            let e = format!("Can't connect to syslog ({})", reason);
+            (Box::new(filter), Some(e))
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn syslog_backend(filter: env_logger::Logger, _json: bool) -> (Box<dyn Log>, Option<String>) {
+    let e = "log_target = \"syslog\" is only supported on Unix".to_string();
+    (Box::new(filter), Some(e))
+}
+
+/// Sends records to the system logger as "nodoze" in the daemon facility.
+/// env_logger still decides which records pass, so RUST_LOG, -v/-q and
+/// `log_level` apply as usual.
+#[cfg(unix)]
+struct Syslog {
+    filter: env_logger::Logger,
+    json: bool,
+    writer: Mutex<syslog::Logger<syslog::LoggerBackend, syslog::Formatter3164>>,
+}
+
+#[cfg(unix)]
+impl Log for Syslog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let message = if self.json {
+            json_line(record)
+        } else {
+            record.args().to_string()
+        };
+        let mut writer = self.writer.lock().unwrap();
+        // Nowhere left to report a failed write
+        let _ = match record.level() {
+            Level::Error => writer.err(message),
+            Level::Warn => writer.warning(message),
+            Level::Info => writer.info(message),
+            Level::Debug | Level::Trace => writer.debug(message),
+        };
+    }
+
+    fn flush(&self) {
+        let _ = self.writer.lock().unwrap().backend.flush();
+    }
+}
+
+fn write_json(buf: &mut env_logger::fmt::Formatter, record: &Record) -> io::Result<()> {
+    writeln!(buf, "{}", json_line(record))
+}
+
+/// A record as one JSON object. Key-values attached to the record (e.g. the
+/// fields of tone events) become top-level fields.
+fn json_line(record: &Record) -> String {
+    let mut line = format!(
+        "{{\"timestamp\":\"{}\",\"level\":\"{}\",\"target\":{},\"message\":{}",
+        chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        record.level(),
+        json_string(record.target()),
+        json_string(&record.args().to_string())
+    );
+    let _ = record.key_values().visit(&mut JsonFields(&mut line));
+    line.push('}');
+    line
+}
+
+struct JsonFields<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(b) = value.to_bool() {
+            b.to_string()
+        } else if let Some(n) = value.to_u64() {
+            n.to_string()
+        } else if let Some(n) = value.to_i64() {
+            n.to_string()
+        } else if let Some(n) = value.to_f64().filter(|n| n.is_finite()) {
+            n.to_string()
+        } else {
+            json_string(&value.to_string())
+        };
+        self.0
+            .push_str(&format!(",{}:{}", json_string(key.as_str()), value));
+        Ok(())
+    }
+}
+
+/// Quote and escape a string for JSON
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// How often `tail` checks a followed log file for new output
+const FOLLOW_POLL: Duration = Duration::from_millis(500);
+
+/// Print the last `lines` lines of a log file. With `follow`, keep printing
+/// whatever is appended until interrupted, starting over from the top of the
+/// file when it shrinks (rotated or truncated).
+pub fn tail(path: &Path, lines: usize, follow: bool) -> Result<(), String> {
+    let contents = std::fs::read(path)
+        .map_err(|e| format!("Failed to read log file {}: {}", path.display(), e))?;
+    let text = String::from_utf8_lossy(&contents);
+    let all: Vec<&str> = text.lines().collect();
+    for line in &all[all.len().saturating_sub(lines)..] {
+        println!("{}", line);
+    }
+    if !follow {
+        return Ok(());
+    }
+
+    let mut offset = contents.len() as u64;
+    let mut stdout = io::stdout();
+    loop {
+        std::thread::sleep(FOLLOW_POLL);
+        // Briefly missing while the logger rotates it
+        let Ok(mut file) = File::open(path) else {
+            continue;
+        };
+        let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+        if len < offset {
+            offset = 0;
+        }
+        if len == offset {
+            continue;
+        }
+        let mut appended = Vec::new();
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_to_end(&mut appended))
+            .map_err(|e| format!("Failed to read log file {}: {}", path.display(), e))?;
+        offset += appended.len() as u64;
+        let _ = stdout.write_all(&appended);
+        let _ = stdout.flush();
+    }
+}
+
+/// Log file that rotates once it exceeds `max_bytes`, keeping up to
+/// `max_files` old copies as `<name>.1` (newest) .. `<name>.N` (oldest)
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: u32,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, max_files: u32) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create log directory: {}", e))?;
+        }
+        let file = open_append(&path)
+            .map_err(|e| format!("Failed to open log file {}: {}", path.display(), e))?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_path(&self, n: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            self.file.set_len(0)?;
+        } else {
+            let _ = std::fs::remove_file(self.rotated_path(self.max_files));
+            for n in (1..self.max_files).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = open_append(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            // Keep logging to the current file if rotation fails
+            let _ = self.rotate();
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
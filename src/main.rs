@@ -1,10 +1,15 @@
-mod audio;
-mod config;
-mod daemon;
+mod advise;
+mod calibrate;
+mod doctor;
+mod logging;
+mod monitor;
 mod service;
+mod verify;
 mod wizard;
 
-use clap::{Parser, Subcommand};
+use nodoze::{audio, config, control, daemon, paths, schedule, state};
+
+use clap::{CommandFactory, Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(
@@ -17,80 +22,973 @@ struct Cli {
     #[arg(short, long, global = true)]
     config: Option<String>,
 
+    /// Config profile to use (overrides `active_profile` in the config file)
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// Run as a separate, named daemon with its own service, config file
+    /// (config-NAME.toml), state and control socket, e.g. one per amp
+    #[arg(long, global = true, value_name = "NAME")]
+    instance: Option<String>,
+
+    /// Increase log verbosity (-v debug, -vv trace). RUST_LOG takes precedence; this flag
+    /// overrides `log_level` in the config.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Only log warnings and errors. RUST_LOG takes precedence; this flag overrides
+    /// `log_level` in the config.
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Exit with an error if the config file can't be parsed or is invalid, instead of
+    /// falling back to the defaults (same as `strict = true`)
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Play tones without a fade in/out (same as `fade_duration = 0` with no
+    /// `attack_duration` or `release_duration`), e.g. to measure the raw tone
+    #[arg(long, global = true)]
+    no_fade: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+impl Cli {
+    /// Log filter used when RUST_LOG is not set, or None to use the config's
+    /// `log_level`
+    fn log_filter(&self) -> Option<&'static str> {
+        match (self.quiet, self.verbose) {
+            (true, _) => Some("warn"),
+            (false, 0) => None,
+            (false, 1) => Some("debug"),
+            (false, _) => Some("trace"),
+        }
+    }
+}
+
+/// How `list-devices` prints the devices
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum ListFormat {
+    /// One per line, the default marked
+    Plain,
+    /// A JSON array, for scripts
+    Json,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Run the daemon (plays tone at configured interval)
     Run,
 
     /// Play the tone once and exit
-    Once,
+    Once {
+        /// Play on every output device in turn, reporting which ones worked,
+        /// to find out which speaker responds
+        #[arg(long)]
+        all_devices: bool,
+
+        /// With --all-devices, play on all of them at the same time
+        #[arg(long, requires = "all_devices")]
+        concurrent: bool,
+
+        /// Play at `wake_boost_volume`, to wake a speaker that has already slept
+        #[arg(long)]
+        boost: bool,
+    },
 
     /// List available audio output devices
-    ListDevices,
+    ListDevices {
+        /// List devices on every available audio host, prefixed with the host
+        #[arg(long)]
+        all_hosts: bool,
+
+        /// List input devices (for `input_device`) instead of outputs
+        #[arg(long)]
+        input: bool,
+
+        /// `plain` for people, or `json` for an array of `{index, name, is_default}` objects
+        #[arg(long, value_enum, default_value_t = ListFormat::Plain)]
+        format: ListFormat,
+    },
+
+    /// List the audio hosts (backends) compiled in, for the `host` setting
+    ListHosts,
+
+    /// Play a short, audible 440 Hz tone on the configured device
+    TestDevice,
+
+    /// Play the tone while recording an input device, and check its frequency was heard
+    Verify {
+        /// Input device to record from, e.g. a loopback or monitor of the output
+        /// (default: `input_device`, else the default input)
+        #[arg(long)]
+        input: Option<String>,
+
+        /// Lowest level in dBFS at which the tone counts as detected
+        #[arg(long, default_value_t = -70.0, allow_negative_numbers = true)]
+        threshold: f64,
+    },
+
+    /// Find the quietest volume that is detected (or that you notice), and recommend a `volume`
+    Calibrate {
+        /// Input device to record from: a microphone near the speakers hears whether the amp
+        /// passes the tone on (default: `input_device`, else the default input)
+        #[arg(long)]
+        input: Option<String>,
+
+        /// Ask after each tone instead of recording
+        #[arg(long)]
+        interactive: bool,
+
+        /// Lowest level in dBFS at which the tone counts as detected
+        #[arg(long, default_value_t = -70.0, allow_negative_numbers = true)]
+        threshold: f64,
+
+        /// Loudest volume to try (0.0 to 1.0)
+        #[arg(long, default_value_t = 0.5)]
+        max_volume: f64,
+    },
+
+    /// Play several tones and report how far their real length is from `duration`
+    Bench {
+        /// Number of tones to play
+        #[arg(long, default_value_t = 5)]
+        count: u32,
+
+        /// Seconds per tone (default: the configured duration)
+        #[arg(long)]
+        duration: Option<u64>,
+    },
+
+    /// Time how long opening the output device takes, phase by phase
+    Probe {
+        /// Number of times to open the device
+        #[arg(long, default_value_t = 5)]
+        count: u32,
+    },
+
+    /// Print when the daemon would play its next tones, and why, without playing anything
+    Schedule {
+        /// Number of tones to show
+        #[arg(long, default_value_t = 10)]
+        count: usize,
+    },
+
+    /// Print the daemon's next tones as an iCalendar (.ics) feed, for calendar apps
+    Ical {
+        /// Number of tones to include
+        #[arg(long, default_value_t = 10)]
+        count: usize,
+    },
+
+    /// Suggest inaudible frequencies for the configured device, and check the configured one
+    Advise,
 
     /// Show active configuration
-    Config,
+    Config {
+        /// Print the resolved settings as TOML that can be saved as a config file
+        #[arg(long)]
+        dump: bool,
+    },
 
     /// Interactive configuration wizard
     Setup,
 
-    /// Install as a system service (LaunchAgent/systemd/Task Scheduler)
-    Install,
+    /// Check that a config file parses and is valid, without playing anything
+    Check {
+        /// Config file to check (default: --config or the standard location)
+        path: Option<String>,
+    },
+
+    /// Check the config, audio devices and service, and suggest fixes
+    Doctor,
+
+    /// Write a copy of the config file with renamed and removed settings updated
+    Migrate {
+        /// Config file to migrate (default: --config or the standard location)
+        path: Option<String>,
+    },
+
+    /// Show the daemon's log output (the log file, LaunchAgent log or journal)
+    Logs {
+        /// Keep printing new output as it's written
+        #[arg(short, long)]
+        follow: bool,
+
+        /// Number of recent lines to show
+        #[arg(short = 'n', long, default_value_t = 50)]
+        lines: usize,
+    },
+
+    /// Live view of the running daemon: next tone, last tone, failures and device
+    Monitor,
+
+    /// Install as a system service (LaunchAgent/systemd/Startup folder)
+    Install {
+        /// Register a Windows service with the Service Control Manager
+        /// instead of using the Startup folder (requires Administrator)
+        #[arg(long)]
+        windows_service: bool,
+
+        /// Install a system-wide systemd unit or LaunchDaemon, started at boot, instead of a
+        /// per-user service (requires root)
+        #[arg(long)]
+        system: bool,
+
+        /// Print the service files and commands instead of applying them
+        #[arg(long)]
+        print_only: bool,
+    },
+
+    /// Print a service definition (plist, systemd unit or startup script) for this binary,
+    /// to deploy without `install`
+    Emit {
+        /// Which definition to print
+        kind: service::Definition,
+    },
 
     /// Remove the system service
-    Uninstall,
+    Uninstall {
+        /// Also delete the LaunchAgent's log files (macOS)
+        #[arg(long)]
+        remove_logs: bool,
+
+        /// Remove the system-wide service `install --system` created (requires root)
+        #[arg(long)]
+        system: bool,
+
+        /// Print the commands instead of running them
+        #[arg(long)]
+        print_only: bool,
+
+        /// Also delete nodoze's config, state, log files and control socket
+        #[arg(long)]
+        purge: bool,
+
+        /// Don't ask before purging
+        #[arg(long, requires = "purge")]
+        yes: bool,
+    },
+
+    /// Show whether the service is installed and the daemon is running
+    Status {
+        /// Print a JSON object instead, for scripts
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Pause scheduled tones in the running daemon
+    Pause,
+
+    /// Resume scheduled tones in the running daemon
+    Resume,
+
+    /// Make the running daemon play a tone immediately
+    PlayNow {
+        /// Play it at `wake_boost_volume`, to wake a speaker that has already slept
+        #[arg(long)]
+        boost: bool,
+    },
+
+    /// Play a short tone at `wake_boost_volume` and wait for it, e.g. from a media player's
+    /// pre-playback hook so the first second of audio isn't lost to a sleeping amp
+    Prime,
+
+    /// Write the tone to a WAV file instead of playing it
+    Export {
+        /// Output .wav path
+        path: std::path::PathBuf,
+
+        /// Sample rate of the exported file in Hz
+        #[arg(long, default_value_t = 48000)]
+        sample_rate: u32,
+
+        /// Number of channels in the exported file
+        #[arg(long, default_value_t = 2)]
+        channels: u16,
+    },
+
+    /// Print the first samples of the tone and those around each fade boundary, for
+    /// diagnosing envelope and waveform problems
+    #[command(hide = true)]
+    DumpSamples {
+        /// Number of frames to print from the start of the tone
+        #[arg(default_value_t = 32)]
+        count: u64,
+
+        /// Sample rate to generate at in Hz
+        #[arg(long, default_value_t = 48000)]
+        sample_rate: u32,
+
+        /// Number of channels to generate
+        #[arg(long, default_value_t = 2)]
+        channels: u16,
+    },
+
+    /// Print a shell completion script
+    Completions {
+        /// Shell to generate completions for
+        shell: clap_complete::Shell,
+    },
+
+    /// Entry point used by the Service Control Manager
+    #[cfg(windows)]
+    #[command(hide = true)]
+    WindowsService,
 }
 
 fn main() {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
-        .format_timestamp_secs()
-        .init();
+    logging::install();
 
     let cli = Cli::parse();
-    let cfg = config::Config::load(cli.config.as_deref());
+    // Before the config is looked up, since each instance has its own
+    if let Some(name) = &cli.instance {
+        if let Err(e) = paths::set_instance(name) {
+            logging::init(&config::Config::default(), cli.log_filter());
+            log::error!("{}", e);
+            ExitCode::Config.exit();
+        }
+    }
+    let mut cfg = match config::Config::load(
+        cli.config.as_deref(),
+        cli.profile.as_deref(),
+        cli.strict,
+    ) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            logging::init(&config::Config::default(), cli.log_filter());
+            log::error!("{}", e);
+            ExitCode::Config.exit();
+        }
+    };
+    if cli.no_fade {
+        cfg.fade_duration = 0.0;
+        cfg.attack_duration = None;
+        cfg.release_duration = None;
+    }
+    state::set_data_dir(&cfg.data_dir);
+    if matches!(cli.command, Some(Commands::Logs { .. })) {
+        // Reads the log file, so keep its own messages out of it
+        let to_stderr = config::Config {
+            log_file: String::new(),
+            ..cfg.clone()
+        };
+        logging::init(&to_stderr, cli.log_filter());
+    } else {
+        logging::init(&cfg, cli.log_filter());
+    }
 
     match cli.command.unwrap_or(Commands::Run) {
         Commands::Run => {
-            daemon::run(&cfg);
+            validate_or_exit(&cfg);
+            if let Err(e) = daemon::run(&cfg) {
+                log::error!("{}", e);
+                ExitCode::Failure.exit();
+            }
         }
-        Commands::Once => {
-            if let Err(e) = audio::play_tone(&cfg) {
+        Commands::Once {
+            all_devices,
+            concurrent,
+            boost,
+        } => {
+            validate_or_exit(&cfg);
+            skip_within_min_gap(&cfg);
+            let mut cfg = cfg;
+            if boost {
+                log::info!(
+                    "Playing a boosted wake tone at {:.0}% volume",
+                    cfg.wake_boost_volume * 100.0
+                );
+                cfg.volume = cfg.wake_boost_volume;
+            }
+            if all_devices {
+                if let Err(code) = play_on_every_device(&cfg, concurrent) {
+                    code.exit();
+                }
+            } else if let Err(e) = audio::play_tone(&cfg).and_then(audio::TonePlayback::wait) {
                 log::error!("{}", e);
-                std::process::exit(1);
+                ExitCode::from(&e).exit();
+            }
+            // Lets a running daemon count this tone towards its interval
+            if let Err(e) = state::record_last_play(std::time::SystemTime::now()) {
+                log::warn!("{}", e);
             }
         }
-        Commands::ListDevices => match audio::list_devices() {
-            Ok(devices) => {
-                println!("Available output devices:");
-                for name in devices {
-                    println!("  {}", name);
+        Commands::TestDevice => {
+            let result = audio::resolve_device_name(&cfg.host, &cfg.device).and_then(|name| {
+                println!("Playing a 440 Hz test tone on '{}'", name);
+                if cfg.preset == Some(config::Preset::Lfe) {
+                    println!("A subwoofer won't reproduce it; listen on the main speakers");
                 }
+                audio::play_tone(&audio::test_tone_config(&cfg))
+            });
+            if let Err(e) = result.and_then(audio::TonePlayback::wait) {
+                log::error!("{}", e);
+                ExitCode::from(&e).exit();
+            }
+        }
+        Commands::Verify { input, threshold } => {
+            validate_or_exit(&cfg);
+            let mut cfg = cfg;
+            if let Some(input) = input {
+                cfg.input_device = vec![input];
+            }
+            if !verify::run(&cfg, threshold) {
+                ExitCode::Failure.exit();
+            }
+        }
+        Commands::Calibrate {
+            input,
+            interactive,
+            threshold,
+            max_volume,
+        } => {
+            validate_or_exit(&cfg);
+            if !(max_volume > 0.0 && max_volume <= 1.0) {
+                log::error!("--max-volume must be above 0 and at most 1");
+                ExitCode::Config.exit();
+            }
+            let mut cfg = cfg;
+            if let Some(input) = input {
+                cfg.input_device = vec![input];
             }
+            if !calibrate::run(&cfg, threshold, max_volume, interactive) {
+                ExitCode::Failure.exit();
+            }
+        }
+        Commands::Bench { count, duration } => {
+            let mut cfg = cfg;
+            if let Some(duration) = duration {
+                cfg.duration = duration;
+            }
+            validate_or_exit(&cfg);
+
+            let requested = cfg.duration as f64;
+            let mut errors_ms = Vec::new();
+            for n in 1..=count.max(1) {
+                match audio::play_tone(&cfg).and_then(audio::TonePlayback::wait_measured) {
+                    Ok(elapsed) => {
+                        let error_ms = (elapsed.as_secs_f64() - requested) * 1000.0;
+                        println!(
+                            "Tone {}: {:.3} s ({:+.1} ms)",
+                            n,
+                            elapsed.as_secs_f64(),
+                            error_ms
+                        );
+                        errors_ms.push(error_ms);
+                    }
+                    Err(e) => {
+                        log::error!("{}", e);
+                        ExitCode::from(&e).exit();
+                    }
+                }
+            }
+
+            let min = errors_ms.iter().cloned().fold(f64::INFINITY, f64::min);
+            let max = errors_ms.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            let mean = errors_ms.iter().sum::<f64>() / errors_ms.len() as f64;
+            println!(
+                "Duration error over {} x {} s tones: min {:+.1} ms, max {:+.1} ms, mean {:+.1} ms",
+                errors_ms.len(),
+                cfg.duration,
+                min,
+                max,
+                mean
+            );
+        }
+        Commands::Schedule { count } => {
+            validate_or_exit(&cfg);
+            let tones = match schedule::simulate(&cfg, std::time::SystemTime::now(), count) {
+                Ok(tones) => tones,
+                Err(e) => {
+                    log::error!("{}", e);
+                    ExitCode::Failure.exit();
+                }
+            };
+            println!("Next {} tone(s) if the daemon started now:", tones.len());
+            for tone in &tones {
+                let gap = tone.gap.map(|gap| format!("+{}s", gap.as_secs()));
+                println!(
+                    "  {}  {:>7}  {}",
+                    chrono::DateTime::<chrono::Local>::from(tone.at).format("%a %H:%M:%S"),
+                    gap.unwrap_or_default(),
+                    tone.reason.name()
+                );
+            }
+            if cfg.max_jitter() > 0 && cfg.random_seed.is_none() {
+                println!("Jitter changes from run to run; set random_seed to repeat it.");
+            }
+        }
+        Commands::Ical { count } => {
+            validate_or_exit(&cfg);
+            let now = std::time::SystemTime::now();
+            match schedule::simulate(&cfg, now, count) {
+                Ok(tones) => print!("{}", schedule::to_ical(&tones, &cfg, now)),
+                Err(e) => {
+                    log::error!("{}", e);
+                    ExitCode::Failure.exit();
+                }
+            }
+        }
+        Commands::Probe { count } => {
+            match audio::resolve_device_name(&cfg.host, &cfg.device) {
+                Ok(name) => println!("Opening '{}' {} times", name, count.max(1)),
+                Err(e) => {
+                    log::error!("{}", e);
+                    ExitCode::from(&e).exit();
+                }
+            }
+
+            let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.0;
+            // find, build, start, close and total per run, in milliseconds
+            let mut runs: Vec<[f64; 5]> = Vec::new();
+            for n in 1..=count.max(1) {
+                match audio::time_open(&cfg) {
+                    Ok(t) => {
+                        let phases = [ms(t.find), ms(t.build), ms(t.start), ms(t.close)];
+                        let total: f64 = phases.iter().sum();
+                        println!(
+                            "Run {}: find {:.1} ms, build {:.1} ms, start {:.1} ms, \
+                             close {:.1} ms (total {:.1} ms)",
+                            n, phases[0], phases[1], phases[2], phases[3], total
+                        );
+                        runs.push([phases[0], phases[1], phases[2], phases[3], total]);
+                    }
+                    Err(e) => {
+                        log::error!("Run {}: {}", n, e);
+                        ExitCode::from(&e).exit();
+                    }
+                }
+                // Give the backend a moment to release the device
+                std::thread::sleep(std::time::Duration::from_millis(200));
+            }
+
+            println!("Over {} runs (min / median / max):", runs.len());
+            for (i, label) in ["Find", "Build", "Start", "Close", "Total"].iter().enumerate() {
+                let mut values: Vec<f64> = runs.iter().map(|run| run[i]).collect();
+                values.sort_by(f64::total_cmp);
+                println!(
+                    "  {:<6} {:.1} / {:.1} / {:.1} ms",
+                    label,
+                    values[0],
+                    values[values.len() / 2],
+                    values[values.len() - 1]
+                );
+            }
+        }
+        Commands::ListDevices {
+            all_hosts,
+            input,
+            format,
+        } => {
+            // The global --verbose flag also lists each device's formats
+            let verbose = cli.verbose > 0;
+            let devices = if all_hosts {
+                audio::list_all_devices(verbose, input)
+            } else {
+                audio::list_devices(&cfg.host, verbose, input)
+            };
+            match devices {
+                Ok(devices) if format == ListFormat::Json => {
+                    match serde_json::to_string(&devices) {
+                        Ok(line) => println!("{}", line),
+                        Err(e) => {
+                            log::error!("Failed to serialize devices: {}", e);
+                            ExitCode::Failure.exit();
+                        }
+                    }
+                }
+                Ok(devices) => {
+                    println!(
+                        "Available {} devices:",
+                        if input { "input" } else { "output" }
+                    );
+                    for device in devices {
+                        println!("  {}", device.label());
+                        for config in device.configs {
+                            println!("      {}", config);
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::error!("{}", e);
+                    ExitCode::Failure.exit();
+                }
+            }
+        }
+        Commands::ListHosts => {
+            println!("Audio hosts (set `host` to one of these):");
+            for host in audio::list_hosts() {
+                let note = match (host.default, host.available) {
+                    (true, _) => " (default)",
+                    (false, true) => "",
+                    (false, false) => " (not available on this system)",
+                };
+                println!("  {}{}", host.name, note);
+            }
+        }
+        Commands::Advise => {
+            if !advise::run(&cfg) {
+                ExitCode::Failure.exit();
+            }
+        }
+        Commands::Config { dump: true } => match toml::to_string(&cfg) {
+            Ok(toml) => print!("{}", toml),
             Err(e) => {
-                log::error!("{}", e);
-                std::process::exit(1);
+                log::error!("Failed to serialize config: {}", e);
+                ExitCode::Failure.exit();
             }
         },
-        Commands::Config => {
+        Commands::Config { dump: false } => {
             println!("Active configuration:");
-            println!("  Frequency:     {} Hz", cfg.frequency);
-            println!("  Duration:      {} s", cfg.duration);
-            println!("  Interval:      {} s ({:.1} min)", cfg.interval, cfg.interval as f64 / 60.0);
-            println!("  Fade duration: {} s", cfg.fade_duration);
-            println!("  Volume:        {:.0}%", cfg.volume * 100.0);
-            println!(
-                "  Device:        {}",
-                if cfg.device.is_empty() {
-                    "(system default)"
+            if let Some(name) = &cfg.active_profile {
+                println!("  Profile:       {}", name);
+            }
+            if let Some(preset) = cfg.preset {
+                println!("  Preset:        {}", preset.name());
+            }
+            if cfg.wav_path.is_empty() {
+                if !cfg.frequency_rotation.is_empty() {
+                    let frequencies: Vec<String> =
+                        cfg.frequency_rotation.iter().map(|f| f.to_string()).collect();
+                    println!(
+                        "  Frequencies:   {} Hz (one per tone, repeating)",
+                        frequencies.join(", ")
+                    );
+                    if cfg.frequency_jitter > 0.0 {
+                        println!("  Jitter:        ± {} Hz per tone", cfg.frequency_jitter);
+                    }
+                } else if cfg.frequency_jitter > 0.0 {
+                    println!(
+                        "  Frequency:     {} Hz (± {} Hz per tone)",
+                        cfg.frequency, cfg.frequency_jitter
+                    );
+                } else {
+                    println!("  Frequency:     {} Hz", cfg.frequency);
+                }
+                match (cfg.min_frequency > 0.0, cfg.max_frequency > 0.0) {
+                    (true, true) => println!(
+                        "  Limits:        {} - {} Hz",
+                        cfg.min_frequency, cfg.max_frequency
+                    ),
+                    (true, false) => println!("  Limits:        at least {} Hz", cfg.min_frequency),
+                    (false, true) => println!("  Limits:        at most {} Hz", cfg.max_frequency),
+                    (false, false) => {}
+                }
+                if cfg.a4_frequency != 440.0 {
+                    println!("  Tuning:        A4 = {} Hz", cfg.a4_frequency);
+                }
+                if cfg.comb_count > 1 {
+                    println!(
+                        "  Harmonics:     {} ({} to {} Hz)",
+                        cfg.comb_count,
+                        cfg.frequency,
+                        cfg.frequency * cfg.comb_count as f64
+                    );
+                }
+                if cfg.waveform != config::Waveform::default() {
+                    println!("  Waveform:      {}", cfg.waveform.name());
+                }
+            } else {
+                println!("  Audio file:    {}", cfg.wav_path);
+            }
+            if cfg.highpass_hz > 0.0 || cfg.lowpass_hz > 0.0 {
+                println!("  Filter:        {}", audio::filter_label(&cfg));
+            }
+            if cfg.is_continuous() {
+                println!("  Interval:      continuous (no gaps between tones)");
+                println!(
+                    "  Power/CPU:     the device stays awake and the audio thread never idles, \
+                     so this draws more than periodic tones"
+                );
+            } else {
+                if cfg.duration == 0 {
+                    println!("  Duration:      one burst");
+                } else {
+                    println!("  Duration:      {} s", cfg.duration);
+                }
+                if cfg.is_burst() {
+                    let each = match cfg.burst_duration() {
+                        0 => "short bursts".to_string(),
+                        secs => format!("bursts of {} s", secs),
+                    };
+                    println!(
+                        "  Bursts:        {} {} per tone, {} s apart",
+                        cfg.burst_count, each, cfg.burst_gap
+                    );
+                }
+                println!(
+                    "  Interval:      {} s ({:.1} min)",
+                    cfg.interval,
+                    cfg.interval as f64 / 60.0
+                );
+            }
+            if !cfg.is_continuous() && cfg.max_jitter() > 0 {
+                println!(
+                    "  Jitter:        ±{} s ({}-{} s)",
+                    cfg.max_jitter(),
+                    cfg.interval - cfg.max_jitter(),
+                    cfg.interval + cfg.max_jitter()
+                );
+            }
+            if cfg.adaptive_interval && !cfg.is_continuous() {
+                let (min, max) = cfg.adaptive_bounds();
+                println!(
+                    "  Adaptive:      {}-{} s, longer after {} tones on time",
+                    min, max, cfg.adaptive_after
+                );
+            }
+            if let Some(seed) = cfg.random_seed {
+                println!("  Random seed:   {} (jitter repeats across runs)", seed);
+            }
+            if cfg.initial_delay > 0 {
+                println!("  Initial delay: {} s", cfg.initial_delay);
+            }
+            if cfg.min_gap > 0 {
+                println!("  Minimum gap:   {} s", cfg.min_gap);
+            }
+            if !cfg.play_on_start && !cfg.is_continuous() {
+                println!("  Startup tone:  off (first tone after one interval)");
+            }
+            if cfg.warmup_duration > 0 {
+                println!(
+                    "  Warmup:        every {} s for the first {} s",
+                    cfg.warmup_interval, cfg.warmup_duration
+                );
+            }
+            if cfg.post_wake_duration > 0 && !cfg.is_continuous() {
+                println!(
+                    "  After wake:    every {} s for {} s after a late tone",
+                    cfg.post_wake_interval, cfg.post_wake_duration
+                );
+            }
+            if cfg.repeat_count > 0 {
+                println!("  Repeat count:  {} tones, then exit", cfg.repeat_count);
+            }
+            let fade = |secs: f64| {
+                if secs == 0.0 {
+                    "none".to_string()
                 } else {
-                    &cfg.device
+                    format!("{} s", secs)
                 }
+            };
+            if cfg.attack() == 0.0 && cfg.release() == 0.0 {
+                println!("  Fade duration: none");
+            } else {
+                println!("  Attack:        {}", fade(cfg.attack()));
+                println!("  Release:       {}", fade(cfg.release()));
+                println!("  Fade curve:    {}", cfg.fade_curve.name());
+            }
+            if cfg.limiter != config::Limiter::default() {
+                println!("  Limiter:       {}", cfg.limiter.name());
+            }
+            println!(
+                "  Volume:        {:.0}% ({} dBFS)",
+                cfg.volume * 100.0,
+                audio::dbfs(cfg.volume)
             );
-            if let Some(path) = config::Config::config_path() {
+            if !cfg.volume_schedule.is_empty() {
+                let volumes: Vec<String> = cfg
+                    .volume_schedule
+                    .iter()
+                    .map(|v| format!("{:.0}%", v * 100.0))
+                    .collect();
+                println!("  Volume cycle:  {} (one per tone, repeating)", volumes.join(", "));
+            }
+            if cfg.stereo_phase != 0.0 || cfg.pan != 0.0 {
+                println!(
+                    "  Stereo:        right phase {}°, pan {} (ignored on mono devices)",
+                    cfg.stereo_phase, cfg.pan
+                );
+            }
+            if let Some(target) = cfg.channel_target {
+                println!(
+                    "  Channels:      {} ({} layout)",
+                    target.name(),
+                    cfg.channel_layout.name()
+                );
+            } else if cfg.channel_mode != config::ChannelMode::default() {
+                println!("  Channels:      {}", cfg.channel_mode.name());
+            }
+            if !cfg.channel_gains.is_empty() {
+                let gains: Vec<String> =
+                    cfg.channel_gains.iter().map(|g| format!("{}", g)).collect();
+                println!("  Channel gains: {} (from channel 0)", gains.join(", "));
+            }
+            if cfg.sample_format != config::OutputFormat::default() {
+                println!("  Sample format: {}", cfg.sample_format.name());
+            }
+            if let Some(channels) = cfg.channels_count {
+                println!("  Channels:      {}", channels);
+            }
+            if cfg.dither {
+                println!("  Dither:        ±1 LSB on integer output");
+            }
+            if cfg.antiphase {
+                println!(
+                    "  Antiphase:     odd channels inverted, so audible leakage partly cancels \
+                     (ignored on mono devices)"
+                );
+            }
+            if cfg.decorrelate && cfg.waveform == config::Waveform::Noise {
+                println!("  Decorrelate:   different noise on each channel");
+            }
+            if cfg.modulation.is_active() {
+                println!(
+                    "  Modulation:    {} Hz, depth {:.0}%",
+                    cfg.modulation.mod_rate,
+                    cfg.modulation.mod_depth.clamp(0.0, 1.0) * 100.0
+                );
+            }
+            if !cfg.host.is_empty() {
+                println!("  Audio host:    {}", cfg.host);
+            }
+            if cfg.all_devices && cfg.device.len() > 1 {
+                println!("  Devices:       {} (all at once)", cfg.device_label());
+            } else if cfg.rotates_devices() {
+                println!(
+                    "  Devices:       {} (one per tone, {})",
+                    cfg.device_label(),
+                    cfg.device_selection.name()
+                );
+            } else {
+                println!("  Device:        {}", cfg.device_label());
+            }
+            if !cfg.device.is_empty() {
+                println!(
+                    "  Fallback:      {}",
+                    if cfg.fallback_to_default {
+                        "system default while no device is found"
+                    } else {
+                        "off"
+                    }
+                );
+            }
+            if !cfg.input_device.is_empty() {
+                println!("  Verify input:  {}", cfg.input_device.join(", "));
+            }
+            println!(
+                "  Retry delay:   {} s, doubling up to {} s",
+                cfg.retry_delay, cfg.max_retry_delay
+            );
+            println!("  Poll interval: {} s", cfg.poll_interval);
+            if cfg.wake_on_resume {
+                println!("  Wake events:   play a tone as the system resumes");
+            }
+            if cfg.idle_aware && !cfg.is_continuous() {
+                println!("  Idle aware:    half the interval when idle, 1.5x when active");
+            }
+            if !cfg.require_process.is_empty() && !cfg.is_continuous() {
+                println!("  Only while:    {} running", cfg.require_process.join(" or "));
+            }
+            if cfg.exit_if_no_device {
+                println!("  No hardware:   exit instead of retrying");
+            }
+            if cfg.max_consecutive_failures > 0 {
+                println!(
+                    "  Give up:       after {} failed tones in a row",
+                    cfg.max_consecutive_failures
+                );
+            }
+            if cfg.concurrent_plays && !cfg.is_continuous() {
+                println!(
+                    "  Plays:         on worker threads, up to {} at once on different devices",
+                    daemon::MAX_CONCURRENT_PLAYS
+                );
+            }
+            if cfg.persistent_stream && !cfg.is_continuous() && !cfg.concurrent_plays {
+                println!("  Stream:        kept open between tones");
+            } else if cfg.drain_ms != config::Config::default().drain_ms {
+                println!("  Drain:         {} ms before closing the stream", cfg.drain_ms);
+            }
+            if cfg.realtime_priority {
+                println!("  Audio thread:  realtime priority requested");
+            }
+            match (&cfg.quiet_hours_start, &cfg.quiet_hours_end) {
+                (Some(start), Some(end)) => println!("  Quiet hours:   {} - {}", start, end),
+                _ => println!("  Quiet hours:   (none)"),
+            }
+            for day in config::WeekSchedule::DAYS {
+                let Some(table) = cfg.schedule.day(day) else {
+                    continue;
+                };
+                let mut parts = Vec::new();
+                if let Some(interval) = table.interval.filter(|_| !cfg.is_continuous()) {
+                    parts.push(format!("every {} s", interval));
+                }
+                if let (Some(start), Some(end)) = (&table.quiet_hours_start, &table.quiet_hours_end)
+                {
+                    parts.push(format!("quiet {} - {}", start, end));
+                }
+                if !parts.is_empty() {
+                    let label = format!("{}:", config::WeekSchedule::name(day));
+                    println!("  {:<15}{}", label, parts.join(", "));
+                }
+            }
+            if !cfg.log_level.is_empty() {
+                println!("  Log level:     {}", cfg.log_level);
+            }
+            if cfg.log_format == config::LogFormat::Json {
+                println!("  Log format:    json");
+            }
+            if !cfg.data_dir.is_empty() {
+                println!("  Data dir:      {}", cfg.data_dir);
+            }
+            match cfg.log_path() {
+                _ if cfg.log_target == config::LogTarget::Syslog => {
+                    println!("  Log file:      (syslog)")
+                }
+                None => println!("  Log file:      (stderr)"),
+                Some(path) => println!(
+                    "  Log file:      {} (rotated at {} KB, keeping {})",
+                    path.display(),
+                    cfg.log_max_bytes / 1024,
+                    cfg.log_max_files
+                ),
+            }
+            if !cfg.launchd_log_dir.is_empty() {
+                println!("  Service logs:  {} (macOS LaunchAgent)", cfg.launchd_log_dir);
+            }
+            match cfg.metrics_port {
+                Some(port) => println!(
+                    "  Metrics:       http://{}:{}/metrics",
+                    cfg.metrics_address, port
+                ),
+                None => println!("  Metrics:       (disabled)"),
+            }
+            if cfg.alert_webhook.is_empty() {
+                println!("  Alerts:        (disabled)");
+            } else {
+                println!(
+                    "  Alerts:        {} after {} consecutive failures",
+                    cfg.alert_webhook,
+                    cfg.failure_threshold.max(1)
+                );
+            }
+            if cfg.desktop_notifications {
+                println!("  Notifications: desktop, after repeated failures");
+            }
+            if cfg.failure_chirp {
+                println!(
+                    "  Chirp:         audible, after {} consecutive failures",
+                    cfg.failure_threshold.max(1)
+                );
+            }
+            if !cfg.runtime_overrides.is_empty() {
+                println!("  Overrides:     {} (re-read before each tone)", cfg.runtime_overrides);
+            }
+            if cfg.watch_config {
+                println!("  Config watch:  reloads this file when it changes");
+            }
+            if let Some(path) = state::path() {
+                println!(
+                    "  State file:    {} (tones from `nodoze once` reset the daemon's interval)",
+                    path.display()
+                );
+            }
+            if let Ok(path) = config::Config::default_path() {
                 println!(
                     "  Config file:   {} {}",
                     path.display(),
@@ -98,23 +996,457 @@ fn main() {
                 );
             }
         }
+        Commands::Logs { follow, lines } => {
+            if let Err(e) = service::logs(&cfg, lines, follow) {
+                log::error!("{}", e);
+                ExitCode::Failure.exit();
+            }
+        }
+        Commands::Monitor => monitor::run(&cfg),
+        Commands::Doctor => {
+            if !doctor::run(&cfg, cli.config.as_deref(), cli.profile.as_deref()) {
+                ExitCode::Failure.exit();
+            }
+        }
+        Commands::Check { path } => {
+            let path = path.or(cli.config);
+            match config::Config::load_strict(path.as_deref(), cli.profile.as_deref()) {
+                Ok((checked, path)) => match checked.validate() {
+                    Ok(()) => {
+                        println!("{}: OK", path.display());
+                        if let Some(name) = &checked.active_profile {
+                            println!("  Profile:  {}", name);
+                        }
+                        if checked.is_continuous() {
+                            println!(
+                                "  Tone:     {} Hz continuously at {:.0}%",
+                                checked.frequency,
+                                checked.volume * 100.0
+                            );
+                        } else {
+                            println!(
+                                "  Tone:     {} Hz for {} s every {} s at {:.0}%",
+                                checked.frequency,
+                                checked.duration,
+                                checked.interval,
+                                checked.volume * 100.0
+                            );
+                        }
+                        println!("  Device:   {}", checked.device_label());
+                    }
+                    Err(errors) => {
+                        println!("{}: invalid", path.display());
+                        for e in errors {
+                            println!("  - {}", e);
+                        }
+                        ExitCode::Config.exit();
+                    }
+                },
+                Err(e) => {
+                    println!("{}", e);
+                    ExitCode::Config.exit();
+                }
+            }
+        }
+        Commands::Migrate { path } => {
+            let explicit = path.is_some() || cli.config.is_some();
+            let path = match path.or(cli.config) {
+                Some(path) => Ok(std::path::PathBuf::from(path)),
+                None => config::Config::default_path(),
+            };
+            let path = match path {
+                Ok(path) => path,
+                Err(e) => {
+                    log::error!("{}", e);
+                    ExitCode::Failure.exit();
+                }
+            };
+            match config::migrate(&path) {
+                Ok((None, _)) => println!("{}: nothing to migrate", path.display()),
+                Ok((Some(out), changes)) => {
+                    for change in changes {
+                        println!("  - {}", change);
+                    }
+                    println!("Wrote {}", out.display());
+                    println!("Comments are not carried over; review it, then replace the original");
+                }
+                Err(e) => {
+                    log::error!("{}", e);
+                    ExitCode::Config.exit();
+                }
+            }
+            match config::Config::config_path() {
+                Ok(new) if !explicit && new != path => {
+                    println!("This is the old wake-speaker config; move it to {}", new.display())
+                }
+                _ => {}
+            }
+        }
         Commands::Setup => {
             if let Err(e) = wizard::run() {
                 log::error!("{}", e);
-                std::process::exit(1);
+                ExitCode::Failure.exit();
+            }
+        }
+        Commands::Install {
+            windows_service,
+            system,
+            print_only,
+        } => {
+            if let Err(e) = service::install(&cfg, windows_service, system, print_only) {
+                log::error!("{}", e);
+                ExitCode::Service.exit();
+            }
+        }
+        Commands::Emit { kind } => {
+            if let Err(e) = service::emit(&cfg, kind) {
+                log::error!("{}", e);
+                ExitCode::Service.exit();
+            }
+        }
+        Commands::Uninstall {
+            remove_logs,
+            system,
+            print_only,
+            purge,
+            yes,
+        } => {
+            // Collected first, so the list can be confirmed before anything
+            // is removed
+            let purged = if purge { service::purge_paths(&cfg) } else { Vec::new() };
+            if purge && !print_only {
+                if purged.is_empty() {
+                    println!("No config, state or log files to delete");
+                } else {
+                    println!("This deletes:");
+                    for path in &purged {
+                        println!("  {}", path.display());
+                    }
+                    if !yes && !confirm("Uninstall and delete these? [y/N]") {
+                        println!("Nothing changed");
+                        return;
+                    }
+                }
+            }
+            if let Err(e) = service::uninstall(&cfg, system, remove_logs || purge, print_only) {
+                log::error!("{}", e);
+                ExitCode::Service.exit();
+            }
+            if let Err(e) = service::purge(&purged, print_only) {
+                log::error!("{}", e);
+                ExitCode::Service.exit();
+            }
+        }
+        Commands::Status { json } => {
+            let status = match service::status() {
+                Ok(status) => status,
+                Err(e) => {
+                    log::error!("{}", e);
+                    ExitCode::Service.exit();
+                }
+            };
+            if json {
+                match serde_json::to_string(&status) {
+                    Ok(line) => println!("{}", line),
+                    Err(e) => {
+                        log::error!("Failed to serialize status: {}", e);
+                        ExitCode::Failure.exit();
+                    }
+                }
+            } else {
+                print_status(&status);
+            }
+        }
+        Commands::Pause => match control::send("pause") {
+            Ok(reply) => println!("{}", reply),
+            Err(e) => {
+                log::error!("{}", e);
+                ExitCode::Failure.exit();
+            }
+        },
+        Commands::Resume => match control::send("resume") {
+            Ok(reply) => println!("{}", reply),
+            Err(e) => {
+                log::error!("{}", e);
+                ExitCode::Failure.exit();
+            }
+        },
+        Commands::Export {
+            path,
+            sample_rate,
+            channels,
+        } => {
+            validate_or_exit(&cfg);
+            if let Err(e) = audio::export_wav(&cfg, &path, sample_rate, channels) {
+                log::error!("{}", e);
+                ExitCode::Failure.exit();
             }
         }
-        Commands::Install => {
-            if let Err(e) = service::install() {
+        Commands::DumpSamples {
+            count,
+            sample_rate,
+            channels,
+        } => {
+            validate_or_exit(&cfg);
+            if let Err(e) = audio::dump_samples(&cfg, sample_rate, channels, count) {
                 log::error!("{}", e);
-                std::process::exit(1);
+                ExitCode::Failure.exit();
             }
         }
-        Commands::Uninstall => {
-            if let Err(e) = service::uninstall() {
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "nodoze", &mut std::io::stdout());
+        }
+        #[cfg(windows)]
+        Commands::WindowsService => {
+            validate_or_exit(&cfg);
+            if let Err(e) = service::scm::run(cfg) {
                 log::error!("{}", e);
-                std::process::exit(1);
+                ExitCode::Service.exit();
+            }
+        }
+        Commands::Prime => {
+            if control::daemon_running() {
+                match control::send("prime") {
+                    Ok(reply) => println!("{}", reply),
+                    Err(e) => {
+                        log::error!("{}", e);
+                        ExitCode::Failure.exit();
+                    }
+                }
+            } else {
+                // No daemon to ask, so play it here
+                skip_within_min_gap(&cfg);
+                let tone = audio::prime_config(&cfg);
+                if let Err(e) = audio::play_tone(&tone).and_then(audio::TonePlayback::wait) {
+                    log::error!("{}", e);
+                    ExitCode::from(&e).exit();
+                }
+                if let Err(e) = state::record_last_play(std::time::SystemTime::now()) {
+                    log::warn!("{}", e);
+                }
+                println!("ok: primed");
+            }
+        }
+        Commands::PlayNow { boost } => {
+            if !control::daemon_running() {
+                println!("No running daemon found. Use `nodoze once` to play a tone directly.");
+                ExitCode::Failure.exit();
+            }
+            match control::send(if boost { "play-now boost" } else { "play-now" }) {
+                Ok(reply) => println!("{}", reply),
+                Err(e) => {
+                    log::error!("{}", e);
+                    ExitCode::Failure.exit();
+                }
+            }
+        }
+    }
+}
+
+/// Exit without playing if a tone (from the daemon or another `once`)
+/// played within `min_gap`
+fn skip_within_min_gap(cfg: &config::Config) {
+    if let Some(ago) = state::last_play().and_then(|at| cfg.within_min_gap(at)) {
+        log::info!(
+            "Skipping tone: one played {}s ago (min_gap {}s)",
+            ago.as_secs(),
+            cfg.min_gap
+        );
+        std::process::exit(0);
+    }
+}
+
+/// Exit status for each class of failure, so scripts can tell them apart.
+/// Documented in the README; keep the numbers stable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExitCode {
+    /// Anything not covered below
+    Failure = 1,
+    /// The config file couldn't be loaded or is invalid, or an argument is
+    /// out of range. clap also exits 2 for a malformed command line.
+    Config = 2,
+    /// The system has no audio output device at all
+    NoDevice = 3,
+    /// No output device matches the configured `device`
+    DeviceNotFound = 4,
+    /// Installing, removing or querying the system service failed
+    Service = 5,
+}
+
+impl ExitCode {
+    fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
+
+impl From<&audio::AudioError> for ExitCode {
+    fn from(e: &audio::AudioError) -> Self {
+        match e {
+            audio::AudioError::NoDevice => ExitCode::NoDevice,
+            audio::AudioError::DeviceNotFound(_) => ExitCode::DeviceNotFound,
+            audio::AudioError::StreamInvalidated(_) | audio::AudioError::Other(_) => {
+                ExitCode::Failure
+            }
+        }
+    }
+}
+
+/// Exit with the config's validation errors, if it has any
+fn validate_or_exit(cfg: &config::Config) {
+    if let Err(errors) = cfg.validate() {
+        for e in errors {
+            log::error!("Invalid config: {}", e);
+        }
+        ExitCode::Config.exit();
+    }
+}
+
+/// Ask a yes/no question on the terminal. Only "y" or "yes" is a yes.
+fn confirm(question: &str) -> bool {
+    match wizard::prompt_raw(question) {
+        Ok(answer) => ["y", "yes"].iter().any(|yes| answer.eq_ignore_ascii_case(yes)),
+        Err(e) => {
+            log::error!("{}", e);
+            false
+        }
+    }
+}
+
+fn print_status(status: &service::Status) {
+    if let Some(instance) = &status.instance {
+        println!("Instance:  {}", instance);
+    }
+    match &status.location {
+        Some(location) => println!("Service:   installed ({})", location),
+        None => println!("Service:   not installed"),
+    }
+    if let (Some(program), Some(false)) = (&status.program, status.program_matches) {
+        println!(
+            "Program:   {} (not this binary; run `nodoze install` to update the service)",
+            program
+        );
+    }
+    match status.pid {
+        Some(pid) => println!("Daemon:    running (pid {})", pid),
+        None => println!("Daemon:    not running"),
+    }
+    if let (Some(version), Some(false)) = (&status.daemon_version, status.version_matches) {
+        println!(
+            "Version:   daemon is {}, this binary is {} (restart the service to run the update)",
+            version,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    match status.last_play_unix {
+        Some(at) => println!("Last tone: {}s ago", now.saturating_sub(at)),
+        None => println!("Last tone: none recorded"),
+    }
+    if let Some(at) = status.last_alive_unix {
+        println!("Heartbeat: {}s ago", now.saturating_sub(at));
+    }
+}
+
+/// Play the tone on every output device of the configured host, one after
+/// another or all at once, and print how each went. A device that fails
+/// doesn't stop the rest. Returns the exit code to use if no device played
+/// the tone.
+fn play_on_every_device(cfg: &config::Config, concurrent: bool) -> Result<(), ExitCode> {
+    let names: Vec<String> = match audio::list_devices(&cfg.host, false, false) {
+        Ok(devices) => devices.into_iter().map(|d| d.name).collect(),
+        Err(e) => {
+            log::error!("{}", e);
+            return Err(ExitCode::Failure);
+        }
+    };
+    if names.is_empty() {
+        log::error!("{}", audio::AudioError::NoDevice);
+        return Err(ExitCode::NoDevice);
+    }
+
+    let single = |name: &String| config::Config {
+        device: vec![name.clone()],
+        ..cfg.clone()
+    };
+    let results: Vec<(&String, Result<(), audio::AudioError>)> = if concurrent {
+        let started: Vec<_> = names
+            .iter()
+            .map(|name| (name, audio::play_tone(&single(name))))
+            .collect();
+        started
+            .into_iter()
+            .map(|(name, playback)| (name, playback.and_then(audio::TonePlayback::wait)))
+            .collect()
+    } else {
+        names
+            .iter()
+            .map(|name| {
+                println!("Playing on '{}'", name);
+                (name, audio::play_tone(&single(name)).and_then(audio::TonePlayback::wait))
+            })
+            .collect()
+    };
+
+    println!("Results:");
+    let mut played = 0;
+    for (name, result) in &results {
+        match result {
+            Ok(()) => {
+                played += 1;
+                println!("  [ok]   {}", name);
             }
+            Err(e) => println!("  [FAIL] {}: {}", name, e),
         }
     }
+    println!("Played on {} of {} devices", played, results.len());
+    if played == 0 {
+        return Err(ExitCode::Failure);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_keep_their_documented_numbers() {
+        let codes = [
+            ExitCode::Failure,
+            ExitCode::Config,
+            ExitCode::NoDevice,
+            ExitCode::DeviceNotFound,
+            ExitCode::Service,
+        ];
+        assert_eq!(codes.map(|code| code as i32), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn audio_errors_map_to_their_exit_codes() {
+        let code = |e: audio::AudioError| ExitCode::from(&e);
+        assert_eq!(code(audio::AudioError::NoDevice), ExitCode::NoDevice);
+        assert_eq!(
+            code(audio::AudioError::DeviceNotFound(vec!["USB".to_string()])),
+            ExitCode::DeviceNotFound
+        );
+        assert_eq!(
+            code(audio::AudioError::StreamInvalidated("USB".to_string())),
+            ExitCode::Failure
+        );
+        assert_eq!(code(audio::AudioError::Other("boom".to_string())), ExitCode::Failure);
+    }
+
+    #[cfg(feature = "mock-audio")]
+    #[test]
+    fn playing_on_every_device_succeeds_if_any_plays() {
+        let cfg = config::Config {
+            duration: 1,
+            ..config::Config::default()
+        };
+        assert_eq!(play_on_every_device(&cfg, false), Ok(()));
+        assert_eq!(play_on_every_device(&cfg, true), Ok(()));
+    }
 }
@@ -0,0 +1,166 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Counters exposed on the metrics endpoint
+#[derive(Default)]
+pub struct Metrics {
+    tones_played: AtomicU64,
+    tone_failures: AtomicU64,
+    last_tone: Mutex<Option<SystemTime>>,
+}
+
+impl Metrics {
+    pub fn tone_played(&self) {
+        self.tones_played.fetch_add(1, Ordering::Relaxed);
+        *self.last_tone.lock().unwrap() = Some(SystemTime::now());
+    }
+
+    pub fn tone_failed(&self) {
+        self.tone_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render in the Prometheus text exposition format
+    fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP nodoze_tones_played_total Tones played successfully.\n");
+        out.push_str("# TYPE nodoze_tones_played_total counter\n");
+        out.push_str(&format!(
+            "nodoze_tones_played_total {}\n",
+            self.tones_played.load(Ordering::Relaxed)
+        ));
+        out.push_str("# HELP nodoze_tone_failures_total Tones that failed to play.\n");
+        out.push_str("# TYPE nodoze_tone_failures_total counter\n");
+        out.push_str(&format!(
+            "nodoze_tone_failures_total {}\n",
+            self.tone_failures.load(Ordering::Relaxed)
+        ));
+        out.push_str("# HELP nodoze_seconds_since_last_tone Seconds since the last successful tone.\n");
+        out.push_str("# TYPE nodoze_seconds_since_last_tone gauge\n");
+        // No sample until the first tone has played
+        if let Some(last) = *self.last_tone.lock().unwrap() {
+            let secs = last.elapsed().map(|d| d.as_secs_f64()).unwrap_or(0.0);
+            out.push_str(&format!("nodoze_seconds_since_last_tone {:.3}\n", secs));
+        }
+        out
+    }
+}
+
+/// Start the metrics HTTP listener on a background thread
+pub fn serve(address: &str, port: u16, metrics: Arc<Metrics>) -> Result<(), String> {
+    let listener = TcpListener::bind((address, port))
+        .map_err(|e| format!("Failed to bind metrics listener on {}:{}: {}", address, port, e))?;
+
+    log::info!("Metrics available at http://{}:{}/metrics", address, port);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = respond(stream, &metrics) {
+                        log::debug!("Metrics client error: {}", e);
+                    }
+                }
+                Err(e) => log::warn!("Metrics accept failed: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Answer a single HTTP/1.x request and close the connection
+fn respond(stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Drain headers up to the blank line
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+        header.clear();
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = if path == "/metrics" {
+        ("200 OK", metrics.render())
+    } else {
+        ("404 Not Found", "Not found. Try /metrics\n".to_string())
+    };
+
+    write!(
+        &stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::Read;
+
+    /// Send `GET path` to `respond` over a real socket and return the reply
+    fn get(metrics: &Metrics, path: &str) -> String {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        write!(client, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        respond(server, metrics).unwrap();
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        reply
+    }
+
+    /// Sample values by metric name, checking every line is a comment or a
+    /// `name value` sample
+    fn parse(body: &str) -> HashMap<String, f64> {
+        let mut samples = HashMap::new();
+        for line in body.lines() {
+            if line.starts_with("# HELP ") || line.starts_with("# TYPE ") {
+                continue;
+            }
+            let (name, value) = line.split_once(' ').expect("sample line");
+            samples.insert(name.to_string(), value.parse().expect("numeric value"));
+        }
+        samples
+    }
+
+    #[test]
+    fn endpoint_serves_counters_in_exposition_format() {
+        let metrics = Metrics::default();
+        metrics.tone_played();
+        metrics.tone_played();
+        metrics.tone_failed();
+
+        let reply = get(&metrics, "/metrics");
+        let (head, body) = reply.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+        assert!(head.contains("Content-Type: text/plain; version=0.0.4"));
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+
+        let samples = parse(body);
+        assert_eq!(samples["nodoze_tones_played_total"], 2.0);
+        assert_eq!(samples["nodoze_tone_failures_total"], 1.0);
+        assert!(samples["nodoze_seconds_since_last_tone"] < 5.0);
+        assert!(body.contains("# TYPE nodoze_tones_played_total counter\n"));
+        assert!(body.contains("# TYPE nodoze_seconds_since_last_tone gauge\n"));
+    }
+
+    #[test]
+    fn no_last_tone_sample_before_the_first_tone() {
+        let samples = parse(&Metrics::default().render());
+        assert_eq!(samples["nodoze_tones_played_total"], 0.0);
+        assert!(!samples.contains_key("nodoze_seconds_since_last_tone"));
+    }
+
+    #[test]
+    fn other_paths_are_not_found() {
+        assert!(get(&Metrics::default(), "/").starts_with("HTTP/1.1 404 Not Found"));
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::audio;
+use crate::config::Config;
+use crate::control::{self, Info};
+use crate::state;
+
+/// How often the view is redrawn
+const REFRESH: Duration = Duration::from_secs(1);
+
+/// Enumerating devices is slow on some hosts, so the resolved device name
+/// is only looked up this often
+const DEVICE_REFRESH: Duration = Duration::from_secs(30);
+
+/// Clear the screen and move the cursor to the top left
+const CLEAR: &str = "\x1b[2J\x1b[H";
+
+/// Live view of the running daemon, redrawn every second until Ctrl-C
+pub fn run(config: &Config) {
+    let stop = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        if let Err(e) = signal_hook::flag::register(signal, stop.clone()) {
+            log::warn!("Failed to register signal handler: {}", e);
+        }
+    }
+
+    let mut device = String::new();
+    let mut device_checked: Option<Instant> = None;
+    while !stop.load(Ordering::Relaxed) {
+        if device_checked.is_none_or(|at| at.elapsed() >= DEVICE_REFRESH) {
+            device = match audio::resolve_device_name(&config.host, &config.device) {
+                Ok(name) => name,
+                Err(e) => e.to_string(),
+            };
+            device_checked = Some(Instant::now());
+        }
+
+        print!("{}{}", CLEAR, render(config, &device));
+
+        let frame = Instant::now();
+        while !stop.load(Ordering::Relaxed) && frame.elapsed() < REFRESH {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+    println!();
+}
+
+fn render(config: &Config, device: &str) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let ago = |at: u64| format!("{}s ago", now.saturating_sub(at));
+
+    let mut out = format!(
+        "nodoze monitor ({}, Ctrl-C to quit)\n\n",
+        chrono::Local::now().format("%H:%M:%S")
+    );
+    let info = control::send("info")
+        .ok()
+        .and_then(|reply| serde_json::from_str::<Info>(&reply).ok());
+
+    match &info {
+        Some(info) if info.paused => out.push_str("Daemon:     paused\n"),
+        Some(_) => out.push_str("Daemon:     running\n"),
+        None => out.push_str("Daemon:     not running\n"),
+    }
+
+    let next = match &info {
+        None => "-".to_string(),
+        Some(info) if info.paused => "paused".to_string(),
+        Some(_) if config.is_continuous() => "playing continuously".to_string(),
+        Some(Info {
+            next_play_unix: Some(at),
+            ..
+        }) if *at > now => format!("in {}s", at - now),
+        Some(Info {
+            next_play_unix: Some(_),
+            ..
+        }) => "due now".to_string(),
+        Some(_) => "not scheduled (quiet hours)".to_string(),
+    };
+    out.push_str(&format!("Next tone:  {}\n", next));
+
+    // The daemon's own record, else the state file shared with `nodoze once`
+    let last_play = info.as_ref().and_then(|i| i.last_play_unix).or_else(|| {
+        state::last_play()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+    });
+    match last_play {
+        Some(at) => out.push_str(&format!("Last tone:  {}\n", ago(at))),
+        None => out.push_str("Last tone:  none recorded\n"),
+    }
+
+    if let Some(info) = &info {
+        out.push_str(&format!("Failures:   {} in a row\n", info.failures));
+    }
+    out.push_str(&format!("Device:     {}\n", device));
+
+    if let Some(alive) = state::last_alive() {
+        let at = alive.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        out.push_str(&format!("Heartbeat:  {}\n", ago(at)));
+    }
+    out
+}
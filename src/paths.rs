@@ -0,0 +1,179 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Name given with `--instance`, if any
+static INSTANCE: OnceLock<String> = OnceLock::new();
+
+/// Run as a named instance, with its own service, config file, state and
+/// control socket, so several daemons can run side by side. Call once,
+/// before anything resolves those. Err if the name can't be used in file
+/// and service names.
+pub fn set_instance(name: &str) -> Result<(), String> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || !name.chars().all(valid) {
+        return Err(format!(
+            "Invalid instance name '{}': use letters, digits, '-' and '_'",
+            name
+        ));
+    }
+    let _ = INSTANCE.set(name.to_string());
+    Ok(())
+}
+
+/// The `--instance` name, or None for the default instance
+pub fn instance() -> Option<&'static str> {
+    INSTANCE.get().map(String::as_str)
+}
+
+/// `base`, suffixed with `-NAME` for a named instance, e.g. "nodoze" or
+/// "nodoze-studio"
+pub fn instance_name(base: &str) -> String {
+    match instance() {
+        Some(name) => format!("{}-{}", base, name),
+        None => base.to_string(),
+    }
+}
+
+/// A directory named by an environment variable, if it's set to an
+/// absolute path. The XDG spec says relative paths are to be ignored.
+fn from_env(var: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+}
+
+/// The user's home directory: `$HOME`, else the platform's record of it
+/// (the password database on Unix, the user profile on Windows)
+pub fn home_dir() -> Result<PathBuf, String> {
+    from_env("HOME")
+        .or_else(dirs::home_dir)
+        .ok_or_else(|| "Could not determine the home directory; set HOME".to_string())
+}
+
+/// Base directory for per-user config files: `$XDG_CONFIG_HOME`, else
+/// `~/.config` on macOS and Linux and the roaming AppData folder on Windows
+pub fn config_dir() -> Result<PathBuf, String> {
+    if let Some(dir) = from_env("XDG_CONFIG_HOME") {
+        return Ok(dir);
+    }
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    let dir = home_dir().ok().map(|home| home.join(".config"));
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    let dir = dirs::config_dir();
+    dir.ok_or_else(|| {
+        "Could not determine the config directory; set XDG_CONFIG_HOME or HOME".to_string()
+    })
+}
+
+/// Base directory for per-user state: `$XDG_STATE_HOME`, else the
+/// platform's state directory (~/.local/state on Linux) or local data
+/// directory, else ~/.local/state
+pub fn state_dir() -> Result<PathBuf, String> {
+    from_env("XDG_STATE_HOME")
+        .or_else(dirs::state_dir)
+        .or_else(dirs::data_local_dir)
+        .or_else(|| home_dir().ok().map(|home| home.join(".local").join("state")))
+        .ok_or_else(|| {
+            "Could not determine the state directory; set XDG_STATE_HOME or HOME".to_string()
+        })
+}
+
+/// Environment variables and the current directory are shared by every
+/// test in the process; tests that change them hold this while they do
+#[cfg(test)]
+pub(crate) fn env_lock() -> std::sync::MutexGuard<'static, ()> {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `f` with the given environment variables set (or unset for
+    /// None), putting the old values back afterwards
+    fn with_env(vars: &[(&str, Option<&str>)], f: impl FnOnce()) {
+        let _env = env_lock();
+        let saved: Vec<_> = vars.iter().map(|&(var, _)| (var, std::env::var_os(var))).collect();
+        for &(var, value) in vars {
+            match value {
+                Some(value) => std::env::set_var(var, value),
+                None => std::env::remove_var(var),
+            }
+        }
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+        for (var, value) in saved {
+            match value {
+                Some(value) => std::env::set_var(var, value),
+                None => std::env::remove_var(var),
+            }
+        }
+        if let Err(panic) = result {
+            std::panic::resume_unwind(panic);
+        }
+    }
+
+    #[test]
+    fn xdg_dirs_come_from_the_environment() {
+        with_env(
+            &[
+                ("XDG_CONFIG_HOME", Some("/srv/config")),
+                ("XDG_STATE_HOME", Some("/srv/state")),
+            ],
+            || {
+                assert_eq!(config_dir(), Ok(PathBuf::from("/srv/config")));
+                assert_eq!(state_dir(), Ok(PathBuf::from("/srv/state")));
+            },
+        );
+    }
+
+    #[test]
+    fn home_comes_from_the_environment() {
+        with_env(&[("HOME", Some("/srv/home"))], || {
+            assert_eq!(home_dir(), Ok(PathBuf::from("/srv/home")));
+        });
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    #[test]
+    fn relative_xdg_dirs_are_ignored() {
+        with_env(
+            &[("HOME", Some("/srv/home")), ("XDG_CONFIG_HOME", Some("relative/config"))],
+            || assert_eq!(config_dir(), Ok(PathBuf::from("/srv/home/.config"))),
+        );
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    #[test]
+    fn unset_xdg_dirs_fall_back_to_home() {
+        with_env(
+            &[
+                ("HOME", Some("/srv/home")),
+                ("XDG_CONFIG_HOME", None),
+                ("XDG_STATE_HOME", None),
+            ],
+            || {
+                assert_eq!(config_dir(), Ok(PathBuf::from("/srv/home/.config")));
+                let state = state_dir().unwrap();
+                #[cfg(target_os = "linux")]
+                assert_eq!(state, PathBuf::from("/srv/home/.local/state"));
+                assert!(state.starts_with("/srv/home"), "{}", state.display());
+            },
+        );
+    }
+
+    #[test]
+    fn unset_home_falls_back_to_the_platform() {
+        with_env(&[("HOME", None)], || match home_dir() {
+            // The password database (or user profile) entry
+            Ok(home) => {
+                assert!(home.is_absolute(), "{}", home.display());
+                assert_eq!(Some(home), dirs::home_dir());
+            }
+            Err(e) => {
+                assert!(e.contains("set HOME"), "{}", e);
+                assert_eq!(dirs::home_dir(), None);
+            }
+        });
+    }
+}
@@ -0,0 +1,126 @@
+//! Only play tones while a given program is running (`require_process`),
+//! e.g. a DAW or music player, so speakers aren't woken when nothing is
+//! going to use them.
+
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System};
+
+use crate::config::Config;
+
+/// Checks whether any of the `require_process` programs is running
+pub(crate) struct ProcessGate {
+    names: Vec<String>,
+    system: System,
+    /// Whether one was running at the last check, once known
+    running: Option<bool>,
+}
+
+impl ProcessGate {
+    /// None unless `require_process` is set (and the daemon isn't playing
+    /// continuously)
+    pub fn new(config: &Config) -> Option<Self> {
+        if config.require_process.is_empty() || config.is_continuous() {
+            return None;
+        }
+        Some(Self {
+            names: config.require_process.clone(),
+            system: System::new(),
+            running: None,
+        })
+    }
+
+    /// Whether one of the programs is running now. Logs when that changes.
+    pub fn allows(&mut self) -> bool {
+        self.system.refresh_processes_specifics(
+            ProcessesToUpdate::All,
+            true,
+            ProcessRefreshKind::nothing(),
+        );
+        let found = find_running(
+            self.system
+                .processes()
+                .values()
+                .map(|process| process.name().to_string_lossy()),
+            &self.names,
+        );
+        let running = found.is_some();
+        if self.running != Some(running) {
+            match found {
+                Some(name) => log::info!("'{}' is running, playing tones", name),
+                None => log::info!(
+                    "Skipping tones until '{}' is running (require_process)",
+                    self.names.join("' or '")
+                ),
+            }
+            self.running = Some(running);
+        }
+        running
+    }
+}
+
+/// The first of `wanted` that one of the `running` process names matches
+fn find_running<S: AsRef<str>>(
+    running: impl Iterator<Item = S>,
+    wanted: &[String],
+) -> Option<String> {
+    for process in running {
+        if let Some(name) = wanted.iter().find(|name| matches(process.as_ref(), name)) {
+            return Some(name.clone());
+        }
+    }
+    None
+}
+
+/// Whether a process name is the wanted program: the same name ignoring
+/// case and any ".exe" suffix, so "reaper" also matches "REAPER.exe"
+fn matches(process: &str, wanted: &str) -> bool {
+    let base = |name: &str| {
+        let name = name.trim();
+        match name.len().checked_sub(4) {
+            Some(end) if name.is_char_boundary(end) && name[end..].eq_ignore_ascii_case(".exe") => {
+                name[..end].to_string()
+            }
+            _ => name.to_string(),
+        }
+    };
+    base(process).eq_ignore_ascii_case(&base(wanted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUNNING: [&str; 4] = ["systemd", "REAPER.exe", "Music", "bash"];
+
+    fn wanted(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn names_match_ignoring_case_and_exe() {
+        assert!(matches("REAPER.exe", "reaper"));
+        assert!(matches("reaper", "Reaper.EXE"));
+        assert!(matches("Music", "music"));
+        assert!(!matches("Music", "Musi"));
+        assert!(!matches("reaper-helper", "reaper"));
+        assert!(!matches(".exe", "reaper"));
+    }
+
+    #[test]
+    fn a_wanted_program_that_is_running_is_found() {
+        let found = find_running(RUNNING.iter(), &wanted(&["ableton", "music", "reaper"]));
+        assert_eq!(found.as_deref(), Some("reaper"));
+        assert_eq!(find_running(RUNNING.iter(), &wanted(&["ableton", "logic"])), None);
+        assert_eq!(find_running(std::iter::empty::<&str>(), &wanted(&["reaper"])), None);
+    }
+
+    #[test]
+    fn gate_is_off_without_require_process() {
+        assert!(ProcessGate::new(&Config::default()).is_none());
+        let continuous = Config {
+            require_process: wanted(&["reaper"]),
+            continuous: true,
+            ..Config::default()
+        };
+        assert!(ProcessGate::new(&continuous).is_none());
+    }
+}
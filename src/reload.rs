@@ -0,0 +1,180 @@
+//! Reload the config file when it changes (`watch_config`), so settings
+//! can be tuned without restarting the daemon. A file that no longer loads
+//! or validates is ignored and the running config kept.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::config::Config;
+
+/// Settings read once at startup, which a reload can't change
+const STARTUP_ONLY: &[&str] = &[
+    "adaptive_interval",
+    "adaptive_after",
+    "min_interval",
+    "max_interval",
+    "random_seed",
+    "initial_delay",
+    "play_on_start",
+    "warmup_interval",
+    "warmup_duration",
+    "repeat_count",
+    "wake_on_resume",
+    "idle_aware",
+    "require_process",
+    "data_dir",
+    "log_file",
+    "log_level",
+    "log_format",
+    "log_target",
+    "log_max_bytes",
+    "log_max_files",
+    "metrics_port",
+    "metrics_address",
+    "alert_webhook",
+    "failure_threshold",
+    "desktop_notifications",
+    "failure_chirp",
+];
+
+/// Watches the file a config was loaded from for changes
+pub(crate) struct ConfigWatch {
+    path: PathBuf,
+    /// Modification time at the last check, if the file could be read
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatch {
+    /// None unless `watch_config` is set and the config came from a file
+    pub fn new(config: &Config) -> Option<Self> {
+        if !config.watch_config {
+            return None;
+        }
+        let Some(path) = config.source.clone() else {
+            log::warn!("watch_config is set but the config wasn't loaded from a file");
+            return None;
+        };
+        log::info!("Watching {} for changes", path.display());
+        let modified = modified(&path);
+        Some(Self { path, modified })
+    }
+
+    /// The reloaded config if the file changed since the last check and
+    /// still loads and validates. Otherwise logs why and returns None, so
+    /// `current` keeps running.
+    pub fn changed(&mut self, current: &Config) -> Option<Config> {
+        let modified = modified(&self.path);
+        if modified == self.modified || modified.is_none() {
+            return None;
+        }
+        self.modified = modified;
+        let config = match current.reload() {
+            Ok(config) => config,
+            Err(e) => {
+                log::warn!("{}; keeping the running config", e);
+                return None;
+            }
+        };
+        if config.is_continuous() != current.is_continuous() {
+            log::warn!(
+                "Switching to or from continuous mode needs a restart; keeping the running config"
+            );
+            return None;
+        }
+        log::info!("Reloaded config from {}", self.path.display());
+        let ignored = startup_only_changes(current, &config);
+        if !ignored.is_empty() {
+            log::warn!("Restart nodoze to apply changes to {}", ignored.join(", "));
+        }
+        Some(config)
+    }
+}
+
+/// Modification time of `path`, or None if it can't be read, e.g. while an
+/// editor replaces the file
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Which of the `STARTUP_ONLY` settings differ between `old` and `new`
+fn startup_only_changes(old: &Config, new: &Config) -> Vec<&'static str> {
+    let (Ok(toml::Value::Table(old)), Ok(toml::Value::Table(new))) =
+        (toml::Value::try_from(old), toml::Value::try_from(new))
+    else {
+        return Vec::new();
+    };
+    STARTUP_ONLY
+        .iter()
+        .copied()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Write `contents` to `path` and move its modification time on by
+    /// `secs`, so the change shows even on filesystems with coarse mtimes
+    fn rewrite(path: &Path, contents: &str, secs: u64) {
+        std::fs::write(path, contents).unwrap();
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(secs)).unwrap();
+    }
+
+    fn watched(contents: &str) -> (tempfile::TempDir, PathBuf, Config, ConfigWatch) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, contents).unwrap();
+        let config = Config::load(path.to_str(), None, true).unwrap();
+        let watch = ConfigWatch::new(&config).unwrap();
+        (dir, path, config, watch)
+    }
+
+    #[test]
+    fn unchanged_file_is_not_reloaded() {
+        let (_dir, _path, config, mut watch) = watched("watch_config = true\nvolume = 0.1\n");
+        assert!(watch.changed(&config).is_none());
+        assert!(watch.changed(&config).is_none());
+    }
+
+    #[test]
+    fn modified_file_is_reloaded_once() {
+        let (_dir, path, config, mut watch) = watched("watch_config = true\nvolume = 0.1\n");
+        rewrite(&path, "watch_config = true\nvolume = 0.2\n", 10);
+        let reloaded = watch.changed(&config).unwrap();
+        assert_eq!(reloaded.volume, 0.2);
+        assert!(watch.changed(&reloaded).is_none());
+    }
+
+    #[test]
+    fn invalid_file_keeps_the_running_config() {
+        let (_dir, path, config, mut watch) = watched("watch_config = true\nvolume = 0.1\n");
+        rewrite(&path, "watch_config = true\nvolume = \"loud\"\n", 10);
+        assert!(watch.changed(&config).is_none());
+        // Fixing it is picked up at the next change
+        rewrite(&path, "watch_config = true\nvolume = 0.3\n", 20);
+        assert_eq!(watch.changed(&config).unwrap().volume, 0.3);
+    }
+
+    #[test]
+    fn no_watch_without_watch_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "volume = 0.1\n").unwrap();
+        let config = Config::load(path.to_str(), None, true).unwrap();
+        assert!(ConfigWatch::new(&config).is_none());
+    }
+
+    #[test]
+    fn startup_only_changes_are_listed() {
+        let old = Config::default();
+        let new = Config {
+            play_on_start: !old.play_on_start,
+            volume: 0.2,
+            ..Config::default()
+        };
+        assert_eq!(startup_only_changes(&old, &new), ["play_on_start"]);
+    }
+}
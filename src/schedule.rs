@@ -0,0 +1,719 @@
+use chrono::{DateTime, Datelike, Local, NaiveTime, Utc, Weekday};
+use std::time::{Duration, SystemTime};
+
+use crate::config::Config;
+use crate::rng::Rng;
+
+/// Why a tone falls when it does
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    /// Played at startup, once `initial_delay` is over
+    Startup,
+    /// One `interval` after the previous tone
+    Interval,
+    /// One `interval`, give or take `interval_jitter`, after the previous tone
+    Jitter,
+    /// One adaptive interval (`adaptive_interval`) after the previous tone
+    Adaptive,
+    /// One `warmup_interval` after the previous tone, while warming up
+    Warmup,
+    /// One `interval` from the day's `[schedule.<day>]` table after the
+    /// previous tone
+    Weekday,
+    /// Due during quiet hours, so played as soon as they ended
+    QuietHours,
+}
+
+impl Reason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Startup => "startup",
+            Self::Interval => "interval",
+            Self::Jitter => "jitter",
+            Self::Adaptive => "adaptive interval",
+            Self::Warmup => "warmup",
+            Self::Weekday => "weekday interval",
+            Self::QuietHours => "after quiet hours",
+        }
+    }
+}
+
+/// The settings for the current local day of the week: the config with
+/// that day's `[schedule.<day>]` overrides applied, and its quiet hours
+pub(crate) struct Today {
+    pub day: Weekday,
+    pub config: Config,
+    pub quiet_hours: Option<(NaiveTime, NaiveTime)>,
+}
+
+impl Today {
+    pub fn new(config: &Config, now: SystemTime) -> Self {
+        let day = weekday(now);
+        let config = config.on_day(day);
+        let quiet_hours = config.quiet_hours().unwrap_or_else(|e| {
+            log::warn!("Ignoring quiet hours: {}", e);
+            None
+        });
+        Self {
+            day,
+            config,
+            quiet_hours,
+        }
+    }
+
+    /// Switch to the settings for the day at `now`, if the config has a
+    /// schedule and the day has changed. True if it switched.
+    pub fn update(&mut self, config: &Config, now: SystemTime) -> bool {
+        if config.schedule.is_empty() || weekday(now) == self.day {
+            return false;
+        }
+        *self = Self::new(config, now);
+        true
+    }
+
+    /// Whether `at` falls inside today's quiet hours
+    pub fn in_quiet_hours(&self, at: SystemTime) -> bool {
+        in_quiet_hours(self.quiet_hours, at)
+    }
+
+    /// Whether today's table sets its own interval
+    pub fn has_interval(&self, config: &Config) -> bool {
+        self.config.interval != config.interval
+    }
+}
+
+/// How much an adaptive interval grows after `adaptive_after` tones on time
+const ADAPTIVE_GROWTH: f64 = 1.1;
+
+/// How much an adaptive interval shrinks after a tone that played late
+const ADAPTIVE_SHRINK: f64 = 0.75;
+
+/// An interval that adapts to how tones are going (`adaptive_interval`):
+/// a scale on the configured interval that grows while tones keep playing
+/// on time and shrinks after one played late, which means the system slept
+/// and the speakers likely did too. Always kept within `min_interval` and
+/// `max_interval`.
+pub(crate) struct Adaptive {
+    scale: f64,
+    /// Tones on time since the interval last changed
+    streak: u32,
+    after: u32,
+    min: Duration,
+    max: Duration,
+    /// The configured interval the scale was last applied to
+    base: Duration,
+}
+
+impl Adaptive {
+    /// None unless `adaptive_interval` is set (and the daemon isn't playing
+    /// continuously)
+    pub fn new(config: &Config) -> Option<Self> {
+        if !config.adaptive_interval || config.is_continuous() {
+            return None;
+        }
+        let (min, max) = config.adaptive_bounds();
+        Some(Self {
+            scale: 1.0,
+            streak: 0,
+            after: config.adaptive_after.max(1),
+            min: Duration::from_secs(min),
+            max: Duration::from_secs(max),
+            base: Duration::from_secs(config.interval),
+        })
+    }
+
+    /// `interval` (the configured one, jittered) scaled and kept in bounds
+    pub fn apply(&mut self, interval: Duration) -> Duration {
+        self.base = interval;
+        interval.mul_f64(self.scale).clamp(self.min, self.max)
+    }
+
+    /// Record a tone that played, late or on time. True if the interval
+    /// changed, so it should be worked out again.
+    pub fn played(&mut self, late: bool) -> bool {
+        let base = self.base.as_secs_f64().max(1.0);
+        // Stop the scale running on past what the bounds allow
+        let lowest = self.min.as_secs_f64() / base;
+        let highest = self.max.as_secs_f64() / base;
+        let scale = if late {
+            self.streak = 0;
+            (self.scale * ADAPTIVE_SHRINK).max(lowest)
+        } else {
+            self.streak += 1;
+            if self.streak < self.after {
+                return false;
+            }
+            self.streak = 0;
+            (self.scale * ADAPTIVE_GROWTH).min(highest)
+        };
+        let changed = scale != self.scale;
+        self.scale = scale;
+        changed
+    }
+
+    /// Record a tone that failed, which starts the run of tones on time over
+    pub fn failed(&mut self) {
+        self.streak = 0;
+    }
+}
+
+/// One tone in a simulated schedule
+#[derive(Debug, Clone)]
+pub struct Tone {
+    /// When the tone starts
+    pub at: SystemTime,
+    /// Time since the previous tone started, if there was one
+    pub gap: Option<Duration>,
+    pub reason: Reason,
+}
+
+/// The next `count` tones the daemon would play if started at `start`,
+/// assuming each plays successfully, nothing pauses it and the system never
+/// sleeps, so `post_wake_interval` doesn't apply. Runs the same
+/// scheduling rules as the daemon without touching any audio. Tones really
+/// start at the first poll after these times, so up to `poll_interval`
+/// later. Jitter is random unless `random_seed` is set.
+pub fn simulate(config: &Config, start: SystemTime, count: usize) -> Result<Vec<Tone>, String> {
+    if config.is_continuous() {
+        return Err("continuous mode plays one unbroken tone, so there is no schedule".to_string());
+    }
+    // The daemon only warns about a window it can't parse, but here that's an error
+    config.quiet_hours()?;
+    let mut rng = match config.random_seed {
+        Some(seed) => Rng::new(seed),
+        None => Rng::from_entropy(),
+    };
+    let count = match config.repeat_count {
+        0 => count,
+        repeats => count.min(repeats as usize),
+    };
+    let jittered = config.max_jitter() > 0;
+    let begin = start + Duration::from_secs(config.initial_delay);
+    let mut today = Today::new(config, begin);
+    let mut adaptive = Adaptive::new(config);
+    let base = next_interval(&today.config, &mut rng);
+    let mut interval = adaptive.as_mut().map_or(base, |adaptive| adaptive.apply(base));
+    let warmup_until = (config.warmup_duration > 0)
+        .then(|| begin + Duration::from_secs(config.warmup_duration));
+    let warmup_interval = Duration::from_secs(config.warmup_interval);
+
+    let mut tones: Vec<Tone> = Vec::new();
+    let mut slot = begin;
+    if config.play_on_start {
+        // Skipped in quiet hours, then played as soon as they end
+        let (at, reason) = match today.quiet_hours.filter(|&w| in_quiet_hours(Some(w), begin)) {
+            Some((_, end)) => (quiet_hours_end(end, begin), Reason::QuietHours),
+            None => (begin, Reason::Startup),
+        };
+        slot = at;
+        tones.push(Tone { at, gap: None, reason });
+    }
+
+    while tones.len() < count {
+        if today.update(config, slot) {
+            let base = next_interval(&today.config, &mut rng);
+            interval = adaptive.as_mut().map_or(base, |adaptive| adaptive.apply(base));
+        }
+        // The daemon switches to the full interval as soon as warmup ends,
+        // so such a tone only falls within it
+        let warmup = warmup_interval.min(interval);
+        let (due, mut reason) = if warmup_until.is_some_and(|until| slot + warmup < until) {
+            (warmup, Reason::Warmup)
+        } else if today.has_interval(config) {
+            (interval, Reason::Weekday)
+        } else if adaptive.is_some() {
+            (interval, Reason::Adaptive)
+        } else if jittered {
+            (interval, Reason::Jitter)
+        } else {
+            (interval, Reason::Interval)
+        };
+        let mut at = slot + due;
+        // Quiet hours of the day the tone falls on, which may be the next
+        let quiet_hours = Today::new(config, at).quiet_hours;
+        if let Some((_, end)) = quiet_hours.filter(|&w| in_quiet_hours(Some(w), at)) {
+            at = quiet_hours_end(end, at);
+            reason = Reason::QuietHours;
+        }
+        let elapsed = at.duration_since(slot).unwrap_or(due);
+        slot = next_slot(slot, elapsed, due, at);
+        let gap = tones.last().and_then(|last| at.duration_since(last.at).ok());
+        tones.push(Tone { at, gap, reason });
+        // No tone plays late here: one held back by quiet hours waited on
+        // purpose rather than because the system slept
+        let adapted = adaptive.as_mut().is_some_and(|adaptive| adaptive.played(false));
+        if jittered || adapted {
+            let base = next_interval(&today.config, &mut rng);
+            interval = adaptive.as_mut().map_or(base, |adaptive| adaptive.apply(base));
+        }
+    }
+    Ok(tones)
+}
+
+/// `tones` as an iCalendar (RFC 5545) feed with one event per tone, for
+/// calendar apps and automations. `stamp` is when the feed was made.
+pub fn to_ical(tones: &[Tone], config: &Config, stamp: SystemTime) -> String {
+    let utc = |at: SystemTime| DateTime::<Utc>::from(at).format("%Y%m%dT%H%M%SZ").to_string();
+    let created = utc(stamp);
+    let id = stamp.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:-//nodoze//nodoze {}//EN", env!("CARGO_PKG_VERSION")),
+        "CALSCALE:GREGORIAN".to_string(),
+    ];
+    for (n, tone) in tones.iter().enumerate() {
+        // A tone of 0 s is one short burst, so give it a second to show
+        let length = Duration::from_secs(config.duration.max(1));
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}-{}@nodoze", id, n + 1),
+            format!("DTSTAMP:{}", created),
+            format!("DTSTART:{}", utc(tone.at)),
+            format!("DTEND:{}", utc(tone.at + length)),
+            format!("SUMMARY:nodoze tone ({})", tone.reason.name()),
+            "TRANSP:TRANSPARENT".to_string(),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+    lines.push("END:VCALENDAR".to_string());
+    // Lines end in CRLF, the last one included
+    lines.iter().map(|line| format!("{}\r\n", line)).collect()
+}
+
+/// Time from one tone to the next: the shortest of `interval` and the
+/// intervals of any `windows` still open at `now`. Each window is a shorter
+/// interval and when it stops applying, e.g. warmup after startup or
+/// `post_wake_interval` after a wake.
+pub(crate) fn due_interval(
+    interval: Duration,
+    windows: &[(Duration, Option<SystemTime>)],
+    now: SystemTime,
+) -> Duration {
+    windows
+        .iter()
+        .filter(|(_, until)| until.is_some_and(|until| now < until))
+        .fold(interval, |due, &(short, _)| due.min(short))
+}
+
+/// Start of the interval after a tone that played at `played`, `elapsed`
+/// into the interval starting at `slot`. Steps on by whole intervals so
+/// tones keep a fixed cadence however long each one takes to play, but a
+/// tone over an interval late (after a wake, a long backoff or quiet hours)
+/// restarts the cadence from when it played.
+pub(crate) fn next_slot(
+    slot: SystemTime,
+    elapsed: Duration,
+    due: Duration,
+    played: SystemTime,
+) -> SystemTime {
+    if elapsed >= due * 2 {
+        played
+    } else {
+        slot + due
+    }
+}
+
+/// How far the wall clock has gone back to before `since`, e.g. after an
+/// NTP correction, or None if `now` is still after it
+pub(crate) fn clock_went_back(since: SystemTime, now: SystemTime) -> Option<Duration> {
+    since.duration_since(now).ok().filter(|back| !back.is_zero())
+}
+
+/// `interval` plus a random offset of up to the configured jitter
+pub(crate) fn next_interval(config: &Config, rng: &mut Rng) -> Duration {
+    let base = config.interval as f64;
+    let jitter = config.max_jitter() as f64;
+    Duration::from_secs_f64(base + rng.symmetric(jitter))
+}
+
+/// Local day of the week at `at`
+fn weekday(at: SystemTime) -> Weekday {
+    DateTime::<Local>::from(at).weekday()
+}
+
+/// Whether `at` falls inside the quiet hours window, in local time
+fn in_quiet_hours(window: Option<(NaiveTime, NaiveTime)>, at: SystemTime) -> bool {
+    let Some((start, end)) = window else {
+        return false;
+    };
+    let now = DateTime::<Local>::from(at).time();
+    if start <= end {
+        now >= start && now < end
+    } else {
+        // Window crosses midnight
+        now >= start || now < end
+    }
+}
+
+/// The first time quiet hours ending at `end` are over, after `at`
+fn quiet_hours_end(end: NaiveTime, at: SystemTime) -> SystemTime {
+    let local = DateTime::<Local>::from(at);
+    let mut day = local.date_naive();
+    if end <= local.time() {
+        day = day.succ_opt().unwrap_or(day);
+    }
+    day.and_time(end)
+        .and_local_timezone(Local)
+        .earliest()
+        .map(SystemTime::from)
+        .unwrap_or(at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fixed start time, so runs don't depend on the clock
+    fn start() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    #[test]
+    fn jittered_intervals_stay_within_bounds() {
+        let config = Config {
+            interval: 540,
+            interval_jitter: 60,
+            ..Config::default()
+        };
+        let mut rng = Rng::new(7);
+        for _ in 0..10_000 {
+            let secs = next_interval(&config, &mut rng).as_secs_f64();
+            assert!((480.0..=600.0).contains(&secs), "{}s", secs);
+        }
+    }
+
+    #[test]
+    fn jitter_never_makes_the_interval_non_positive() {
+        // More jitter than interval is capped at half the interval
+        let config = Config {
+            interval: 10,
+            interval_jitter: 100,
+            ..Config::default()
+        };
+        let mut rng = Rng::new(7);
+        for _ in 0..10_000 {
+            let secs = next_interval(&config, &mut rng).as_secs_f64();
+            assert!((5.0..=15.0).contains(&secs), "{}s", secs);
+        }
+    }
+
+    #[test]
+    fn first_tone_plays_at_startup_by_default() {
+        let config = Config {
+            interval: 540,
+            ..Config::default()
+        };
+        let tones = simulate(&config, start(), 2).unwrap();
+        assert_eq!(tones[0].at, start());
+        assert_eq!(tones[0].reason, Reason::Startup);
+        assert_eq!(tones[1].at, start() + Duration::from_secs(540));
+    }
+
+    #[test]
+    fn play_on_start_off_waits_a_full_interval() {
+        let config = Config {
+            interval: 540,
+            play_on_start: false,
+            ..Config::default()
+        };
+        let tones = simulate(&config, start(), 2).unwrap();
+        assert_eq!(tones[0].at, start() + Duration::from_secs(540));
+        assert_eq!(tones[0].reason, Reason::Interval);
+        assert_eq!(tones[1].at, start() + Duration::from_secs(1080));
+    }
+
+    #[test]
+    fn slots_keep_a_fixed_cadence() {
+        let due = Duration::from_secs(540);
+        let mut slot = start();
+        for cycle in 1..=10 {
+            // Each tone starts a little after its slot, at the next poll
+            let played = slot + due + Duration::from_millis(900);
+            let elapsed = played.duration_since(slot).unwrap();
+            slot = next_slot(slot, elapsed, due, played);
+            assert_eq!(slot, start() + due * cycle);
+        }
+    }
+
+    #[test]
+    fn a_tone_over_an_interval_late_restarts_the_cadence() {
+        let due = Duration::from_secs(540);
+        let played = start() + due * 3;
+        assert_eq!(next_slot(start(), due * 3, due, played), played);
+    }
+
+    fn weekly() -> Config {
+        toml::from_str(
+            r#"
+            interval = 540
+            quiet_hours_start = "23:00"
+            quiet_hours_end = "07:00"
+
+            [schedule.saturday]
+            interval = 240
+
+            [schedule.sunday]
+            quiet_hours_start = "22:00"
+            quiet_hours_end = "09:00"
+            "#,
+        )
+        .unwrap()
+    }
+
+    /// Noon local time on the next `day` on or after 2026-01-05 (a Monday)
+    fn noon_on(day: Weekday) -> SystemTime {
+        let monday = chrono::NaiveDate::from_ymd_opt(2026, 1, 5).unwrap();
+        let date = monday + chrono::Days::new(day.num_days_from_monday() as u64);
+        let noon = date.and_hms_opt(12, 0, 0).unwrap();
+        SystemTime::from(noon.and_local_timezone(Local).earliest().unwrap())
+    }
+
+    /// Quiet hours from `start` to `end` o'clock
+    fn window(start: u32, end: u32) -> Option<(NaiveTime, NaiveTime)> {
+        let hour = |h| NaiveTime::from_hms_opt(h, 0, 0).unwrap();
+        Some((hour(start), hour(end)))
+    }
+
+    #[test]
+    fn days_with_a_table_override_the_top_level() {
+        let config = weekly();
+        let saturday = Today::new(&config, noon_on(Weekday::Sat));
+        assert_eq!(saturday.day, Weekday::Sat);
+        assert_eq!(saturday.config.interval, 240);
+        assert!(saturday.has_interval(&config));
+
+        let sunday = Today::new(&config, noon_on(Weekday::Sun));
+        assert_eq!(sunday.config.interval, 540);
+        assert_eq!(sunday.quiet_hours, window(22, 9));
+    }
+
+    #[test]
+    fn days_without_a_table_fall_back_to_the_top_level() {
+        let config = weekly();
+        let monday = Today::new(&config, noon_on(Weekday::Mon));
+        assert_eq!(monday.config.interval, 540);
+        assert!(!monday.has_interval(&config));
+        assert_eq!(monday.quiet_hours, window(23, 7));
+    }
+
+    #[test]
+    fn today_switches_when_the_day_changes() {
+        let config = weekly();
+        let mut today = Today::new(&config, noon_on(Weekday::Fri));
+        assert!(!today.update(&config, noon_on(Weekday::Fri) + Duration::from_secs(3600)));
+        assert!(today.update(&config, noon_on(Weekday::Sat)));
+        assert_eq!(today.config.interval, 240);
+    }
+
+    fn adaptive() -> Adaptive {
+        Adaptive::new(&Config {
+            interval: 300,
+            adaptive_interval: true,
+            adaptive_after: 3,
+            min_interval: 120,
+            max_interval: 540,
+            ..Config::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn adaptive_interval_grows_on_time_up_to_the_maximum() {
+        let mut adaptive = adaptive();
+        let base = Duration::from_secs(300);
+        assert_eq!(adaptive.apply(base), base);
+        // Only every `adaptive_after` tones on time
+        assert!(!adaptive.played(false));
+        assert!(!adaptive.played(false));
+        assert!(adaptive.played(false));
+        assert_eq!(adaptive.apply(base), Duration::from_secs(330));
+
+        for _ in 0..100 {
+            adaptive.played(false);
+            let interval = adaptive.apply(base);
+            assert!(interval <= Duration::from_secs(540), "{:?}", interval);
+        }
+        assert_eq!(adaptive.apply(base), Duration::from_secs(540));
+    }
+
+    #[test]
+    fn adaptive_interval_shrinks_when_late_down_to_the_minimum() {
+        let mut adaptive = adaptive();
+        let base = Duration::from_secs(300);
+        adaptive.apply(base);
+        assert!(adaptive.played(true));
+        assert_eq!(adaptive.apply(base), Duration::from_secs(225));
+
+        for _ in 0..100 {
+            adaptive.played(true);
+            let interval = adaptive.apply(base);
+            assert!(interval >= Duration::from_secs(120), "{:?}", interval);
+        }
+        assert_eq!(adaptive.apply(base), Duration::from_secs(120));
+        // Already at the bottom, so nothing changes
+        assert!(!adaptive.played(true));
+    }
+
+    #[test]
+    fn adaptive_interval_holds_through_quiet_hours() {
+        let config = Config {
+            interval: 300,
+            adaptive_interval: true,
+            adaptive_after: 3,
+            min_interval: 120,
+            max_interval: 540,
+            quiet_hours_start: Some("01:00".to_string()),
+            quiet_hours_end: Some("04:00".to_string()),
+            ..Config::default()
+        };
+        // Started just before quiet hours, so the second tone waits them out
+        let evening = chrono::NaiveDate::from_ymd_opt(2026, 1, 5).unwrap().and_hms_opt(0, 58, 0);
+        let evening = evening.unwrap().and_local_timezone(Local).earliest().unwrap();
+        let begin = SystemTime::from(evening);
+        let tones = simulate(&config, begin, 8).unwrap();
+
+        assert_eq!(tones[1].reason, Reason::QuietHours);
+        // The tone resumed after quiet hours counts as on time, so it's
+        // one of the three that lengthen the interval rather than shrinking it
+        let gaps: Vec<u64> = tones[2..].iter().map(|tone| tone.gap.unwrap().as_secs()).collect();
+        assert_eq!(gaps, [300, 300, 330, 330, 330, 363]);
+    }
+
+    #[test]
+    fn adaptive_interval_is_off_unless_enabled() {
+        assert!(Adaptive::new(&Config::default()).is_none());
+    }
+
+    #[test]
+    fn short_windows_apply_only_while_open() {
+        let interval = Duration::from_secs(540);
+        let woke = start();
+        let windows = [(Duration::from_secs(60), Some(woke + Duration::from_secs(300)))];
+        assert_eq!(due_interval(interval, &windows, woke), Duration::from_secs(60));
+        let later = woke + Duration::from_secs(299);
+        assert_eq!(due_interval(interval, &windows, later), Duration::from_secs(60));
+        let after = woke + Duration::from_secs(300);
+        assert_eq!(due_interval(interval, &windows, after), interval);
+        assert_eq!(due_interval(interval, &[(Duration::from_secs(60), None)], woke), interval);
+    }
+
+    #[test]
+    fn tone_after_quiet_hours_is_not_taken_for_a_wake() {
+        // Nothing slept, so no post-wake tones follow it
+        let config = Config {
+            interval: 540,
+            post_wake_interval: 60,
+            post_wake_duration: 300,
+            quiet_hours_start: Some("01:00".to_string()),
+            quiet_hours_end: Some("04:00".to_string()),
+            ..Config::default()
+        };
+        let night = chrono::NaiveDate::from_ymd_opt(2026, 1, 5).unwrap().and_hms_opt(3, 0, 0);
+        let begin = SystemTime::from(night.unwrap().and_local_timezone(Local).earliest().unwrap());
+        let tones = simulate(&config, begin, 4).unwrap();
+
+        let resumed = begin + Duration::from_secs(3600);
+        assert_eq!(tones[0].at, resumed);
+        assert_eq!(tones[0].reason, Reason::QuietHours);
+        for (n, tone) in tones[1..].iter().enumerate() {
+            assert_eq!(tone.reason, Reason::Interval);
+            assert_eq!(tone.at, resumed + Duration::from_secs(540 * (n as u64 + 1)));
+        }
+    }
+
+    #[test]
+    fn backward_clock_jump_is_measured() {
+        let since = start();
+        let now = since - Duration::from_secs(3600);
+        assert_eq!(clock_went_back(since, now), Some(Duration::from_secs(3600)));
+        assert_eq!(
+            clock_went_back(since, since - Duration::from_millis(1)),
+            Some(Duration::from_millis(1))
+        );
+    }
+
+    #[test]
+    fn clock_moving_forward_is_not_a_jump_back() {
+        let since = start();
+        assert_eq!(clock_went_back(since, since), None);
+        assert_eq!(clock_went_back(since, since + Duration::from_secs(1)), None);
+        // Even far ahead, e.g. after a suspend: that's a wake, not a jump back
+        assert_eq!(clock_went_back(since, since + Duration::from_secs(86_400)), None);
+    }
+
+    fn two_tones() -> Vec<Tone> {
+        vec![
+            Tone {
+                at: start(),
+                gap: None,
+                reason: Reason::Startup,
+            },
+            Tone {
+                at: start() + Duration::from_secs(60),
+                gap: Some(Duration::from_secs(60)),
+                reason: Reason::Interval,
+            },
+        ]
+    }
+
+    #[test]
+    fn ical_feed_has_one_event_per_tone() {
+        let ical = to_ical(&two_tones(), &Config::default(), start());
+        let lines: Vec<&str> = ical.split("\r\n").collect();
+        assert_eq!(lines.first(), Some(&"BEGIN:VCALENDAR"));
+        // Every line ends in CRLF, the last one included
+        assert_eq!(lines[lines.len() - 2..], ["END:VCALENDAR", ""]);
+        assert!(!ical.replace("\r\n", "").contains('\n'));
+        assert!(lines.contains(&"VERSION:2.0"));
+        let count = |line: &str| lines.iter().filter(|l| **l == line).count();
+        assert_eq!(count("BEGIN:VEVENT"), 2);
+        assert_eq!(count("END:VEVENT"), 2);
+    }
+
+    #[test]
+    fn ical_events_carry_time_uid_and_reason() {
+        let config = Config {
+            duration: 15,
+            ..Config::default()
+        };
+        let ical = to_ical(&two_tones(), &config, start());
+        let events: Vec<&str> = ical.split("BEGIN:VEVENT\r\n").skip(1).collect();
+        assert_eq!(events.len(), 2);
+        for line in [
+            "UID:1700000000-1@nodoze",
+            "DTSTAMP:20231114T221320Z",
+            "DTSTART:20231114T221320Z",
+            "DTEND:20231114T221335Z",
+            "SUMMARY:nodoze tone (startup)",
+        ] {
+            assert!(events[0].contains(&format!("{}\r\n", line)), "{} in {}", line, events[0]);
+        }
+        for line in [
+            "UID:1700000000-2@nodoze",
+            "DTSTART:20231114T221420Z",
+            "DTEND:20231114T221435Z",
+            "SUMMARY:nodoze tone (interval)",
+        ] {
+            assert!(events[1].contains(&format!("{}\r\n", line)), "{} in {}", line, events[1]);
+        }
+    }
+
+    #[test]
+    fn ical_event_for_a_burst_lasts_a_second() {
+        let config = Config {
+            duration: 0,
+            ..Config::default()
+        };
+        let ical = to_ical(&two_tones()[..1], &config, start());
+        assert!(ical.contains("DTEND:20231114T221321Z\r\n"), "{}", ical);
+    }
+
+    #[test]
+    fn empty_ical_feed_is_still_a_calendar() {
+        let ical = to_ical(&[], &Config::default(), start());
+        assert!(ical.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ical.ends_with("END:VCALENDAR\r\n"));
+        assert!(!ical.contains("VEVENT"));
+    }
+}
@@ -1,66 +1,475 @@
-use std::path::PathBuf;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
-#[cfg(target_os = "macos")]
-const LAUNCHD_LABEL: &str = "com.nodoze.daemon";
+use crate::config::{Config, RestartPolicy};
+use crate::{control, logging, paths, state};
+
+/// LaunchAgent stderr and stdout files, inside the log directory
+const LAUNCHD_LOG_FILES: [&str; 2] = ["nodoze.stderr.log", "nodoze.stdout.log"];
+
+/// The LaunchAgent's label: `com.nodoze.daemon`, or `com.nodoze.NAME` for
+/// a named instance
+fn launchd_label() -> String {
+    format!("com.nodoze.{}", paths::instance().unwrap_or("daemon"))
+}
+
+/// The systemd unit's name: `nodoze`, or `nodoze-NAME` for a named instance
 #[cfg(target_os = "linux")]
-const SYSTEMD_SERVICE: &str = "nodoze";
+fn systemd_service() -> String {
+    paths::instance_name("nodoze")
+}
+
+/// Arguments selecting the named instance, if any, for the command a
+/// service runs
+fn instance_args() -> Vec<String> {
+    match paths::instance() {
+        Some(name) => vec!["--instance".to_string(), name.to_string()],
+        None => Vec::new(),
+    }
+}
 
-/// Install nodoze as a system service
-pub fn install() -> Result<(), String> {
+/// Install nodoze as a system service. `windows_service` registers it with
+/// the Windows Service Control Manager instead of the Startup folder, and
+/// `system` installs a system-wide systemd unit or LaunchDaemon instead of
+/// a per-user one. `print_only` prints the files and commands instead of
+/// applying them.
+pub fn install(
+    config: &Config,
+    windows_service: bool,
+    system: bool,
+    print_only: bool,
+) -> Result<(), String> {
     let exe = std::env::current_exe()
         .map_err(|e| format!("Failed to get executable path: {}", e))?;
+    #[cfg(not(target_os = "windows"))]
+    if windows_service {
+        return Err("--windows-service is only supported on Windows".to_string());
+    }
+    check_system(system, print_only, "install")?;
 
     #[cfg(target_os = "macos")]
-    return install_launchd(&exe);
+    return install_launchd(&exe, &launchd_log_dir(config, system)?, config, system, print_only);
 
     #[cfg(target_os = "linux")]
-    return install_systemd(&exe);
+    return install_systemd(&exe, config, system, print_only);
 
     #[cfg(target_os = "windows")]
-    return install_windows_task(&exe);
+    return if windows_service {
+        scm::install(&exe, config, print_only)
+    } else {
+        install_windows_task(&exe, config, print_only)
+    };
 
     #[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
     return Err("Service installation not supported on this platform".to_string());
 }
 
-/// Uninstall nodoze system service
-pub fn uninstall() -> Result<(), String> {
+/// Service definitions `nodoze emit` can print
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Definition {
+    /// macOS LaunchAgent plist
+    Launchd,
+    /// systemd user unit
+    Systemd,
+    /// Windows Startup folder script
+    WindowsTask,
+}
+
+/// Print the service definition `install` would write for this binary,
+/// for deploying it some other way. Works on any platform.
+pub fn emit(config: &Config, definition: Definition) -> Result<(), String> {
+    let exe = std::env::current_exe()
+        .map_err(|e| format!("Failed to get executable path: {}", e))?;
+    let rendered = match definition {
+        Definition::Launchd => {
+            render_launchd_plist(&exe, &launchd_log_dir(config, false)?, config)
+        }
+        #[cfg(target_os = "linux")]
+        Definition::Systemd => render_systemd_unit(&exe, &detect_audio_units(), config, false),
+        #[cfg(not(target_os = "linux"))]
+        Definition::Systemd => render_systemd_unit(&exe, &[], config, false),
+        Definition::WindowsTask => render_windows_task_cmd(&exe, config),
+    };
+    println!("{}", rendered.trim_end());
+    Ok(())
+}
+
+/// Uninstall nodoze system service. `system` removes the system-wide one
+/// `install --system` created. `remove_logs` also deletes the LaunchAgent's
+/// log directory. `print_only` prints the commands instead of running them.
+pub fn uninstall(
+    config: &Config,
+    system: bool,
+    remove_logs: bool,
+    print_only: bool,
+) -> Result<(), String> {
+    #[cfg(not(target_os = "macos"))]
+    if remove_logs {
+        log::info!("No service log files to remove on this platform");
+    }
+    #[cfg(not(target_os = "macos"))]
+    let _ = config;
+    check_system(system, print_only, "uninstall")?;
+
     #[cfg(target_os = "macos")]
-    return uninstall_launchd();
+    return uninstall_launchd(
+        remove_logs.then(|| launchd_log_dir(config, system)).transpose()?,
+        system,
+        print_only,
+    );
 
     #[cfg(target_os = "linux")]
-    return uninstall_systemd();
+    return uninstall_systemd(system, print_only);
 
     #[cfg(target_os = "windows")]
-    return uninstall_windows_task();
+    return scm::uninstall(print_only).and_then(|_| uninstall_windows_task(print_only));
 
     #[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
     return Err("Service uninstallation not supported on this platform".to_string());
 }
 
-// ── macOS LaunchAgent ──────────────────────────────────────────────
+/// What `uninstall --purge` deletes, of what exists: the config directory
+/// (and the old wake-speaker one), or a named instance's config file, the
+/// state directory, the log file with its rotations, and the control socket
+pub fn purge_paths(config: &Config) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    // Only the per-user config directories, not ./nodoze.toml or /etc. A
+    // named instance's config file shares its directory with the others'.
+    if paths::instance().is_some() {
+        paths.extend(Config::config_path().ok());
+    } else if let Ok(config_dir) = paths::config_dir() {
+        paths.extend(
+            Config::search_paths()
+                .iter()
+                .filter(|path| path.starts_with(&config_dir))
+                .filter_map(|path| path.parent().map(Path::to_path_buf)),
+        );
+    }
+    // data_dir may be shared with other files, so only the state directory
+    // nodoze made itself is removed whole
+    if state::data_dir().is_some() {
+        log::warn!(
+            "Not removing data_dir {}; delete it yourself if it's nodoze's alone",
+            config.data_dir
+        );
+    } else {
+        paths.extend(state::dir().ok());
+    }
+    if let Some(log) = config.log_path() {
+        let rotations = (1..=config.log_max_files).map(|n| {
+            let mut name = log.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        });
+        paths.extend(rotations);
+        paths.push(log);
+    }
+    paths.push(control::socket_path());
+    paths.retain(|path| path.exists());
+    paths.dedup();
+    paths
+}
 
-#[cfg(target_os = "macos")]
-fn launchd_plist_path() -> Result<PathBuf, String> {
-    let home = dirs::home_dir().ok_or("Could not determine home directory")?;
-    Ok(home
-        .join("Library")
-        .join("LaunchAgents")
-        .join(format!("{}.plist", LAUNCHD_LABEL)))
+/// Delete what `purge_paths` found. `print_only` prints the commands
+/// instead.
+pub fn purge(paths: &[PathBuf], print_only: bool) -> Result<(), String> {
+    for path in paths {
+        if print_only {
+            let rm = if path.is_dir() { "rm -r" } else { "rm" };
+            println!("$ {} {}", rm, path.display());
+            continue;
+        }
+        let removed = if path.is_dir() {
+            std::fs::remove_dir_all(path)
+        } else {
+            std::fs::remove_file(path)
+        };
+        match removed {
+            Ok(()) => println!("Removed {}", path.display()),
+            // Gone already, e.g. the socket of a daemon that just stopped
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to remove {}: {}", path.display(), e)),
+        }
+    }
+    Ok(())
+}
+
+/// For `--system`: an error unless this platform has system-wide services
+/// and we're root. Not needed just to print what would be done.
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn check_system(system: bool, print_only: bool, action: &str) -> Result<(), String> {
+    // SAFETY: geteuid has no preconditions and can't fail
+    if system && !print_only && unsafe { libc::geteuid() } != 0 {
+        return Err(format!(
+            "A system-wide service needs root to {}; run with sudo: sudo nodoze {} --system",
+            action, action
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn check_system(system: bool, _print_only: bool, _action: &str) -> Result<(), String> {
+    if system {
+        #[cfg(target_os = "windows")]
+        return Err("--system is for systemd and launchd; on Windows use --windows-service"
+            .to_string());
+        #[cfg(not(target_os = "windows"))]
+        return Err("--system isn't supported on this platform".to_string());
+    }
+    Ok(())
+}
+
+/// Snapshot printed by `nodoze status`
+#[derive(Serialize)]
+pub struct Status {
+    /// The `--instance` name, or None for the default instance
+    pub instance: Option<&'static str>,
+    pub installed: bool,
+    /// Service file (or Windows service name), if installed
+    pub location: Option<String>,
+    /// Program the installed service runs, if it could be read back
+    pub program: Option<String>,
+    /// Whether `program` is this nodoze binary. False after the binary was
+    /// moved or reinstalled elsewhere, which leaves the service broken.
+    pub program_matches: Option<bool>,
+    /// A daemon is answering on the control socket
+    pub running: bool,
+    pub pid: Option<u32>,
+    /// Version the running daemon was started as, from the state file
+    pub daemon_version: Option<String>,
+    /// Whether `daemon_version` is this binary's version. False after an
+    /// update while the old daemon keeps running.
+    pub version_matches: Option<bool>,
+    /// Time of the last tone played by any nodoze process, in Unix seconds
+    pub last_play_unix: Option<u64>,
+    /// When the daemon last touched its liveness file, in Unix seconds
+    pub last_alive_unix: Option<u64>,
+    pub platform: &'static str,
+}
+
+/// Print the daemon's recent log output, then with `follow` keep printing
+/// it as it's written. Reads `log_file` if one is configured, otherwise
+/// wherever the installed service's output goes.
+pub fn logs(config: &Config, lines: usize, follow: bool) -> Result<(), String> {
+    if let Some(path) = config.log_path() {
+        return logging::tail(&path, lines, follow);
+    }
+
+    #[cfg(target_os = "macos")]
+    return logging::tail(
+        &launchd_log_dir(config, is_system_installed()?)?.join(LAUNCHD_LOG_FILES[0]),
+        lines,
+        follow,
+    );
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut journalctl = std::process::Command::new("journalctl");
+        if !is_system_installed()? {
+            journalctl.arg("--user");
+        }
+        journalctl
+            .args(["-u", &systemd_service(), "-n"])
+            .arg(lines.to_string());
+        if follow {
+            journalctl.arg("-f");
+        }
+        let status = journalctl
+            .status()
+            .map_err(|e| format!("Failed to run journalctl: {}", e))?;
+        if !status.success() {
+            return Err(format!("journalctl exited with {}", status));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    return Err(
+        "The service's output isn't kept on this platform; set `log_file` in the config"
+            .to_string(),
+    );
+}
+
+/// Whether the service is installed and a daemon is running
+pub fn status() -> Result<Status, String> {
+    let location = location()?;
+    let program = location.as_ref().and_then(|_| installed_program());
+    let pid = control::send("pid").ok().and_then(|reply| reply.parse().ok());
+    // Left behind by whichever daemon ran last, so only meaningful while one runs
+    let daemon_version = pid.and(state::daemon_version());
+    Ok(Status {
+        instance: paths::instance(),
+        installed: location.is_some(),
+        location,
+        program_matches: program.as_deref().map(is_current_exe),
+        program: program.map(|p| p.display().to_string()),
+        running: pid.is_some(),
+        pid,
+        version_matches: daemon_version.as_deref().map(|v| v == env!("CARGO_PKG_VERSION")),
+        daemon_version,
+        last_play_unix: state::last_play()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs()),
+        last_alive_unix: state::last_alive()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs()),
+        platform: std::env::consts::OS,
+    })
+}
+
+/// Where the service is installed, or None if it isn't
+fn location() -> Result<Option<String>, String> {
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    return installed_path().map(|path| path.map(|path| path.display().to_string()));
+
+    #[cfg(target_os = "windows")]
+    return if scm::is_installed() {
+        Ok(Some(format!("Windows service {}", scm::service_name())))
+    } else {
+        startup_script_path().map(|path| path.exists().then(|| path.display().to_string()))
+    };
+
+    #[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+    return Err("Service installation not supported on this platform".to_string());
+}
+
+/// `--print-only`: show a file that would be written
+#[cfg(any(target_os = "macos", target_os = "linux", target_os = "windows"))]
+fn print_file(path: &Path, contents: &str) {
+    println!("# {}", path.display());
+    println!("{}", contents.trim_end());
+    println!();
+}
+
+/// `--print-only`: show commands that would be run, one per line
+#[cfg(any(target_os = "macos", target_os = "linux", target_os = "windows"))]
+fn print_commands(commands: &[String]) {
+    for command in commands {
+        println!("$ {}", command);
+    }
+}
+
+/// Undo writing a service file after a later install step failed: put back
+/// the `previous` contents if there were any, otherwise remove it. Returns
+/// what state that leaves, for the error message.
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn roll_back(path: &Path, previous: Option<String>) -> String {
+    match previous {
+        Some(contents) => match std::fs::write(path, contents) {
+            Ok(()) => format!("restored the previous {}", path.display()),
+            Err(e) => format!("couldn't restore the previous {} ({})", path.display(), e),
+        },
+        None => match std::fs::remove_file(path) {
+            Ok(()) => format!("removed {}, so nothing is left installed", path.display()),
+            Err(e) => format!(
+                "couldn't remove {} ({}); delete it or run `nodoze uninstall`",
+                path.display(),
+                e
+            ),
+        },
+    }
 }
 
+/// True if `program` is the running executable. Assumed true if the running
+/// executable can't be determined, so there's no false alarm.
+fn is_current_exe(program: &Path) -> bool {
+    let Ok(exe) = std::env::current_exe() else {
+        return true;
+    };
+    let canonical = |p: &Path| p.canonicalize().unwrap_or_else(|_| p.to_path_buf());
+    canonical(program) == canonical(&exe)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+fn installed_program() -> Option<PathBuf> {
+    None
+}
+
+/// The installed plist or unit file: the per-user one, otherwise the
+/// system-wide one, or None if neither is installed
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn installed_path() -> Result<Option<PathBuf>, String> {
+    #[cfg(target_os = "macos")]
+    let path = launchd_plist_path;
+    #[cfg(target_os = "linux")]
+    let path = systemd_unit_path;
+    for system in [false, true] {
+        let path = path(system)?;
+        if path.exists() {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Whether the installed service is the system-wide one
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn is_system_installed() -> Result<bool, String> {
+    #[cfg(target_os = "macos")]
+    let system = launchd_plist_path(true)?;
+    #[cfg(target_os = "linux")]
+    let system = systemd_unit_path(true)?;
+    Ok(installed_path()? == Some(system))
+}
+
+// ── macOS LaunchAgent ──────────────────────────────────────────────
+
+/// The LaunchAgent's plist, or with `system` the LaunchDaemon's
 #[cfg(target_os = "macos")]
-fn install_launchd(exe: &PathBuf) -> Result<(), String> {
-    let plist_path = launchd_plist_path()?;
+fn launchd_plist_path(system: bool) -> Result<PathBuf, String> {
+    let library = if system {
+        PathBuf::from("/Library/LaunchDaemons")
+    } else {
+        paths::home_dir()?.join("Library").join("LaunchAgents")
+    };
+    Ok(library.join(format!("{}.plist", launchd_label())))
+}
 
-    if let Some(parent) = plist_path.parent() {
-        std::fs::create_dir_all(parent)
-            .map_err(|e| format!("Failed to create LaunchAgents directory: {}", e))?;
+/// Directory for the LaunchAgent's stdout/stderr files. Defaults to a
+/// per-user directory rather than world-writable /tmp, or with `system` to
+/// the system-wide logs directory.
+fn launchd_log_dir(config: &Config, system: bool) -> Result<PathBuf, String> {
+    if !config.launchd_log_dir.is_empty() {
+        return Ok(PathBuf::from(&config.launchd_log_dir));
+    }
+    if !config.data_dir.is_empty() {
+        return Ok(PathBuf::from(&config.data_dir));
     }
+    let library = if system {
+        PathBuf::from("/Library")
+    } else {
+        paths::home_dir()?.join("Library")
+    };
+    Ok(library.join("Logs").join(paths::instance_name("nodoze")))
+}
 
-    let exe_str = exe.to_string_lossy();
+/// First of the plist's ProgramArguments
+#[cfg(target_os = "macos")]
+fn installed_program() -> Option<PathBuf> {
+    let plist = std::fs::read_to_string(installed_path().ok()??).ok()?;
+    let arguments = &plist[plist.find("<key>ProgramArguments</key>")?..];
+    let start = arguments.find("<string>")? + "<string>".len();
+    let end = start + arguments[start..].find("</string>")?;
+    Some(PathBuf::from(&arguments[start..end]))
+}
 
-    let plist = format!(
+/// LaunchAgent plist running `<exe> run` at login, logging into `log_dir`
+/// and restarted as `restart_policy` says
+fn render_launchd_plist(exe: &Path, log_dir: &Path, config: &Config) -> String {
+    let instance: String = instance_args()
+        .iter()
+        .map(|arg| format!("      <string>{}</string>\n", arg))
+        .collect();
+    let keep_alive = match config.restart_policy {
+        RestartPolicy::Always => "<true/>",
+        RestartPolicy::OnFailure => {
+            "<dict>\n      <key>SuccessfulExit</key>\n      <false/>\n    </dict>"
+        }
+        RestartPolicy::Never => "<false/>",
+    };
+    format!(
         r#"<?xml version="1.0" encoding="UTF-8"?>
 <!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
 <plist version="1.0">
@@ -70,42 +479,104 @@ fn install_launchd(exe: &PathBuf) -> Result<(), String> {
     <key>ProgramArguments</key>
     <array>
       <string>{exe}</string>
-      <string>run</string>
+{instance}      <string>run</string>
     </array>
     <key>RunAtLoad</key>
     <true/>
     <key>KeepAlive</key>
-    <true/>
+    {keep_alive}
+    <key>ThrottleInterval</key>
+    <integer>{throttle}</integer>
     <key>StandardErrorPath</key>
-    <string>/tmp/nodoze.err</string>
+    <string>{stderr}</string>
     <key>StandardOutPath</key>
-    <string>/tmp/nodoze.out</string>
+    <string>{stdout}</string>
   </dict>
 </plist>"#,
-        label = LAUNCHD_LABEL,
-        exe = exe_str,
-    );
+        label = launchd_label(),
+        exe = exe.to_string_lossy(),
+        instance = instance,
+        keep_alive = keep_alive,
+        throttle = config.restart_delay,
+        stderr = log_dir.join(LAUNCHD_LOG_FILES[0]).display(),
+        stdout = log_dir.join(LAUNCHD_LOG_FILES[1]).display(),
+    )
+}
+
+#[cfg(target_os = "macos")]
+fn install_launchd(
+    exe: &Path,
+    log_dir: &Path,
+    config: &Config,
+    system: bool,
+    print_only: bool,
+) -> Result<(), String> {
+    let plist_path = launchd_plist_path(system)?;
+    let plist = render_launchd_plist(exe, log_dir, config);
+
+    if print_only {
+        print_file(&plist_path, &plist);
+        print_commands(&[
+            format!("mkdir -p {}", log_dir.display()),
+            format!("launchctl load -w {}", plist_path.display()),
+        ]);
+        return Ok(());
+    }
+
+    if let Some(parent) = plist_path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    std::fs::create_dir_all(log_dir)
+        .map_err(|e| format!("Failed to create log directory {}: {}", log_dir.display(), e))?;
 
+    // Put back on failure, so a reinstall that fails leaves the old one
+    let previous = std::fs::read_to_string(&plist_path).ok();
     std::fs::write(&plist_path, plist)
         .map_err(|e| format!("Failed to write plist: {}", e))?;
 
-    let status = std::process::Command::new("launchctl")
+    let load = match std::process::Command::new("launchctl")
         .args(["load", "-w"])
         .arg(&plist_path)
         .status()
-        .map_err(|e| format!("Failed to run launchctl: {}", e))?;
-
-    if status.success() {
-        println!("Service installed and started: {}", plist_path.display());
-        Ok(())
-    } else {
-        Err("launchctl load failed".to_string())
+    {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("failed ({})", status)),
+        Err(e) => Err(format!("couldn't be run ({})", e)),
+    };
+    if let Err(e) = load {
+        return Err(format!(
+            "`launchctl load -w {}` {}; {}",
+            plist_path.display(),
+            e,
+            roll_back(&plist_path, previous)
+        ));
     }
+    println!("Service installed and started: {}", plist_path.display());
+    Ok(())
 }
 
 #[cfg(target_os = "macos")]
-fn uninstall_launchd() -> Result<(), String> {
-    let plist_path = launchd_plist_path()?;
+fn uninstall_launchd(
+    log_dir: Option<PathBuf>,
+    system: bool,
+    print_only: bool,
+) -> Result<(), String> {
+    let plist_path = launchd_plist_path(system)?;
+
+    if print_only {
+        let mut commands = vec![
+            format!("launchctl unload {}", plist_path.display()),
+            format!("rm {}", plist_path.display()),
+        ];
+        if let Some(log_dir) = &log_dir {
+            for name in LAUNCHD_LOG_FILES {
+                commands.push(format!("rm {}", log_dir.join(name).display()));
+            }
+        }
+        print_commands(&commands);
+        return Ok(());
+    }
 
     if plist_path.exists() {
         let _ = std::process::Command::new("launchctl")
@@ -121,88 +592,220 @@ fn uninstall_launchd() -> Result<(), String> {
         println!("Service not installed (plist not found)");
     }
 
+    // Only nodoze's own files, in case `launchd_log_dir` is shared
+    if let Some(log_dir) = log_dir {
+        for name in LAUNCHD_LOG_FILES {
+            let path = log_dir.join(name);
+            if path.exists() {
+                std::fs::remove_file(&path)
+                    .map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+                println!("Removed log: {}", path.display());
+            }
+        }
+        let _ = std::fs::remove_dir(&log_dir);
+    }
+
     Ok(())
 }
 
 // ── Linux systemd ──────────────────────────────────────────────────
 
+/// The user unit, or with `system` the system-wide one
 #[cfg(target_os = "linux")]
-fn systemd_unit_path() -> Result<PathBuf, String> {
-    let home = dirs::home_dir().ok_or("Could not determine home directory")?;
-    Ok(home
-        .join(".config")
-        .join("systemd")
-        .join("user")
-        .join(format!("{}.service", SYSTEMD_SERVICE)))
+fn systemd_unit_path(system: bool) -> Result<PathBuf, String> {
+    let units = if system {
+        PathBuf::from("/etc/systemd/system")
+    } else {
+        paths::home_dir()?.join(".config").join("systemd").join("user")
+    };
+    Ok(units.join(format!("{}.service", systemd_service())))
 }
 
+/// `systemctl`, talking to the user's service manager unless `system`
 #[cfg(target_os = "linux")]
-fn install_systemd(exe: &PathBuf) -> Result<(), String> {
-    let unit_path = systemd_unit_path()?;
-    let exe_str = exe.to_string_lossy();
-
-    if let Some(parent) = unit_path.parent() {
-        std::fs::create_dir_all(parent)
-            .map_err(|e| format!("Failed to create systemd directory: {}", e))?;
+fn systemctl(system: bool) -> std::process::Command {
+    let mut command = std::process::Command::new("systemctl");
+    if !system {
+        command.arg("--user");
     }
+    command
+}
+
+/// Program in the unit's ExecStart line, which `install_systemd` writes as
+/// `<exe> run`, or `<exe> --instance NAME run`
+#[cfg(target_os = "linux")]
+fn installed_program() -> Option<PathBuf> {
+    let unit = std::fs::read_to_string(installed_path().ok()??).ok()?;
+    let command = unit.lines().find_map(|line| line.strip_prefix("ExecStart="))?;
+    let command = command.strip_suffix(" run").unwrap_or(command);
+    Some(PathBuf::from(command.split(" --instance ").next().unwrap_or(command)))
+}
+
+/// User-session audio server units present on this system. The service is
+/// ordered after these so the first tone doesn't race the audio stack.
+#[cfg(target_os = "linux")]
+fn detect_audio_units() -> Vec<&'static str> {
+    [
+        "pipewire.service",
+        "pipewire-pulse.service",
+        "wireplumber.service",
+        "pulseaudio.service",
+    ]
+    .into_iter()
+    .filter(|unit| {
+        std::process::Command::new("systemctl")
+            .args(["--user", "cat", unit])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success())
+    })
+    .collect()
+}
+
+/// systemd unit running `<exe> run`, ordered after (and wanting) the given
+/// audio server units and restarted as `restart_policy` says. A user unit,
+/// or with `system` one started at boot with no user session.
+fn render_systemd_unit(
+    exe: &Path,
+    audio_units: &[&str],
+    config: &Config,
+    system: bool,
+) -> String {
+    let (after, wants) = if audio_units.is_empty() {
+        ("sound.target".to_string(), String::new())
+    } else {
+        let units = audio_units.join(" ");
+        (format!("sound.target {}", units), format!("Wants={}\n", units))
+    };
+    let restart = match config.restart_policy {
+        RestartPolicy::Always => format!("Restart=always\nRestartSec={}", config.restart_delay),
+        RestartPolicy::OnFailure => {
+            format!("Restart=on-failure\nRestartSec={}", config.restart_delay)
+        }
+        RestartPolicy::Never => "Restart=no".to_string(),
+    };
+    let (environment, wanted_by) = if system {
+        ("", "multi-user.target")
+    } else {
+        (
+            concat!(
+                "# Audio servers listen on sockets in the user runtime dir\n",
+                "Environment=XDG_RUNTIME_DIR=%t\n",
+            ),
+            "default.target",
+        )
+    };
 
-    let unit = format!(
+    format!(
         r#"[Unit]
 Description=NoDoze - Keep speakers alive with inaudible tones
-After=sound.target
-
+After={after}
+{wants}
 [Service]
 Type=simple
-ExecStart={exe} run
-Restart=on-failure
-RestartSec=10
-
+ExecStart={exe}{instance} run
+{restart}
+{environment}
 [Install]
-WantedBy=default.target
+WantedBy={wanted_by}
 "#,
-        exe = exe_str,
-    );
+        exe = exe.to_string_lossy(),
+        instance = instance_args().iter().map(|arg| format!(" {}", arg)).collect::<String>(),
+        after = after,
+        wants = wants,
+        restart = restart,
+        environment = environment,
+        wanted_by = wanted_by,
+    )
+}
 
-    std::fs::write(&unit_path, unit)
-        .map_err(|e| format!("Failed to write unit file: {}", e))?;
+#[cfg(target_os = "linux")]
+fn install_systemd(
+    exe: &Path,
+    config: &Config,
+    system: bool,
+    print_only: bool,
+) -> Result<(), String> {
+    let unit_path = systemd_unit_path(system)?;
+    // Per-user audio servers aren't running for a system-wide service
+    let audio_units = if system { Vec::new() } else { detect_audio_units() };
+    let unit = render_systemd_unit(exe, &audio_units, config, system);
 
-    let reload = std::process::Command::new("systemctl")
-        .args(["--user", "daemon-reload"])
-        .status()
-        .map_err(|e| format!("Failed to reload systemd: {}", e))?;
+    if print_only {
+        let scope = if system { "" } else { " --user" };
+        print_file(&unit_path, &unit);
+        print_commands(&[
+            format!("systemctl{} daemon-reload", scope),
+            format!("systemctl{} enable --now {}", scope, systemd_service()),
+        ]);
+        return Ok(());
+    }
 
-    if !reload.success() {
-        return Err("systemctl daemon-reload failed".to_string());
+    if let Some(parent) = unit_path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create systemd directory: {}", e))?;
     }
 
-    let enable = std::process::Command::new("systemctl")
-        .args(["--user", "enable", "--now", SYSTEMD_SERVICE])
-        .status()
-        .map_err(|e| format!("Failed to enable service: {}", e))?;
+    // Put back on failure, so a reinstall that fails leaves the old one
+    let previous = std::fs::read_to_string(&unit_path).ok();
+    std::fs::write(&unit_path, unit)
+        .map_err(|e| format!("Failed to write unit file: {}", e))?;
 
-    if enable.success() {
-        println!("Service installed and started: {}", unit_path.display());
-        Ok(())
-    } else {
-        Err("systemctl enable failed".to_string())
+    let scope = if system { "" } else { " --user" };
+    let service = systemd_service();
+    let steps: [&[&str]; 2] = [&["daemon-reload"], &["enable", "--now", &service]];
+    for args in steps {
+        let result = match systemctl(system).args(args).status() {
+            Ok(status) if status.success() => continue,
+            Ok(status) => format!("failed ({})", status),
+            Err(e) => format!("couldn't be run ({})", e),
+        };
+        // `enable --now` may have enabled a new unit before failing to
+        // start it. One being reinstalled stays as it was.
+        if previous.is_none() {
+            let _ = systemctl(system)
+                .args(["disable", &systemd_service()])
+                .stderr(std::process::Stdio::null())
+                .status();
+        }
+        let state = roll_back(&unit_path, previous);
+        let _ = systemctl(system).arg("daemon-reload").status();
+        return Err(format!(
+            "`systemctl{} {}` {}; {}",
+            scope,
+            args.join(" "),
+            result,
+            state
+        ));
     }
+    println!("Service installed and started: {}", unit_path.display());
+    Ok(())
 }
 
 #[cfg(target_os = "linux")]
-fn uninstall_systemd() -> Result<(), String> {
-    let unit_path = systemd_unit_path()?;
+fn uninstall_systemd(system: bool, print_only: bool) -> Result<(), String> {
+    let unit_path = systemd_unit_path(system)?;
+
+    if print_only {
+        let scope = if system { "" } else { " --user" };
+        print_commands(&[
+            format!("systemctl{} disable --now {}", scope, systemd_service()),
+            format!("rm {}", unit_path.display()),
+            format!("systemctl{} daemon-reload", scope),
+        ]);
+        return Ok(());
+    }
 
-    let _ = std::process::Command::new("systemctl")
-        .args(["--user", "disable", "--now", SYSTEMD_SERVICE])
+    let _ = systemctl(system)
+        .args(["disable", "--now", &systemd_service()])
         .status();
 
     if unit_path.exists() {
         std::fs::remove_file(&unit_path)
             .map_err(|e| format!("Failed to remove unit file: {}", e))?;
 
-        let _ = std::process::Command::new("systemctl")
-            .args(["--user", "daemon-reload"])
-            .status();
+        let _ = systemctl(system).arg("daemon-reload").status();
 
         println!("Service uninstalled: {}", unit_path.display());
     } else {
@@ -217,41 +820,105 @@ fn uninstall_systemd() -> Result<(), String> {
 #[cfg(target_os = "windows")]
 fn startup_script_path() -> Result<PathBuf, String> {
     let appdata = std::env::var("APPDATA")
-        .map_err(|_| "Could not determine APPDATA path".to_string())?;
+        .map_err(|_| "Could not determine the Startup folder; set APPDATA".to_string())?;
     Ok(PathBuf::from(appdata)
         .join("Microsoft")
         .join("Windows")
         .join("Start Menu")
         .join("Programs")
         .join("Startup")
-        .join("nodoze.vbs"))
+        .join(format!("{}.vbs", paths::instance_name("nodoze"))))
+}
+
+/// Program run by the Windows service if one is registered, otherwise the
+/// one quoted in the Startup folder script
+#[cfg(target_os = "windows")]
+fn installed_program() -> Option<PathBuf> {
+    if let Some(program) = scm::installed_program() {
+        return Some(program);
+    }
+    let script = std::fs::read_to_string(startup_script_path().ok()?).ok()?;
+    let start = script.find(r#"""""#)? + 3;
+    let end = start + script[start..].find(r#""""#)?;
+    Some(PathBuf::from(&script[start..end]))
+}
+
+/// Startup folder script running `<exe> run`. VBScript launches nodoze
+/// hidden (no console window). Unless `restart_policy` is "never", it waits
+/// for nodoze and relaunches it after `restart_delay`, until the script
+/// itself is deleted by `uninstall`.
+fn render_windows_task_cmd(exe: &Path, config: &Config) -> String {
+    let exe = exe.to_string_lossy();
+    let instance: String = instance_args().iter().map(|arg| format!(" {}", arg)).collect();
+    let exit_on_success = match config.restart_policy {
+        RestartPolicy::Never => {
+            return format!(
+                r#"CreateObject("Wscript.Shell").Run """{}""{} run", 0, False"#,
+                exe, instance
+            );
+        }
+        RestartPolicy::Always => "",
+        RestartPolicy::OnFailure => "  If code = 0 Then Exit Do\n",
+    };
+    format!(
+        r#"Set shell = CreateObject("Wscript.Shell")
+Set files = CreateObject("Scripting.FileSystemObject")
+Do
+  code = shell.Run("""{exe}""{instance} run", 0, True)
+{exit_on_success}  WScript.Sleep {delay_ms}
+  If Not files.FileExists(WScript.ScriptFullName) Then Exit Do
+Loop
+"#,
+        exe = exe,
+        instance = instance,
+        exit_on_success = exit_on_success,
+        delay_ms = config.restart_delay * 1000,
+    )
 }
 
 #[cfg(target_os = "windows")]
-fn install_windows_task(exe: &PathBuf) -> Result<(), String> {
+fn install_windows_task(exe: &Path, config: &Config, print_only: bool) -> Result<(), String> {
     let vbs_path = startup_script_path()?;
-    let exe_str = exe.to_string_lossy();
+    let vbs_content = render_windows_task_cmd(exe, config);
 
-    // VBScript launches nodoze hidden (no console window)
-    let vbs_content = format!(
-        r#"CreateObject("Wscript.Shell").Run """{}"" run", 0, False"#,
-        exe_str
-    );
+    if print_only {
+        print_file(&vbs_path, &vbs_content);
+        print_commands(&[format!("wscript.exe \"{}\"", vbs_path.display())]);
+        return Ok(());
+    }
 
     std::fs::write(&vbs_path, &vbs_content)
         .map_err(|e| format!("Failed to write startup script: {}", e))?;
 
-    // Start it immediately (spawn so we don't block)
-    let _ = std::process::Command::new("wscript.exe")
-        .arg(&vbs_path)
-        .spawn();
-
     println!("Service installed to Startup folder: {}", vbs_path.display());
+    // Start it immediately (spawn so we don't block). The script is in
+    // place either way, so failing here still leaves a working install.
+    if let Err(e) = std::process::Command::new("wscript.exe")
+        .arg(&vbs_path)
+        .spawn()
+    {
+        log::warn!(
+            "Couldn't start it now (wscript.exe: {}); it starts at your next login, or run \
+             `wscript.exe \"{}\"`",
+            e,
+            vbs_path.display()
+        );
+    }
     Ok(())
 }
 
 #[cfg(target_os = "windows")]
-fn uninstall_windows_task() -> Result<(), String> {
+fn uninstall_windows_task(print_only: bool) -> Result<(), String> {
+    if print_only {
+        print_commands(&[
+            "taskkill /IM nodoze.exe /F".to_string(),
+            format!("del \"{}\"", startup_script_path()?.display()),
+            "schtasks /End /TN NoDoze".to_string(),
+            "schtasks /Delete /TN NoDoze /F".to_string(),
+        ]);
+        return Ok(());
+    }
+
     // Stop any running instance
     let _ = std::process::Command::new("taskkill")
         .args(["/IM", "nodoze.exe", "/F"])
@@ -277,3 +944,375 @@ fn uninstall_windows_task() -> Result<(), String> {
 
     Ok(())
 }
+
+// ── Windows Service Control Manager ────────────────────────────────
+
+#[cfg(target_os = "windows")]
+pub mod scm {
+    use std::ffi::OsString;
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc;
+    use std::sync::OnceLock;
+    use std::time::Duration;
+
+    use windows_service::service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl,
+        ServiceExitCode, ServiceInfo, ServiceStartType, ServiceState, ServiceStatus,
+        ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    use crate::config::{Config, RestartPolicy};
+
+    /// "NoDoze", or "NoDoze-NAME" for a named instance
+    pub fn service_name() -> String {
+        crate::paths::instance_name("NoDoze")
+    }
+
+    const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+    /// Config handed to the service main, which the dispatcher calls without
+    /// any context of its own
+    static CONFIG: OnceLock<Config> = OnceLock::new();
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// Hand the process over to the service dispatcher. Blocks until the
+    /// service is stopped.
+    pub fn run(config: Config) -> Result<(), String> {
+        let _ = CONFIG.set(config);
+        service_dispatcher::start(service_name(), ffi_service_main)
+            .map_err(|e| format!("Failed to start service dispatcher: {}", e))
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(e) = run_service() {
+            log::error!("Service failed: {}", e);
+        }
+    }
+
+    fn run_service() -> windows_service::Result<()> {
+        let (stop_sender, stop) = mpsc::channel();
+        let stop_tx = stop_sender.clone();
+
+        let status_handle = service_control_handler::register(service_name(), move |control| {
+            match control {
+                ServiceControl::Stop | ServiceControl::Shutdown => {
+                    log::info!("Stop requested by the service manager, shutting down");
+                    let _ = stop_tx.send(());
+                    ServiceControlHandlerResult::NoError
+                }
+                ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                _ => ServiceControlHandlerResult::NotImplemented,
+            }
+        })?;
+
+        let status = |state, controls_accepted, exit_code| ServiceStatus {
+            service_type: SERVICE_TYPE,
+            current_state: state,
+            controls_accepted,
+            exit_code,
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        };
+
+        status_handle.set_service_status(status(
+            ServiceState::Running,
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            ServiceExitCode::Win32(0),
+        ))?;
+
+        let config = CONFIG.get().cloned().unwrap_or_default();
+        // Reported as a failure, so the recovery actions restart it
+        let exit_code = match crate::daemon::run_until(&config, stop_sender, stop) {
+            Ok(()) => ServiceExitCode::Win32(0),
+            Err(e) => {
+                log::error!("{}", e);
+                ServiceExitCode::ServiceSpecific(1)
+            }
+        };
+
+        status_handle.set_service_status(status(
+            ServiceState::Stopped,
+            ServiceControlAccept::empty(),
+            exit_code,
+        ))?;
+        Ok(())
+    }
+
+    pub fn install(exe: &Path, config: &Config, print_only: bool) -> Result<(), String> {
+        let recovery = recovery_commands(config);
+        if print_only {
+            let mut command = format!("\"{}\"", exe.display());
+            if let Ok(path) = Config::default_path() {
+                command.push_str(&format!(" --config \"{}\"", path.display()));
+            }
+            if let Some(instance) = crate::paths::instance() {
+                command.push_str(&format!(" --instance {}", instance));
+            }
+            let mut commands = vec![format!(
+                "sc create {} binPath= \"{} windows-service\" start= auto DisplayName= {}",
+                service_name(),
+                command.replace('"', "\\\""),
+                service_name(),
+            )];
+            commands.extend(recovery.iter().map(|args| format!("sc {}", args.join(" "))));
+            commands.push(format!("sc start {}", service_name()));
+            super::print_commands(&commands);
+            return Ok(());
+        }
+
+        let manager = ServiceManager::local_computer(
+            None::<&str>,
+            ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+        )
+        .map_err(|e| format!("Failed to open service manager (run as Administrator): {}", e))?;
+
+        // Services run as LocalSystem, so point them at this user's config
+        let mut launch_arguments = Vec::new();
+        if let Ok(path) = Config::default_path() {
+            launch_arguments.push(OsString::from("--config"));
+            launch_arguments.push(path.into_os_string());
+        }
+        if let Some(instance) = crate::paths::instance() {
+            launch_arguments.push(OsString::from("--instance"));
+            launch_arguments.push(OsString::from(instance));
+        }
+        launch_arguments.push(OsString::from("windows-service"));
+
+        let info = ServiceInfo {
+            name: OsString::from(service_name()),
+            display_name: OsString::from(service_name()),
+            service_type: SERVICE_TYPE,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path: exe.to_path_buf(),
+            launch_arguments,
+            dependencies: vec![],
+            account_name: None,
+            account_password: None,
+        };
+
+        let service = manager
+            .create_service(
+                &info,
+                ServiceAccess::CHANGE_CONFIG | ServiceAccess::START | ServiceAccess::DELETE,
+            )
+            .map_err(|e| format!("Failed to create service: {}", e))?;
+        let _ = service.set_description("Keep speakers alive with inaudible tones");
+        for args in &recovery {
+            let applied = std::process::Command::new("sc")
+                .args(args)
+                .stdout(std::process::Stdio::null())
+                .status()
+                .is_ok_and(|s| s.success());
+            if !applied {
+                log::warn!("Failed to set the service's recovery actions (sc {})", args.join(" "));
+            }
+        }
+        if let Err(e) = service.start::<&str>(&[]) {
+            // Creating it failed if it already existed, so it's ours to remove
+            let state = match service.delete() {
+                Ok(()) => "removed it again, so nothing is left installed".to_string(),
+                Err(delete) => format!(
+                    "couldn't remove it ({}); run `nodoze uninstall` as Administrator",
+                    delete
+                ),
+            };
+            return Err(format!(
+                "Service {} created but failed to start ({}); {}",
+                service_name(), e, state
+            ));
+        }
+
+        println!("Service installed and started: {}", service_name());
+        Ok(())
+    }
+
+    /// `sc` arguments setting the service's recovery actions for
+    /// `restart_policy`. A new service has none, which suits "never". The
+    /// SCM only acts on failures, so both other policies also count a stop
+    /// with an error exit code as one, e.g. after `max_consecutive_failures`.
+    /// The SCM can't restart a service that stopped cleanly, so "always"
+    /// ends up the same as "on-failure".
+    fn recovery_commands(config: &Config) -> Vec<Vec<String>> {
+        let restart = format!("restart/{}", config.restart_delay * 1000);
+        let name = service_name();
+        let failure = ["failure", &name, "reset=", "86400", "actions=", &restart];
+        let commands: Vec<&[&str]> = match config.restart_policy {
+            RestartPolicy::Never => Vec::new(),
+            RestartPolicy::OnFailure | RestartPolicy::Always => {
+                vec![&failure, &["failureflag", &name, "1"]]
+            }
+        };
+        commands
+            .iter()
+            .map(|args| args.iter().map(|a| a.to_string()).collect())
+            .collect()
+    }
+
+    /// True if the service is registered with the Service Control Manager
+    pub fn is_installed() -> bool {
+        ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+            .and_then(|manager| {
+                manager.open_service(service_name(), ServiceAccess::QUERY_STATUS)
+            })
+            .is_ok()
+    }
+
+    /// Program the registered service runs. The SCM stores the whole command
+    /// line, with the program quoted if it contains spaces.
+    pub fn installed_program() -> Option<PathBuf> {
+        let manager =
+            ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT).ok()?;
+        let service = manager
+            .open_service(service_name(), ServiceAccess::QUERY_CONFIG)
+            .ok()?;
+        let command = service.query_config().ok()?.executable_path;
+        let command = command.to_string_lossy();
+        let program = match command.strip_prefix('"') {
+            Some(rest) => rest.split('"').next()?,
+            None => command.split(' ').next()?,
+        };
+        Some(PathBuf::from(program))
+    }
+
+    /// Remove the service if it is registered. Not having one is fine, since
+    /// the default install uses the Startup folder.
+    pub fn uninstall(print_only: bool) -> Result<(), String> {
+        if print_only {
+            if is_installed() {
+                super::print_commands(&[
+                    format!("sc stop {}", service_name()),
+                    format!("sc delete {}", service_name()),
+                ]);
+            }
+            return Ok(());
+        }
+
+        let Ok(manager) =
+            ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        else {
+            return Ok(());
+        };
+        let Ok(service) = manager.open_service(
+            service_name(),
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        ) else {
+            return Ok(());
+        };
+
+        service
+            .delete()
+            .map_err(|e| format!("Failed to delete service (run as Administrator): {}", e))?;
+        if service
+            .query_status()
+            .is_ok_and(|s| s.current_state != ServiceState::Stopped)
+        {
+            let _ = service.stop();
+        }
+
+        println!("Service uninstalled: {}", service_name());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXE: &str = "/opt/nodoze/bin/nodoze";
+
+    #[test]
+    fn plist_runs_the_exe_under_its_label() {
+        let plist =
+            render_launchd_plist(Path::new(EXE), Path::new("/tmp/logs"), &Config::default());
+        assert!(plist.contains("<key>Label</key>\n    <string>com.nodoze.daemon</string>"));
+        assert!(plist.contains(&format!("<string>{}</string>\n      <string>run</string>", EXE)));
+        assert!(plist.contains("<string>/tmp/logs/nodoze.stderr.log</string>"));
+    }
+
+    #[test]
+    fn systemd_unit_runs_the_exe_after_the_audio_servers() {
+        let unit = render_systemd_unit(
+            Path::new(EXE),
+            &["pipewire.service"],
+            &Config::default(),
+            false,
+        );
+        assert!(unit.contains(&format!("\nExecStart={} run\n", EXE)));
+        assert!(unit.contains("\nAfter=sound.target pipewire.service\n"));
+        assert!(unit.contains("\nWants=pipewire.service\n"));
+        assert!(unit.contains("\nWantedBy=default.target\n"));
+
+        let system = render_systemd_unit(Path::new(EXE), &[], &Config::default(), true);
+        assert!(system.contains("\nWantedBy=multi-user.target\n"));
+        assert!(!system.contains("XDG_RUNTIME_DIR"));
+    }
+
+    #[test]
+    fn windows_script_runs_the_exe() {
+        let script = render_windows_task_cmd(Path::new(EXE), &Config::default());
+        assert!(script.contains(&format!(r#"shell.Run("""{}"" run", 0, True)"#, EXE)));
+    }
+
+    #[test]
+    fn systemd_unit_follows_the_restart_policy() {
+        let unit = |restart_policy| {
+            let config = Config {
+                restart_policy,
+                restart_delay: 30,
+                ..Config::default()
+            };
+            render_systemd_unit(Path::new(EXE), &[], &config, false)
+        };
+        assert!(unit(RestartPolicy::Always).contains("\nRestart=always\nRestartSec=30\n"));
+        assert!(unit(RestartPolicy::OnFailure).contains("\nRestart=on-failure\nRestartSec=30\n"));
+        let never = unit(RestartPolicy::Never);
+        assert!(never.contains("\nRestart=no\n"));
+        assert!(!never.contains("RestartSec"));
+    }
+
+    #[test]
+    fn plist_follows_the_restart_policy() {
+        let plist = |restart_policy| {
+            let config = Config {
+                restart_policy,
+                restart_delay: 30,
+                ..Config::default()
+            };
+            render_launchd_plist(Path::new(EXE), Path::new("/tmp/logs"), &config)
+        };
+        let keep_alive = |plist: &str| {
+            let start = plist.find("<key>KeepAlive</key>").unwrap();
+            let end = plist.find("<key>ThrottleInterval</key>").unwrap();
+            plist[start..end].split_whitespace().collect::<Vec<_>>().join(" ")
+        };
+        assert_eq!(keep_alive(&plist(RestartPolicy::Always)), "<key>KeepAlive</key> <true/>");
+        assert_eq!(
+            keep_alive(&plist(RestartPolicy::OnFailure)),
+            "<key>KeepAlive</key> <dict> <key>SuccessfulExit</key> <false/> </dict>"
+        );
+        assert_eq!(keep_alive(&plist(RestartPolicy::Never)), "<key>KeepAlive</key> <false/>");
+        assert!(plist(RestartPolicy::Always).contains("<integer>30</integer>"));
+    }
+
+    #[test]
+    fn windows_script_follows_the_restart_policy() {
+        let script = |restart_policy| {
+            let config = Config {
+                restart_policy,
+                restart_delay: 30,
+                ..Config::default()
+            };
+            render_windows_task_cmd(Path::new(EXE), &config)
+        };
+        assert!(!script(RestartPolicy::Never).contains("Loop"));
+        assert!(script(RestartPolicy::Always).contains("WScript.Sleep 30000"));
+        assert!(!script(RestartPolicy::Always).contains("If code = 0"));
+        assert!(script(RestartPolicy::OnFailure).contains("If code = 0 Then Exit Do"));
+    }
+}
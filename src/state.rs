@@ -0,0 +1,163 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::paths;
+
+/// `data_dir` from the config, if set
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Keep state (and the control socket) in `data_dir` rather than the
+/// default locations. Call once, before anything reads or writes state.
+pub fn set_data_dir(dir: &str) {
+    if !dir.is_empty() {
+        let _ = DATA_DIR.set(PathBuf::from(dir));
+    }
+}
+
+/// The configured `data_dir`, if any
+pub fn data_dir() -> Option<&'static Path> {
+    DATA_DIR.get().map(PathBuf::as_path)
+}
+
+/// Directory for state shared between nodoze processes and runs: `data_dir`,
+/// else the per-user state dir where the platform has one
+pub fn dir() -> Result<PathBuf, String> {
+    if let Some(dir) = data_dir() {
+        return Ok(dir.to_path_buf());
+    }
+    paths::state_dir().map(|d| d.join(paths::instance_name("nodoze")))
+}
+
+/// Create the state directory if it doesn't exist yet
+pub fn create_dir() -> Result<PathBuf, String> {
+    let dir = dir()?;
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    Ok(dir)
+}
+
+/// File holding the time of the last tone played by any nodoze process, so
+/// `nodoze once` and a running daemon don't play redundant tones back to back
+pub fn path() -> Option<PathBuf> {
+    dir().ok().map(|d| d.join("last_play"))
+}
+
+/// Liveness file the daemon touches on every loop iteration, so an external
+/// watchdog can tell a hung daemon from one waiting out its interval
+pub fn alive_path() -> Option<PathBuf> {
+    dir().ok().map(|d| d.join("alive"))
+}
+
+/// Version of the nodoze binary the daemon was started from, so `status`
+/// and `doctor` can tell when it's older than the installed binary
+fn daemon_version_path() -> Result<PathBuf, String> {
+    dir().map(|d| d.join("daemon_version"))
+}
+
+/// Failed tones in a row and the last error, while tones are failing, so a
+/// restarted daemon carries on from there
+fn failures_path() -> Result<PathBuf, String> {
+    dir().map(|d| d.join("failures"))
+}
+
+fn device_ids_path() -> Result<PathBuf, String> {
+    dir().map(|d| d.join("device_ids"))
+}
+
+fn write(path: Result<PathBuf, String>, contents: &str) -> Result<(), String> {
+    let path = path?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create state directory: {}", e))?;
+    }
+    std::fs::write(&path, contents)
+        .map_err(|e| format!("Failed to write state file {}: {}", path.display(), e))
+}
+
+/// Record that a tone just finished playing
+pub fn record_last_play(time: SystemTime) -> Result<(), String> {
+    let millis = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    write(dir().map(|d| d.join("last_play")), &format!("{}\n", millis))
+}
+
+/// Time of the last recorded tone, if any
+pub fn last_play() -> Option<SystemTime> {
+    let contents = std::fs::read_to_string(path()?).ok()?;
+    let millis: u64 = contents.trim().parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_millis(millis))
+}
+
+/// Mark the daemon as alive by updating the liveness file's mtime
+pub fn touch_alive() -> Result<(), String> {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    write(dir().map(|d| d.join("alive")), &format!("{}\n", millis))
+}
+
+/// When the daemon last touched the liveness file, if ever
+pub fn last_alive() -> Option<SystemTime> {
+    std::fs::metadata(alive_path()?).ok()?.modified().ok()
+}
+
+/// Record this build's version as the running daemon's
+pub fn record_daemon_version() -> Result<(), String> {
+    write(daemon_version_path(), &format!("{}\n", env!("CARGO_PKG_VERSION")))
+}
+
+/// Version the daemon recorded when it last started, if any
+pub fn daemon_version() -> Option<String> {
+    let contents = std::fs::read_to_string(daemon_version_path().ok()?).ok()?;
+    Some(contents.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+/// Record how many tones in a row have failed, and why the last one did
+pub fn record_failures(count: u32, reason: &str) -> Result<(), String> {
+    // One line for the count, the rest for the reason
+    write(failures_path(), &format!("{}\n{}\n", count, reason.trim()))
+}
+
+/// Failed tones in a row recorded by the last run and why the last one
+/// failed, if it ended while they were failing
+pub fn failures() -> Option<(u32, String)> {
+    let contents = std::fs::read_to_string(failures_path().ok()?).ok()?;
+    let (count, reason) = contents.split_once('\n').unwrap_or((&contents, ""));
+    let count = count.trim().parse().ok().filter(|&count| count > 0)?;
+    Some((count, reason.trim().to_string()))
+}
+
+/// Forget recorded failures, once a tone has played
+pub fn clear_failures() -> Result<(), String> {
+    let path = failures_path()?;
+    match std::fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(format!("Failed to remove state file {}: {}", path.display(), e))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Stable ID of the device a configured name last resolved to.
+/// Stored one `name<TAB>id` pair per line.
+pub fn device_id(name: &str) -> Option<String> {
+    let contents = std::fs::read_to_string(device_ids_path().ok()?).ok()?;
+    contents
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .find(|(n, _)| *n == name)
+        .map(|(_, id)| id.to_string())
+}
+
+/// Remember the stable ID a configured name resolved to
+pub fn record_device_id(name: &str, id: &str) -> Result<(), String> {
+    let existing = device_ids_path()
+        .ok()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .unwrap_or_default();
+    let mut contents: String = existing
+        .lines()
+        .filter(|line| line.split_once('\t').is_none_or(|(n, _)| n != name))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    contents.push_str(&format!("{}\t{}\n", name, id));
+    write(device_ids_path(), &contents)
+}
@@ -0,0 +1,107 @@
+use crate::audio;
+use crate::config::Config;
+
+/// Length of each stretch of the recording measured on its own, so the
+/// silence around the tone doesn't dilute its level
+const WINDOW_SECS: f64 = 0.5;
+
+/// Fewest whole cycles of the tone a window holds, for very low frequencies
+const WINDOW_CYCLES: f64 = 10.0;
+
+/// How far the tone must stand above the neighbouring frequencies to count,
+/// so broadband noise that happens to pass the threshold isn't mistaken
+/// for it
+const MIN_SNR_DB: f64 = 12.0;
+
+/// Play the tone while recording `input_device` (or the default input) and
+/// report whether its frequency shows up in the recording above `threshold`
+/// dBFS. Returns false if it didn't, or the check couldn't run.
+pub fn run(config: &Config, threshold: f64) -> bool {
+    if !config.wav_path.is_empty() {
+        log::error!("verify listens for the sine tone's frequency; it can't check `wav_path`");
+        return false;
+    }
+
+    let recording = match audio::record_tone(config) {
+        Ok(recording) => recording,
+        Err(e) => {
+            log::error!("{}", e);
+            return false;
+        }
+    };
+    println!("Recorded from '{}'", recording.device);
+
+    let detection = measure(&recording, config.frequency);
+    if detection.detected(threshold) {
+        println!(
+            "{} Hz tone detected at {:.1} dBFS ({:.0} dB above nearby frequencies)",
+            config.frequency, detection.level_db, detection.snr_db
+        );
+        true
+    } else {
+        println!(
+            "{} Hz tone not detected: strongest {:.1} dBFS ({:.0} dB above nearby frequencies); \
+             needed {} dBFS and {} dB",
+            config.frequency, detection.level_db, detection.snr_db, threshold, MIN_SNR_DB
+        );
+        false
+    }
+}
+
+/// How strongly a frequency showed up in a recording
+pub struct Detection {
+    /// Level in the loudest window, in dBFS
+    pub level_db: f64,
+    /// How far that stands above the neighbouring frequencies, in dB
+    pub snr_db: f64,
+}
+
+impl Detection {
+    /// Whether the tone counts as heard at `threshold` dBFS
+    pub fn detected(&self, threshold: f64) -> bool {
+        self.level_db >= threshold && self.snr_db >= MIN_SNR_DB
+    }
+}
+
+/// Level of `frequency` in the recording, taken from the window where it
+/// is loudest
+pub fn measure(recording: &audio::Recording, frequency: f64) -> Detection {
+    let window =
+        ((WINDOW_SECS.max(WINDOW_CYCLES / frequency) * recording.sample_rate) as usize).max(1);
+    // Loudest window at the tone's frequency, and the noise beside it there
+    let (level, noise) = recording
+        .samples
+        .chunks(window)
+        .filter(|chunk| chunk.len() == window || recording.samples.len() < window)
+        .map(|chunk| {
+            let level = tone_level(chunk, recording.sample_rate, frequency);
+            let noise = tone_level(chunk, recording.sample_rate, frequency * 0.7)
+                .max(tone_level(chunk, recording.sample_rate, frequency * 1.4));
+            (level, noise)
+        })
+        .fold((0.0, 0.0), |best: (f64, f64), w| if w.0 > best.0 { w } else { best });
+
+    let level_db = to_db(level);
+    Detection {
+        level_db,
+        snr_db: level_db - to_db(noise),
+    }
+}
+
+/// Amplitude of `frequency` in the samples, as a fraction of full scale,
+/// from a single-bin DFT (the Goertzel algorithm)
+fn tone_level(samples: &[f32], sample_rate: f64, frequency: f64) -> f64 {
+    let coeff = 2.0 * (2.0 * std::f64::consts::PI * frequency / sample_rate).cos();
+    let (mut s1, mut s2) = (0.0, 0.0);
+    for &x in samples {
+        let s0 = x as f64 + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    let power = (s1 * s1 + s2 * s2 - coeff * s1 * s2).max(0.0);
+    2.0 * power.sqrt() / samples.len().max(1) as f64
+}
+
+fn to_db(level: f64) -> f64 {
+    20.0 * level.max(1e-9).log10()
+}
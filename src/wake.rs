@@ -0,0 +1,129 @@
+//! System wake notifications (`wake_on_resume`). Where the OS reports a
+//! resume, the daemon plays a tone straight away instead of waiting for its
+//! next poll to notice that the interval has passed.
+
+use std::sync::Arc;
+
+use crate::control::Shared;
+
+/// Start listening for the system resuming from sleep, calling
+/// `shared.wake()` each time it does. Returns the name of the source
+/// listened to, or why there is none.
+pub fn watch(shared: Arc<Shared>) -> Result<&'static str, String> {
+    platform::watch(shared)
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::sync::Arc;
+
+    use crate::control::Shared;
+
+    const LOGIND: &str = "org.freedesktop.login1";
+
+    /// Follow systemd-logind's `PrepareForSleep` signal on the system bus
+    pub fn watch(shared: Arc<Shared>) -> Result<&'static str, String> {
+        let connection = zbus::blocking::Connection::system()
+            .map_err(|e| format!("Can't connect to the system D-Bus: {}", e))?;
+        let rule = zbus::MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .sender(LOGIND)
+            .and_then(|rule| rule.interface("org.freedesktop.login1.Manager"))
+            .and_then(|rule| rule.member("PrepareForSleep"))
+            .map_err(|e| e.to_string())?
+            .build();
+        let messages = zbus::blocking::MessageIterator::for_match_rule(rule, &connection, None)
+            .map_err(|e| format!("Can't subscribe to systemd-logind sleep signals: {}", e))?;
+        std::thread::spawn(move || {
+            for message in messages {
+                if shared.is_stopped() {
+                    return;
+                }
+                // true as the system goes to sleep, false once it has resumed
+                if let Ok(false) = message.and_then(|m| m.body().deserialize::<bool>()) {
+                    log::info!("System resumed from sleep");
+                    shared.wake();
+                }
+            }
+        });
+        Ok("systemd-logind")
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::ffi::c_void;
+    use std::sync::Arc;
+
+    use crate::control::Shared;
+
+    /// The recipient is a callback rather than a window handle
+    const DEVICE_NOTIFY_CALLBACK: u32 = 2;
+    /// Sent on every resume, whether or not anyone is at the machine
+    const PBT_APMRESUMEAUTOMATIC: u32 = 0x12;
+
+    type Callback = unsafe extern "system" fn(*const c_void, u32, *const c_void) -> u32;
+
+    /// `DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS`
+    #[repr(C)]
+    struct Subscription {
+        callback: Callback,
+        context: *const c_void,
+    }
+
+    #[link(name = "powrprof")]
+    extern "system" {
+        fn PowerRegisterSuspendResumeNotification(
+            flags: u32,
+            recipient: *const c_void,
+            handle: *mut *mut c_void,
+        ) -> u32;
+    }
+
+    unsafe extern "system" fn on_power_event(
+        context: *const c_void,
+        event: u32,
+        _setting: *const c_void,
+    ) -> u32 {
+        if event == PBT_APMRESUMEAUTOMATIC {
+            // SAFETY: context is the Arc leaked in `watch`, never freed
+            let shared = unsafe { &*(context as *const Shared) };
+            log::info!("System resumed from sleep");
+            shared.wake();
+        }
+        0
+    }
+
+    /// Register for suspend/resume notifications from the power manager
+    pub fn watch(shared: Arc<Shared>) -> Result<&'static str, String> {
+        // Both live as long as the registration, which is the whole process
+        let subscription: &'static Subscription = Box::leak(Box::new(Subscription {
+            callback: on_power_event,
+            context: Arc::into_raw(shared) as *const c_void,
+        }));
+        let mut handle = std::ptr::null_mut();
+        // SAFETY: the subscription is 'static and the handle is written once
+        let status = unsafe {
+            PowerRegisterSuspendResumeNotification(
+                DEVICE_NOTIFY_CALLBACK,
+                subscription as *const Subscription as *const c_void,
+                &mut handle,
+            )
+        };
+        if status != 0 {
+            return Err(format!("PowerRegisterSuspendResumeNotification failed (error {})", status));
+        }
+        Ok("Windows power notifications")
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod platform {
+    use std::sync::Arc;
+
+    use crate::control::Shared;
+
+    pub fn watch(_shared: Arc<Shared>) -> Result<&'static str, String> {
+        Err("wake notifications aren't supported on this platform".to_string())
+    }
+}
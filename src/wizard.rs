@@ -19,8 +19,7 @@ pub fn run() -> Result<(), String> {
     let volume = prompt_volume("Volume 0-100%", (defaults.volume * 100.0) as u64)?;
     let device = prompt_device()?;
 
-    let config_path = Config::config_path()
-        .ok_or_else(|| "Could not determine config directory".to_string())?;
+    let config_path = Config::config_path()?;
 
     if let Some(parent) = config_path.parent() {
         std::fs::create_dir_all(parent)
@@ -104,7 +103,10 @@ fn prompt_volume(label: &str, default_pct: u64) -> Result<f64, String> {
 }
 
 fn prompt_device() -> Result<String, String> {
-    let devices = audio::list_devices()?;
+    let devices: Vec<String> = audio::list_devices("", false, false)?
+        .into_iter()
+        .map(|d| d.name)
+        .collect();
 
     if devices.is_empty() {
         println!("  No output devices found, using system default.");
@@ -115,8 +117,7 @@ fn prompt_device() -> Result<String, String> {
     println!("Available output devices:");
     println!("  1. System default");
     for (i, name) in devices.iter().enumerate() {
-        let clean = name.strip_suffix(" (default)").unwrap_or(name);
-        println!("  {}. {}", i + 2, clean);
+        println!("  {}. {}", i + 2, name);
     }
     println!();
 
@@ -137,13 +138,11 @@ fn prompt_device() -> Result<String, String> {
     if chosen == 1 {
         Ok(String::new())
     } else {
-        let device_name = &devices[chosen - 2];
-        let clean = device_name.strip_suffix(" (default)").unwrap_or(device_name);
-        Ok(clean.to_string())
+        Ok(devices[chosen - 2].clone())
     }
 }
 
-fn prompt_raw(label: &str) -> Result<String, String> {
+pub fn prompt_raw(label: &str) -> Result<String, String> {
     print!("  {}: ", label);
     io::stdout()
         .flush()
@@ -0,0 +1,70 @@
+//! The daemon loop against the in-memory audio backend. Run with
+//! `cargo test --features mock-audio`.
+#![cfg(feature = "mock-audio")]
+
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use nodoze::audio::mock;
+use nodoze::{Config, Daemon};
+
+/// The mock device's takes and the state directory are shared by the whole
+/// process, so only one daemon runs at a time
+fn exclusive() -> MutexGuard<'static, ()> {
+    static LOCK: Mutex<()> = Mutex::new(());
+    static DATA_DIR: OnceLock<tempfile::TempDir> = OnceLock::new();
+
+    let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = DATA_DIR.get_or_init(|| tempfile::tempdir().unwrap());
+    nodoze::state::set_data_dir(dir.path().to_str().unwrap());
+    // Start from a clean slate: no takes, and no tone played recently
+    mock::takes();
+    let _ = std::fs::remove_file(dir.path().join("last_play"));
+    guard
+}
+
+#[test]
+fn rapid_double_trigger_plays_once() {
+    let _guard = exclusive();
+    // The startup tone, then a request for another straight after it
+    let daemon = Daemon::start(Config {
+        duration: 1,
+        min_gap: 60,
+        ..Config::default()
+    });
+    let second = daemon.prime().unwrap();
+    assert!(second.starts_with("skipped, a tone played"), "{}", second);
+    daemon.stop().unwrap();
+
+    assert_eq!(mock::takes().len(), 1);
+}
+
+#[test]
+fn daemon_plays_a_tone_every_interval() {
+    let _guard = exclusive();
+    let config = Config {
+        interval: 1,
+        duration: 1,
+        volume: 0.1,
+        ..Config::default()
+    };
+    let daemon = Daemon::start(config.clone());
+    std::thread::sleep(std::time::Duration::from_millis(2500));
+    daemon.stop().unwrap();
+
+    // The startup tone, then one each second at the next poll
+    let takes = mock::takes();
+    assert!((2..=4).contains(&takes.len()), "{} tones played", takes.len());
+    for take in &takes {
+        assert_eq!(take.sample_rate, mock::SAMPLE_RATE as f64);
+        assert_eq!(take.channels, mock::CHANNELS as usize);
+        let frames = take.samples.len() / take.channels;
+        let expected = config.duration as usize * mock::SAMPLE_RATE as usize;
+        assert!(frames >= expected && frames < expected + 4096, "{} frames", frames);
+
+        let peak = take.samples.iter().fold(0.0, |peak: f32, s| peak.max(s.abs()));
+        assert!((peak - 0.1).abs() < 0.001, "peaks at {}", peak);
+        for frame in take.samples.chunks(take.channels) {
+            assert_eq!(frame[0], frame[1]);
+        }
+    }
+}